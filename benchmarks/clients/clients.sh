@@ -2,6 +2,7 @@
 set -e
 
 cargo run --bin rumqttasync --release | tee results/clients.txt
+cargo run --bin rumqttasyncbatch --release | tee -a results/clients.txt
 cargo run --bin rumqttsync --release | tee -a results/clients.txt
 # cargo run --bin rumqttasyncqos0 --release | tee -a results/clients.txt
 # cargo run --bin pahoasync --release | tee -a results/clients.txt
@@ -0,0 +1,64 @@
+use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS};
+
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use tokio::task;
+use tokio::time;
+
+mod common;
+
+#[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+async fn main() {
+    // pretty_env_logger::init();
+    let guard = pprof::ProfilerGuard::new(100).unwrap();
+    start("rumqtt-async-batch", 100, 1_000_000).await.unwrap();
+    common::profile("bench.pb", guard);
+}
+
+pub async fn start(id: &str, payload_size: usize, count: usize) -> Result<(), Box<dyn Error>> {
+    let mut mqttoptions = MqttOptions::new(id, "localhost", 1883);
+    mqttoptions.set_keep_alive(Duration::from_secs(20));
+    mqttoptions.set_inflight(100);
+
+    let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+    task::spawn(async move {
+        for _i in 0..count {
+            let payload = vec![0; payload_size];
+            let qos = QoS::AtLeastOnce;
+            client
+                .publish("hello/benchmarks/world", qos, false, payload)
+                .await
+                .unwrap();
+        }
+
+        time::sleep(Duration::from_secs(10)).await;
+    });
+
+    let mut acks_count = 0;
+    let start = Instant::now();
+    'poll: loop {
+        for event in eventloop.poll_batch(100).await? {
+            if let Event::Incoming(Incoming::PubAck(_)) = event {
+                acks_count += 1;
+                if acks_count == count {
+                    break 'poll;
+                }
+            }
+        }
+    }
+
+    let elapsed_ms = start.elapsed().as_millis();
+    let throughput = acks_count / elapsed_ms as usize;
+    let throughput = throughput * 1000;
+
+    let print = common::Print {
+        id: id.to_owned(),
+        messages: count,
+        payload_size,
+        throughput,
+    };
+
+    println!("{}", serde_json::to_string_pretty(&print).unwrap());
+    Ok(())
+}
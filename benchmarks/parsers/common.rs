@@ -15,6 +15,7 @@ pub fn profile(name: &str, guard: ProfilerGuard) {
     };
 }
 
+#[allow(unused)]
 #[derive(Serialize, Deserialize)]
 pub struct Print {
     pub id: String,
@@ -24,3 +25,15 @@ pub struct Print {
     pub write_throughput_gpbs: f32,
     pub read_throughput_gpbs: f32,
 }
+
+/// Write throughput of a publish encoded on every send vs encoded once
+#[allow(unused)]
+#[derive(Serialize, Deserialize)]
+pub struct PrintEncoded {
+    pub id: String,
+    pub messages: usize,
+    pub payload_size: usize,
+    pub total_size_gb: f32,
+    pub write_throughput_gpbs: f32,
+    pub encoded_write_throughput_gpbs: f32,
+}
@@ -0,0 +1,65 @@
+use bytes::BytesMut;
+use rumqttc::mqttbytes::v4::{EncodedPublish, Publish};
+use rumqttc::mqttbytes::QoS;
+use std::time::Instant;
+
+mod common;
+
+fn main() {
+    pretty_env_logger::init();
+    let count = 1024 * 1024;
+    let payload_size = 64;
+    let guard = pprof::ProfilerGuard::new(100).unwrap();
+
+    // Same heartbeat publish sent repeatedly, each time with a new packet id
+    let publish = Publish::new(
+        "hello/world/heartbeat",
+        QoS::AtLeastOnce,
+        vec![1; payload_size],
+    );
+
+    // ------------------------- encoding every send -------------------------------
+    let mut output = BytesMut::with_capacity(10 * 1024);
+    let start = Instant::now();
+    for i in 0..count {
+        let mut publish = publish.clone();
+        publish.pkid = (i % 100 + 1) as u16;
+        publish.write(&mut output).unwrap();
+    }
+
+    let write_throughput = throughput(output.len(), start);
+    let total_size = output.len();
+    let total_size_gb = total_size as f32 / 1024.0 / 1024.0 / 1024.0;
+
+    // ------------------------- pre-encoded publish --------------------------------
+    let encoded = EncodedPublish::new(publish).unwrap();
+    let mut output = BytesMut::with_capacity(10 * 1024);
+    let start = Instant::now();
+    for i in 0..count {
+        let encoded = encoded.clone();
+        encoded.write((i % 100 + 1) as u16, &mut output);
+    }
+
+    assert_eq!(output.len(), total_size);
+    let encoded_write_throughput = throughput(output.len(), start);
+
+    // --------------------------- results ---------------------------------------
+
+    let print = common::PrintEncoded {
+        id: "mqttv4encodedpublish".to_owned(),
+        messages: count,
+        payload_size,
+        total_size_gb,
+        write_throughput_gpbs: write_throughput,
+        encoded_write_throughput_gpbs: encoded_write_throughput,
+    };
+
+    println!("{}", serde_json::to_string_pretty(&print).unwrap());
+    common::profile("bench.pb", guard);
+}
+
+fn throughput(total_size: usize, start: Instant) -> f32 {
+    let elapsed_micros = start.elapsed().as_micros();
+    let throughput = (total_size * 1_000_000) / elapsed_micros as usize;
+    throughput as f32 / 1024.0 / 1024.0 / 1024.0
+}
@@ -1,13 +1,21 @@
 //! This module offers a high level synchronous and asynchronous abstraction to
 //! async eventloop.
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use crate::mqttbytes::{v4::*, QoS};
-use crate::{valid_filter, valid_topic, ConnectionError, Event, EventLoop, MqttOptions, Request};
+use crate::state::{LastValues, SubscriptionStreams};
+use crate::{
+    valid_filter, valid_topic, BufferOverflow, ConnectionError, DeliveryNotice, DeliveryStatus,
+    Event, EventLoop, FlushNotice, MqttOptions, PublishStream, Request,
+};
 
 use bytes::Bytes;
-use flume::{SendError, Sender, TrySendError};
-use futures_util::FutureExt;
+use flume::{Receiver, SendError, Sender, TrySendError, WeakSender};
+use futures_util::{FutureExt, Stream};
+use tokio::io::AsyncRead;
 use tokio::runtime::{self, Runtime};
 use tokio::time::timeout;
 
@@ -18,6 +26,16 @@ pub enum ClientError {
     Request(Request),
     #[error("Failed to send mqtt requests to eventloop")]
     TryRequest(Request),
+    #[error("Eventloop failed to flush requests")]
+    Flush,
+    #[error("Buffer of requests waiting to be sent is full")]
+    BufferFull(Request),
+    #[error("Failed to reserve a slot, eventloop was dropped")]
+    Reserve,
+    /// Payload of `publish_json` couldn't be serialized, nothing was published
+    #[cfg(feature = "json")]
+    #[error("Failed to serialize JSON payload: {0}")]
+    Json(#[source] serde_json::Error),
 }
 
 impl From<SendError<Request>> for ClientError {
@@ -39,20 +57,51 @@ fn from(e: TrySendError<Request>) -> Self {
 ///
 /// **NOTE**: The `EventLoop` must be regularly polled in order to send, receive and process packets
 /// from the broker, i.e. move ahead.
+///
+/// Requests are written in the order they are sent, irrespective of QoS. While the inflight
+/// window is full, QoS 0 publishes wait behind QoS 1 and 2 publishes as well, which limits
+/// throughput of QoS 0 publishes to that of QoS 1 and 2 ones sent alongside.
 #[derive(Clone, Debug)]
 pub struct AsyncClient {
-    request_tx: Sender<Request>,
+    request_tx: RequestSender<Request>,
+    drained: Arc<Mutex<Vec<Request>>>,
+    next_last_will: Arc<Mutex<Option<LastWill>>>,
+    subscription_streams: SubscriptionStreams,
+    last_values: LastValues,
+    /// Requests buffered by the `EventLoop`
+    buffered: Arc<AtomicUsize>,
+    /// Buffered requests past which publishes are refused
+    max_buffered: Option<usize>,
 }
 
 impl AsyncClient {
     /// Create a new `AsyncClient`.
     ///
-    /// `cap` specifies the capacity of the bounded async channel.
+    /// `cap` specifies the capacity of the bounded async channel, shared by requests and
+    /// permits from [`reserve`](`AsyncClient::reserve`).
     pub fn new(options: MqttOptions, cap: usize) -> (AsyncClient, EventLoop) {
-        let eventloop = EventLoop::new(options, cap);
-        let request_tx = eventloop.requests_tx.clone();
+        let mut eventloop = EventLoop::new(options, cap);
+        let request_tx = eventloop.requests_tx.take().unwrap();
+        let request_tx = RequestSender::new(request_tx, eventloop.slots.handle());
+        let drained = eventloop.drained.clone();
+        let next_last_will = eventloop.next_last_will.clone();
+        let subscription_streams = eventloop.state.subscription_streams.clone();
+        let last_values = eventloop.state.last_values.clone();
+        let buffered = eventloop.buffered.clone();
+        let max_buffered = match eventloop.mqtt_options.buffer_overflow() {
+            BufferOverflow::Error => eventloop.mqtt_options.max_buffered_messages(),
+            _ => None,
+        };
 
-        let client = AsyncClient { request_tx };
+        let client = AsyncClient {
+            request_tx,
+            drained,
+            next_last_will,
+            subscription_streams,
+            last_values,
+            buffered,
+            max_buffered,
+        };
 
         (client, eventloop)
     }
@@ -60,9 +109,67 @@ pub fn new(options: MqttOptions, cap: usize) -> (AsyncClient, EventLoop) {
     /// Create a new `AsyncClient` from a channel `Sender`.
     ///
     /// This is mostly useful for creating a test instance where you can
-    /// listen on the corresponding receiver.
+    /// listen on the corresponding receiver. Publishes sent with permits from
+    /// [`reserve`](`AsyncClient::reserve`) are also sent on this channel.
     pub fn from_senders(request_tx: Sender<Request>) -> AsyncClient {
-        AsyncClient { request_tx }
+        AsyncClient {
+            request_tx: RequestSender::new(request_tx, None),
+            drained: Arc::new(Mutex::new(Vec::new())),
+            next_last_will: Arc::new(Mutex::new(None)),
+            subscription_streams: SubscriptionStreams::default(),
+            last_values: LastValues::default(),
+            buffered: Arc::new(AtomicUsize::new(0)),
+            max_buffered: None,
+        }
+    }
+
+    /// Replaces the last will sent in CONNECT. Only takes effect from the next connection
+    /// attempt of the `EventLoop`, the broker keeps the will of the current connection
+    pub fn set_last_will(&self, will: LastWill) {
+        *self.next_last_will.lock().unwrap() = Some(will);
+    }
+
+    /// Latest incoming publish on `topic`, when the last value cache is enabled with
+    /// [`MqttOptions::set_last_value_cache`]. Publishes dropped by publish filters
+    /// aren't cached
+    pub fn last_value(&self, topic: &str) -> Option<Publish> {
+        self.last_values.get(topic)
+    }
+
+    /// Takes requests which were accepted but never acknowledged, so that they can be
+    /// persisted on shutdown. Available once the `EventLoop` is dropped, returns nothing
+    /// before that. Requests are taken only once, even across clones of this client.
+    ///
+    /// Requests are ordered as
+    /// 1. QoS 1 and 2 publishes in flight, in the order they were sent, followed by
+    ///    releases of QoS 2 publishes awaiting completion
+    /// 2. requests of previous connections waiting to be resent, in their order
+    /// 3. requests which were still queued in the channel, in the order they were sent
+    pub fn drain_pending(&self) -> Vec<Request> {
+        std::mem::take(&mut *self.drained.lock().unwrap())
+    }
+
+    /// Waits for a slot to send a request to the `EventLoop` and reserves it. Fails once
+    /// the `EventLoop` is dropped.
+    ///
+    /// Returned [`Permit`] sends a publish without waiting again, separating admission
+    /// control from sending. Permits take slots of the request channel like requests do,
+    /// and the publish is queued after the requests sent before it.
+    ///
+    /// A channel with a capacity of `0` has no slots, its permits send only when the
+    /// `EventLoop` is waiting for a request, like [`try_publish`](Self::try_publish).
+    /// Same goes for channels passed to [`from_senders`](Self::from_senders).
+    pub async fn reserve(&self) -> Result<Permit, ClientError> {
+        let reserved = self
+            .request_tx
+            .reserve_slot()
+            .await
+            .map_err(|_| ClientError::Reserve)?;
+
+        Ok(Permit {
+            request_tx: self.request_tx.clone(),
+            reserved,
+        })
     }
 
     /// Sends a MQTT Publish to the `EventLoop`.
@@ -84,6 +191,7 @@ pub async fn publish<S, V>(
         if !valid_topic(&topic) {
             return Err(ClientError::Request(publish));
         }
+        let publish = self.check_buffer(publish)?;
         self.request_tx.send_async(publish).await?;
         Ok(())
     }
@@ -107,10 +215,57 @@ pub fn try_publish<S, V>(
         if !valid_topic(&topic) {
             return Err(ClientError::TryRequest(publish));
         }
+        let publish = self.check_buffer(publish)?;
         self.request_tx.try_send(publish)?;
         Ok(())
     }
 
+    /// Sends a MQTT Publish to the `EventLoop` and calls `callback` once with its final
+    /// [`DeliveryStatus`]. QoS 0 publishes are delivered once written, QoS 1 publishes once
+    /// acked with PUBACK and QoS 2 publishes once completed with PUBCOMP. Publishes resent
+    /// after a reconnection keep their callback.
+    ///
+    /// The callback is called by the task polling the `EventLoop`, from within
+    /// [`poll`](EventLoop::poll), and must not block it. [`DeliveryStatus::Dropped`] is
+    /// reported by whichever thread drops the last copy of the request, e.g. the one dropping
+    /// the `EventLoop` or the error returned here
+    pub async fn publish_with_callback<S, V, F>(
+        &self,
+        topic: S,
+        qos: QoS,
+        retain: bool,
+        payload: V,
+        callback: F,
+    ) -> Result<(), ClientError>
+    where
+        S: Into<String>,
+        V: Into<Vec<u8>>,
+        F: FnOnce(DeliveryStatus) + Send + 'static,
+    {
+        let topic = topic.into();
+        let mut publish = Publish::new(&topic, qos, payload);
+        publish.retain = retain;
+        let publish = Request::PublishTracked(publish, DeliveryNotice::new(callback));
+        if !valid_topic(&topic) {
+            return Err(ClientError::Request(publish));
+        }
+        let publish = self.check_buffer(publish)?;
+        self.request_tx.send_async(publish).await?;
+        Ok(())
+    }
+
+    /// Refuses `request` while the `EventLoop` buffers the maximum number of requests,
+    /// when configured with [`BufferOverflow::Error`]. Requests are only buffered while
+    /// disconnected, the request channel alone bounds them while connected
+    fn check_buffer(&self, request: Request) -> Result<Request, ClientError> {
+        let buffered = self.buffered.load(Ordering::Relaxed);
+        if matches!(self.max_buffered, Some(max) if buffered >= max) {
+            return Err(ClientError::BufferFull(request));
+        }
+
+        Ok(request)
+    }
+
     /// Sends a MQTT PubAck to the `EventLoop`. Only needed in if `manual_acks` flag is set.
     pub async fn ack(&self, publish: &Publish) -> Result<(), ClientError> {
         let ack = get_ack_req(publish);
@@ -144,6 +299,60 @@ pub async fn publish_bytes<S>(
         let mut publish = Publish::from_bytes(topic, qos, payload);
         publish.retain = retain;
         let publish = Request::Publish(publish);
+        let publish = self.check_buffer(publish)?;
+        self.request_tx.send_async(publish).await?;
+        Ok(())
+    }
+
+    /// Sends a MQTT Publish which was encoded beforehand to the `EventLoop`, which copies
+    /// it to network without encoding it again. Cloning the [`EncodedPublish`] is cheap,
+    /// so it can be sent repeatedly
+    pub async fn publish_encoded(&self, publish: EncodedPublish) -> Result<(), ClientError> {
+        let valid = valid_topic(&publish.publish().topic);
+        let publish = Request::PublishEncoded(publish);
+        if !valid {
+            return Err(ClientError::Request(publish));
+        }
+        let publish = self.check_buffer(publish)?;
+        self.request_tx.send_async(publish).await?;
+        Ok(())
+    }
+
+    /// Attempts to send a MQTT Publish which was encoded beforehand to the `EventLoop`
+    pub fn try_publish_encoded(&self, publish: EncodedPublish) -> Result<(), ClientError> {
+        let valid = valid_topic(&publish.publish().topic);
+        let publish = Request::PublishEncoded(publish);
+        if !valid {
+            return Err(ClientError::TryRequest(publish));
+        }
+        let publish = self.check_buffer(publish)?;
+        self.request_tx.try_send(publish)?;
+        Ok(())
+    }
+
+    /// Sends a QoS 0 MQTT Publish whose payload of `len` bytes is streamed from `reader`
+    /// to the network by the `EventLoop`, without holding it in memory. Failure to write
+    /// the stream, including a reader ending before `len` bytes, is returned as an error
+    /// by the `EventLoop`. Bytes past `len` are left unread. Only available with MQTT 3.1.1
+    pub async fn publish_stream<S, R>(
+        &self,
+        topic: S,
+        retain: bool,
+        len: usize,
+        reader: R,
+    ) -> Result<(), ClientError>
+    where
+        S: Into<String>,
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        let topic = topic.into();
+        let mut publish = Publish::new(&topic, QoS::AtMostOnce, Vec::new());
+        publish.retain = retain;
+        let publish = Request::PublishStream(PublishStream::new(publish, len, reader));
+        if !valid_topic(&topic) {
+            return Err(ClientError::Request(publish));
+        }
+        let publish = self.check_buffer(publish)?;
         self.request_tx.send_async(publish).await?;
         Ok(())
     }
@@ -160,6 +369,25 @@ pub async fn subscribe<S: Into<String>>(&self, topic: S, qos: QoS) -> Result<(),
         Ok(())
     }
 
+    /// Sends a MQTT Subscribe to the `EventLoop` and returns a stream of incoming
+    /// publishes matching `filter`, so that topics can be handled in separate tasks.
+    ///
+    /// Streams get copies of publishes which are still yielded by the `EventLoop`, a
+    /// publish matching filters of several streams is sent to each of them. Publishes
+    /// are buffered till the stream is read, dropping it stops the buffering but
+    /// doesn't unsubscribe
+    pub async fn subscribe_stream<S: Into<String>>(
+        &self,
+        filter: S,
+        qos: QoS,
+    ) -> Result<impl Stream<Item = Publish>, ClientError> {
+        let filter = filter.into();
+        // Registered before subscribing to not miss retained publishes
+        let publishes = self.subscription_streams.add(filter.clone());
+        self.subscribe(filter, qos).await?;
+        Ok(publishes.into_stream())
+    }
+
     /// Attempts to send a MQTT Subscribe to the `EventLoop`
     pub fn try_subscribe<S: Into<String>>(&self, topic: S, qos: QoS) -> Result<(), ClientError> {
         let topic = topic.into();
@@ -177,9 +405,11 @@ pub async fn subscribe_many<T>(&self, topics: T) -> Result<(), ClientError>
     where
         T: IntoIterator<Item = SubscribeFilter>,
     {
-        let mut topics_iter = topics.into_iter();
-        let is_valid_filters = topics_iter.all(|filter| valid_filter(&filter.path));
-        let subscribe = Subscribe::new_many(topics_iter);
+        let subscribe = Subscribe::new_many(topics);
+        let is_valid_filters = subscribe
+            .filters
+            .iter()
+            .all(|filter| valid_filter(&filter.path));
         let request = Request::Subscribe(subscribe);
         if !is_valid_filters {
             return Err(ClientError::Request(request));
@@ -193,9 +423,11 @@ pub fn try_subscribe_many<T>(&self, topics: T) -> Result<(), ClientError>
     where
         T: IntoIterator<Item = SubscribeFilter>,
     {
-        let mut topics_iter = topics.into_iter();
-        let is_valid_filters = topics_iter.all(|filter| valid_filter(&filter.path));
-        let subscribe = Subscribe::new_many(topics_iter);
+        let subscribe = Subscribe::new_many(topics);
+        let is_valid_filters = subscribe
+            .filters
+            .iter()
+            .all(|filter| valid_filter(&filter.path));
         let request = Request::Subscribe(subscribe);
         if !is_valid_filters {
             return Err(ClientError::TryRequest(request));
@@ -233,6 +465,216 @@ pub fn try_disconnect(&self) -> Result<(), ClientError> {
         self.request_tx.try_send(request)?;
         Ok(())
     }
+
+    /// Waits till the `EventLoop` has written and flushed all the requests sent before
+    /// to the network. Unlike acks, this doesn't mean the broker received them. Fails
+    /// with [`ClientError::Flush`] if writing fails or the `EventLoop` is dropped before
+    pub async fn flush(&self) -> Result<(), ClientError> {
+        let (notice, flushed) = FlushNotice::new();
+        self.request_tx.send_async(Request::Flush(notice)).await?;
+        flushed.recv_async().await.map_err(|_| ClientError::Flush)
+    }
+}
+
+/// A slot reserved to send a publish to the `EventLoop`, obtained with
+/// [`reserve`](`AsyncClient::reserve`).
+///
+/// Dropping a permit without sending releases the slot.
+#[derive(Debug)]
+pub struct Permit {
+    request_tx: RequestSender<Request>,
+    /// Set till the slot, if any, is handed over to the publish
+    reserved: bool,
+}
+
+impl Permit {
+    /// Sends a MQTT Publish to the `EventLoop` using the reserved slot.
+    pub fn send(mut self, publish: Publish) -> Result<(), ClientError> {
+        let valid = valid_topic(&publish.topic);
+        let publish = Request::Publish(publish);
+        if !valid {
+            return Err(ClientError::TryRequest(publish));
+        }
+        self.request_tx.send_reserved(publish)?;
+        // Slot is released by the `EventLoop` once it receives the publish
+        self.reserved = false;
+        Ok(())
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.reserved {
+            self.request_tx.release_slot();
+        }
+    }
+}
+
+/// Free slots of the request channel. Requests and permits alike take a slot before they
+/// are sent, so that permits can always send, and the `EventLoop` puts a slot back for
+/// every request it receives. A rendezvous channel has no slots to take.
+#[derive(Debug)]
+pub(crate) struct Slots {
+    slots: Option<(Sender<()>, Receiver<()>)>,
+}
+
+impl Slots {
+    /// Slots of a channel with capacity `cap`, all of them free
+    pub(crate) fn new(cap: usize) -> Slots {
+        if cap == 0 {
+            return Slots { slots: None };
+        }
+
+        let (tx, rx) = flume::bounded(cap);
+        for _ in 0..cap {
+            let _ = tx.try_send(());
+        }
+
+        Slots {
+            slots: Some((tx, rx)),
+        }
+    }
+
+    /// Handle for clients to take slots. Only the `EventLoop` holds a strong sender, so
+    /// that taking a slot fails once it's dropped
+    pub(crate) fn handle(&self) -> Option<SlotHandle> {
+        self.slots.as_ref().map(|(tx, rx)| SlotHandle {
+            tx: Arc::new(tx.downgrade()),
+            rx: rx.clone(),
+        })
+    }
+
+    /// Puts back slots of `count` requests received from the channel
+    pub(crate) fn release(&self, count: usize) {
+        if let Some((tx, _)) = &self.slots {
+            for _ in 0..count {
+                let _ = tx.try_send(());
+            }
+        }
+    }
+}
+
+/// Takes slots of the channel for clients and puts back the ones they don't use
+#[derive(Clone)]
+pub(crate) struct SlotHandle {
+    /// Weak so that slots close with the `EventLoop`, shared as it isn't `Clone`
+    tx: Arc<WeakSender<()>>,
+    rx: Receiver<()>,
+}
+
+impl fmt::Debug for SlotHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlotHandle")
+            .field("free", &self.rx.len())
+            .finish()
+    }
+}
+
+impl SlotHandle {
+    fn put_back(&self) {
+        if let Some(tx) = self.tx.upgrade() {
+            let _ = tx.try_send(());
+        }
+    }
+}
+
+/// Sends requests on the channel once they take a slot, if the channel has any. Channels
+/// created by users are bounded by themselves and have none.
+#[derive(Debug)]
+pub(crate) struct RequestSender<T> {
+    tx: Sender<T>,
+    slots: Option<SlotHandle>,
+}
+
+impl<T> Clone for RequestSender<T> {
+    fn clone(&self) -> Self {
+        RequestSender {
+            tx: self.tx.clone(),
+            slots: self.slots.clone(),
+        }
+    }
+}
+
+impl<T> RequestSender<T> {
+    pub(crate) fn new(tx: Sender<T>, slots: Option<SlotHandle>) -> RequestSender<T> {
+        RequestSender { tx, slots }
+    }
+
+    pub(crate) async fn send_async(&self, request: T) -> Result<(), SendError<T>> {
+        if let Some(slots) = &self.slots {
+            if slots.rx.recv_async().await.is_err() {
+                return Err(SendError(request));
+            }
+        }
+
+        self.tx.send_async(request).await.map_err(|e| {
+            self.release_slot();
+            e
+        })
+    }
+
+    pub(crate) fn send(&self, request: T) -> Result<(), SendError<T>> {
+        if let Some(slots) = &self.slots {
+            if slots.rx.recv().is_err() {
+                return Err(SendError(request));
+            }
+        }
+
+        self.tx.send(request).map_err(|e| {
+            self.release_slot();
+            e
+        })
+    }
+
+    pub(crate) fn try_send(&self, request: T) -> Result<(), TrySendError<T>> {
+        if let Some(slots) = &self.slots {
+            match slots.rx.try_recv() {
+                Ok(()) => {}
+                Err(flume::TryRecvError::Empty) => return Err(TrySendError::Full(request)),
+                Err(flume::TryRecvError::Disconnected) => {
+                    return Err(TrySendError::Disconnected(request))
+                }
+            }
+        }
+
+        self.tx.try_send(request).map_err(|e| {
+            self.release_slot();
+            e
+        })
+    }
+
+    /// Waits for a slot and takes it, returning whether the channel has slots at all.
+    /// Fails once the `EventLoop` is dropped
+    pub(crate) async fn reserve_slot(&self) -> Result<bool, flume::RecvError> {
+        let reserved = match &self.slots {
+            Some(slots) => {
+                slots.rx.recv_async().await?;
+                true
+            }
+            None => false,
+        };
+
+        // Slots which were free when the `EventLoop` was dropped can still be taken
+        if self.tx.is_disconnected() {
+            if reserved {
+                self.release_slot();
+            }
+            return Err(flume::RecvError::Disconnected);
+        }
+
+        Ok(reserved)
+    }
+
+    /// Sends `request` with a slot reserved beforehand, which stays reserved on failure
+    pub(crate) fn send_reserved(&self, request: T) -> Result<(), TrySendError<T>> {
+        self.tx.try_send(request)
+    }
+
+    pub(crate) fn release_slot(&self) {
+        if let Some(slots) = &self.slots {
+            slots.put_back();
+        }
+    }
 }
 
 fn get_ack_req(publish: &Publish) -> Option<Request> {
@@ -304,6 +746,7 @@ pub fn publish<S, V>(
         if !valid_topic(&topic) {
             return Err(ClientError::Request(publish));
         }
+        let publish = self.client.check_buffer(publish)?;
         self.client.request_tx.send(publish)?;
         Ok(())
     }
@@ -323,6 +766,51 @@ pub fn try_publish<S, V>(
         Ok(())
     }
 
+    /// Sends a MQTT Publish to the `EventLoop` and calls `callback` once with its final
+    /// status. See [`AsyncClient::publish_with_callback`]
+    pub fn publish_with_callback<S, V, F>(
+        &self,
+        topic: S,
+        qos: QoS,
+        retain: bool,
+        payload: V,
+        callback: F,
+    ) -> Result<(), ClientError>
+    where
+        S: Into<String>,
+        V: Into<Vec<u8>>,
+        F: FnOnce(DeliveryStatus) + Send + 'static,
+    {
+        let topic = topic.into();
+        let mut publish = Publish::new(&topic, qos, payload);
+        publish.retain = retain;
+        let publish = Request::PublishTracked(publish, DeliveryNotice::new(callback));
+        if !valid_topic(&topic) {
+            return Err(ClientError::Request(publish));
+        }
+        let publish = self.client.check_buffer(publish)?;
+        self.client.request_tx.send(publish)?;
+        Ok(())
+    }
+
+    /// Sends a MQTT Publish which was encoded beforehand to the `EventLoop`
+    pub fn publish_encoded(&self, publish: EncodedPublish) -> Result<(), ClientError> {
+        let valid = valid_topic(&publish.publish().topic);
+        let publish = Request::PublishEncoded(publish);
+        if !valid {
+            return Err(ClientError::Request(publish));
+        }
+        let publish = self.client.check_buffer(publish)?;
+        self.client.request_tx.send(publish)?;
+        Ok(())
+    }
+
+    /// Attempts to send a MQTT Publish which was encoded beforehand to the `EventLoop`
+    pub fn try_publish_encoded(&self, publish: EncodedPublish) -> Result<(), ClientError> {
+        self.client.try_publish_encoded(publish)?;
+        Ok(())
+    }
+
     /// Sends a MQTT PubAck to the `EventLoop`. Only needed in if `manual_acks` flag is set.
     pub fn ack(&self, publish: &Publish) -> Result<(), ClientError> {
         let ack = get_ack_req(publish);
@@ -362,9 +850,11 @@ pub fn subscribe_many<T>(&self, topics: T) -> Result<(), ClientError>
     where
         T: IntoIterator<Item = SubscribeFilter>,
     {
-        let mut topics_iter = topics.into_iter();
-        let is_valid_filters = topics_iter.all(|filter| valid_filter(&filter.path));
-        let subscribe = Subscribe::new_many(topics_iter);
+        let subscribe = Subscribe::new_many(topics);
+        let is_valid_filters = subscribe
+            .filters
+            .iter()
+            .all(|filter| valid_filter(&filter.path));
         let request = Request::Subscribe(subscribe);
         if !is_valid_filters {
             return Err(ClientError::Request(request));
@@ -406,6 +896,31 @@ pub fn try_disconnect(&self) -> Result<(), ClientError> {
         self.client.try_disconnect()?;
         Ok(())
     }
+
+    /// Blocks till the `EventLoop` has written and flushed all the requests sent before.
+    /// See [`AsyncClient::flush`]
+    pub fn flush(&self) -> Result<(), ClientError> {
+        let (notice, flushed) = FlushNotice::new();
+        self.client.request_tx.send(Request::Flush(notice))?;
+        flushed.recv().map_err(|_| ClientError::Flush)
+    }
+
+    /// Latest incoming publish on `topic`. See [`AsyncClient::last_value`]
+    pub fn last_value(&self, topic: &str) -> Option<Publish> {
+        self.client.last_value(topic)
+    }
+
+    /// Takes requests which were never acknowledged once `Connection` is dropped.
+    /// See [`AsyncClient::drain_pending`] for their order
+    pub fn drain_pending(&self) -> Vec<Request> {
+        self.client.drain_pending()
+    }
+
+    /// Replaces the last will sent in CONNECT from the next connection attempt.
+    /// See [`AsyncClient::set_last_will`]
+    pub fn set_last_will(&self, will: LastWill) {
+        self.client.set_last_will(will)
+    }
 }
 
 /// Error type returned by [`Connection::recv`]
@@ -538,6 +1053,135 @@ fn calling_iter_twice_on_connection_shouldnt_panic() {
         let _ = connection.iter();
     }
 
+    #[tokio::test]
+    async fn reserved_permits_are_limited_by_capacity() {
+        let mqttoptions = MqttOptions::new("test-1", "localhost", 1883);
+        let (client, mut eventloop) = AsyncClient::new(mqttoptions, 2);
+
+        let first = client.reserve().await.unwrap();
+        let second = client.reserve().await.unwrap();
+        assert!(client
+            .try_subscribe("hello/world", QoS::AtMostOnce)
+            .is_err());
+
+        // Dropped permit releases its slot
+        drop(second);
+        let second = client.reserve().await.unwrap();
+
+        first
+            .send(Publish::new("hello/world", QoS::AtLeastOnce, "hello"))
+            .unwrap();
+        second
+            .send(Publish::new("hello/world", QoS::AtLeastOnce, "world"))
+            .unwrap();
+        assert!(client
+            .try_subscribe("hello/world", QoS::AtMostOnce)
+            .is_err());
+
+        // Slots are released when eventloop takes pending requests
+        eventloop.clean();
+        assert_eq!(eventloop.pending.len(), 2);
+        client
+            .try_subscribe("hello/world", QoS::AtMostOnce)
+            .unwrap();
+        client
+            .try_subscribe("hello/world", QoS::AtMostOnce)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn reserved_publishes_are_ordered_with_other_requests() {
+        let mqttoptions = MqttOptions::new("test-1", "localhost", 1883);
+        let (client, mut eventloop) = AsyncClient::new(mqttoptions, 3);
+
+        client
+            .subscribe("hello/world", QoS::AtMostOnce)
+            .await
+            .unwrap();
+        let permit = client.reserve().await.unwrap();
+        client.unsubscribe("hello/world").await.unwrap();
+        permit
+            .send(Publish::new("hello/world", QoS::AtLeastOnce, "hello"))
+            .unwrap();
+
+        eventloop.clean();
+        let pending = Vec::from(eventloop.pending.clone());
+        assert!(matches!(
+            pending[..],
+            [
+                Request::Subscribe(_),
+                Request::Unsubscribe(_),
+                Request::Publish(_)
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn reserve_fails_once_eventloop_is_dropped() {
+        let mqttoptions = MqttOptions::new("test-1", "localhost", 1883);
+        let (client, eventloop) = AsyncClient::new(mqttoptions, 1);
+
+        let permit = client.reserve().await.unwrap();
+        let waiting = tokio::spawn({
+            let client = client.clone();
+            async move { client.reserve().await.map(drop) }
+        });
+        tokio::task::yield_now().await;
+        drop(eventloop);
+
+        assert!(matches!(waiting.await.unwrap(), Err(ClientError::Reserve)));
+        assert!(permit
+            .send(Publish::new("hello/world", QoS::AtLeastOnce, "hello"))
+            .is_err());
+        assert!(matches!(client.reserve().await, Err(ClientError::Reserve)));
+    }
+
+    #[tokio::test]
+    async fn requests_are_not_queued_without_capacity() {
+        let mqttoptions = MqttOptions::new("test-1", "localhost", 1883);
+        let (client, _eventloop) = AsyncClient::new(mqttoptions, 0);
+
+        assert!(client
+            .try_subscribe("hello/world", QoS::AtMostOnce)
+            .is_err());
+        let permit = client.reserve().await.unwrap();
+        assert!(permit
+            .send(Publish::new("hello/world", QoS::AtLeastOnce, "hello"))
+            .is_err());
+    }
+
+    #[test]
+    fn unacked_requests_are_drained_after_eventloop_is_dropped() {
+        let mqttoptions = MqttOptions::new("test-1", "localhost", 1883);
+        let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+
+        let inflight = Publish::new("hello/inflight", QoS::AtLeastOnce, "hello");
+        eventloop
+            .state
+            .handle_outgoing_packet(Request::Publish(inflight))
+            .unwrap();
+        let resend = Request::Publish(Publish::new("hello/resend", QoS::AtLeastOnce, "hi"));
+        eventloop.pending.push_back(resend.clone());
+        client
+            .try_publish("hello/queued", QoS::AtMostOnce, false, "hey")
+            .unwrap();
+
+        assert!(client.drain_pending().is_empty());
+        drop(eventloop);
+
+        let pending = client.drain_pending();
+        let topics: Vec<&str> = pending
+            .iter()
+            .map(|request| match request {
+                Request::Publish(publish) => publish.topic.as_str(),
+                request => panic!("Unexpected request {request:?}"),
+            })
+            .collect();
+        assert_eq!(topics, ["hello/inflight", "hello/resend", "hello/queued"]);
+        assert_eq!(pending[1], resend);
+        assert!(client.drain_pending().is_empty());
+    }
+
     #[test]
     fn should_be_able_to_build_test_client_from_channel() {
         let (tx, rx) = flume::bounded(1);
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::net::lookup_host;
+
+/// Addresses of a host and when they were resolved
+type Entry = (Instant, Vec<SocketAddr>);
+
+/// Addresses of resolved hosts, reused until their ttl expires. Connections try
+/// all the addresses of a host in order, so caching keeps brokers behind round
+/// robin DNS reachable when one of the addresses is unhealthy
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DnsCache {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl DnsCache {
+    /// Resolves `host` (`domain:port`) to all its addresses. Cached addresses are
+    /// used if they were resolved less than `ttl` ago. Nothing is cached without `ttl`
+    pub(crate) async fn resolve(
+        &self,
+        host: &str,
+        ttl: Option<Duration>,
+    ) -> io::Result<Vec<SocketAddr>> {
+        let ttl = match ttl {
+            Some(ttl) => ttl,
+            None => return Ok(lookup_host(host).await?.collect()),
+        };
+
+        if let Some((resolved_at, addrs)) = self.entries.lock().unwrap().get(host) {
+            if resolved_at.elapsed() < ttl {
+                return Ok(addrs.clone());
+            }
+        }
+
+        let addrs: Vec<SocketAddr> = lookup_host(host).await?.collect();
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(host.to_owned(), (Instant::now(), addrs.clone()));
+
+        Ok(addrs)
+    }
+
+    /// Forgets addresses of `host`, e.g. when none of them were reachable
+    pub(crate) fn invalidate(&self, host: &str) {
+        self.entries.lock().unwrap().remove(host);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolved_addresses_are_cached_until_invalidated() {
+        let cache = DnsCache::default();
+        let host = "localhost:1883";
+
+        cache.resolve(host, None).await.unwrap();
+        assert!(cache.entries.lock().unwrap().is_empty());
+
+        let addrs = cache
+            .resolve(host, Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        assert!(!addrs.is_empty());
+        assert_eq!(cache.entries.lock().unwrap()[host].1, addrs);
+
+        cache.invalidate(host);
+        assert!(cache.entries.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use crate::MqttOptions;
+
+/// Broker host, `localhost` if not set
+const HOST: &str = "MQTT_HOST";
+/// Broker port, 1883 if not set or 8883 when `MQTT_CA_PATH` is set
+const PORT: &str = "MQTT_PORT";
+/// Client id, `rumqttc-{process id}` if not set
+const CLIENT_ID: &str = "MQTT_CLIENT_ID";
+/// Username, no credentials are sent if not set
+const USERNAME: &str = "MQTT_USERNAME";
+/// Password of `MQTT_USERNAME`, empty if not set
+const PASSWORD: &str = "MQTT_PASSWORD";
+/// Keep alive in seconds, 60 if not set
+const KEEP_ALIVE_SECS: &str = "MQTT_KEEP_ALIVE_SECS";
+/// PEM file of the CA verifying the broker. Connects with TLS when set
+const CA_PATH: &str = "MQTT_CA_PATH";
+/// PEM file of the client certificate, for TLS client authentication
+const CLIENT_CERT_PATH: &str = "MQTT_CLIENT_CERT_PATH";
+/// PEM file of the key of `MQTT_CLIENT_CERT_PATH`
+const CLIENT_KEY_PATH: &str = "MQTT_CLIENT_KEY_PATH";
+
+/// Errors of [`MqttOptions::from_env`]
+#[derive(Debug, thiserror::Error)]
+pub enum EnvError {
+    #[error("Invalid value of {0}")]
+    Invalid(&'static str),
+    #[error("{0} is set without {1}")]
+    Missing(&'static str, &'static str),
+    #[error("Couldn't read file of {0}: {1}")]
+    Read(&'static str, #[source] std::io::Error),
+    #[error("{0} is set, but TLS support isn't enabled")]
+    TlsDisabled(&'static str),
+}
+
+impl MqttOptions {
+    /// Options read from environment variables, for deployments configured through
+    /// the environment. Options which aren't set keep their defaults
+    ///
+    /// | Variable | Option |
+    /// | --- | --- |
+    /// | `MQTT_HOST` | broker host, `localhost` by default |
+    /// | `MQTT_PORT` | broker port, 1883 by default or 8883 with TLS |
+    /// | `MQTT_CLIENT_ID` | client id, `rumqttc-{process id}` by default |
+    /// | `MQTT_USERNAME`, `MQTT_PASSWORD` | credentials |
+    /// | `MQTT_KEEP_ALIVE_SECS` | keep alive in seconds |
+    /// | `MQTT_CA_PATH` | CA of the broker, connects with TLS when set |
+    /// | `MQTT_CLIENT_CERT_PATH`, `MQTT_CLIENT_KEY_PATH` | TLS client certificate and key |
+    ///
+    /// Returns an error for malformed values and files which can't be read
+    pub fn from_env() -> Result<MqttOptions, EnvError> {
+        from_vars(|name| std::env::var(name).ok())
+    }
+}
+
+fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<MqttOptions, EnvError> {
+    let tls = var(CA_PATH).is_some();
+    let host = var(HOST).unwrap_or_else(|| "localhost".to_owned());
+    let port = match var(PORT) {
+        Some(port) => port.parse().map_err(|_| EnvError::Invalid(PORT))?,
+        None if tls => 8883,
+        None => 1883,
+    };
+    let client_id = var(CLIENT_ID).unwrap_or_else(|| format!("rumqttc-{}", std::process::id()));
+
+    let mut options = MqttOptions::new(client_id, host, port);
+
+    match (var(USERNAME), var(PASSWORD)) {
+        (Some(username), password) => {
+            options.set_credentials(username, password.unwrap_or_default());
+        }
+        (None, Some(_)) => return Err(EnvError::Missing(PASSWORD, USERNAME)),
+        (None, None) => {}
+    }
+
+    if let Some(keep_alive) = var(KEEP_ALIVE_SECS) {
+        let keep_alive = keep_alive
+            .parse()
+            .map_err(|_| EnvError::Invalid(KEEP_ALIVE_SECS))?;
+        options.set_keep_alive(Duration::from_secs(keep_alive));
+    }
+
+    let client_auth = match (var(CLIENT_CERT_PATH), var(CLIENT_KEY_PATH)) {
+        (Some(cert), Some(key)) => {
+            Some((read(CLIENT_CERT_PATH, cert)?, read(CLIENT_KEY_PATH, key)?))
+        }
+        (Some(_), None) => return Err(EnvError::Missing(CLIENT_CERT_PATH, CLIENT_KEY_PATH)),
+        (None, Some(_)) => return Err(EnvError::Missing(CLIENT_KEY_PATH, CLIENT_CERT_PATH)),
+        (None, None) => None,
+    };
+
+    match var(CA_PATH) {
+        #[cfg(feature = "use-rustls")]
+        Some(ca) => {
+            let ca = read(CA_PATH, ca)?;
+            options.set_transport(crate::Transport::tls(ca, client_auth, None));
+        }
+        #[cfg(not(feature = "use-rustls"))]
+        Some(_) => return Err(EnvError::TlsDisabled(CA_PATH)),
+        None if client_auth.is_some() => return Err(EnvError::Missing(CLIENT_CERT_PATH, CA_PATH)),
+        None => {}
+    }
+
+    Ok(options)
+}
+
+fn read(name: &'static str, path: String) -> Result<Vec<u8>, EnvError> {
+    std::fs::read(path).map_err(|e| EnvError::Read(name, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Login;
+    use std::collections::HashMap;
+
+    fn options(vars: &[(&str, &str)]) -> Result<MqttOptions, EnvError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn unset_variables_fall_back_to_defaults() {
+        let options = options(&[]).unwrap();
+
+        assert_eq!(options.broker_address(), ("localhost".to_owned(), 1883));
+        assert_eq!(
+            options.client_id(),
+            format!("rumqttc-{}", std::process::id())
+        );
+        assert_eq!(options.credentials(), None);
+        assert_eq!(options.keep_alive(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn variables_are_read() {
+        let options = options(&[
+            (HOST, "broker.example.com"),
+            (PORT, "1884"),
+            (CLIENT_ID, "client"),
+            (USERNAME, "user"),
+            (PASSWORD, "pass"),
+            (KEEP_ALIVE_SECS, "30"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            options.broker_address(),
+            ("broker.example.com".to_owned(), 1884)
+        );
+        assert_eq!(options.client_id(), "client");
+        assert_eq!(options.credentials(), Some(Login::new("user", "pass")));
+        assert_eq!(options.keep_alive(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn malformed_values_are_errors() {
+        assert!(matches!(
+            options(&[(PORT, "mqtt")]),
+            Err(EnvError::Invalid(PORT))
+        ));
+        assert!(matches!(
+            options(&[(KEEP_ALIVE_SECS, "-1")]),
+            Err(EnvError::Invalid(KEEP_ALIVE_SECS))
+        ));
+        assert!(matches!(
+            options(&[(PASSWORD, "pass")]),
+            Err(EnvError::Missing(PASSWORD, USERNAME))
+        ));
+        assert!(matches!(
+            options(&[(CA_PATH, "/does/not/exist.pem")]),
+            Err(EnvError::Read(CA_PATH, _) | EnvError::TlsDisabled(CA_PATH))
+        ));
+    }
+}
@@ -1,11 +1,24 @@
+use crate::dns::DnsCache;
+#[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+use crate::TlsInfo;
 use crate::{framed::Network, Transport};
-use crate::{Incoming, MqttState, NetworkOptions, Packet, Request, StateError};
+use crate::{
+    BufferOverflow, ClientDropBehavior, ClientMetrics, DeliveryStatus, FailoverStrategy, Incoming,
+    MqttState, NetworkOptions, Packet, PublishSink, PublishStream, Qos0DropPolicy, Request,
+    StateError,
+};
 use crate::{MqttOptions, Outgoing};
 
+use crate::client::Slots;
 use crate::framed::AsyncReadWrite;
 use crate::mqttbytes::v4::*;
+use crate::mqttbytes::QoS;
+use crate::state::LastValues;
+use crate::trace::PacketTrace;
 use flume::{bounded, Receiver, Sender};
-use tokio::net::{lookup_host, TcpSocket, TcpStream};
+#[cfg(feature = "proxy")]
+use tokio::net::lookup_host;
+use tokio::net::{TcpSocket, TcpStream};
 use tokio::select;
 use tokio::time::{self, Instant, Sleep};
 
@@ -13,7 +26,9 @@
 use std::io;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 #[cfg(unix)]
 use {std::path::Path, tokio::net::UnixStream};
@@ -40,6 +55,8 @@ pub enum ConnectionError {
     NetworkTimeout,
     #[error("Flush timeout")]
     FlushTimeout,
+    #[error("Write timeout")]
+    WriteTimeout,
     #[cfg(feature = "websocket")]
     #[error("Websocket: {0}")]
     Websocket(#[from] async_tungstenite::tungstenite::error::Error),
@@ -57,6 +74,14 @@ pub enum ConnectionError {
     NotConnAck(Packet),
     #[error("Requests done")]
     RequestsDone,
+    #[error("Gave up after maximum failed connection attempts")]
+    GaveUp,
+    /// Error along with the packets polled before it, see [`MqttOptions::set_packet_trace`]
+    #[error("{error}, after packets: {trace}")]
+    Traced {
+        error: Box<ConnectionError>,
+        trace: PacketTrace,
+    },
     #[cfg(feature = "websocket")]
     #[error("Invalid Url: {0}")]
     InvalidUrl(#[from] UrlError),
@@ -76,18 +101,54 @@ pub struct EventLoop {
     pub state: MqttState,
     /// Request stream
     requests_rx: Receiver<Request>,
-    /// Requests handle to send requests
-    pub(crate) requests_tx: Sender<Request>,
+    /// Requests handle to send requests. Taken by `AsyncClient` so that the channel
+    /// is closed once all the clients are dropped
+    pub(crate) requests_tx: Option<Sender<Request>>,
+    /// Free slots of the request channel, put back as requests are received
+    pub(crate) slots: Slots,
     /// Pending packets from last session
     pub pending: VecDeque<Request>,
     /// Network connection to the broker
     pub network: Option<Network>,
     /// Keep alive time
     keepalive_timeout: Option<Pin<Box<Sleep>>>,
+    /// Deadline to receive pingresp for the last pingreq
+    pingresp_timeout: Option<Pin<Box<Sleep>>>,
     pub network_options: NetworkOptions,
+    /// Metrics of previous connections
+    metrics: ClientMetrics,
+    /// Resolved addresses of the broker
+    dns_cache: DnsCache,
+    /// Unacked requests handed over to `AsyncClient::drain_pending` when dropped
+    pub(crate) drained: Arc<Mutex<Vec<Request>>>,
+    /// Will set with `AsyncClient::set_last_will`, sent from the next connection
+    pub(crate) next_last_will: Arc<Mutex<Option<LastWill>>>,
+    /// Set after disconnecting because all the clients were dropped
+    requests_done: bool,
+    /// Last packets polled, when tracing is enabled
+    trace: Option<PacketTrace>,
+    /// Length of `pending` as of the last poll, for clients to refuse publishes while
+    /// the buffer is full
+    pub(crate) buffered: Arc<AtomicUsize>,
+    /// Connection attempts which failed since the last successful one
+    failed_connects: u32,
+    /// Broker of `MqttOptions::broker_list` connected to next
+    broker_index: usize,
+    /// Set till the first request arrives when connecting lazily
+    awaiting_first_request: bool,
+    /// Copies of outgoing requests, see `mirror_requests`
+    #[cfg(feature = "test-util")]
+    mirror_tx: Option<Sender<Request>>,
 }
 
 /// Events which can be yielded by the event loop
+///
+/// Every packet read or written is yielded, acknowledgements included, along with its
+/// packet identifier. E.g. a QoS 2 publish sent by the client yields `Outgoing::Publish`,
+/// `Outgoing::PubRel`, `Incoming::PubRec` and `Incoming::PubComp`. An incoming packet
+/// which is answered right away, like a `PubRec` by a `PubRel`, yields the outgoing
+/// answer first. Enable [`MqttOptions::set_packet_trace`] to also keep when each of
+/// them was polled, see [`EventLoop::packet_trace`]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Event {
     Incoming(Incoming),
@@ -101,22 +162,106 @@ impl EventLoop {
     /// access and update `options`, `state` and `requests`.
     pub fn new(mqtt_options: MqttOptions, cap: usize) -> EventLoop {
         let (requests_tx, requests_rx) = bounded(cap);
+        let slots = Slots::new(cap);
         let pending = VecDeque::new();
         let max_inflight = mqtt_options.inflight;
         let manual_acks = mqtt_options.manual_acks;
+        let mut state = MqttState::new(max_inflight, manual_acks);
+        state.last_values = LastValues::new(mqtt_options.last_value_cache);
+        state.topic_validation = mqtt_options.topic_validation();
+        state.max_unacked_incoming_qos2 = mqtt_options.max_unacked_incoming_qos2();
+        let awaiting_first_request = mqtt_options.lazy_connect;
+        let network_options = mqtt_options.network_options();
 
         EventLoop {
             mqtt_options,
-            state: MqttState::new(max_inflight, manual_acks),
-            requests_tx,
+            state,
+            requests_tx: Some(requests_tx),
             requests_rx,
+            slots,
             pending,
             network: None,
             keepalive_timeout: None,
-            network_options: NetworkOptions::new(),
+            pingresp_timeout: None,
+            network_options,
+            metrics: ClientMetrics::default(),
+            dns_cache: DnsCache::default(),
+            drained: Arc::new(Mutex::new(Vec::new())),
+            next_last_will: Arc::new(Mutex::new(None)),
+            requests_done: false,
+            trace: None,
+            buffered: Arc::new(AtomicUsize::new(0)),
+            failed_connects: 0,
+            broker_index: 0,
+            awaiting_first_request,
+            #[cfg(feature = "test-util")]
+            mirror_tx: None,
         }
     }
 
+    /// Sends a copy of every request on returned channel before it's handled and
+    /// written to network, to assert on requests produced by the client API in tests.
+    /// Requests are mirrored as sent by the client, before packet ids are assigned.
+    /// Replaces channel of a previous call
+    #[cfg(feature = "test-util")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "test-util")))]
+    pub fn mirror_requests(&mut self) -> Receiver<Request> {
+        let (tx, rx) = flume::unbounded();
+        self.mirror_tx = Some(tx);
+        rx
+    }
+
+    /// Drops incoming publishes for which `predicate` returns `false` before they are
+    /// surfaced as events, reducing pressure on slow consumers. Dropped QoS 1 and 2
+    /// publishes are still acked, even with manual acks.
+    ///
+    /// > NOTE: Acked publishes are never redelivered by the broker. Silently dropping
+    /// > messages with a predicate hence breaks at-least-once semantics for them.
+    pub fn set_publish_filter<F>(&mut self, predicate: F)
+    where
+        F: Fn(&Publish) -> bool + Send + Sync + 'static,
+    {
+        self.state
+            .publish_filters
+            .set_global(Some(Arc::new(predicate)));
+    }
+
+    /// Removes the predicate set with [`set_publish_filter`](Self::set_publish_filter)
+    pub fn clear_publish_filter(&mut self) {
+        self.state.publish_filters.set_global(None);
+    }
+
+    /// Same as [`set_publish_filter`](Self::set_publish_filter), but only evaluated for
+    /// publishes on topics matching subscription `filter`. Replaces the existing
+    /// predicate of `filter`, if any
+    pub fn set_subscription_filter<S, F>(&mut self, filter: S, predicate: F)
+    where
+        S: Into<String>,
+        F: Fn(&Publish) -> bool + Send + Sync + 'static,
+    {
+        self.state
+            .publish_filters
+            .set_subscription(filter.into(), Some(Arc::new(predicate)));
+    }
+
+    /// Removes the predicate of subscription `filter`
+    pub fn clear_subscription_filter(&mut self, filter: &str) {
+        self.state
+            .publish_filters
+            .set_subscription(filter.to_owned(), None);
+    }
+
+    /// Passes incoming publishes to `sink` instead of yielding them as events. Acks are
+    /// handled as usual and all the other events are still yielded by `poll`
+    pub fn set_publish_sink<S: PublishSink + 'static>(&mut self, sink: S) {
+        self.state.publish_sink = Some(Arc::new(sink));
+    }
+
+    /// Removes the sink set with [`set_publish_sink`](Self::set_publish_sink)
+    pub fn clear_publish_sink(&mut self) {
+        self.state.publish_sink = None;
+    }
+
     /// Last session might contain packets which aren't acked. MQTT says these packets should be
     /// republished in the next session. Move pending messages from state to eventloop, drops the
     /// underlying network connection and clears the keepalive timeout if any.
@@ -125,13 +270,101 @@ pub fn new(mqtt_options: MqttOptions, cap: usize) -> EventLoop {
     /// > Also, while this helps prevent data loss, the pending list length should be managed properly.
     /// > For this reason we recommend setting [`AsycClient`](crate::AsyncClient)'s channel capacity to `0`.
     pub fn clean(&mut self) {
-        self.network = None;
+        if let Some(network) = self.network.take() {
+            self.metrics.merge(&network.metrics);
+        }
         self.keepalive_timeout = None;
+        self.pingresp_timeout = None;
         self.pending.extend(self.state.clean());
 
-        // drain requests from channel which weren't yet received
-        let requests_in_channel = self.requests_rx.drain();
-        self.pending.extend(requests_in_channel);
+        // drain requests from channel which weren't yet received. Requests past the maximum
+        // number of buffered requests stay in the channel
+        let room = self.buffer_room().min(self.requests_rx.len());
+        self.pending.extend(self.requests_rx.try_iter().take(room));
+        self.slots.release(room);
+        self.limit_queued_qos0();
+        self.limit_buffered();
+        self.buffered.store(self.pending.len(), Ordering::Relaxed);
+    }
+
+    /// Number of requests which can still be buffered without overflowing. Unbounded when
+    /// overflowing requests are dropped instead of being left in the channels
+    fn buffer_room(&self) -> usize {
+        match self.mqtt_options.max_buffered_messages() {
+            Some(max) if self.mqtt_options.buffer_overflow() != BufferOverflow::DropOldest => {
+                max.saturating_sub(self.pending.len())
+            }
+            _ => usize::MAX,
+        }
+    }
+
+    /// Drops the oldest pending publishes past `max_buffered_messages` when configured to
+    fn limit_buffered(&mut self) {
+        let max = match self.mqtt_options.max_buffered_messages() {
+            Some(max) if self.mqtt_options.buffer_overflow() == BufferOverflow::DropOldest => max,
+            _ => return,
+        };
+
+        let excess = self.pending.len().saturating_sub(max);
+        if excess == 0 {
+            return;
+        }
+
+        // Releases and subscriptions are kept
+        let mut dropped = 0;
+        self.pending.retain(|request| {
+            if dropped < excess && is_publish(request) {
+                dropped += 1;
+                return false;
+            }
+
+            true
+        });
+
+        warn!("Dropped {dropped} buffered publishes");
+        self.metrics.buffer_dropped += dropped as u64;
+    }
+
+    /// Drops pending QoS 0 publishes past `max_queued_qos0` as per the drop policy
+    fn limit_queued_qos0(&mut self) {
+        let max = match self.mqtt_options.max_queued_qos0 {
+            Some(max) => max,
+            None => return,
+        };
+
+        let queued = self
+            .pending
+            .iter()
+            .filter(|request| is_qos0_publish(request))
+            .count();
+        let mut excess = queued.saturating_sub(max);
+        if excess == 0 {
+            return;
+        }
+
+        warn!("Dropping {excess} queued QoS 0 publishes");
+        self.metrics.qos0_dropped += excess as u64;
+        match self.mqtt_options.qos0_drop_policy {
+            Qos0DropPolicy::DropOldest => self.pending.retain(|request| {
+                if excess > 0 && is_qos0_publish(request) {
+                    excess -= 1;
+                    return false;
+                }
+
+                true
+            }),
+            Qos0DropPolicy::DropNewest => {
+                let mut kept = 0;
+                self.pending.retain(|request| {
+                    if !is_qos0_publish(request) {
+                        return true;
+                    }
+
+                    kept += 1;
+                    kept <= max
+                })
+            }
+        }
     }
 
     /// Yields Next notification or outgoing request and periodically pings
@@ -139,18 +372,116 @@ pub fn clean(&mut self) {
     /// a disconnection.
     /// **NOTE** Don't block this while iterating
     pub async fn poll(&mut self) -> Result<Event, ConnectionError> {
+        let result = self.next_event().await;
+        let trace = match self.mqtt_options.packet_trace() {
+            Some(capacity) => self.trace.get_or_insert_with(|| PacketTrace::new(capacity)),
+            None => return result,
+        };
+
+        match result {
+            Ok(event) => {
+                trace.record(&event);
+                Ok(event)
+            }
+            // Clients are gone, nothing to diagnose
+            Err(ConnectionError::RequestsDone) => Err(ConnectionError::RequestsDone),
+            Err(error) => Err(ConnectionError::Traced {
+                error: Box::new(error),
+                trace: trace.clone(),
+            }),
+        }
+    }
+
+    async fn next_event(&mut self) -> Result<Event, ConnectionError> {
+        self.buffered.store(self.pending.len(), Ordering::Relaxed);
+        // Don't reconnect when there is nothing left to send
+        if self.requests_done {
+            return Err(ConnectionError::RequestsDone);
+        }
+
+        if self.network.is_none() && self.awaiting_first_request && self.pending.is_empty() {
+            let request = Self::next_request(
+                &mut self.pending,
+                &self.requests_rx,
+                &self.slots,
+                Duration::ZERO,
+                self.mqtt_options.client_drop_behavior,
+                0,
+            )
+            .await;
+
+            match request {
+                // Sent once connected, like requests of a previous session
+                Ok(request) => self.pending.push_back(request),
+                Err(e) => {
+                    self.requests_done = true;
+                    return Err(e);
+                }
+            }
+        }
+
         if self.network.is_none() {
+            self.awaiting_first_request = false;
+            if let Some(will) = self.next_last_will.lock().unwrap().take() {
+                self.mqtt_options.set_last_will(will);
+            }
+
+            if let Some(max) = self.mqtt_options.max_reconnect_attempts() {
+                if self.failed_connects >= max {
+                    return Err(ConnectionError::GaveUp);
+                }
+            }
+
+            self.select_broker();
+            if let Some(callback) = &self.mqtt_options.on_connect_attempt {
+                callback(&connect_address(&self.mqtt_options), self.failed_connects);
+            }
+
             let (network, connack) = match time::timeout(
                 Duration::from_secs(self.network_options.connection_timeout()),
-                connect(&self.mqtt_options, self.network_options.clone()),
+                connect(
+                    &self.mqtt_options,
+                    self.network_options.clone(),
+                    &self.dns_cache,
+                ),
             )
             .await
             {
-                Ok(inner) => inner?,
-                Err(_) => return Err(ConnectionError::NetworkTimeout),
+                Ok(Ok(inner)) => inner,
+                Ok(Err(e)) => {
+                    self.metrics.last_error = Some(SystemTime::now());
+                    self.failed_connects += 1;
+                    self.broker_index += 1;
+                    return Err(e);
+                }
+                Err(_) => {
+                    self.metrics.last_error = Some(SystemTime::now());
+                    self.failed_connects += 1;
+                    self.broker_index += 1;
+                    return Err(ConnectionError::NetworkTimeout);
+                }
             };
+
+            self.failed_connects = 0;
+            if self.mqtt_options.failover_strategy() == FailoverStrategy::PrimaryWithFallback {
+                self.broker_index = 0;
+            }
+
+            // Metrics of all the previous connections are merged by now
+            if self.metrics.packets_received.connack > 0 {
+                self.metrics.reconnects += 1;
+            }
             self.network = Some(network);
 
+            // Acks of publishes from a session which wasn't resumed aren't valid anymore
+            if let Packet::ConnAck(ConnAck {
+                session_present: false,
+                ..
+            }) = &connack
+            {
+                self.state.forget_unacked_incoming();
+            }
+
             if self.keepalive_timeout.is_none() && !self.mqtt_options.keep_alive.is_zero() {
                 self.keepalive_timeout = Some(Box::pin(time::sleep(self.mqtt_options.keep_alive)));
             }
@@ -161,19 +492,88 @@ pub async fn poll(&mut self) -> Result<Event, ConnectionError> {
         match self.select().await {
             Ok(v) => Ok(v),
             Err(e) => {
+                self.metrics.last_error = Some(SystemTime::now());
                 self.clean();
                 Err(e)
             }
         }
     }
 
+    /// Points options at the broker of the broker list to connect to next, so that the
+    /// connection and TLS verification use its host
+    fn select_broker(&mut self) {
+        let brokers = &self.mqtt_options.broker_list;
+        if brokers.is_empty() {
+            return;
+        }
+
+        self.broker_index %= brokers.len();
+        let (host, port) = brokers[self.broker_index].clone();
+        self.mqtt_options.broker_addr = host;
+        self.mqtt_options.port = port;
+    }
+
+    /// Version and cipher suite negotiated by the current connection when `Transport::Tls`
+    /// is used with rustls
+    #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        self.network
+            .as_ref()
+            .and_then(|network| network.tls_info.clone())
+    }
+
+    /// Like [`poll`](EventLoop::poll), but also takes events which are already decoded
+    /// and buffered, returning up to `max` of them without waiting again. Always returns
+    /// at least one event, and a single one when nothing else is buffered
+    pub async fn poll_batch(&mut self, max: usize) -> Result<Vec<Event>, ConnectionError> {
+        let mut events = Vec::with_capacity(max.min(self.state.events.len() + 1));
+        events.push(self.poll().await?);
+
+        while events.len() < max {
+            match self.state.events.pop_front() {
+                Some(event) => {
+                    if let Some(trace) = &mut self.trace {
+                        trace.record(&event);
+                    }
+                    events.push(event);
+                }
+                None => break,
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Snapshot of cumulative metrics of all the connections made by this `EventLoop`
+    pub fn metrics(&self) -> ClientMetrics {
+        let mut metrics = self.metrics.clone();
+        if let Some(network) = &self.network {
+            metrics.merge(&network.metrics);
+        }
+        metrics.buffered = self.pending.len() + self.requests_rx.len();
+
+        metrics
+    }
+
+    /// Last events polled with the time at which they were polled, `None` till an event is
+    /// polled with [`MqttOptions::set_packet_trace`] enabled
+    pub fn packet_trace(&self) -> Option<&PacketTrace> {
+        self.trace.as_ref()
+    }
+
     /// Select on network and requests and generate keepalive pings when necessary
     async fn select(&mut self) -> Result<Event, ConnectionError> {
         let network = self.network.as_mut().unwrap();
         // let await_acks = self.state.await_acks;
         let inflight_full = self.state.inflight >= self.mqtt_options.inflight;
         let collision = self.state.collision.is_some();
+        // Copied as state is borrowed by the network branch of `select!`
+        let inflight = self.state.inflight;
+        // Pending requests bypass flow control unless paced
+        let pending_paced = self.mqtt_options.paced_resend() && inflight_full;
+        let pending_allowed = !self.pending.is_empty() && !pending_paced;
         let network_timeout = Duration::from_secs(self.network_options.connection_timeout());
+        let write_timeout = self.mqtt_options.write_timeout;
 
         // Read buffered events from previous polls before calling a new poll
         if let Some(event) = self.state.events.pop_front() {
@@ -181,17 +581,27 @@ async fn select(&mut self) -> Result<Event, ConnectionError> {
         }
 
         let mut no_sleep = Box::pin(time::sleep(Duration::ZERO));
+        let mut no_pingresp_sleep = Box::pin(time::sleep(Duration::ZERO));
+        let ack_deadline = self
+            .mqtt_options
+            .ack_timeout()
+            .and_then(|timeout| self.state.next_ack_deadline(timeout));
+        let ack_timeout =
+            time::sleep_until(ack_deadline.map_or_else(Instant::now, Instant::from_std));
+        // Reading waits for the user to ack incoming QoS 2 publishes when too many are held.
+        // Acks come in with requests, so reading goes on while requests are blocked on acks
+        // from the broker
+        let read_paused = !inflight_full && !collision && self.state.unacked_incoming_qos2_full();
+
         // this loop is necessary since self.incoming.pop_front() might return None. In that case,
         // instead of returning a None event, we try again.
         select! {
             // Pull a bunch of packets from network, reply in bunch and yield the first item
-            o = network.readb(&mut self.state) => {
+            o = network.readb(&mut self.state), if !read_paused => {
                 o?;
                 // flush all the acks and return first incoming packet
-                match time::timeout(network_timeout, network.flush()).await {
-                    Ok(inner) => inner?,
-                    Err(_)=> return Err(ConnectionError::FlushTimeout),
-                };
+                write(network, None, write_timeout, network_timeout).await?;
+                reset_keepalive(&mut self.keepalive_timeout, &self.state, &self.mqtt_options);
                 Ok(self.state.events.pop_front().unwrap())
             },
              // Handles pending and new requests.
@@ -225,34 +635,99 @@ async fn select(&mut self) -> Result<Event, ConnectionError> {
             o = Self::next_request(
                 &mut self.pending,
                 &self.requests_rx,
-                self.mqtt_options.pending_throttle
-            ), if !self.pending.is_empty() || (!inflight_full && !collision) => match o {
-                Ok(request) => {
-                    if let Some(outgoing) = self.state.handle_outgoing_packet(request)? {
-                        network.write(outgoing).await?;
+                &self.slots,
+                self.mqtt_options.pending_throttle,
+                self.mqtt_options.client_drop_behavior,
+                inflight,
+            ), if pending_allowed || (!inflight_full && !collision) => match o {
+                // Streamed payloads are written directly and can take arbitrarily long
+                // depending on the reader, hence only bounded by write timeout
+                Ok(Request::PublishStream(stream)) => {
+                    #[cfg(feature = "test-util")]
+                    mirror(&self.mirror_tx, &Request::PublishStream(stream.clone()));
+                    self.state.outgoing_publish_stream();
+                    write_stream(network, stream, write_timeout).await?;
+                    reset_keepalive(&mut self.keepalive_timeout, &self.state, &self.mqtt_options);
+                    Ok(self.state.events.pop_front().unwrap())
+                }
+                // Copied to network as is, with the packet id assigned by state
+                Ok(Request::PublishEncoded(publish)) => {
+                    #[cfg(feature = "test-util")]
+                    mirror(&self.mirror_tx, &Request::PublishEncoded(publish.clone()));
+                    if let Some(pkid) = self.state.outgoing_encoded_publish(&publish)? {
+                        network.write_encoded(&publish, pkid)?;
+                    }
+                    write(network, None, write_timeout, network_timeout).await?;
+                    reset_keepalive(&mut self.keepalive_timeout, &self.state, &self.mqtt_options);
+                    Ok(self.state.events.pop_front().unwrap())
+                }
+                // QoS 0 publishes are delivered once written, so they aren't left to state
+                Ok(Request::PublishTracked(publish, notice))
+                    if publish.qos == QoS::AtMostOnce =>
+                {
+                    #[cfg(feature = "test-util")]
+                    mirror(
+                        &self.mirror_tx,
+                        &Request::PublishTracked(publish.clone(), notice.clone()),
+                    );
+                    let outgoing = self.state.handle_outgoing_packet(Request::Publish(publish))?;
+                    if let Err(e) = write(network, outgoing, write_timeout, network_timeout).await {
+                        notice.notify(DeliveryStatus::Failed);
+                        return Err(e);
                     }
-                    match time::timeout(network_timeout, network.flush()).await {
-                        Ok(inner) => inner?,
-                        Err(_)=> return Err(ConnectionError::FlushTimeout),
-                    };
+                    notice.notify(DeliveryStatus::Delivered);
+                    reset_keepalive(&mut self.keepalive_timeout, &self.state, &self.mqtt_options);
                     Ok(self.state.events.pop_front().unwrap())
                 }
-                Err(_) => Err(ConnectionError::RequestsDone),
+                // Requests are handled in order, so the ones before are already written.
+                // Not mirrored, a copy would keep the flush from failing
+                Ok(Request::Flush(notice)) => {
+                    write(network, None, write_timeout, network_timeout).await?;
+                    notice.notify();
+                    Ok(Event::Outgoing(Outgoing::Flush))
+                }
+                Ok(request) => {
+                    #[cfg(feature = "test-util")]
+                    mirror(&self.mirror_tx, &request);
+                    let outgoing = self.state.handle_outgoing_packet(request)?;
+                    write(network, outgoing, write_timeout, network_timeout).await?;
+                    reset_keepalive(&mut self.keepalive_timeout, &self.state, &self.mqtt_options);
+                    Ok(self.state.events.pop_front().unwrap())
+                }
+                // All the clients are dropped, disconnect cleanly
+                Err(_) => {
+                    write(network, Some(Packet::Disconnect), write_timeout, network_timeout).await?;
+                    self.requests_done = true;
+                    Err(ConnectionError::RequestsDone)
+                }
             },
-            // We generate pings irrespective of network activity. This keeps the ping logic
-            // simple. We can change this behavior in future if necessary (to prevent extra pings)
+            // By default, pings are generated irrespective of network activity. This keeps the
+            // ping logic simple. With `reset_keep_alive_on_outgoing`, every outgoing packet
+            // pushes the timer so that pings are only sent on idle connections
             _ = self.keepalive_timeout.as_mut().unwrap_or(&mut no_sleep),
                 if self.keepalive_timeout.is_some() && !self.mqtt_options.keep_alive.is_zero() => {
                 let timeout = self.keepalive_timeout.as_mut().unwrap();
                 timeout.as_mut().reset(Instant::now() + self.mqtt_options.keep_alive);
 
-                if let Some(outgoing) = self.state.handle_outgoing_packet(Request::PingReq(PingReq))? {
-                    network.write(outgoing).await?;
+                let outgoing = self.state.handle_outgoing_packet(Request::PingReq(PingReq))?;
+                write(network, outgoing, write_timeout, network_timeout).await?;
+                if let Some(timeout) = self.mqtt_options.pingresp_timeout {
+                    self.pingresp_timeout = Some(Box::pin(time::sleep(timeout)));
                 }
-                match time::timeout(network_timeout, network.flush()).await {
-                    Ok(inner) => inner?,
-                    Err(_)=> return Err(ConnectionError::FlushTimeout),
-                };
+                Ok(self.state.events.pop_front().unwrap())
+            }
+            // Consider connection dead when pingresp isn't received in time instead of
+            // waiting for the next keep alive
+            _ = self.pingresp_timeout.as_mut().unwrap_or(&mut no_pingresp_sleep),
+                if self.pingresp_timeout.is_some() && self.state.await_pingresp => {
+                Err(ConnectionError::MqttState(StateError::AwaitPingResp))
+            }
+            // Resend the publish whose ack is overdue, the broker may have dropped it
+            _ = ack_timeout, if ack_deadline.is_some() => {
+                let retries = self.mqtt_options.ack_timeout_retries();
+                let publish = self.state.handle_ack_timeout(retries)?;
+                write(network, Some(publish), write_timeout, network_timeout).await?;
+                reset_keepalive(&mut self.keepalive_timeout, &self.state, &self.mqtt_options);
                 Ok(self.state.events.pop_front().unwrap())
             }
         }
@@ -270,7 +745,10 @@ pub fn set_network_options(&mut self, network_options: NetworkOptions) -> &mut S
     async fn next_request(
         pending: &mut VecDeque<Request>,
         rx: &Receiver<Request>,
+        slots: &Slots,
         pending_throttle: Duration,
+        client_drop_behavior: ClientDropBehavior,
+        inflight: u16,
     ) -> Result<Request, ConnectionError> {
         if !pending.is_empty() {
             time::sleep(pending_throttle).await;
@@ -278,14 +756,137 @@ async fn next_request(
             // advanced the iterator but the future might be canceled before return
             Ok(pending.pop_front().unwrap())
         } else {
-            match rx.recv_async().await {
-                Ok(r) => Ok(r),
-                Err(_) => Err(ConnectionError::RequestsDone),
+            select! {
+                Ok(r) = rx.recv_async() => {
+                    slots.release(1);
+                    Ok(r)
+                }
+                else => match client_drop_behavior {
+                    // Disconnect once inflight publishes are acked. Until then, or forever
+                    // when keeping alive, the connection is only driven by network and pings
+                    ClientDropBehavior::Disconnect if inflight == 0 => {
+                        Err(ConnectionError::RequestsDone)
+                    }
+                    _ => std::future::pending().await,
+                },
             }
         }
     }
 }
 
+impl Drop for EventLoop {
+    fn drop(&mut self) {
+        // Publishes in state were sent before the ones waiting to be resent from
+        // previous sessions, which were in turn sent before requests in channels
+        let mut drained = self.state.clean();
+        drained.extend(self.pending.drain(..));
+        drained.extend(self.requests_rx.drain());
+        // Waiting flushes fail as they are dropped
+        drained.retain(|request| !matches!(request, Request::Flush(_)));
+
+        self.drained.lock().unwrap().extend(drained);
+    }
+}
+
+/// Restarts keepalive timer from the last outgoing packet when pings are to be sent
+/// only on connections which are idle for keep alive time
+fn reset_keepalive(
+    keepalive_timeout: &mut Option<Pin<Box<Sleep>>>,
+    state: &MqttState,
+    options: &MqttOptions,
+) {
+    if !options.reset_keep_alive_on_outgoing {
+        return;
+    }
+
+    if let Some(timeout) = keepalive_timeout {
+        let deadline = Instant::from_std(state.last_outgoing()) + options.keep_alive;
+        timeout.as_mut().reset(deadline);
+    }
+}
+
+/// Writes `packet`, if any, and flushes buffered packets to the network. With a write
+/// timeout, the whole write must complete in time, so that a peer which stopped reading
+/// can't block the eventloop. Otherwise only the flush is bounded by network timeout
+async fn write(
+    network: &mut Network,
+    packet: Option<Packet>,
+    write_timeout: Option<Duration>,
+    network_timeout: Duration,
+) -> Result<(), ConnectionError> {
+    let write_timeout = match write_timeout {
+        Some(timeout) => timeout,
+        None => {
+            if let Some(packet) = packet {
+                network.write(packet).await?;
+            }
+
+            return match time::timeout(network_timeout, network.flush()).await {
+                Ok(inner) => Ok(inner?),
+                Err(_) => Err(ConnectionError::FlushTimeout),
+            };
+        }
+    };
+
+    let write = async {
+        if let Some(packet) = packet {
+            network.write(packet).await?;
+        }
+
+        network.flush().await
+    };
+
+    match time::timeout(write_timeout, write).await {
+        Ok(inner) => Ok(inner?),
+        Err(_) => Err(ConnectionError::WriteTimeout),
+    }
+}
+
+/// Writes a streamed publish. With a write timeout, the whole publish, including reads
+/// of its payload, must be written in time like other writes
+async fn write_stream(
+    network: &mut Network,
+    stream: PublishStream,
+    write_timeout: Option<Duration>,
+) -> Result<(), ConnectionError> {
+    let write_timeout = match write_timeout {
+        Some(timeout) => timeout,
+        None => return Ok(network.write_stream(stream).await?),
+    };
+
+    match time::timeout(write_timeout, network.write_stream(stream)).await {
+        Ok(inner) => Ok(inner?),
+        Err(_) => Err(ConnectionError::WriteTimeout),
+    }
+}
+
+fn is_qos0_publish(request: &Request) -> bool {
+    match request {
+        Request::Publish(publish) => publish.qos == QoS::AtMostOnce,
+        Request::PublishEncoded(publish) => publish.publish().qos == QoS::AtMostOnce,
+        Request::PublishTracked(publish, _) => publish.qos == QoS::AtMostOnce,
+        _ => false,
+    }
+}
+
+fn is_publish(request: &Request) -> bool {
+    matches!(
+        request,
+        Request::Publish(_)
+            | Request::PublishEncoded(_)
+            | Request::PublishStream(_)
+            | Request::PublishTracked(..)
+    )
+}
+
+#[cfg(feature = "test-util")]
+fn mirror(mirror_tx: &Option<Sender<Request>>, request: &Request) {
+    if let Some(tx) = mirror_tx {
+        // Receiver might have been dropped by the test
+        tx.send(request.clone()).ok();
+    }
+}
+
 /// This stream internally processes requests from the request stream provided to the eventloop
 /// while also consuming byte stream from the network and yielding mqtt packets as the output of
 /// the stream.
@@ -294,9 +895,10 @@ async fn next_request(
 async fn connect(
     mqtt_options: &MqttOptions,
     network_options: NetworkOptions,
+    dns_cache: &DnsCache,
 ) -> Result<(Network, Incoming), ConnectionError> {
     // connect to the broker
-    let mut network = network_connect(mqtt_options, network_options).await?;
+    let mut network = network_connect(mqtt_options, network_options, dns_cache).await?;
 
     // make MQTT connection request (which internally awaits for ack)
     let packet = mqtt_connect(mqtt_options, &mut network).await?;
@@ -304,11 +906,50 @@ async fn connect(
     Ok((network, packet))
 }
 
+/// Address `network_connect` connects to, for logs
+fn connect_address(options: &MqttOptions) -> String {
+    match options.transport() {
+        #[cfg(unix)]
+        Transport::Unix => options.broker_addr.clone(),
+        #[cfg(feature = "websocket")]
+        Transport::Ws => options.broker_addr.clone(),
+        #[cfg(all(feature = "use-rustls", feature = "websocket"))]
+        Transport::Wss(_) => options.broker_addr.clone(),
+        _ => format!("{}:{}", options.broker_addr, options.port),
+    }
+}
+
+#[cfg(feature = "proxy")]
 pub(crate) async fn socket_connect(
     host: String,
     network_options: NetworkOptions,
 ) -> io::Result<TcpStream> {
     let addrs = lookup_host(host).await?;
+    socket_connect_addrs(addrs, network_options).await
+}
+
+/// Connects to `host` with addresses from `dns_cache`. Cached addresses are
+/// dropped when none of them can be connected to
+pub(crate) async fn socket_connect_cached(
+    host: String,
+    network_options: NetworkOptions,
+    dns_cache: &DnsCache,
+    ttl: Option<Duration>,
+) -> io::Result<TcpStream> {
+    let addrs = dns_cache.resolve(&host, ttl).await?;
+    let result = socket_connect_addrs(addrs, network_options).await;
+    if result.is_err() {
+        dns_cache.invalidate(&host);
+    }
+
+    result
+}
+
+/// Tries connecting to `addrs` in order and returns the first successful connection
+async fn socket_connect_addrs(
+    addrs: impl IntoIterator<Item = SocketAddr>,
+    network_options: NetworkOptions,
+) -> io::Result<TcpStream> {
     let mut last_err = None;
 
     for addr in addrs {
@@ -318,10 +959,10 @@ pub(crate) async fn socket_connect(
         };
 
         if let Some(send_buff_size) = network_options.tcp_send_buffer_size {
-            socket.set_send_buffer_size(send_buff_size).unwrap();
+            socket.set_send_buffer_size(send_buff_size)?;
         }
         if let Some(recv_buffer_size) = network_options.tcp_recv_buffer_size {
-            socket.set_recv_buffer_size(recv_buffer_size).unwrap();
+            socket.set_recv_buffer_size(recv_buffer_size)?;
         }
 
         #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
@@ -353,6 +994,7 @@ pub(crate) async fn socket_connect(
 async fn network_connect(
     options: &MqttOptions,
     network_options: NetworkOptions,
+    dns_cache: &DnsCache,
 ) -> Result<Network, ConnectionError> {
     // Process Unix files early, as proxy is not supported for them.
     #[cfg(unix)]
@@ -382,14 +1024,16 @@ async fn network_connect(
             Some(proxy) => proxy.connect(&domain, port, network_options).await?,
             None => {
                 let addr = format!("{domain}:{port}");
-                let tcp = socket_connect(addr, network_options).await?;
+                let ttl = options.dns_cache_ttl();
+                let tcp = socket_connect_cached(addr, network_options, dns_cache, ttl).await?;
                 Box::new(tcp)
             }
         }
         #[cfg(not(feature = "proxy"))]
         {
             let addr = format!("{domain}:{port}");
-            let tcp = socket_connect(addr, network_options).await?;
+            let ttl = options.dns_cache_ttl();
+            let tcp = socket_connect_cached(addr, network_options, dns_cache, ttl).await?;
             Box::new(tcp)
         }
     };
@@ -402,14 +1046,16 @@ async fn network_connect(
         ),
         #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
         Transport::Tls(tls_config) => {
-            let socket =
+            let (socket, tls_info) =
                 tls::tls_connect(&options.broker_addr, options.port, &tls_config, tcp_stream)
                     .await?;
-            Network::new(
+            let mut network = Network::new(
                 socket,
                 options.max_incoming_packet_size,
                 options.max_outgoing_packet_size,
-            )
+            );
+            network.tls_info = tls_info;
+            network
         }
         #[cfg(unix)]
         Transport::Unix => unreachable!(),
@@ -475,10 +1121,17 @@ async fn mqtt_connect(
     let last_will = options.last_will();
 
     let mut connect = Connect::new(options.client_id());
+    connect.protocol = options.protocol_version();
     connect.keep_alive = keep_alive;
     connect.clean_session = clean_session;
     connect.last_will = last_will;
-    connect.login = options.credentials();
+    connect.login = match options.credentials_provider() {
+        Some(provider) => {
+            let (username, password) = provider().await;
+            Some(Login::new(username, password))
+        }
+        None => options.credentials(),
+    };
 
     // send mqtt connect packet
     network.connect(connect).await?;
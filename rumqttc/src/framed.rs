@@ -1,21 +1,39 @@
+use bytes::BytesMut;
 use futures_util::{FutureExt, SinkExt, StreamExt};
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio_util::codec::Framed;
 
 use crate::mqttbytes::{self, v4::*};
-use crate::{Incoming, MqttState, StateError};
+#[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+use crate::TlsInfo;
+use crate::{ClientMetrics, Incoming, MqttState, PublishStream, StateError};
+
+/// Size of chunks in which streamed payloads are copied to network
+const STREAM_CHUNK_SIZE: usize = 8 * 1024;
 
 /// Network transforms packets <-> frames efficiently. It takes
 /// advantage of pre-allocation, buffering and vectorization when
 /// appropriate to achieve performance
+///
+/// This is an advanced API for bridges and proxies which handle packets themselves,
+/// reusing the codec of the [`EventLoop`](crate::EventLoop) without its state. Packets
+/// are only framed: acks, packet ids and pings are up to the user. Packets written
+/// with [`write`](Network::write) are buffered till [`flush`](Network::flush)
 pub struct Network {
     /// Frame MQTT packets from network connection
     framed: Framed<Box<dyn AsyncReadWrite>, Codec>,
     /// Maximum readv count
     max_readb_count: usize,
+    /// Packets and bytes exchanged on this connection
+    pub(crate) metrics: ClientMetrics,
+    /// Parameters negotiated by TLS handshake
+    #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+    pub(crate) tls_info: Option<TlsInfo>,
 }
 
 impl Network {
+    /// Frames packets on an established connection, e.g. a `TcpStream`. Packets larger
+    /// than the maximum sizes fail to read or write
     pub fn new(
         socket: impl AsyncReadWrite + 'static,
         max_incoming_size: usize,
@@ -31,13 +49,19 @@ pub fn new(
         Network {
             framed,
             max_readb_count: 10,
+            metrics: ClientMetrics::default(),
+            #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+            tls_info: None,
         }
     }
 
     /// Reads and returns a single packet from network
     pub async fn read(&mut self) -> Result<Incoming, StateError> {
         match self.framed.next().await {
-            Some(Ok(packet)) => Ok(packet),
+            Some(Ok(packet)) => {
+                self.metrics.received(&packet);
+                Ok(packet)
+            }
             Some(Err(mqttbytes::Error::InsufficientBytes(_))) => unreachable!(),
             Some(Err(e)) => Err(StateError::Deserialization(e)),
             None => Err(StateError::ConnectionAborted),
@@ -45,20 +69,35 @@ pub async fn read(&mut self) -> Result<Incoming, StateError> {
     }
 
     /// Read packets in bulk. This allow replies to be in bulk. This method is used
-    /// after the connection is established to read a bunch of incoming packets
+    /// after the connection is established to read a bunch of incoming packets.
+    /// Keeps reading until there is at least one event, as filtered publishes
+    /// don't generate any
     pub async fn readb(&mut self, state: &mut MqttState) -> Result<(), StateError> {
+        loop {
+            self.readb_once(state).await?;
+            if !state.events.is_empty() {
+                return Ok(());
+            }
+
+            self.flush().await?;
+        }
+    }
+
+    async fn readb_once(&mut self, state: &mut MqttState) -> Result<(), StateError> {
         // wait for the first read
         let mut res = self.framed.next().await;
         let mut count = 1;
         loop {
             match res {
                 Some(Ok(packet)) => {
+                    self.metrics.received(&packet);
                     if let Some(outgoing) = state.handle_incoming_packet(packet)? {
                         self.write(outgoing).await?;
                     }
 
                     count += 1;
-                    if count >= self.max_readb_count {
+                    // the rest waits for the user to ack held QoS 2 publishes
+                    if count >= self.max_readb_count || state.unacked_incoming_qos2_full() {
                         break;
                     }
                 }
@@ -78,18 +117,72 @@ pub async fn readb(&mut self, state: &mut MqttState) -> Result<(), StateError> {
 
     /// Serializes packet into write buffer
     pub async fn write(&mut self, packet: Packet) -> Result<(), StateError> {
+        self.metrics.sent(&packet);
         self.framed
             .feed(packet)
             .await
             .map_err(StateError::Deserialization)
     }
 
+    /// Copies an encoded publish with `pkid` into write buffer
+    pub fn write_encoded(&mut self, publish: &EncodedPublish, pkid: u16) -> Result<(), StateError> {
+        let max = self.framed.codec().max_outgoing_size;
+        let pkt_size = publish.size();
+        if pkt_size > max {
+            return Err(mqttbytes::Error::OutgoingPacketTooLarge { pkt_size, max }.into());
+        }
+
+        publish.write(pkid, self.framed.write_buffer_mut());
+        self.metrics.sent_encoded(pkt_size);
+        Ok(())
+    }
+
+    /// Writes a publish whose payload is copied from the stream's reader in chunks,
+    /// without buffering the whole payload. Buffered packets are flushed first. Only the
+    /// announced length is read, the reader isn't polled past it as it could block forever
+    pub async fn write_stream(&mut self, stream: PublishStream) -> Result<(), StateError> {
+        self.flush().await?;
+
+        let mut reader = stream.take_reader().ok_or(StateError::InvalidState)?;
+        let mut header = BytesMut::new();
+        stream.publish.write_header(stream.len, &mut header)?;
+
+        let max = self.framed.codec().max_outgoing_size;
+        let pkt_size = header.len() + stream.len;
+        if pkt_size > max {
+            return Err(mqttbytes::Error::OutgoingPacketTooLarge { pkt_size, max }.into());
+        }
+
+        let socket = self.framed.get_mut();
+        socket.write_all(&header).await?;
+
+        let mut chunk = vec![0; STREAM_CHUNK_SIZE.min(stream.len.max(1))];
+        let mut remaining = stream.len;
+        while remaining > 0 {
+            let size = remaining.min(chunk.len());
+            let read = reader.read(&mut chunk[..size]).await?;
+            if read == 0 {
+                return Err(StateError::StreamLengthMismatch(stream.len));
+            }
+
+            socket.write_all(&chunk[..read]).await?;
+            remaining -= read;
+        }
+
+        socket.flush().await?;
+
+        self.metrics.packets_sent.publish += 1;
+        self.metrics.bytes_sent += pkt_size as u64;
+        Ok(())
+    }
+
     pub async fn connect(&mut self, connect: Connect) -> Result<(), StateError> {
         self.write(Packet::Connect(connect)).await?;
 
         self.flush().await
     }
 
+    /// Writes buffered packets to network
     pub async fn flush(&mut self) -> Result<(), crate::state::StateError> {
         self.framed
             .flush()
@@ -100,3 +193,54 @@ pub async fn flush(&mut self) -> Result<(), crate::state::StateError> {
 
 pub trait AsyncReadWrite: AsyncRead + AsyncWrite + Send + Unpin {}
 impl<T> AsyncReadWrite for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::QoS;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn packets_written_are_read_on_other_end() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut client = Network::new(client, 1024, 1024);
+        let mut server = Network::new(server, 1024, 1024);
+
+        let mut publish = Publish::new("hello/world", QoS::AtLeastOnce, vec![1, 2, 3]);
+        publish.pkid = 1;
+        client
+            .write(Packet::Publish(publish.clone()))
+            .await
+            .unwrap();
+        client.write(Packet::PingReq).await.unwrap();
+        client.flush().await.unwrap();
+
+        assert_eq!(server.read().await.unwrap(), Packet::Publish(publish));
+        assert_eq!(server.read().await.unwrap(), Packet::PingReq);
+
+        // oversized packets aren't written
+        let publish = Publish::new("hello/world", QoS::AtMostOnce, vec![0; 2048]);
+        client.write(Packet::Publish(publish)).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn streams_are_written_without_reading_past_their_length() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut client = Network::new(client, 1024, 1024);
+        let mut server = Network::new(server, 1024, 1024);
+
+        // reader stays open after the payload, reading past it would wait forever
+        let (mut payload_tx, payload_rx) = tokio::io::duplex(1024);
+        payload_tx.write_all(&[1, 2, 3]).await.unwrap();
+        let publish = Publish::new("hello/world", QoS::AtMostOnce, Vec::new());
+        let stream = PublishStream::new(publish, 3, payload_rx);
+
+        tokio::time::timeout(Duration::from_secs(1), client.write_stream(stream))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let publish = Publish::new("hello/world", QoS::AtMostOnce, vec![1, 2, 3]);
+        assert_eq!(server.read().await.unwrap(), Packet::Publish(publish));
+    }
+}
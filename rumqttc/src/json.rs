@@ -0,0 +1,177 @@
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::v5::mqttbytes::v5::PublishProperties;
+use crate::{v5, AsyncClient, ClientError, QoS};
+
+const CONTENT_TYPE: &str = "application/json";
+
+/// Payload of an incoming publish which couldn't be deserialized by a stream of
+/// [`AsyncClient::subscribe_json`]
+#[derive(Debug, thiserror::Error)]
+#[error("Couldn't decode JSON payload of publish on {topic}: {error}")]
+pub struct DecodeError {
+    pub topic: String,
+    /// Raw payload, as received
+    pub payload: Bytes,
+    #[source]
+    pub error: serde_json::Error,
+}
+
+impl AsyncClient {
+    /// Like [`AsyncClient::subscribe_stream`], with payloads of incoming publishes
+    /// deserialized from JSON. Payloads which can't be deserialized as `T` are yielded
+    /// as errors, the stream goes on after them
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    pub async fn subscribe_json<T, S>(
+        &self,
+        filter: S,
+        qos: QoS,
+    ) -> Result<impl Stream<Item = Result<T, DecodeError>>, ClientError>
+    where
+        T: DeserializeOwned,
+        S: Into<String>,
+    {
+        let publishes = self.subscribe_stream(filter, qos).await?;
+        let decoded = publishes.map(|publish| {
+            serde_json::from_slice(&publish.payload).map_err(|error| DecodeError {
+                topic: publish.topic,
+                payload: publish.payload,
+                error,
+            })
+        });
+
+        Ok(decoded)
+    }
+
+    /// Sends a MQTT Publish to the `EventLoop` with `value` serialized to JSON as payload.
+    /// Fails with [`ClientError::Json`] if `value` can't be serialized
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    pub async fn publish_json<S, T>(
+        &self,
+        topic: S,
+        qos: QoS,
+        retain: bool,
+        value: &T,
+    ) -> Result<(), ClientError>
+    where
+        S: Into<String>,
+        T: Serialize + ?Sized,
+    {
+        let payload = serde_json::to_vec(value).map_err(ClientError::Json)?;
+        self.publish(topic, qos, retain, payload).await
+    }
+}
+
+impl v5::AsyncClient {
+    /// Sends a MQTT Publish to the `EventLoop` with `value` serialized to JSON as payload
+    /// and its content type set to `application/json`. Fails with [`v5::ClientError::Json`]
+    /// if `value` can't be serialized
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    pub async fn publish_json<S, T>(
+        &self,
+        topic: S,
+        qos: v5::mqttbytes::QoS,
+        retain: bool,
+        value: &T,
+    ) -> Result<(), v5::ClientError>
+    where
+        S: Into<String>,
+        T: Serialize + ?Sized,
+    {
+        let payload = serde_json::to_vec(value).map_err(v5::ClientError::Json)?;
+        let properties = PublishProperties {
+            content_type: Some(CONTENT_TYPE.to_owned()),
+            ..Default::default()
+        };
+
+        self.publish_with_properties(topic, qos, retain, payload, properties)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Incoming, MqttOptions, Publish, Request};
+    use serde::Deserialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Reading {
+        value: u32,
+    }
+
+    #[tokio::test]
+    async fn payloads_are_decoded_and_errors_keep_raw_payload() {
+        let options = MqttOptions::new("test-1", "localhost", 1883);
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+        let readings = client
+            .subscribe_json::<Reading, _>("sensors/+", QoS::AtMostOnce)
+            .await
+            .unwrap();
+        futures_util::pin_mut!(readings);
+
+        for payload in [r#"{"value": 1}"#, "not json"] {
+            let publish = Publish::new("sensors/a", QoS::AtMostOnce, payload);
+            eventloop
+                .state
+                .handle_incoming_packet(Incoming::Publish(publish))
+                .unwrap();
+        }
+
+        let reading = readings.next().await.unwrap().unwrap();
+        assert_eq!(reading, Reading { value: 1 });
+
+        let e = readings.next().await.unwrap().unwrap_err();
+        assert_eq!(e.topic, "sensors/a");
+        assert_eq!(e.payload, Bytes::from("not json"));
+    }
+
+    #[tokio::test]
+    async fn values_are_published_as_json() {
+        let options = MqttOptions::new("test-1", "localhost", 1883);
+        let (client, mut eventloop) = AsyncClient::new(options, 10);
+        let value = HashMap::from([("value", 1)]);
+        client
+            .publish_json("sensors/a", QoS::AtLeastOnce, false, &value)
+            .await
+            .unwrap();
+
+        // keys which aren't strings can't be serialized
+        let invalid = HashMap::from([((1, 2), 1)]);
+        let e = client
+            .publish_json("sensors/a", QoS::AtLeastOnce, false, &invalid)
+            .await;
+        assert!(matches!(e, Err(ClientError::Json(_))));
+
+        eventloop.clean();
+        assert_eq!(eventloop.pending.len(), 1);
+        match &eventloop.pending[0] {
+            Request::Publish(publish) => assert_eq!(publish.payload, r#"{"value":1}"#),
+            request => panic!("Invalid request {request:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn v5_json_publishes_have_content_type() {
+        let options = v5::MqttOptions::new("test-1", "localhost", 1883);
+        let (client, mut eventloop) = v5::AsyncClient::new(options, 10);
+        client
+            .publish_json("sensors/a", v5::mqttbytes::QoS::AtLeastOnce, false, &[1, 2])
+            .await
+            .unwrap();
+
+        eventloop.clean();
+        match &eventloop.pending[0] {
+            v5::Request::Publish(publish) => {
+                assert_eq!(publish.payload, "[1,2]");
+                let properties = publish.properties.as_ref().unwrap();
+                assert_eq!(properties.content_type.as_deref(), Some(CONTENT_TYPE));
+            }
+            request => panic!("Invalid request {request:?}"),
+        }
+    }
+}
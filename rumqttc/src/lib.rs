@@ -100,16 +100,21 @@
 
 use std::fmt::{self, Debug, Formatter};
 
-#[cfg(any(feature = "use-rustls", feature = "websocket"))]
 use std::sync::Arc;
 
 use std::time::Duration;
 
+use tokio::io::AsyncRead;
+
 mod client;
+mod dns;
+mod env;
 mod eventloop;
 mod framed;
+mod metrics;
 pub mod mqttbytes;
 mod state;
+mod trace;
 pub mod v5;
 
 #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
@@ -118,7 +123,9 @@
 #[cfg(feature = "websocket")]
 mod websockets;
 
-#[cfg(feature = "websocket")]
+#[cfg(feature = "json")]
+mod json;
+
 use std::{
     future::{Future, IntoFuture},
     pin::Pin,
@@ -131,24 +138,40 @@
         + Sync,
 >;
 
+type CredentialsProviderFn =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = (String, String)> + Send>> + Send + Sync>;
+
+type ConnectAttemptFn = Arc<dyn Fn(&str, u32) + Send + Sync>;
+
 #[cfg(feature = "proxy")]
 mod proxy;
 
 pub use client::{
-    AsyncClient, Client, ClientError, Connection, Iter, RecvError, RecvTimeoutError, TryRecvError,
+    AsyncClient, Client, ClientError, Connection, Iter, Permit, RecvError, RecvTimeoutError,
+    TryRecvError,
 };
+pub use env::EnvError;
 pub use eventloop::{ConnectionError, Event, EventLoop};
+pub use framed::{AsyncReadWrite, Network};
+#[cfg(feature = "json")]
+pub use json::DecodeError;
+pub use metrics::{ClientMetrics, PacketCounts};
 pub use mqttbytes::v4::*;
 pub use mqttbytes::*;
 #[cfg(feature = "use-rustls")]
 use rustls_native_certs::load_native_certs;
-pub use state::{MqttState, StateError};
+pub use state::{MqttState, PublishPredicate, PublishSink, StateError};
 #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
 pub use tls::Error as TlsError;
 #[cfg(feature = "use-rustls")]
+pub use tls::SpkiPinningVerifier;
+#[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+pub use tls::TlsInfo;
+#[cfg(feature = "use-rustls")]
 pub use tokio_rustls;
 #[cfg(feature = "use-rustls")]
-use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::rustls::{client::danger::ServerCertVerifier, ClientConfig, RootCertStore};
+pub use trace::{PacketTrace, TracedPacket};
 
 #[cfg(feature = "proxy")]
 pub use proxy::{Proxy, ProxyAuth, ProxyType};
@@ -180,6 +203,8 @@ pub enum Outgoing {
     Disconnect,
     /// Await for an ack for more outgoing progress
     AwaitAck(u16),
+    /// Requests before [`AsyncClient::flush`] were written and flushed
+    Flush,
 }
 
 /// Requests by the client to mqtt event loop. Request are
@@ -198,6 +223,10 @@ pub enum Request {
     Unsubscribe(Unsubscribe),
     UnsubAck(UnsubAck),
     Disconnect(Disconnect),
+    PublishStream(PublishStream),
+    Flush(FlushNotice),
+    PublishEncoded(EncodedPublish),
+    PublishTracked(Publish, DeliveryNotice),
 }
 
 impl From<Publish> for Request {
@@ -206,6 +235,12 @@ fn from(publish: Publish) -> Request {
     }
 }
 
+impl From<EncodedPublish> for Request {
+    fn from(publish: EncodedPublish) -> Request {
+        Request::PublishEncoded(publish)
+    }
+}
+
 impl From<Subscribe> for Request {
     fn from(subscribe: Subscribe) -> Request {
         Request::Subscribe(subscribe)
@@ -218,6 +253,157 @@ fn from(unsubscribe: Unsubscribe) -> Request {
     }
 }
 
+/// QoS 0 publish whose payload is streamed from a reader to the network without
+/// being held in memory. Created by [`AsyncClient::publish_stream`].
+///
+/// As the payload can't be read again, a stream which couldn't be written
+/// is not retransmitted.
+#[derive(Clone)]
+pub struct PublishStream {
+    /// Publish without payload
+    pub publish: Publish,
+    /// Length of the payload in bytes
+    pub len: usize,
+    reader: std::sync::Arc<std::sync::Mutex<Option<Box<dyn AsyncRead + Send + Unpin>>>>,
+}
+
+impl PublishStream {
+    pub fn new<R>(publish: Publish, len: usize, reader: R) -> PublishStream
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+    {
+        PublishStream {
+            publish,
+            len,
+            reader: std::sync::Arc::new(std::sync::Mutex::new(Some(Box::new(reader)))),
+        }
+    }
+
+    /// Takes the reader of payload. Returns `None` if the stream was already written
+    pub(crate) fn take_reader(&self) -> Option<Box<dyn AsyncRead + Send + Unpin>> {
+        self.reader.lock().unwrap().take()
+    }
+}
+
+impl Debug for PublishStream {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("PublishStream")
+            .field("publish", &self.publish)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+// Clones share the same reader
+impl PartialEq for PublishStream {
+    fn eq(&self, other: &Self) -> bool {
+        std::sync::Arc::ptr_eq(&self.reader, &other.reader)
+    }
+}
+
+impl Eq for PublishStream {}
+
+/// Notifies [`AsyncClient::flush`] once the requests sent before it are written and
+/// flushed to the network. Dropping it without notifying fails the flush
+#[derive(Clone)]
+pub struct FlushNotice {
+    tx: flume::Sender<()>,
+}
+
+impl FlushNotice {
+    pub(crate) fn new() -> (FlushNotice, flume::Receiver<()>) {
+        let (tx, rx) = flume::bounded(1);
+        (FlushNotice { tx }, rx)
+    }
+
+    pub(crate) fn notify(&self) {
+        // Client might have stopped waiting
+        self.tx.try_send(()).ok();
+    }
+}
+
+impl Debug for FlushNotice {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("FlushNotice").finish()
+    }
+}
+
+impl PartialEq for FlushNotice {
+    fn eq(&self, other: &Self) -> bool {
+        self.tx.same_channel(&other.tx)
+    }
+}
+
+impl Eq for FlushNotice {}
+
+/// Final status of a publish sent with [`AsyncClient::publish_with_callback`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// QoS 0 publish was written, QoS 1 publish was acked with PUBACK or QoS 2 publish
+    /// was completed with PUBCOMP
+    Delivered,
+    /// Writing the QoS 0 publish failed. It isn't retransmitted
+    Failed,
+    /// Publish was dropped before being delivered, e.g. a QoS 0 publish queued past
+    /// [`MqttOptions::set_max_queued_qos0`] while disconnected, or a publish left over
+    /// when the `EventLoop` and requests taken with [`AsyncClient::drain_pending`] are dropped
+    Dropped,
+}
+
+type DeliveryCallback = Box<dyn FnOnce(DeliveryStatus) + Send>;
+
+/// Calls the callback of a publish with its [`DeliveryStatus`]. Clones share the callback,
+/// which is called only once, with [`DeliveryStatus::Dropped`] if all of them are dropped
+/// before delivery completes
+#[derive(Clone)]
+pub struct DeliveryNotice {
+    callback: Arc<DeliveryCallbackSlot>,
+}
+
+struct DeliveryCallbackSlot(std::sync::Mutex<Option<DeliveryCallback>>);
+
+impl DeliveryNotice {
+    pub fn new<F>(callback: F) -> DeliveryNotice
+    where
+        F: FnOnce(DeliveryStatus) + Send + 'static,
+    {
+        let slot = DeliveryCallbackSlot(std::sync::Mutex::new(Some(Box::new(callback))));
+        DeliveryNotice {
+            callback: Arc::new(slot),
+        }
+    }
+
+    pub(crate) fn notify(&self, status: DeliveryStatus) {
+        // Not called with the lock held, so that the callback can't deadlock on it
+        let callback = self.callback.0.lock().unwrap().take();
+        if let Some(callback) = callback {
+            callback(status);
+        }
+    }
+}
+
+impl Drop for DeliveryCallbackSlot {
+    fn drop(&mut self) {
+        if let Some(callback) = self.0.get_mut().ok().and_then(Option::take) {
+            callback(DeliveryStatus::Dropped);
+        }
+    }
+}
+
+impl Debug for DeliveryNotice {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("DeliveryNotice").finish()
+    }
+}
+
+impl PartialEq for DeliveryNotice {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.callback, &other.callback)
+    }
+}
+
+impl Eq for DeliveryNotice {}
+
 /// Transport methods. Defaults to TCP.
 #[derive(Clone)]
 pub enum Transport {
@@ -272,6 +458,8 @@ pub fn tls_with_config(tls_config: TlsConfiguration) -> Self {
         Self::Tls(tls_config)
     }
 
+    /// Use a unix domain socket as transport, e.g. to a rumqttd server with `unix_socket`
+    /// set. Host of [`MqttOptions`] is the path of the socket and port is ignored
     #[cfg(unix)]
     pub fn unix() -> Self {
         Self::Unix
@@ -327,6 +515,17 @@ pub enum TlsConfiguration {
         /// tls client_authentication
         client_auth: Option<(Vec<u8>, Vec<u8>)>,
     },
+    #[cfg(feature = "use-rustls")]
+    /// Like `Simple`, with the client certificate and key read from files on every
+    /// connection, so that certificates rotated on disk are used from the next reconnection
+    SimpleFiles {
+        /// connection method
+        ca: Vec<u8>,
+        /// alpn settings
+        alpn: Option<Vec<Vec<u8>>>,
+        /// paths of PEM encoded client certificate chain and key
+        client_auth: (std::path::PathBuf, std::path::PathBuf),
+    },
     #[cfg(feature = "use-native-tls")]
     SimpleNative {
         /// ca certificate
@@ -338,6 +537,34 @@ pub enum TlsConfiguration {
     #[cfg(feature = "use-rustls")]
     /// Injected rustls ClientConfig for TLS, to allow more customisation.
     Rustls(Arc<ClientConfig>),
+    #[cfg(feature = "use-rustls")]
+    /// Trusts roots of the operating system's certificate store
+    SystemRoots {
+        /// additional ca certificates to trust along with the system roots
+        ca: Option<Vec<u8>>,
+        /// alpn settings
+        alpn: Option<Vec<Vec<u8>>>,
+        /// tls client_authentication
+        client_auth: Option<(Vec<u8>, Vec<u8>)>,
+    },
+    #[cfg(feature = "use-rustls")]
+    /// Trusts only servers presenting a certificate whose public key hashes to one
+    /// of the SHA-256 `pins`. See [`SpkiPinningVerifier`]
+    Pinned {
+        /// SHA-256 hashes of `SubjectPublicKeyInfo` of expected certificates
+        pins: Vec<[u8; 32]>,
+    },
+    #[cfg(feature = "use-rustls")]
+    /// Custom verification of server certificates in place of a ca, e.g. to pin
+    /// certificates or trust roots which webpki can't parse
+    Verifier {
+        /// verifier installed with rustls' dangerous config api
+        verifier: Arc<dyn ServerCertVerifier>,
+        /// alpn settings
+        alpn: Option<Vec<Vec<u8>>>,
+        /// tls client_authentication
+        client_auth: Option<(Vec<u8>, Vec<u8>)>,
+    },
     #[cfg(feature = "use-native-tls")]
     Native,
 }
@@ -357,6 +584,17 @@ fn default() -> Self {
     }
 }
 
+#[cfg(feature = "use-rustls")]
+impl TlsConfiguration {
+    /// Trusts only servers whose certificates carry a public key with one of the
+    /// SHA-256 `spki_hashes`. See [`TlsConfiguration::Pinned`]
+    pub fn pinned(spki_hashes: impl IntoIterator<Item = [u8; 32]>) -> Self {
+        TlsConfiguration::Pinned {
+            pins: spki_hashes.into_iter().collect(),
+        }
+    }
+}
+
 #[cfg(feature = "use-rustls")]
 impl From<ClientConfig> for TlsConfiguration {
     fn from(config: ClientConfig) -> Self {
@@ -393,6 +631,25 @@ pub fn set_tcp_recv_buffer_size(&mut self, size: u32) {
         self.tcp_recv_buffer_size = Some(size);
     }
 
+    /// Overrides buffer sizes which are set, saturating at `u32::MAX`
+    pub(crate) fn set_socket_buffer_sizes(&mut self, send: Option<usize>, recv: Option<usize>) {
+        let size = |size: usize| u32::try_from(size).unwrap_or(u32::MAX);
+        if let Some(send) = send {
+            self.tcp_send_buffer_size = Some(size(send));
+        }
+
+        if let Some(recv) = recv {
+            self.tcp_recv_buffer_size = Some(size(recv));
+        }
+    }
+
+    pub(crate) fn socket_buffer_sizes(&self) -> (Option<usize>, Option<usize>) {
+        (
+            self.tcp_send_buffer_size.map(|size| size as usize),
+            self.tcp_recv_buffer_size.map(|size| size as usize),
+        )
+    }
+
     /// set connection timeout in secs
     pub fn set_connection_timeout(&mut self, timeout: u64) -> &mut Self {
         self.conn_timeout = timeout;
@@ -416,6 +673,69 @@ pub fn set_bind_device(&mut self, bind_device: &str) -> &mut Self {
     }
 }
 
+/// Behaviour of `EventLoop` once all the clients created with it, and permits
+/// reserved by them, are dropped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClientDropBehavior {
+    /// Keep the connection alive, reading incoming packets and pinging the broker
+    #[default]
+    KeepAlive,
+    /// Send a disconnect to the broker once the last request is sent and inflight publishes
+    /// are acked. Every poll after that returns [`ConnectionError::RequestsDone`]
+    Disconnect,
+}
+
+/// Which QoS 0 publishes are dropped when more than the maximum are queued,
+/// see [`MqttOptions::set_max_queued_qos0`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Qos0DropPolicy {
+    /// Drop publishes queued first, keeping the most recent ones
+    #[default]
+    DropOldest,
+    /// Drop publishes queued last, keeping the earliest ones
+    DropNewest,
+}
+
+/// What is done with requests once [`MqttOptions::set_max_buffered_messages`] requests
+/// are buffered to be sent after a reconnection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BufferOverflow {
+    /// Leave further requests in the request channel, so that publishing waits once
+    /// the channel is full as well
+    #[default]
+    Block,
+    /// Like `Block`, but publishing fails with [`ClientError::BufferFull`] while the
+    /// buffer is full. Publishing while connected isn't refused
+    Error,
+    /// Drop the oldest buffered publishes, counted in [`ClientMetrics::buffer_dropped`]
+    DropOldest,
+}
+
+/// What is done with incoming publishes whose topic doesn't match a filter the client
+/// subscribed to, see [`MqttOptions::set_topic_validation`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TopicValidation {
+    /// Publishes aren't checked
+    #[default]
+    Off,
+    /// Log a warning and surface the publish as usual
+    Warn,
+    /// Log a warning and drop the publish. QoS 1 and 2 publishes are still acked
+    Drop,
+}
+
+/// Order in which brokers of [`MqttOptions::set_broker_list`] are connected to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailoverStrategy {
+    /// Every connection starts with the first broker, the next ones are tried only
+    /// when connecting to the previous one fails
+    #[default]
+    PrimaryWithFallback,
+    /// Moves to the next broker when connecting fails and stays with a broker till
+    /// connecting to it fails
+    RoundRobin,
+}
+
 // TODO: Should all the options be exposed as public? Drawback
 // would be loosing the ability to panic when the user options
 // are wrong (e.g empty client id) or aggressive (keep alive time)
@@ -430,12 +750,54 @@ pub struct MqttOptions {
     transport: Transport,
     /// keep alive time to send pingreq to broker when the connection is idle
     keep_alive: Duration,
+    /// time to wait for pingresp after sending pingreq before considering connection dead
+    pingresp_timeout: Option<Duration>,
+    /// time to wait for the ack of an outgoing publish before resending it, and maximum resends
+    ack_timeout: (Option<Duration>, u16),
+    /// time within which an outgoing write must complete before the connection is considered stuck
+    write_timeout: Option<Duration>,
+    /// send pingreq only when no packet was sent for keep alive time
+    reset_keep_alive_on_outgoing: bool,
+    /// time for which resolved addresses of the broker are reused
+    dns_cache_ttl: Option<Duration>,
+    /// what to do once all the clients are dropped
+    client_drop_behavior: ClientDropBehavior,
+    /// connect only once the first request is made
+    lazy_connect: bool,
+    /// brokers connected to in turn, only `broker_addr` and `port` if empty
+    broker_list: Vec<(String, u16)>,
+    /// order in which `broker_list` is tried
+    failover_strategy: FailoverStrategy,
+    /// consecutive failed connection attempts after which polling gives up
+    max_reconnect_attempts: Option<u32>,
+    /// QoS 0 publishes kept queued while the connection is down, unbounded if not set
+    max_queued_qos0: Option<usize>,
+    /// which QoS 0 publishes are dropped past `max_queued_qos0`
+    qos0_drop_policy: Qos0DropPolicy,
+    /// requests buffered to be sent after a reconnection, unbounded if not set
+    max_buffered_messages: Option<usize>,
+    /// what is done with requests past `max_buffered_messages`
+    buffer_overflow: BufferOverflow,
+    /// number of topics whose latest incoming publish is kept, disabled if not set
+    last_value_cache: Option<usize>,
+    /// number of last packets attached to connection errors, disabled if not set
+    packet_trace: Option<usize>,
+    /// checks incoming publishes against subscribed filters
+    topic_validation: TopicValidation,
+    /// network options the event loop is created with
+    network_options: NetworkOptions,
+    /// protocol level written in CONNECT, MQTT 3.1.1 by default
+    protocol: Protocol,
     /// clean (or) persistent session
     clean_session: bool,
     /// client identifier
     client_id: String,
     /// username and password
     credentials: Option<Login>,
+    /// called for username and password before every connection
+    credentials_provider: Option<CredentialsProviderFn>,
+    /// called with the broker address and failed attempts before every connection attempt
+    on_connect_attempt: Option<ConnectAttemptFn>,
     /// maximum incoming packet size (verifies remaining length of the packet)
     max_incoming_packet_size: usize,
     /// Maximum outgoing packet size (only verifies publish payload size)
@@ -447,6 +809,8 @@ pub struct MqttOptions {
     /// Minimum delay time between consecutive outgoing packets
     /// while retransmitting pending packets
     pending_throttle: Duration,
+    /// Hold back pending packets while the inflight window is full
+    paced_resend: bool,
     /// maximum number of outgoing inflight messages
     inflight: u16,
     /// Last will that will be issued on unexpected disconnect
@@ -454,6 +818,8 @@ pub struct MqttOptions {
     /// If set to `true` MQTT acknowledgements are not sent automatically.
     /// Every incoming publish packet must be manually acknowledged with `client.ack(...)` method.
     manual_acks: bool,
+    /// Incoming QoS 2 publishes awaiting manual acks beyond which network isn't read
+    max_unacked_incoming_qos2: Option<u16>,
     #[cfg(feature = "proxy")]
     /// Proxy configuration.
     proxy: Option<Proxy>,
@@ -477,17 +843,40 @@ pub fn new<S: Into<String>, T: Into<String>>(id: S, host: T, port: u16) -> MqttO
             port,
             transport: Transport::tcp(),
             keep_alive: Duration::from_secs(60),
+            pingresp_timeout: None,
+            ack_timeout: (None, 0),
+            write_timeout: None,
+            reset_keep_alive_on_outgoing: false,
+            dns_cache_ttl: None,
+            client_drop_behavior: ClientDropBehavior::KeepAlive,
+            lazy_connect: false,
+            broker_list: Vec::new(),
+            failover_strategy: FailoverStrategy::PrimaryWithFallback,
+            max_reconnect_attempts: None,
+            max_queued_qos0: None,
+            qos0_drop_policy: Qos0DropPolicy::DropOldest,
+            max_buffered_messages: None,
+            buffer_overflow: BufferOverflow::Block,
+            last_value_cache: None,
+            packet_trace: None,
+            topic_validation: TopicValidation::Off,
+            network_options: NetworkOptions::new(),
+            protocol: Protocol::V4,
             clean_session: true,
             client_id: id.into(),
             credentials: None,
+            credentials_provider: None,
+            on_connect_attempt: None,
             max_incoming_packet_size: 10 * 1024,
             max_outgoing_packet_size: 10 * 1024,
             request_channel_capacity: 10,
             max_request_batch: 0,
             pending_throttle: Duration::from_micros(0),
+            paced_resend: false,
             inflight: 100,
             last_will: None,
             manual_acks: false,
+            max_unacked_incoming_qos2: None,
             #[cfg(feature = "proxy")]
             proxy: None,
             #[cfg(feature = "websocket")]
@@ -533,6 +922,8 @@ pub fn broker_address(&self) -> (String, u16) {
         (self.broker_addr.clone(), self.port)
     }
 
+    /// Will sent in CONNECT. Changes to the options of a running `EventLoop` only take
+    /// effect from the next connection attempt. See [`AsyncClient::set_last_will`]
     pub fn set_last_will(&mut self, will: LastWill) -> &mut Self {
         self.last_will = Some(will);
         self
@@ -553,6 +944,16 @@ pub fn transport(&self) -> Transport {
 
     /// Set number of seconds after which client should ping the broker
     /// if there is no other data exchange
+    ///
+    /// Brokers may cap the keep alive, 30 to 300 seconds is accepted by most of them.
+    /// MQTT 3.1.1 has no way for the broker to say so: a broker enforcing a shorter
+    /// keep alive closes connections which are idle for longer, which shows up as
+    /// `ConnectionError::MqttState(StateError::ConnectionAborted)` between pings.
+    /// Lower the keep alive when that happens on an otherwise idle connection.
+    ///
+    /// `Duration::ZERO` disables keep alive: no PINGREQ is sent, and the broker doesn't
+    /// expect any. A dead connection is then only detected by TCP, i.e. when a write fails
+    /// or the OS gives up on the socket
     pub fn set_keep_alive(&mut self, duration: Duration) -> &mut Self {
         assert!(
             duration.is_zero() || duration >= Duration::from_secs(1),
@@ -569,6 +970,258 @@ pub fn keep_alive(&self) -> Duration {
         self.keep_alive
     }
 
+    /// Set duration within which PINGRESP must be received after sending a PINGREQ.
+    /// Connection is considered dead if it isn't, instead of waiting for next keep alive
+    pub fn set_pingresp_timeout(&mut self, duration: Duration) -> &mut Self {
+        self.pingresp_timeout = Some(duration);
+        self
+    }
+
+    /// PINGRESP timeout
+    pub fn pingresp_timeout(&self) -> Option<Duration> {
+        self.pingresp_timeout
+    }
+
+    /// Resends outgoing QoS 1 and 2 publishes with DUP set when their PUBACK (or PUBREC)
+    /// isn't received within `timeout` of sending them, for brokers which silently drop
+    /// acks. Once a publish is resent `max_retries` times without an ack, polling fails
+    /// with [`StateError::AckTimeout`] and the next poll reconnects, resending it again.
+    /// Defaults to `None`, which waits for acks for as long as the connection is up
+    pub fn set_ack_timeout(&mut self, timeout: Option<Duration>, max_retries: u16) -> &mut Self {
+        self.ack_timeout = (timeout, max_retries);
+        self
+    }
+
+    /// Time to wait for acks of outgoing publishes before resending them
+    pub fn ack_timeout(&self) -> Option<Duration> {
+        self.ack_timeout.0
+    }
+
+    /// Maximum number of resends of an unacked publish
+    pub fn ack_timeout_retries(&self) -> u16 {
+        self.ack_timeout.1
+    }
+
+    /// Set duration within which writing (and flushing) outgoing packets to the network must
+    /// complete. Polling fails with [`ConnectionError::WriteTimeout`] if it doesn't, e.g when
+    /// the broker stops reading and socket buffers are full, so that the next poll reconnects.
+    /// Publishes streamed with `publish_stream` must be written in time too, including reads
+    /// of their payload. Without it, only flushing is bounded by the network connection
+    /// timeout and streamed publishes aren't bounded
+    pub fn set_write_timeout(&mut self, duration: Duration) -> &mut Self {
+        self.write_timeout = Some(duration);
+        self
+    }
+
+    /// Write timeout
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// Resets keep alive timer on every outgoing packet, so that PINGREQ is only sent when
+    /// nothing was sent to the broker for keep alive time. By default, pings are sent every
+    /// keep alive time irrespective of other outgoing packets
+    pub fn set_reset_keep_alive_on_outgoing(&mut self, reset: bool) -> &mut Self {
+        self.reset_keep_alive_on_outgoing = reset;
+        self
+    }
+
+    /// Whether keep alive timer is reset on outgoing packets
+    pub fn reset_keep_alive_on_outgoing(&self) -> bool {
+        self.reset_keep_alive_on_outgoing
+    }
+
+    /// Sets what `EventLoop` does once all the clients are dropped and no more
+    /// requests can arrive. Defaults to [`ClientDropBehavior::KeepAlive`]
+    pub fn set_client_drop_behavior(&mut self, behavior: ClientDropBehavior) -> &mut Self {
+        self.client_drop_behavior = behavior;
+        self
+    }
+
+    /// What `EventLoop` does once all the clients are dropped
+    pub fn client_drop_behavior(&self) -> ClientDropBehavior {
+        self.client_drop_behavior
+    }
+
+    /// Defers the first connection till a request is made with a client, for clients used
+    /// intermittently which shouldn't hold a connection until then. Polls wait for the
+    /// request without connecting. Reconnects after that aren't deferred. Defaults to `false`
+    pub fn set_lazy_connect(&mut self, lazy: bool) -> &mut Self {
+        self.lazy_connect = lazy;
+        self
+    }
+
+    /// Whether the first connection waits for a request
+    pub fn lazy_connect(&self) -> bool {
+        self.lazy_connect
+    }
+
+    /// Sets hosts and ports of brokers to fail over between, e.g. members of a cluster. A
+    /// connection attempt is made to one broker at a time, moving to the next one when it
+    /// fails as per [`set_failover_strategy`](Self::set_failover_strategy). With TLS, each
+    /// broker is verified against its own host.
+    ///
+    /// [`broker_address`](Self::broker_address) is the broker of the last connection
+    /// attempt. An empty list only connects to the address the options were created with
+    pub fn set_broker_list(&mut self, brokers: Vec<(String, u16)>) -> &mut Self {
+        if let Some((host, port)) = brokers.first() {
+            self.broker_addr = host.clone();
+            self.port = *port;
+        }
+
+        self.broker_list = brokers;
+        self
+    }
+
+    /// Brokers to fail over between
+    pub fn broker_list(&self) -> &[(String, u16)] {
+        &self.broker_list
+    }
+
+    /// Sets order in which brokers of [`set_broker_list`](Self::set_broker_list) are
+    /// connected to. Defaults to [`FailoverStrategy::PrimaryWithFallback`]
+    pub fn set_failover_strategy(&mut self, strategy: FailoverStrategy) -> &mut Self {
+        self.failover_strategy = strategy;
+        self
+    }
+
+    /// Order in which brokers are connected to
+    pub fn failover_strategy(&self) -> FailoverStrategy {
+        self.failover_strategy
+    }
+
+    /// Sets number of consecutive failed connection attempts after which every poll
+    /// returns [`ConnectionError::GaveUp`] instead of connecting again. Defaults to
+    /// `None`, which retries forever
+    pub fn set_max_reconnect_attempts(&mut self, attempts: Option<u32>) -> &mut Self {
+        self.max_reconnect_attempts = attempts;
+        self
+    }
+
+    /// Consecutive failed connection attempts after which polling gives up
+    pub fn max_reconnect_attempts(&self) -> Option<u32> {
+        self.max_reconnect_attempts
+    }
+
+    /// Sets maximum number of QoS 0 publishes queued to be sent after a reconnection, i.e
+    /// publishes which couldn't be written before the connection went down. Publishes past
+    /// `max` are dropped as per `policy` and counted in [`ClientMetrics::qos0_dropped`].
+    /// Defaults to `None`, which keeps all of them. QoS 1 and 2 publishes are never dropped
+    pub fn set_max_queued_qos0(&mut self, max: Option<usize>, policy: Qos0DropPolicy) -> &mut Self {
+        self.max_queued_qos0 = max;
+        self.qos0_drop_policy = policy;
+        self
+    }
+
+    /// Maximum number of queued QoS 0 publishes
+    pub fn max_queued_qos0(&self) -> Option<usize> {
+        self.max_queued_qos0
+    }
+
+    /// Which QoS 0 publishes are dropped past the maximum
+    pub fn qos0_drop_policy(&self) -> Qos0DropPolicy {
+        self.qos0_drop_policy
+    }
+
+    /// Sets maximum number of requests buffered by the `EventLoop` to be sent after a
+    /// reconnection, i.e. unacked publishes, requests which couldn't be written and
+    /// requests drained from the request channel when the connection went down. Requests
+    /// past `max` are handled as per `overflow`, bounding memory used while disconnected
+    /// to `max` requests and the capacity of the request channel. Defaults to `None`, which
+    /// buffers all of them.
+    ///
+    /// Requests currently buffered are in [`ClientMetrics::buffered`]
+    pub fn set_max_buffered_messages(
+        &mut self,
+        max: Option<usize>,
+        overflow: BufferOverflow,
+    ) -> &mut Self {
+        self.max_buffered_messages = max;
+        self.buffer_overflow = overflow;
+        self
+    }
+
+    /// Maximum number of buffered requests
+    pub fn max_buffered_messages(&self) -> Option<usize> {
+        self.max_buffered_messages
+    }
+
+    /// What is done with requests past the maximum
+    pub fn buffer_overflow(&self) -> BufferOverflow {
+        self.buffer_overflow
+    }
+
+    /// Keeps the latest incoming publish of up to `capacity` topics, returned by
+    /// [`AsyncClient::last_value`]. The least recently updated or read topic is evicted
+    /// when the cache is full. Defaults to `None`, which disables the cache
+    pub fn set_last_value_cache(&mut self, capacity: Option<usize>) -> &mut Self {
+        self.last_value_cache = capacity;
+        self
+    }
+
+    /// Number of topics kept in the last value cache
+    pub fn last_value_cache(&self) -> Option<usize> {
+        self.last_value_cache
+    }
+
+    /// Keeps the last `capacity` packets polled from the `EventLoop`, i.e. its events, and
+    /// attaches them to connection errors as [`ConnectionError::Traced`] to diagnose what led
+    /// to a disconnection. [`ConnectionError::RequestsDone`] isn't traced. Defaults to `None`,
+    /// which disables tracing and keeps errors as they are
+    pub fn set_packet_trace(&mut self, capacity: Option<usize>) -> &mut Self {
+        self.packet_trace = capacity;
+        self
+    }
+
+    /// Number of packets attached to connection errors
+    pub fn packet_trace(&self) -> Option<usize> {
+        self.packet_trace
+    }
+
+    /// Checks the topic of incoming publishes against filters subscribed with this client,
+    /// to catch brokers routing publishes to the wrong clients. Filters are active from
+    /// the time subscribe is sent till unsubscribe is acked. Publishes of subscriptions
+    /// made by earlier processes with a persistent session are mismatches too.
+    /// Defaults to [`TopicValidation::Off`]
+    pub fn set_topic_validation(&mut self, validation: TopicValidation) -> &mut Self {
+        self.topic_validation = validation;
+        self
+    }
+
+    /// What is done with incoming publishes not matching subscribed filters
+    pub fn topic_validation(&self) -> TopicValidation {
+        self.topic_validation
+    }
+
+    /// Sets protocol version spoken with the broker, [`Protocol::V3`] to connect to brokers
+    /// which only support MQTT 3.1. Apart from CONNECT, packets are the same as 3.1.1, but
+    /// 3.1 brokers reject client ids which are empty or longer than 23 characters.
+    /// Use [`v5`] client for MQTT 5
+    ///
+    /// # Panics
+    ///
+    /// Panics when `protocol` is [`Protocol::V5`]
+    ///
+    /// ```should_panic
+    /// # use rumqttc::{MqttOptions, Protocol};
+    /// let mut options = MqttOptions::new("123", "localhost", 1883);
+    /// options.set_protocol_version(Protocol::V5);
+    /// ```
+    pub fn set_protocol_version(&mut self, protocol: Protocol) -> &mut Self {
+        assert!(
+            protocol != Protocol::V5,
+            "MQTT 5 is supported by the v5 client, not with a protocol version"
+        );
+
+        self.protocol = protocol;
+        self
+    }
+
+    /// Protocol version spoken with the broker
+    pub fn protocol_version(&self) -> Protocol {
+        self.protocol
+    }
+
     /// Client identifier
     pub fn client_id(&self) -> String {
         self.client_id.clone()
@@ -631,6 +1284,45 @@ pub fn credentials(&self) -> Option<Login> {
         self.credentials.clone()
     }
 
+    /// Sets a provider of username and password which `EventLoop` awaits right before
+    /// every CONNECT, e.g. to send a fresh token on each reconnection. Takes precedence
+    /// over [`set_credentials`](MqttOptions::set_credentials)
+    pub fn set_credentials_provider<F, O>(&mut self, provider: F) -> &mut Self
+    where
+        F: Fn() -> O + Send + Sync + 'static,
+        O: IntoFuture<Output = (String, String)> + 'static,
+        O::IntoFuture: Send,
+    {
+        self.credentials_provider = Some(Arc::new(move || {
+            let credentials = provider().into_future();
+            Box::pin(credentials)
+        }));
+
+        self
+    }
+
+    /// Provider of username and password called before every CONNECT
+    pub fn credentials_provider(&self) -> Option<CredentialsProviderFn> {
+        self.credentials_provider.clone()
+    }
+
+    /// Sets a callback which `EventLoop` calls before every connection attempt, e.g. to
+    /// trace attempts along with DNS or socket issues. It's called with the address
+    /// connected to, `host:port`, the websocket url or the unix socket path, and the
+    /// number of attempts which failed since the last successful connection
+    pub fn set_on_connect_attempt<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: Fn(&str, u32) + Send + Sync + 'static,
+    {
+        self.on_connect_attempt = Some(Arc::new(callback));
+        self
+    }
+
+    /// Callback called before every connection attempt
+    pub fn on_connect_attempt(&self) -> Option<ConnectAttemptFn> {
+        self.on_connect_attempt.clone()
+    }
+
     /// Set request channel capacity
     pub fn set_request_channel_capacity(&mut self, capacity: usize) -> &mut Self {
         self.request_channel_capacity = capacity;
@@ -653,6 +1345,64 @@ pub fn pending_throttle(&self) -> Duration {
         self.pending_throttle
     }
 
+    /// Pending packets, e.g. unacked publishes republished after a reconnection and requests
+    /// queued while disconnected, are written irrespective of the inflight window by default.
+    /// When set, they are held back while the window is full and written as acks free it, so
+    /// that a large backlog doesn't flood the broker after a reconnection. Combine with
+    /// [`set_pending_throttle`](Self::set_pending_throttle) to also limit their rate
+    pub fn set_paced_resend(&mut self, paced: bool) -> &mut Self {
+        self.paced_resend = paced;
+        self
+    }
+
+    /// Whether pending packets wait for the inflight window
+    pub fn paced_resend(&self) -> bool {
+        self.paced_resend
+    }
+
+    /// Cache resolved addresses of the broker for `ttl` instead of resolving them on every
+    /// (re)connection. All the addresses are tried in order and cache is cleared when none
+    /// of them can be connected to
+    pub fn set_dns_cache_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.dns_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// DNS cache ttl
+    pub fn dns_cache_ttl(&self) -> Option<Duration> {
+        self.dns_cache_ttl
+    }
+
+    /// Sets send (SO_SNDBUF) and receive (SO_RCVBUF) buffer sizes of the TCP socket,
+    /// which is also the one under TLS and websockets, in the [`NetworkOptions`] the
+    /// event loop is created with. Larger buffers help bulk transfers over links with
+    /// high latency. Buffers are set before connecting, so that TCP window scaling is
+    /// negotiated for them. Sizes which aren't set keep their value in the network
+    /// options, OS defaults unless set there. [`EventLoop::set_network_options`]
+    /// replaces them.
+    ///
+    /// **NOTE:** Sizes are hints. Linux doubles the requested size for bookkeeping and
+    /// clamps it to `net.core.wmem_max` and `net.core.rmem_max`, while macOS and
+    /// Windows have limits of their own, all without reporting an error
+    pub fn set_socket_buffer_sizes(
+        &mut self,
+        send: Option<usize>,
+        recv: Option<usize>,
+    ) -> &mut Self {
+        self.network_options.set_socket_buffer_sizes(send, recv);
+        self
+    }
+
+    /// Send and receive buffer sizes of the socket
+    pub fn socket_buffer_sizes(&self) -> (Option<usize>, Option<usize>) {
+        self.network_options.socket_buffer_sizes()
+    }
+
+    /// Network options the event loop is created with
+    pub(crate) fn network_options(&self) -> NetworkOptions {
+        self.network_options.clone()
+    }
+
     /// Set number of concurrent in flight messages
     pub fn set_inflight(&mut self, inflight: u16) -> &mut Self {
         assert!(inflight != 0, "zero in flight is not allowed");
@@ -666,7 +1416,9 @@ pub fn inflight(&self) -> u16 {
         self.inflight
     }
 
-    /// set manual acknowledgements
+    /// set manual acknowledgements. Incoming QoS 1, 2 publishes are then acked only
+    /// with [`AsyncClient::ack`]. Acks of publishes received before a reconnection
+    /// which didn't resume the session are dropped, as are repeated acks
     pub fn set_manual_acks(&mut self, manual_acks: bool) -> &mut Self {
         self.manual_acks = manual_acks;
         self
@@ -677,6 +1429,25 @@ pub fn manual_acks(&self) -> bool {
         self.manual_acks
     }
 
+    /// With manual acks, stop reading from the network while this many incoming QoS 2
+    /// publishes await [`AsyncClient::ack`], and resume once some are acked. This bounds
+    /// publishes held by the application when the broker floods QoS 2 publishes. Publishes
+    /// already read in a batch are still handled, so up to a batch more can be pending.
+    ///
+    /// Nothing is read while paused, pings included, so acks shouldn't be held back for
+    /// longer than keep alive. Without manual acks, PUBRECs are sent as publishes are
+    /// read and the broker's PUBRELs have to be read to complete them, so reading is
+    /// never paused
+    pub fn set_max_unacked_incoming_qos2(&mut self, max: Option<u16>) -> &mut Self {
+        self.max_unacked_incoming_qos2 = max;
+        self
+    }
+
+    /// get maximum incoming QoS 2 publishes awaiting manual acks
+    pub fn max_unacked_incoming_qos2(&self) -> Option<u16> {
+        self.max_unacked_incoming_qos2
+    }
+
     #[cfg(feature = "proxy")]
     pub fn set_proxy(&mut self, proxy: Proxy) -> &mut Self {
         self.proxy = Some(proxy);
@@ -882,6 +1653,28 @@ fn fmt(&self, f: &mut Formatter) -> fmt::Result {
             .field("broker_addr", &self.broker_addr)
             .field("port", &self.port)
             .field("keep_alive", &self.keep_alive)
+            .field("pingresp_timeout", &self.pingresp_timeout)
+            .field("ack_timeout", &self.ack_timeout)
+            .field("write_timeout", &self.write_timeout)
+            .field(
+                "reset_keep_alive_on_outgoing",
+                &self.reset_keep_alive_on_outgoing,
+            )
+            .field("dns_cache_ttl", &self.dns_cache_ttl)
+            .field("client_drop_behavior", &self.client_drop_behavior)
+            .field("lazy_connect", &self.lazy_connect)
+            .field("broker_list", &self.broker_list)
+            .field("failover_strategy", &self.failover_strategy)
+            .field("max_reconnect_attempts", &self.max_reconnect_attempts)
+            .field("max_queued_qos0", &self.max_queued_qos0)
+            .field("max_buffered_messages", &self.max_buffered_messages)
+            .field("buffer_overflow", &self.buffer_overflow)
+            .field("qos0_drop_policy", &self.qos0_drop_policy)
+            .field("last_value_cache", &self.last_value_cache)
+            .field("packet_trace", &self.packet_trace)
+            .field("topic_validation", &self.topic_validation)
+            .field("socket_buffer_sizes", &self.socket_buffer_sizes())
+            .field("protocol", &self.protocol)
             .field("clean_session", &self.clean_session)
             .field("client_id", &self.client_id)
             .field("credentials", &self.credentials)
@@ -889,9 +1682,11 @@ fn fmt(&self, f: &mut Formatter) -> fmt::Result {
             .field("request_channel_capacity", &self.request_channel_capacity)
             .field("max_request_batch", &self.max_request_batch)
             .field("pending_throttle", &self.pending_throttle)
+            .field("paced_resend", &self.paced_resend)
             .field("inflight", &self.inflight)
             .field("last_will", &self.last_will)
             .field("manual_acks", &self.manual_acks)
+            .field("max_unacked_incoming_qos2", &self.max_unacked_incoming_qos2)
             .finish()
     }
 }
@@ -994,4 +1789,26 @@ fn set_clean_session_when_client_id_present() {
         options.set_clean_session(false);
         options.set_clean_session(true);
     }
+
+    #[test]
+    fn socket_buffer_sizes_override_network_options() {
+        let mut network_options = NetworkOptions::new();
+        network_options.set_tcp_send_buffer_size(1024);
+        network_options.set_tcp_recv_buffer_size(2048);
+
+        network_options.set_socket_buffer_sizes(Some(usize::MAX), None);
+        assert_eq!(network_options.tcp_send_buffer_size, Some(u32::MAX));
+        assert_eq!(network_options.tcp_recv_buffer_size, Some(2048));
+    }
+
+    #[test]
+    fn socket_buffer_sizes_are_in_network_options_of_eventloop() {
+        let mut options = MqttOptions::new("client_id", "127.0.0.1", 1883);
+        options.set_socket_buffer_sizes(Some(1024), None);
+        assert_eq!(options.socket_buffer_sizes(), (Some(1024), None));
+
+        let network_options = EventLoop::new(options, 10).network_options();
+        assert_eq!(network_options.tcp_send_buffer_size, Some(1024));
+        assert_eq!(network_options.tcp_recv_buffer_size, None);
+    }
 }
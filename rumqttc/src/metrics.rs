@@ -0,0 +1,132 @@
+use std::time::SystemTime;
+
+use crate::mqttbytes::v4::Packet;
+
+/// Cumulative metrics of all the connections of an `EventLoop`.
+/// Snapshot is returned by [`EventLoop::metrics`](crate::EventLoop::metrics)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientMetrics {
+    /// Packets written to network
+    pub packets_sent: PacketCounts,
+    /// Packets read from network
+    pub packets_received: PacketCounts,
+    /// Serialized size of packets written to network
+    pub bytes_sent: u64,
+    /// Serialized size of packets read from network
+    pub bytes_received: u64,
+    /// Successful connections after the first one
+    pub reconnects: u64,
+    /// Queued QoS 0 publishes dropped past `MqttOptions::set_max_queued_qos0`
+    pub qos0_dropped: u64,
+    /// Buffered publishes dropped past `MqttOptions::set_max_buffered_messages`
+    pub buffer_dropped: u64,
+    /// Requests waiting to be sent when the snapshot was taken, i.e. the ones buffered
+    /// by the `EventLoop` and queued in request channels
+    pub buffered: usize,
+    /// Time at which `EventLoop::poll` last returned an error
+    pub last_error: Option<SystemTime>,
+}
+
+impl ClientMetrics {
+    pub(crate) fn sent(&mut self, packet: &Packet) {
+        self.packets_sent.increment(packet);
+        self.bytes_sent += packet.size() as u64;
+    }
+
+    /// Counts a publish which was encoded beforehand
+    pub(crate) fn sent_encoded(&mut self, size: usize) {
+        self.packets_sent.publish += 1;
+        self.bytes_sent += size as u64;
+    }
+
+    pub(crate) fn received(&mut self, packet: &Packet) {
+        self.packets_received.increment(packet);
+        self.bytes_received += packet.size() as u64;
+    }
+
+    /// Adds counters of `other`. Reconnects, dropped publishes, buffered requests and last
+    /// error aren't tracked per connection
+    pub(crate) fn merge(&mut self, other: &ClientMetrics) {
+        self.packets_sent.merge(&other.packets_sent);
+        self.packets_received.merge(&other.packets_received);
+        self.bytes_sent += other.bytes_sent;
+        self.bytes_received += other.bytes_received;
+    }
+}
+
+/// Number of packets of each type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketCounts {
+    pub connect: u64,
+    pub connack: u64,
+    pub publish: u64,
+    pub puback: u64,
+    pub pubrec: u64,
+    pub pubrel: u64,
+    pub pubcomp: u64,
+    pub subscribe: u64,
+    pub suback: u64,
+    pub unsubscribe: u64,
+    pub unsuback: u64,
+    pub pingreq: u64,
+    pub pingresp: u64,
+    pub disconnect: u64,
+}
+
+impl PacketCounts {
+    /// Number of packets of all types
+    pub fn total(&self) -> u64 {
+        self.connect
+            + self.connack
+            + self.publish
+            + self.puback
+            + self.pubrec
+            + self.pubrel
+            + self.pubcomp
+            + self.subscribe
+            + self.suback
+            + self.unsubscribe
+            + self.unsuback
+            + self.pingreq
+            + self.pingresp
+            + self.disconnect
+    }
+
+    fn increment(&mut self, packet: &Packet) {
+        let count = match packet {
+            Packet::Connect(_) => &mut self.connect,
+            Packet::ConnAck(_) => &mut self.connack,
+            Packet::Publish(_) => &mut self.publish,
+            Packet::PubAck(_) => &mut self.puback,
+            Packet::PubRec(_) => &mut self.pubrec,
+            Packet::PubRel(_) => &mut self.pubrel,
+            Packet::PubComp(_) => &mut self.pubcomp,
+            Packet::Subscribe(_) => &mut self.subscribe,
+            Packet::SubAck(_) => &mut self.suback,
+            Packet::Unsubscribe(_) => &mut self.unsubscribe,
+            Packet::UnsubAck(_) => &mut self.unsuback,
+            Packet::PingReq => &mut self.pingreq,
+            Packet::PingResp => &mut self.pingresp,
+            Packet::Disconnect => &mut self.disconnect,
+        };
+
+        *count += 1;
+    }
+
+    fn merge(&mut self, other: &PacketCounts) {
+        self.connect += other.connect;
+        self.connack += other.connack;
+        self.publish += other.publish;
+        self.puback += other.puback;
+        self.pubrec += other.pubrec;
+        self.pubrel += other.pubrel;
+        self.pubcomp += other.pubcomp;
+        self.subscribe += other.subscribe;
+        self.suback += other.suback;
+        self.unsubscribe += other.unsubscribe;
+        self.unsuback += other.unsuback;
+        self.pingreq += other.pingreq;
+        self.pingresp += other.pingresp;
+        self.disconnect += other.disconnect;
+    }
+}
@@ -64,6 +64,16 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error("Cannot send packet of size '{pkt_size:?}'. It's greater than the broker's maximum packet size of: '{max:?}'")]
     OutgoingPacketTooLarge { pkt_size: usize, max: usize },
+    /// Framed packet couldn't be decoded. Carries the first byte of the packet, the
+    /// remaining length it claimed and the offset from start of the packet at which
+    /// decoding failed
+    #[error("Failed to decode packet with type byte {packet_type:#04x} and remaining length {remaining_len} at offset {offset}: {source}")]
+    Decode {
+        packet_type: u8,
+        remaining_len: usize,
+        offset: usize,
+        source: Box<Error>,
+    },
 }
 
 /// MQTT packet type
@@ -89,6 +99,9 @@ pub enum PacketType {
 /// Protocol type
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Protocol {
+    /// MQTT 3.1, protocol level 3 named "MQIsdp"
+    V3,
+    /// MQTT 3.1.1
     V4,
     V5,
 }
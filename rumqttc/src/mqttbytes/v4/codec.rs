@@ -42,7 +42,7 @@ fn encode(&mut self, item: Packet, dst: &mut BytesMut) -> Result<(), Self::Error
 #[cfg(test)]
 mod tests {
     use bytes::BytesMut;
-    use tokio_util::codec::Encoder;
+    use tokio_util::codec::{Decoder, Encoder};
 
     use super::Codec;
     use crate::{mqttbytes::Error, Packet, Publish, QoS};
@@ -70,4 +70,47 @@ fn outgoing_max_packet_size_check() {
             _ => unreachable!(),
         }
     }
+
+    fn decode_error(stream: &[u8]) -> (u8, usize, usize, Error) {
+        let mut codec = Codec {
+            max_incoming_size: 100,
+            max_outgoing_size: 100,
+        };
+
+        let mut buf = BytesMut::from(stream);
+        match codec.decode(&mut buf) {
+            Err(Error::Decode {
+                packet_type,
+                remaining_len,
+                offset,
+                source,
+            }) => (packet_type, remaining_len, offset, *source),
+            v => panic!("Expected decode error, got {v:?}"),
+        }
+    }
+
+    #[test]
+    fn malformed_packets_report_failure_location() {
+        // publish with qos 3 fails on the first byte
+        let (packet_type, remaining_len, offset, e) =
+            decode_error(&[0x36, 0x05, 0x00, 0x03, b'a', b'/', b'b']);
+        assert_eq!((packet_type, remaining_len, offset), (0x36, 5, 0));
+        assert!(matches!(e, Error::InvalidQoS(3)));
+
+        // publish with topic length beyond remaining length fails after topic length
+        let (packet_type, remaining_len, offset, e) =
+            decode_error(&[0x30, 0x04, 0x00, 0x05, b'a', b'b']);
+        assert_eq!((packet_type, remaining_len, offset), (0x30, 4, 4));
+        assert!(matches!(e, Error::BoundaryCrossed(5)));
+
+        // connack with invalid return code fails after the return code
+        let (packet_type, remaining_len, offset, e) = decode_error(&[0x20, 0x02, 0x00, 0x09]);
+        assert_eq!((packet_type, remaining_len, offset), (0x20, 2, 4));
+        assert!(matches!(e, Error::InvalidConnectReturnCode(9)));
+
+        // puback without packet id
+        let (packet_type, remaining_len, offset, e) = decode_error(&[0x40, 0x01, 0x00]);
+        assert_eq!((packet_type, remaining_len, offset), (0x40, 1, 2));
+        assert!(matches!(e, Error::MalformedPacket));
+    }
 }
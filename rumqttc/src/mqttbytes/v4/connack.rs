@@ -34,12 +34,12 @@ fn len(&self) -> usize {
         1 + 1
     }
 
-    pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<Self, Error> {
+    pub fn read(fixed_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, Error> {
         let variable_header_index = fixed_header.fixed_header_len;
         bytes.advance(variable_header_index);
 
-        let flags = read_u8(&mut bytes)?;
-        let return_code = read_u8(&mut bytes)?;
+        let flags = read_u8(bytes)?;
+        let return_code = read_u8(bytes)?;
 
         let session_present = (flags & 0x01) == 1;
         let code = connect_return(return_code)?;
@@ -105,8 +105,8 @@ fn connack_parsing_works() {
 
         stream.extend_from_slice(&packetstream[..]);
         let fixed_header = parse_fixed_header(stream.iter()).unwrap();
-        let connack_bytes = stream.split_to(fixed_header.frame_length()).freeze();
-        let connack = ConnAck::read(fixed_header, connack_bytes).unwrap();
+        let mut connack_bytes = stream.split_to(fixed_header.frame_length()).freeze();
+        let connack = ConnAck::read(fixed_header, &mut connack_bytes).unwrap();
 
         assert_eq!(
             connack,
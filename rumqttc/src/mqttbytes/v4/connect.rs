@@ -40,8 +40,16 @@ pub fn set_login<U: Into<String>, P: Into<String>>(&mut self, u: U, p: P) -> &mu
         self
     }
 
+    /// Protocol name, which MQTT 3.1 names differently
+    fn protocol_name(&self) -> &'static str {
+        match self.protocol {
+            Protocol::V3 => "MQIsdp",
+            Protocol::V4 | Protocol::V5 => "MQTT",
+        }
+    }
+
     fn len(&self) -> usize {
-        let mut len = 2 + "MQTT".len() // protocol name
+        let mut len = 2 + self.protocol_name().len() // protocol name
                               + 1            // protocol version
                               + 1            // connect flags
                               + 2; // keep alive
@@ -61,30 +69,28 @@ fn len(&self) -> usize {
         len
     }
 
-    pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<Connect, Error> {
+    pub fn read(fixed_header: FixedHeader, bytes: &mut Bytes) -> Result<Connect, Error> {
         let variable_header_index = fixed_header.fixed_header_len;
         bytes.advance(variable_header_index);
 
         // Variable header
-        let protocol_name = read_mqtt_string(&mut bytes)?;
-        let protocol_level = read_u8(&mut bytes)?;
-        if protocol_name != "MQTT" {
-            return Err(Error::InvalidProtocol);
-        }
-
-        let protocol = match protocol_level {
-            4 => Protocol::V4,
-            5 => Protocol::V5,
-            num => return Err(Error::InvalidProtocolLevel(num)),
+        let protocol_name = read_mqtt_string(bytes)?;
+        let protocol_level = read_u8(bytes)?;
+        let protocol = match (protocol_name.as_str(), protocol_level) {
+            ("MQIsdp", 3) => Protocol::V3,
+            ("MQTT", 4) => Protocol::V4,
+            ("MQTT", 5) => Protocol::V5,
+            ("MQIsdp", num) | ("MQTT", num) => return Err(Error::InvalidProtocolLevel(num)),
+            _ => return Err(Error::InvalidProtocol),
         };
 
-        let connect_flags = read_u8(&mut bytes)?;
+        let connect_flags = read_u8(bytes)?;
         let clean_session = (connect_flags & 0b10) != 0;
-        let keep_alive = read_u16(&mut bytes)?;
+        let keep_alive = read_u16(bytes)?;
 
-        let client_id = read_mqtt_string(&mut bytes)?;
-        let last_will = LastWill::read(connect_flags, &mut bytes)?;
-        let login = Login::read(connect_flags, &mut bytes)?;
+        let client_id = read_mqtt_string(bytes)?;
+        let last_will = LastWill::read(connect_flags, bytes)?;
+        let login = Login::read(connect_flags, bytes)?;
 
         let connect = Connect {
             protocol,
@@ -102,14 +108,16 @@ pub fn write(&self, buffer: &mut BytesMut) -> Result<usize, Error> {
         let len = self.len();
         buffer.put_u8(0b0001_0000);
         let count = write_remaining_length(buffer, len)?;
-        write_mqtt_string(buffer, "MQTT");
+        let protocol_name = self.protocol_name();
+        write_mqtt_string(buffer, protocol_name);
 
         match self.protocol {
+            Protocol::V3 => buffer.put_u8(0x03),
             Protocol::V4 => buffer.put_u8(0x04),
             Protocol::V5 => buffer.put_u8(0x05),
         }
 
-        let flags_index = 1 + count + 2 + 4 + 1;
+        let flags_index = 1 + count + 2 + protocol_name.len() + 1;
 
         let mut connect_flags = 0;
         if self.clean_session {
@@ -332,8 +340,8 @@ fn connect_parsing_works() {
 
         stream.extend_from_slice(&packetstream[..]);
         let fixed_header = parse_fixed_header(stream.iter()).unwrap();
-        let connect_bytes = stream.split_to(fixed_header.frame_length()).freeze();
-        let packet = Connect::read(fixed_header, connect_bytes).unwrap();
+        let mut connect_bytes = stream.split_to(fixed_header.frame_length()).freeze();
+        let packet = Connect::read(fixed_header, &mut connect_bytes).unwrap();
 
         assert_eq!(
             packet,
@@ -413,4 +421,49 @@ fn connect_encoding_works() {
 
         assert_eq!(buf, sample_bytes());
     }
+
+    #[test]
+    fn v31_connect_round_trips() {
+        let connect = Connect {
+            protocol: Protocol::V3,
+            keep_alive: 10,
+            client_id: "test".to_owned(),
+            clean_session: true,
+            last_will: Some(LastWill::new("/a", "offline", QoS::AtLeastOnce, false)),
+            login: Some(Login::new("rust", "mq")),
+        };
+
+        let mut buf = BytesMut::new();
+        let written = connect.write(&mut buf).unwrap();
+        assert_eq!(written, connect.size());
+        assert_eq!(
+            &buf[2..11],
+            &[0x00, 0x06, b'M', b'Q', b'I', b's', b'd', b'p', 0x03]
+        );
+        // +username, +password, -will retain, will qos=1, +last_will, +clean_session
+        assert_eq!(buf[11], 0b1100_1110);
+
+        let fixed_header = parse_fixed_header(buf.iter()).unwrap();
+        let mut bytes = buf.split_to(fixed_header.frame_length()).freeze();
+        assert_eq!(Connect::read(fixed_header, &mut bytes).unwrap(), connect);
+    }
+
+    #[test]
+    fn will_retain_is_encoded_in_connect_flags() {
+        // +will retain, will qos=1, +last_will, +clean_session
+        for (retain, flags) in [(true, 0b0010_1110), (false, 0b0000_1110)] {
+            let mut connect = Connect::new("test");
+            let will = LastWill::new("presence/test", "offline", QoS::AtLeastOnce, retain);
+            connect.last_will = Some(will);
+
+            let mut buf = BytesMut::new();
+            connect.write(&mut buf).unwrap();
+            assert_eq!(buf[9], flags);
+
+            let fixed_header = parse_fixed_header(buf.iter()).unwrap();
+            let mut bytes = buf.split_to(fixed_header.frame_length()).freeze();
+            let connect = Connect::read(fixed_header, &mut bytes).unwrap();
+            assert_eq!(connect.last_will.unwrap().retain, retain);
+        }
+    }
 }
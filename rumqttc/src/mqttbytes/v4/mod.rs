@@ -75,6 +75,18 @@ pub fn read(stream: &mut BytesMut, max_size: usize) -> Result<Self, Error> {
 
         // Test with a stream with exactly the size to check border panics
         let packet = stream.split_to(fixed_header.frame_length());
+        let mut bytes = packet.freeze();
+        Self::read_frame(fixed_header, &mut bytes).map_err(|e| Error::Decode {
+            packet_type: fixed_header.byte1,
+            remaining_len: fixed_header.remaining_len,
+            offset: fixed_header.frame_length() - bytes.len(),
+            source: Box::new(e),
+        })
+    }
+
+    /// Extracts MQTT packet out of a complete frame. `bytes` is advanced as the frame
+    /// is decoded, which locates the failure when the frame is malformed
+    fn read_frame(fixed_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, Error> {
         let packet_type = fixed_header.packet_type()?;
 
         if fixed_header.remaining_len == 0 {
@@ -87,21 +99,18 @@ pub fn read(stream: &mut BytesMut, max_size: usize) -> Result<Self, Error> {
             };
         }
 
-        let packet = packet.freeze();
         let packet = match packet_type {
-            PacketType::Connect => Packet::Connect(Connect::read(fixed_header, packet)?),
-            PacketType::ConnAck => Packet::ConnAck(ConnAck::read(fixed_header, packet)?),
-            PacketType::Publish => Packet::Publish(Publish::read(fixed_header, packet)?),
-            PacketType::PubAck => Packet::PubAck(PubAck::read(fixed_header, packet)?),
-            PacketType::PubRec => Packet::PubRec(PubRec::read(fixed_header, packet)?),
-            PacketType::PubRel => Packet::PubRel(PubRel::read(fixed_header, packet)?),
-            PacketType::PubComp => Packet::PubComp(PubComp::read(fixed_header, packet)?),
-            PacketType::Subscribe => Packet::Subscribe(Subscribe::read(fixed_header, packet)?),
-            PacketType::SubAck => Packet::SubAck(SubAck::read(fixed_header, packet)?),
-            PacketType::Unsubscribe => {
-                Packet::Unsubscribe(Unsubscribe::read(fixed_header, packet)?)
-            }
-            PacketType::UnsubAck => Packet::UnsubAck(UnsubAck::read(fixed_header, packet)?),
+            PacketType::Connect => Packet::Connect(Connect::read(fixed_header, bytes)?),
+            PacketType::ConnAck => Packet::ConnAck(ConnAck::read(fixed_header, bytes)?),
+            PacketType::Publish => Packet::Publish(Publish::read(fixed_header, bytes)?),
+            PacketType::PubAck => Packet::PubAck(PubAck::read(fixed_header, bytes)?),
+            PacketType::PubRec => Packet::PubRec(PubRec::read(fixed_header, bytes)?),
+            PacketType::PubRel => Packet::PubRel(PubRel::read(fixed_header, bytes)?),
+            PacketType::PubComp => Packet::PubComp(PubComp::read(fixed_header, bytes)?),
+            PacketType::Subscribe => Packet::Subscribe(Subscribe::read(fixed_header, bytes)?),
+            PacketType::SubAck => Packet::SubAck(SubAck::read(fixed_header, bytes)?),
+            PacketType::Unsubscribe => Packet::Unsubscribe(Unsubscribe::read(fixed_header, bytes)?),
+            PacketType::UnsubAck => Packet::UnsubAck(UnsubAck::read(fixed_header, bytes)?),
             PacketType::PingReq => Packet::PingReq,
             PacketType::PingResp => Packet::PingResp,
             PacketType::Disconnect => Packet::Disconnect,
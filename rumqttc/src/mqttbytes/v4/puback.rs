@@ -24,10 +24,10 @@ pub fn size(&self) -> usize {
         1 + remaining_len_size + len
     }
 
-    pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<Self, Error> {
+    pub fn read(fixed_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, Error> {
         let variable_header_index = fixed_header.fixed_header_len;
         bytes.advance(variable_header_index);
-        let pkid = read_u16(&mut bytes)?;
+        let pkid = read_u16(bytes)?;
 
         // No reason code or properties if remaining length == 2
         if fixed_header.remaining_len == 2 {
@@ -73,8 +73,8 @@ fn puback_encoding_works() {
         ];
         let mut stream = BytesMut::from(&stream[..]);
         let fixed_header = parse_fixed_header(stream.iter()).unwrap();
-        let ack_bytes = stream.split_to(fixed_header.frame_length()).freeze();
-        let packet = PubAck::read(fixed_header, ack_bytes).unwrap();
+        let mut ack_bytes = stream.split_to(fixed_header.frame_length()).freeze();
+        let packet = PubAck::read(fixed_header, &mut ack_bytes).unwrap();
 
         assert_eq!(packet, PubAck { pkid: 10 });
     }
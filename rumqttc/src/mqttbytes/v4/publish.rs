@@ -1,11 +1,33 @@
 use super::*;
 use bytes::{Buf, Bytes};
+use std::sync::Arc;
 
 /// Publish packet
+///
+/// Incoming publishes are surfaced with all fields parsed, there is no need to look at
+/// the packet bytes
+///
+/// ```
+/// use rumqttc::{Event, Incoming, QoS};
+///
+/// fn handle(event: Event) {
+///     if let Event::Incoming(Incoming::Publish(publish)) = event {
+///         if publish.dup && publish.qos == QoS::AtLeastOnce {
+///             println!("{} resent as {}", publish.topic, publish.pkid);
+///         }
+///
+///         if publish.retain {
+///             println!("Retained {:?}", publish.payload);
+///         }
+///     }
+/// }
+/// ```
 #[derive(Clone, PartialEq, Eq)]
 pub struct Publish {
     pub dup: bool,
     pub qos: QoS,
+    /// On incoming publishes, set when the broker delivers a retained message because of a
+    /// new subscription and unset for messages published while subscribed
     pub retain: bool,
     pub topic: String,
     pub pkid: u16,
@@ -51,19 +73,19 @@ pub fn size(&self) -> usize {
         1 + remaining_len_size + len
     }
 
-    pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<Self, Error> {
+    pub fn read(fixed_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, Error> {
         let qos = qos((fixed_header.byte1 & 0b0110) >> 1)?;
         let dup = (fixed_header.byte1 & 0b1000) != 0;
         let retain = (fixed_header.byte1 & 0b0001) != 0;
 
         let variable_header_index = fixed_header.fixed_header_len;
         bytes.advance(variable_header_index);
-        let topic = read_mqtt_string(&mut bytes)?;
+        let topic = read_mqtt_string(bytes)?;
 
         // Packet identifier exists where QoS > 0
         let pkid = match qos {
             QoS::AtMostOnce => 0,
-            QoS::AtLeastOnce | QoS::ExactlyOnce => read_u16(&mut bytes)?,
+            QoS::AtLeastOnce | QoS::ExactlyOnce => read_u16(bytes)?,
         };
 
         if qos != QoS::AtMostOnce && pkid == 0 {
@@ -76,14 +98,23 @@ pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<Self, Error>
             qos,
             pkid,
             topic,
-            payload: bytes,
+            payload: bytes.split_to(bytes.len()),
         };
 
         Ok(publish)
     }
 
     pub fn write(&self, buffer: &mut BytesMut) -> Result<usize, Error> {
-        let len = self.len();
+        let count = self.write_header(self.payload.len(), buffer)?;
+        buffer.extend_from_slice(&self.payload);
+
+        Ok(count + self.payload.len())
+    }
+
+    /// Serializes everything except the payload, for a payload of `payload_len` bytes
+    /// which is written separately. Returns number of bytes written
+    pub fn write_header(&self, payload_len: usize, buffer: &mut BytesMut) -> Result<usize, Error> {
+        let len = self.len() - self.payload.len() + payload_len;
 
         let dup = self.dup as u8;
         let qos = self.qos as u8;
@@ -102,9 +133,7 @@ pub fn write(&self, buffer: &mut BytesMut) -> Result<usize, Error> {
             buffer.put_u16(pkid);
         }
 
-        buffer.extend_from_slice(&self.payload);
-
-        Ok(1 + count + len)
+        Ok(1 + count + len - payload_len)
     }
 }
 
@@ -122,12 +151,126 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     }
 }
 
+/// Publish serialized once, to be written repeatedly without encoding it again (e.g.
+/// heartbeats). QoS 1 and 2 publishes need a new packet id on every send, which is
+/// patched into a copy of the encoded packet when it's written.
+///
+/// Clones share the publish and the encoded packet.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncodedPublish {
+    publish: Arc<Publish>,
+    bytes: Bytes,
+}
+
+impl EncodedPublish {
+    pub fn new(mut publish: Publish) -> Result<EncodedPublish, Error> {
+        // Placeholder packet id, replaced on every write
+        let pkid = publish.pkid;
+        if publish.qos != QoS::AtMostOnce && pkid == 0 {
+            publish.pkid = u16::MAX;
+        }
+
+        let mut bytes = BytesMut::with_capacity(publish.size());
+        publish.write(&mut bytes)?;
+        publish.pkid = pkid;
+
+        Ok(EncodedPublish {
+            publish: Arc::new(publish),
+            bytes: bytes.freeze(),
+        })
+    }
+
+    /// Publish which was encoded
+    pub fn publish(&self) -> &Publish {
+        &self.publish
+    }
+
+    /// Size of the encoded packet in bytes
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Copies the encoded packet with `pkid`, which is ignored for QoS 0 publishes.
+    /// Returns number of bytes written
+    pub fn write(&self, pkid: u16, buffer: &mut BytesMut) -> usize {
+        let start = buffer.len();
+        buffer.extend_from_slice(&self.bytes);
+
+        if self.publish.qos != QoS::AtMostOnce {
+            // Packet id is right before the payload
+            let offset = start + self.bytes.len() - self.publish.payload.len() - 2;
+            buffer[offset..offset + 2].copy_from_slice(&pkid.to_be_bytes());
+        }
+
+        self.bytes.len()
+    }
+}
+
+impl fmt::Debug for EncodedPublish {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}, Encoded Size = {}", self.publish, self.bytes.len())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use bytes::{Bytes, BytesMut};
     use pretty_assertions::assert_eq;
 
+    #[test]
+    fn encoded_publish_is_written_with_packet_id_of_each_send() {
+        let publish = Publish::new("hello/world", QoS::AtLeastOnce, vec![1, 2, 3]);
+        let encoded = EncodedPublish::new(publish.clone()).unwrap();
+        assert_eq!(encoded.publish(), &publish);
+
+        for pkid in [1, 300] {
+            let mut expected = BytesMut::new();
+            let mut publish = publish.clone();
+            publish.pkid = pkid;
+            publish.write(&mut expected).unwrap();
+
+            let mut buf = BytesMut::new();
+            assert_eq!(encoded.write(pkid, &mut buf), expected.len());
+            assert_eq!(buf, expected);
+        }
+
+        let publish = Publish::new("hello/world", QoS::AtMostOnce, vec![1, 2, 3]);
+        let mut expected = BytesMut::new();
+        publish.write(&mut expected).unwrap();
+
+        let mut buf = BytesMut::new();
+        EncodedPublish::new(publish).unwrap().write(0, &mut buf);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn flags_of_dup_retained_qos1_publish_are_parsed() {
+        let stream = &[
+            0b0011_1011, // publish with dup, qos 1 and retain
+            7,           // remaining len
+            0x00,
+            0x03,
+            b'a',
+            b'/',
+            b'b', // topic name = 'a/b'
+            0x00,
+            0x0a, // pkid = 10
+        ];
+
+        let mut stream = BytesMut::from(&stream[..]);
+        let fixed_header = parse_fixed_header(stream.iter()).unwrap();
+        let mut publish_bytes = stream.split_to(fixed_header.frame_length()).freeze();
+        let publish = Publish::read(fixed_header, &mut publish_bytes).unwrap();
+
+        assert!(publish.dup);
+        assert!(publish.retain);
+        assert_eq!(publish.qos, QoS::AtLeastOnce);
+        assert_eq!(publish.pkid, 10);
+        assert_eq!(publish.topic, "a/b");
+        assert!(publish.payload.is_empty());
+    }
+
     #[test]
     fn qos1_publish_parsing_works() {
         let stream = &[
@@ -152,8 +295,8 @@ fn qos1_publish_parsing_works() {
 
         let mut stream = BytesMut::from(&stream[..]);
         let fixed_header = parse_fixed_header(stream.iter()).unwrap();
-        let publish_bytes = stream.split_to(fixed_header.frame_length()).freeze();
-        let packet = Publish::read(fixed_header, publish_bytes).unwrap();
+        let mut publish_bytes = stream.split_to(fixed_header.frame_length()).freeze();
+        let packet = Publish::read(fixed_header, &mut publish_bytes).unwrap();
 
         let payload = &[0xF1, 0xF2, 0xF3, 0xF4];
         assert_eq!(
@@ -189,8 +332,8 @@ fn qos0_publish_parsing_works() {
 
         let mut stream = BytesMut::from(&stream[..]);
         let fixed_header = parse_fixed_header(stream.iter()).unwrap();
-        let publish_bytes = stream.split_to(fixed_header.frame_length()).freeze();
-        let packet = Publish::read(fixed_header, publish_bytes).unwrap();
+        let mut publish_bytes = stream.split_to(fixed_header.frame_length()).freeze();
+        let packet = Publish::read(fixed_header, &mut publish_bytes).unwrap();
 
         assert_eq!(
             packet,
@@ -205,6 +348,29 @@ fn qos0_publish_parsing_works() {
         );
     }
 
+    #[test]
+    fn retain_flag_is_preserved_through_decoding() {
+        let stream = &[
+            0b0011_0001,
+            5, // packet type, flags and remaining len
+            0x00,
+            0x03,
+            b'a',
+            b'/',
+            b'b', // variable header. topic name = 'a/b'
+        ];
+
+        let mut stream = BytesMut::from(&stream[..]);
+        let fixed_header = parse_fixed_header(stream.iter()).unwrap();
+        let mut publish_bytes = stream.split_to(fixed_header.frame_length()).freeze();
+        let packet = Publish::read(fixed_header, &mut publish_bytes).unwrap();
+        assert!(packet.retain);
+
+        let mut buf = BytesMut::new();
+        packet.write(&mut buf).unwrap();
+        assert_eq!(buf[0], 0b0011_0001);
+    }
+
     #[test]
     fn qos1_publish_encoding_works() {
         let publish = Publish {
@@ -270,4 +436,22 @@ fn qos0_publish_encoding_works() {
             ]
         );
     }
+
+    #[test]
+    fn publish_header_encoding_matches_full_encoding() {
+        let payload = vec![0xE1, 0xE2, 0xE3, 0xE4];
+        let mut publish = Publish::new("a/b", QoS::AtLeastOnce, payload.clone());
+        publish.pkid = 10;
+        let mut full = BytesMut::new();
+        let full_len = publish.write(&mut full).unwrap();
+
+        let mut header = Publish::new("a/b", QoS::AtLeastOnce, Vec::new());
+        header.pkid = 10;
+        let mut buf = BytesMut::new();
+        let header_len = header.write_header(payload.len(), &mut buf).unwrap();
+        buf.extend_from_slice(&payload);
+
+        assert_eq!(header_len + payload.len(), full_len);
+        assert_eq!(buf, full);
+    }
 }
@@ -24,10 +24,10 @@ pub fn size(&self) -> usize {
         1 + remaining_len_size + len
     }
 
-    pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<Self, Error> {
+    pub fn read(fixed_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, Error> {
         let variable_header_index = fixed_header.fixed_header_len;
         bytes.advance(variable_header_index);
-        let pkid = read_u16(&mut bytes)?;
+        let pkid = read_u16(bytes)?;
         if fixed_header.remaining_len == 2 {
             return Ok(PubRel { pkid });
         }
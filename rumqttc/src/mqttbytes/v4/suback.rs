@@ -24,10 +24,10 @@ pub fn size(&self) -> usize {
         1 + remaining_len_size + len
     }
 
-    pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<Self, Error> {
+    pub fn read(fixed_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, Error> {
         let variable_header_index = fixed_header.fixed_header_len;
         bytes.advance(variable_header_index);
-        let pkid = read_u16(&mut bytes)?;
+        let pkid = read_u16(bytes)?;
 
         if !bytes.has_remaining() {
             return Err(Error::MalformedPacket);
@@ -35,7 +35,7 @@ pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<Self, Error>
 
         let mut return_codes = Vec::new();
         while bytes.has_remaining() {
-            let return_code = read_u8(&mut bytes)?;
+            let return_code = read_u8(bytes)?;
             return_codes.push(return_code.try_into()?);
         }
 
@@ -101,8 +101,8 @@ fn suback_parsing_works() {
 
         let mut stream = BytesMut::from(&stream[..]);
         let fixed_header = parse_fixed_header(stream.iter()).unwrap();
-        let ack_bytes = stream.split_to(fixed_header.frame_length()).freeze();
-        let packet = SubAck::read(fixed_header, ack_bytes).unwrap();
+        let mut ack_bytes = stream.split_to(fixed_header.frame_length()).freeze();
+        let packet = SubAck::read(fixed_header, &mut ack_bytes).unwrap();
 
         assert_eq!(
             packet,
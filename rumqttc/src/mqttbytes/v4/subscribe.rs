@@ -49,18 +49,18 @@ pub fn size(&self) -> usize {
         1 + remaining_len_size + len
     }
 
-    pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<Self, Error> {
+    pub fn read(fixed_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, Error> {
         let variable_header_index = fixed_header.fixed_header_len;
         bytes.advance(variable_header_index);
 
-        let pkid = read_u16(&mut bytes)?;
+        let pkid = read_u16(bytes)?;
 
         // variable header size = 2 (packet identifier)
         let mut filters = Vec::new();
 
         while bytes.has_remaining() {
-            let path = read_mqtt_string(&mut bytes)?;
-            let options = read_u8(&mut bytes)?;
+            let path = read_mqtt_string(bytes)?;
+            let options = read_u8(bytes)?;
             let requested_qos = options & 0b0000_0011;
 
             filters.push(SubscribeFilter {
@@ -182,8 +182,8 @@ fn subscribe_parsing_works() {
         ];
         let mut stream = BytesMut::from(&stream[..]);
         let fixed_header = parse_fixed_header(stream.iter()).unwrap();
-        let subscribe_bytes = stream.split_to(fixed_header.frame_length()).freeze();
-        let packet = Subscribe::read(fixed_header, subscribe_bytes).unwrap();
+        let mut subscribe_bytes = stream.split_to(fixed_header.frame_length()).freeze();
+        let packet = Subscribe::read(fixed_header, &mut subscribe_bytes).unwrap();
 
         assert_eq!(
             packet,
@@ -28,16 +28,16 @@ pub fn size(&self) -> usize {
         1 + remaining_len_size + len
     }
 
-    pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<Self, Error> {
+    pub fn read(fixed_header: FixedHeader, bytes: &mut Bytes) -> Result<Self, Error> {
         let variable_header_index = fixed_header.fixed_header_len;
         bytes.advance(variable_header_index);
 
-        let pkid = read_u16(&mut bytes)?;
+        let pkid = read_u16(bytes)?;
         let mut payload_bytes = fixed_header.remaining_len - 2;
         let mut topics = Vec::with_capacity(1);
 
         while payload_bytes > 0 {
-            let topic_filter = read_mqtt_string(&mut bytes)?;
+            let topic_filter = read_mqtt_string(bytes)?;
             payload_bytes -= topic_filter.len() + 2;
             topics.push(topic_filter);
         }
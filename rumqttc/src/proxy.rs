@@ -55,7 +55,9 @@ pub(crate) async fn connect(
             ProxyType::Http => tcp,
             #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
             ProxyType::Https(tls_config) => {
-                tls::tls_connect(&self.addr, self.port, &tls_config, tcp).await?
+                tls::tls_connect(&self.addr, self.port, &tls_config, tcp)
+                    .await?
+                    .0
             }
         };
         self.auth.auth(broker_addr, broker_port, &mut tcp).await?;
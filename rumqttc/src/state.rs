@@ -1,9 +1,14 @@
-use crate::{Event, Incoming, Outgoing, Request};
+use crate::{DeliveryNotice, DeliveryStatus, Event, Incoming, Outgoing, Request, TopicValidation};
 
 use crate::mqttbytes::v4::*;
 use crate::mqttbytes::{self, *};
-use std::collections::VecDeque;
-use std::{io, time::Instant};
+use flume::{Receiver, Sender};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
 
 /// Errors during state handling
 #[derive(Debug, thiserror::Error)]
@@ -17,6 +22,9 @@ pub enum StateError {
     /// Received a packet (ack) which isn't asked for
     #[error("Received unsolicited ack pkid: {0}")]
     Unsolicited(u16),
+    /// Outgoing publish isn't acked after maximum resends, see `MqttOptions::set_ack_timeout`
+    #[error("Publish isn't acked after maximum resends, pkid: {0}")]
+    AckTimeout(u16),
     /// Last pingreq isn't acked
     #[error("Last pingreq isn't acked")]
     AwaitPingResp,
@@ -31,6 +39,237 @@ pub enum StateError {
     Deserialization(#[from] mqttbytes::Error),
     #[error("Connection closed by peer abruptly")]
     ConnectionAborted,
+    /// Payload reader of a streamed publish didn't yield the announced length
+    #[error("Payload stream doesn't match the announced length of {0} bytes")]
+    StreamLengthMismatch(usize),
+}
+
+/// Predicate deciding if an incoming publish is surfaced as an event
+pub type PublishPredicate = Arc<dyn Fn(&Publish) -> bool + Send + Sync>;
+
+/// Predicates evaluated on incoming publishes. A publish is surfaced only when the
+/// global predicate and the predicates of all subscription filters matching its
+/// topic allow it
+#[derive(Clone, Default)]
+pub(crate) struct PublishFilters {
+    global: Option<PublishPredicate>,
+    subscriptions: Vec<(String, PublishPredicate)>,
+}
+
+impl PublishFilters {
+    pub(crate) fn set_global(&mut self, predicate: Option<PublishPredicate>) {
+        self.global = predicate;
+    }
+
+    pub(crate) fn set_subscription(&mut self, filter: String, predicate: Option<PublishPredicate>) {
+        self.subscriptions.retain(|(f, _)| *f != filter);
+        if let Some(predicate) = predicate {
+            self.subscriptions.push((filter, predicate));
+        }
+    }
+
+    fn allows(&self, publish: &Publish) -> bool {
+        if let Some(global) = &self.global {
+            if !global(publish) {
+                return false;
+            }
+        }
+
+        self.subscriptions
+            .iter()
+            .filter(|(filter, _)| matches(&publish.topic, filter))
+            .all(|(_, predicate)| predicate(publish))
+    }
+}
+
+impl fmt::Debug for PublishFilters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let filters: Vec<&String> = self.subscriptions.iter().map(|(f, _)| f).collect();
+        f.debug_struct("PublishFilters")
+            .field("global", &self.global.is_some())
+            .field("subscriptions", &filters)
+            .finish()
+    }
+}
+
+/// Filters subscribed by the client, to validate topics of incoming publishes. Filters
+/// are active from the time subscribe is sent till unsubscribe is acked, as the broker
+/// can still forward publishes till then
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SubscribedFilters {
+    filters: Vec<String>,
+    /// Filters of unsubscribes which aren't acked yet, by packet id
+    unsubscribing: HashMap<u16, Vec<String>>,
+}
+
+impl SubscribedFilters {
+    fn subscribe(&mut self, subscribe: &Subscribe) {
+        for filter in &subscribe.filters {
+            if !self.filters.contains(&filter.path) {
+                self.filters.push(filter.path.clone());
+            }
+        }
+    }
+
+    fn unsubscribe(&mut self, unsubscribe: &Unsubscribe) {
+        self.unsubscribing
+            .insert(unsubscribe.pkid, unsubscribe.topics.clone());
+    }
+
+    fn unsubscribed(&mut self, pkid: u16) {
+        if let Some(topics) = self.unsubscribing.remove(&pkid) {
+            self.filters.retain(|filter| !topics.contains(filter));
+        }
+    }
+
+    fn matches(&self, topic: &str) -> bool {
+        self.filters.iter().any(|filter| {
+            // shared subscriptions are `$share/{group}/{filter}`
+            let filter = match filter.strip_prefix("$share/") {
+                Some(shared) => shared.split_once('/').map_or(shared, |(_, filter)| filter),
+                None => filter,
+            };
+
+            matches(topic, filter)
+        })
+    }
+}
+
+/// Filter of a stream and the channel of its publishes
+type Stream = (String, Sender<Publish>);
+
+/// Channels of streams returned by [`AsyncClient::subscribe_stream`](crate::AsyncClient::subscribe_stream),
+/// with their filters. Shared between the client and the state
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SubscriptionStreams {
+    streams: Arc<Mutex<Vec<Stream>>>,
+}
+
+impl SubscriptionStreams {
+    /// Registers a stream of publishes matching `filter`
+    pub(crate) fn add(&self, filter: String) -> Receiver<Publish> {
+        let (tx, rx) = flume::unbounded();
+        self.streams.lock().unwrap().push((filter, tx));
+        rx
+    }
+
+    /// Sends a copy of `publish` to every stream with a matching filter and removes
+    /// streams which were dropped
+    fn deliver(&self, publish: &Publish) {
+        self.streams.lock().unwrap().retain(|(filter, tx)| {
+            if tx.is_disconnected() {
+                return false;
+            }
+
+            if matches(&publish.topic, filter) {
+                tx.send(publish.clone()).ok();
+            }
+
+            true
+        });
+    }
+}
+
+/// Latest incoming publish of each topic, for [`AsyncClient::last_value`](crate::AsyncClient::last_value).
+/// Shared between the client and the state. Disabled unless a capacity is configured
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LastValues {
+    cache: Arc<Mutex<Option<LastValueCache>>>,
+}
+
+#[derive(Debug)]
+struct LastValueCache {
+    capacity: usize,
+    /// Latest publish of a topic with the sequence number of its last use
+    values: HashMap<String, (u64, Publish)>,
+    /// Topics by the sequence number of their last use, least recent first
+    uses: BTreeMap<u64, String>,
+    next_use: u64,
+}
+
+impl LastValueCache {
+    fn touch(&mut self, topic: &str) -> u64 {
+        let next_use = self.next_use;
+        self.next_use += 1;
+        if let Some((last_use, _)) = self.values.get_mut(topic) {
+            self.uses.remove(last_use);
+            *last_use = next_use;
+        }
+
+        self.uses.insert(next_use, topic.to_owned());
+        next_use
+    }
+}
+
+impl LastValues {
+    pub(crate) fn new(capacity: Option<usize>) -> LastValues {
+        let cache = capacity.map(|capacity| LastValueCache {
+            capacity,
+            values: HashMap::new(),
+            uses: BTreeMap::new(),
+            next_use: 0,
+        });
+
+        LastValues {
+            cache: Arc::new(Mutex::new(cache)),
+        }
+    }
+
+    /// Replaces the value of the topic of `publish`, evicting the least recently used
+    /// topic when the cache is full
+    fn update(&self, publish: &Publish) {
+        let mut cache = self.cache.lock().unwrap();
+        let cache = match cache.as_mut() {
+            Some(cache) if cache.capacity > 0 => cache,
+            _ => return,
+        };
+
+        let is_new = !cache.values.contains_key(&publish.topic);
+        if is_new && cache.values.len() >= cache.capacity {
+            let least_recent = cache.uses.keys().next().copied();
+            if let Some(topic) = least_recent.and_then(|last_use| cache.uses.remove(&last_use)) {
+                cache.values.remove(&topic);
+            }
+        }
+
+        let last_use = cache.touch(&publish.topic);
+        cache
+            .values
+            .insert(publish.topic.clone(), (last_use, publish.clone()));
+    }
+
+    pub(crate) fn get(&self, topic: &str) -> Option<Publish> {
+        let mut cache = self.cache.lock().unwrap();
+        let cache = cache.as_mut()?;
+        if !cache.values.contains_key(topic) {
+            return None;
+        }
+
+        cache.touch(topic);
+        cache.values.get(topic).map(|(_, publish)| publish.clone())
+    }
+}
+
+/// Destination of incoming publishes which bypasses the event stream. Useful for
+/// ingestion workloads which don't want to match on every event
+pub trait PublishSink: Send + Sync {
+    /// Called for every incoming publish allowed by publish filters
+    fn publish(&self, publish: Publish);
+}
+
+impl<F> PublishSink for F
+where
+    F: Fn(Publish) + Send + Sync,
+{
+    fn publish(&self, publish: Publish) {
+        self(publish)
+    }
+}
+
+impl fmt::Debug for dyn PublishSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PublishSink")
+    }
 }
 
 /// State of the mqtt connection.
@@ -61,16 +300,43 @@ pub struct MqttState {
     pub(crate) max_inflight: u16,
     /// Outgoing QoS 1, 2 publishes which aren't acked yet
     pub(crate) outgoing_pub: Vec<Option<Publish>>,
+    /// Time of the last send and number of resends of publishes in `outgoing_pub`
+    outgoing_sent: Vec<Option<(Instant, u16)>>,
+    /// Notices of tracked outgoing QoS 1, 2 publishes which aren't acked or completed yet
+    delivery_notices: Vec<Option<DeliveryNotice>>,
     /// Packet ids of released QoS 2 publishes
     pub(crate) outgoing_rel: Vec<Option<u16>>,
     /// Packet ids on incoming QoS 2 publishes
     pub(crate) incoming_pub: Vec<Option<u16>>,
+    /// Packet ids of incoming QoS 1, 2 publishes which the user is yet to ack
+    /// when acks are manual
+    pub(crate) unacked_incoming: Vec<Option<u16>>,
+    /// Number of publishes in `unacked_incoming`
+    unacked_incoming_count: usize,
+    /// Number of QoS 2 publishes in `unacked_incoming`
+    unacked_incoming_qos2: u16,
+    /// Number of unacked incoming QoS 2 publishes after which reading pauses
+    pub(crate) max_unacked_incoming_qos2: Option<u16>,
     /// Last collision due to broker not acking in order
     pub collision: Option<Publish>,
+    /// Notice of the collided publish, if tracked
+    collision_notice: Option<DeliveryNotice>,
     /// Buffered incoming packets
     pub events: VecDeque<Event>,
     /// Indicates if acknowledgements should be send immediately
     pub manual_acks: bool,
+    /// Predicates which drop incoming publishes before they are surfaced
+    pub(crate) publish_filters: PublishFilters,
+    /// What is done with incoming publishes not matching `subscribed_filters`
+    pub(crate) topic_validation: TopicValidation,
+    /// Filters subscribed by the client
+    pub(crate) subscribed_filters: SubscribedFilters,
+    /// Sink which receives incoming publishes instead of the event stream
+    pub(crate) publish_sink: Option<Arc<dyn PublishSink>>,
+    /// Streams which receive copies of incoming publishes matching their filter
+    pub(crate) subscription_streams: SubscriptionStreams,
+    /// Latest incoming publish of each topic
+    pub(crate) last_values: LastValues,
 }
 
 impl MqttState {
@@ -89,12 +355,25 @@ pub fn new(max_inflight: u16, manual_acks: bool) -> Self {
             max_inflight,
             // index 0 is wasted as 0 is not a valid packet id
             outgoing_pub: vec![None; max_inflight as usize + 1],
+            outgoing_sent: vec![None; max_inflight as usize + 1],
+            delivery_notices: vec![None; max_inflight as usize + 1],
             outgoing_rel: vec![None; max_inflight as usize + 1],
             incoming_pub: vec![None; std::u16::MAX as usize + 1],
+            unacked_incoming: vec![None; u16::MAX as usize + 1],
+            unacked_incoming_count: 0,
+            unacked_incoming_qos2: 0,
+            max_unacked_incoming_qos2: None,
             collision: None,
+            collision_notice: None,
             // TODO: Optimize these sizes later
             events: VecDeque::with_capacity(100),
             manual_acks,
+            publish_filters: PublishFilters::default(),
+            topic_validation: TopicValidation::Off,
+            subscribed_filters: SubscribedFilters::default(),
+            publish_sink: None,
+            subscription_streams: SubscriptionStreams::default(),
+            last_values: LastValues::default(),
         }
     }
 
@@ -107,11 +386,22 @@ pub fn clean(&mut self) -> Vec<Request> {
 
         for publish in second_half.iter_mut().chain(first_half) {
             if let Some(publish) = publish.take() {
-                let request = Request::Publish(publish);
+                // Resent publishes are tracked again. Notices of releases stay, to be
+                // notified once the resent releases are completed
+                let notice = self.delivery_notices.get_mut(publish.pkid as usize);
+                let request = match notice.and_then(Option::take) {
+                    Some(notice) => Request::PublishTracked(publish, notice),
+                    None => Request::Publish(publish),
+                };
                 pending.push(request);
             }
         }
 
+        // resent publishes are sent afresh
+        for sent in self.outgoing_sent.iter_mut() {
+            sent.take();
+        }
+
         // remove and collect pending releases
         for rel in self.outgoing_rel.iter_mut() {
             if let Some(pkid) = rel.take() {
@@ -131,6 +421,35 @@ pub fn clean(&mut self) -> Vec<Request> {
         pending
     }
 
+    /// Number of incoming QoS 1, 2 publishes which the user is yet to ack
+    pub fn unacked_incoming(&self) -> usize {
+        self.unacked_incoming_count
+    }
+
+    /// Number of incoming QoS 2 publishes which the user is yet to ack
+    pub fn unacked_incoming_qos2(&self) -> u16 {
+        self.unacked_incoming_qos2
+    }
+
+    /// Checks if reading should pause till the user acks incoming QoS 2 publishes
+    pub(crate) fn unacked_incoming_qos2_full(&self) -> bool {
+        self.manual_acks
+            && self
+                .max_unacked_incoming_qos2
+                .map_or(false, |max| self.unacked_incoming_qos2 >= max)
+    }
+
+    /// Forgets incoming publishes which the user is yet to ack. Called when the broker
+    /// didn't resume the session, as acks of publishes received in the previous session
+    /// would ack whatever the broker sends next with the same packet ids
+    pub(crate) fn forget_unacked_incoming(&mut self) {
+        for id in self.unacked_incoming.iter_mut() {
+            id.take();
+        }
+        self.unacked_incoming_count = 0;
+        self.unacked_incoming_qos2 = 0;
+    }
+
     pub fn inflight(&self) -> u16 {
         self.inflight
     }
@@ -143,6 +462,9 @@ pub fn handle_outgoing_packet(
     ) -> Result<Option<Packet>, StateError> {
         let packet = match request {
             Request::Publish(publish) => self.outgoing_publish(publish)?,
+            Request::PublishTracked(publish, notice) => {
+                self.outgoing_tracked_publish(publish, notice)?
+            }
             Request::PubRel(pubrel) => self.outgoing_pubrel(pubrel)?,
             Request::Subscribe(subscribe) => self.outgoing_subscribe(subscribe)?,
             Request::Unsubscribe(unsubscribe) => self.outgoing_unsubscribe(unsubscribe)?,
@@ -157,6 +479,34 @@ pub fn handle_outgoing_packet(
         Ok(packet)
     }
 
+    /// Handles an encoded publish like any other publish, except for not returning a
+    /// packet to encode. Returns the packet id to write it with, `None` on a collision
+    pub fn outgoing_encoded_publish(
+        &mut self,
+        publish: &EncodedPublish,
+    ) -> Result<Option<u16>, StateError> {
+        let publish = publish.publish();
+        if publish.qos == QoS::AtMostOnce {
+            self.events.push_back(Event::Outgoing(Outgoing::Publish(0)));
+            self.last_outgoing = Instant::now();
+            return Ok(Some(0));
+        }
+
+        // Copy is kept for retransmission
+        let pkid = match self.handle_outgoing_packet(Request::Publish(publish.clone()))? {
+            Some(Packet::Publish(publish)) => Some(publish.pkid),
+            _ => None,
+        };
+
+        Ok(pkid)
+    }
+
+    /// Records a streamed QoS 0 publish which is written directly by the network
+    pub fn outgoing_publish_stream(&mut self) {
+        self.events.push_back(Event::Outgoing(Outgoing::Publish(0)));
+        self.last_outgoing = Instant::now();
+    }
+
     /// Consolidates handling of all incoming mqtt packets. Returns a `Notification` which for the
     /// user to consume and `Packet` which for the eventloop to put on the network
     /// E.g For incoming QoS1 publish packet, this method returns (Publish, Puback). Publish packet will
@@ -169,7 +519,7 @@ pub fn handle_incoming_packet(
             Incoming::PingResp => self.handle_incoming_pingresp()?,
             Incoming::Publish(publish) => self.handle_incoming_publish(publish)?,
             Incoming::SubAck(_suback) => self.handle_incoming_suback()?,
-            Incoming::UnsubAck(_unsuback) => self.handle_incoming_unsuback()?,
+            Incoming::UnsubAck(unsuback) => self.handle_incoming_unsuback(unsuback)?,
             Incoming::PubAck(puback) => self.handle_incoming_puback(puback)?,
             Incoming::PubRec(pubrec) => self.handle_incoming_pubrec(pubrec)?,
             Incoming::PubRel(pubrel) => self.handle_incoming_pubrel(pubrel)?,
@@ -179,17 +529,55 @@ pub fn handle_incoming_packet(
                 return Err(StateError::WrongPacket);
             }
         };
-        self.events.push_back(Event::Incoming(packet));
         self.last_incoming = Instant::now();
 
+        let outgoing = self.deliver_incoming(packet, outgoing)?;
+        if outgoing.is_some() {
+            self.last_outgoing = Instant::now();
+        }
+
+        Ok(outgoing)
+    }
+
+    /// Surfaces incoming packet as an event, unless it's a publish which is filtered
+    /// or passed to publish sink. Allowed publishes are also copied to subscription streams
+    /// and last values
+    fn deliver_incoming(
+        &mut self,
+        packet: Incoming,
+        outgoing: Option<Packet>,
+    ) -> Result<Option<Packet>, StateError> {
+        if let Incoming::Publish(publish) = &packet {
+            if !self.topic_subscribed(publish) || !self.publish_filters.allows(publish) {
+                return self.ack_filtered_publish(publish, outgoing);
+            }
+
+            self.subscription_streams.deliver(publish);
+            self.last_values.update(publish);
+        }
+
+        match (&self.publish_sink, packet) {
+            (Some(sink), Incoming::Publish(publish)) => sink.publish(publish),
+            (_, packet) => self.events.push_back(Event::Incoming(packet)),
+        }
+
         Ok(outgoing)
     }
 
+    /// Time at which last packet was sent
+    pub(crate) fn last_outgoing(&self) -> Instant {
+        self.last_outgoing
+    }
+
     fn handle_incoming_suback(&mut self) -> Result<Option<Packet>, StateError> {
         Ok(None)
     }
 
-    fn handle_incoming_unsuback(&mut self) -> Result<Option<Packet>, StateError> {
+    fn handle_incoming_unsuback(
+        &mut self,
+        unsuback: &UnsubAck,
+    ) -> Result<Option<Packet>, StateError> {
+        self.subscribed_filters.unsubscribed(unsuback.pkid);
         Ok(None)
     }
 
@@ -197,6 +585,7 @@ fn handle_incoming_unsuback(&mut self) -> Result<Option<Packet>, StateError> {
     /// in case of QoS1 and Replys rec in case of QoS while also storing the message
     fn handle_incoming_publish(&mut self, publish: &Publish) -> Result<Option<Packet>, StateError> {
         let qos = publish.qos;
+        debug!(topic = %publish.topic, pkid = publish.pkid, ?qos, "Incoming publish");
 
         match qos {
             QoS::AtMostOnce => Ok(None),
@@ -205,6 +594,14 @@ fn handle_incoming_publish(&mut self, publish: &Publish) -> Result<Option<Packet
                     let puback = PubAck::new(publish.pkid);
                     return self.outgoing_puback(puback);
                 }
+
+                // broker resends publishes which aren't acked yet after reconnecting
+                if self.unacked_incoming[publish.pkid as usize]
+                    .replace(publish.pkid)
+                    .is_none()
+                {
+                    self.unacked_incoming_count += 1;
+                }
                 Ok(None)
             }
             QoS::ExactlyOnce => {
@@ -215,27 +612,76 @@ fn handle_incoming_publish(&mut self, publish: &Publish) -> Result<Option<Packet
                     let pubrec = PubRec::new(pkid);
                     return self.outgoing_pubrec(pubrec);
                 }
+
+                // broker resends publishes which aren't acked yet after reconnecting
+                if self.unacked_incoming[pkid as usize].replace(pkid).is_none() {
+                    self.unacked_incoming_count += 1;
+                    self.unacked_incoming_qos2 += 1;
+                }
                 Ok(None)
             }
         }
     }
 
+    /// Checks topic of the publish against subscribed filters as per `topic_validation`.
+    /// Returns false if the publish is to be dropped
+    fn topic_subscribed(&self, publish: &Publish) -> bool {
+        if self.topic_validation == TopicValidation::Off
+            || self.subscribed_filters.matches(&publish.topic)
+        {
+            return true;
+        }
+
+        warn!(
+            "Incoming publish doesn't match any subscription. Topic = {}, Pkid = {}",
+            publish.topic, publish.pkid
+        );
+        self.topic_validation == TopicValidation::Warn
+    }
+
+    /// Filtered publishes are never seen by the user and hence are acked
+    /// here even when acks are manual
+    fn ack_filtered_publish(
+        &mut self,
+        publish: &Publish,
+        outgoing: Option<Packet>,
+    ) -> Result<Option<Packet>, StateError> {
+        if outgoing.is_some() || !self.manual_acks {
+            return Ok(outgoing);
+        }
+
+        match publish.qos {
+            QoS::AtMostOnce => Ok(None),
+            QoS::AtLeastOnce => self.outgoing_puback(PubAck::new(publish.pkid)),
+            QoS::ExactlyOnce => self.outgoing_pubrec(PubRec::new(publish.pkid)),
+        }
+    }
+
     fn handle_incoming_puback(&mut self, puback: &PubAck) -> Result<Option<Packet>, StateError> {
-        let publish = self
-            .outgoing_pub
-            .get_mut(puback.pkid as usize)
-            .ok_or(StateError::Unsolicited(puback.pkid))?;
+        // Duplicate acks and acks of packet ids which were never sent are ignored, quirky
+        // brokers aren't worth a disconnection
+        let publish = self.outgoing_pub.get_mut(puback.pkid as usize);
+        if publish.and_then(Option::take).is_none() {
+            warn!("Ignoring puback of unknown packet id: {:?}", puback.pkid);
+            return Ok(None);
+        }
 
         self.last_puback = puback.pkid;
 
-        if publish.take().is_none() {
-            error!("Unsolicited puback packet: {:?}", puback.pkid);
-            return Err(StateError::Unsolicited(puback.pkid));
+        if let Some(notice) = self.delivery_notices[puback.pkid as usize].take() {
+            notice.notify(DeliveryStatus::Delivered);
         }
 
         self.inflight -= 1;
+        debug!(
+            pkid = puback.pkid,
+            inflight = self.inflight,
+            "Incoming puback"
+        );
         let packet = self.check_collision(puback.pkid).map(|publish| {
+            debug!(pkid = publish.pkid, "Resending collided publish");
             self.outgoing_pub[publish.pkid as usize] = Some(publish.clone());
+            self.outgoing_sent[publish.pkid as usize] = Some((Instant::now(), 0));
             self.inflight += 1;
 
             let event = Event::Outgoing(Outgoing::Publish(publish.pkid));
@@ -249,18 +695,16 @@ fn handle_incoming_puback(&mut self, puback: &PubAck) -> Result<Option<Packet>,
     }
 
     fn handle_incoming_pubrec(&mut self, pubrec: &PubRec) -> Result<Option<Packet>, StateError> {
-        let publish = self
-            .outgoing_pub
-            .get_mut(pubrec.pkid as usize)
-            .ok_or(StateError::Unsolicited(pubrec.pkid))?;
-
-        if publish.take().is_none() {
-            error!("Unsolicited pubrec packet: {:?}", pubrec.pkid);
-            return Err(StateError::Unsolicited(pubrec.pkid));
+        let publish = self.outgoing_pub.get_mut(pubrec.pkid as usize);
+        if publish.and_then(Option::take).is_none() {
+            warn!("Ignoring pubrec of unknown packet id: {:?}", pubrec.pkid);
+            return Ok(None);
         }
 
         // NOTE: Inflight - 1 for qos2 in comp
+        debug!(pkid = pubrec.pkid, "Incoming pubrec");
         self.outgoing_rel[pubrec.pkid as usize] = Some(pubrec.pkid);
+        debug!(pkid = pubrec.pkid, "Pubrel");
         let pubrel = PubRel { pkid: pubrec.pkid };
         let event = Event::Outgoing(Outgoing::PubRel(pubrec.pkid));
         self.events.push_back(event);
@@ -274,11 +718,15 @@ fn handle_incoming_pubrel(&mut self, pubrel: &PubRel) -> Result<Option<Packet>,
             .get_mut(pubrel.pkid as usize)
             .ok_or(StateError::Unsolicited(pubrel.pkid))?;
 
+        // Broker resends PUBREL of a publish which is already released when PUBCOMP was lost,
+        // or after the client restarted without its state. Completing it lets the broker
+        // release the packet id instead of disconnecting
         if publish.take().is_none() {
-            error!("Unsolicited pubrel packet: {:?}", pubrel.pkid);
-            return Err(StateError::Unsolicited(pubrel.pkid));
+            warn!("Pubrel of unknown packet id: {:?}", pubrel.pkid);
         }
 
+        debug!(pkid = pubrel.pkid, "Incoming pubrel");
+        debug!(pkid = pubrel.pkid, "Pubcomp");
         let event = Event::Outgoing(Outgoing::PubComp(pubrel.pkid));
         let pubcomp = PubComp { pkid: pubrel.pkid };
         self.events.push_back(event);
@@ -287,19 +735,24 @@ fn handle_incoming_pubrel(&mut self, pubrel: &PubRel) -> Result<Option<Packet>,
     }
 
     fn handle_incoming_pubcomp(&mut self, pubcomp: &PubComp) -> Result<Option<Packet>, StateError> {
-        if self
-            .outgoing_rel
-            .get_mut(pubcomp.pkid as usize)
-            .ok_or(StateError::Unsolicited(pubcomp.pkid))?
-            .take()
-            .is_none()
-        {
-            error!("Unsolicited pubcomp packet: {:?}", pubcomp.pkid);
-            return Err(StateError::Unsolicited(pubcomp.pkid));
+        let pubrel = self.outgoing_rel.get_mut(pubcomp.pkid as usize);
+        if pubrel.and_then(Option::take).is_none() {
+            warn!("Ignoring pubcomp of unknown packet id: {:?}", pubcomp.pkid);
+            return Ok(None);
+        }
+
+        if let Some(notice) = self.delivery_notices[pubcomp.pkid as usize].take() {
+            notice.notify(DeliveryStatus::Delivered);
         }
 
         self.inflight -= 1;
+        debug!(
+            pkid = pubcomp.pkid,
+            inflight = self.inflight,
+            "Incoming pubcomp"
+        );
         let packet = self.check_collision(pubcomp.pkid).map(|publish| {
+            debug!(pkid = publish.pkid, "Resending collided publish");
             let event = Event::Outgoing(Outgoing::Publish(publish.pkid));
             self.events.push_back(event);
             self.collision_ping_count = 0;
@@ -341,14 +794,15 @@ fn outgoing_publish(&mut self, mut publish: Publish) -> Result<Option<Packet>, S
             // if there is an existing publish at this pkid, this implies that broker hasn't acked this
             // packet yet. This error is possible only when broker isn't acking sequentially
             self.outgoing_pub[pkid as usize] = Some(publish.clone());
+            self.outgoing_sent[pkid as usize] = Some((Instant::now(), 0));
             self.inflight += 1;
         };
 
         debug!(
-            "Publish. Topic = {}, Pkid = {:?}, Payload Size = {:?}",
-            publish.topic,
-            publish.pkid,
-            publish.payload.len()
+            topic = %publish.topic,
+            pkid = publish.pkid,
+            payload_size = publish.payload.len(),
+            "Publish"
         );
 
         let event = Event::Outgoing(Outgoing::Publish(publish.pkid));
@@ -357,10 +811,73 @@ fn outgoing_publish(&mut self, mut publish: Publish) -> Result<Option<Packet>, S
         Ok(Some(Packet::Publish(publish)))
     }
 
+    /// Publish whose notice is notified once acked, or once handed out to be written
+    /// for QoS 0
+    fn outgoing_tracked_publish(
+        &mut self,
+        publish: Publish,
+        notice: DeliveryNotice,
+    ) -> Result<Option<Packet>, StateError> {
+        let qos = publish.qos;
+        let packet = self.outgoing_publish(publish)?;
+        match &packet {
+            _ if qos == QoS::AtMostOnce => notice.notify(DeliveryStatus::Delivered),
+            Some(Packet::Publish(publish)) => {
+                self.delivery_notices[publish.pkid as usize] = Some(notice)
+            }
+            // Collided, follows the publish once it is resent
+            _ => self.collision_notice = Some(notice),
+        }
+
+        Ok(packet)
+    }
+
+    /// Time at which the earliest sent unacked publish times out
+    pub(crate) fn next_ack_deadline(&self, timeout: Duration) -> Option<Instant> {
+        self.unacked_sends().map(|(_, sent)| sent + timeout).min()
+    }
+
+    /// Resends the earliest sent unacked publish with DUP set, or fails once it was
+    /// already resent `max_retries` times
+    pub(crate) fn handle_ack_timeout(&mut self, max_retries: u16) -> Result<Packet, StateError> {
+        let pkid = self
+            .unacked_sends()
+            .min_by_key(|(_, sent)| *sent)
+            .map(|(pkid, _)| pkid)
+            .ok_or(StateError::InvalidState)?;
+
+        let sent = self.outgoing_sent[pkid as usize].as_mut().unwrap();
+        if sent.1 >= max_retries {
+            return Err(StateError::AckTimeout(pkid));
+        }
+
+        *sent = (Instant::now(), sent.1 + 1);
+        let mut publish = self.outgoing_pub[pkid as usize].clone().unwrap();
+        publish.dup = true;
+
+        debug!(pkid, resends = sent.1, "Resending unacked publish");
+        let event = Event::Outgoing(Outgoing::Publish(pkid));
+        self.events.push_back(event);
+        self.last_outgoing = Instant::now();
+
+        Ok(Packet::Publish(publish))
+    }
+
+    fn unacked_sends(&self) -> impl Iterator<Item = (u16, Instant)> + '_ {
+        self.outgoing_pub
+            .iter()
+            .zip(&self.outgoing_sent)
+            .enumerate()
+            .filter_map(|(pkid, sent)| match sent {
+                (Some(_), Some((sent, _))) => Some((pkid as u16, *sent)),
+                _ => None,
+            })
+    }
+
     fn outgoing_pubrel(&mut self, pubrel: PubRel) -> Result<Option<Packet>, StateError> {
         let pubrel = self.save_pubrel(pubrel)?;
 
-        debug!("Pubrel. Pkid = {}", pubrel.pkid);
+        debug!(pkid = pubrel.pkid, "Pubrel");
         let event = Event::Outgoing(Outgoing::PubRel(pubrel.pkid));
         self.events.push_back(event);
 
@@ -368,6 +885,19 @@ fn outgoing_pubrel(&mut self, pubrel: PubRel) -> Result<Option<Packet>, StateErr
     }
 
     fn outgoing_puback(&mut self, puback: PubAck) -> Result<Option<Packet>, StateError> {
+        if self.manual_acks {
+            if self.unacked_incoming[puback.pkid as usize].take().is_none() {
+                debug!(
+                    pkid = puback.pkid,
+                    "Dropping puback of a stale or acked publish"
+                );
+                return Ok(None);
+            }
+
+            self.unacked_incoming_count -= 1;
+        }
+
+        debug!(pkid = puback.pkid, "Puback");
         let event = Event::Outgoing(Outgoing::PubAck(puback.pkid));
         self.events.push_back(event);
 
@@ -375,6 +905,20 @@ fn outgoing_puback(&mut self, puback: PubAck) -> Result<Option<Packet>, StateErr
     }
 
     fn outgoing_pubrec(&mut self, pubrec: PubRec) -> Result<Option<Packet>, StateError> {
+        if self.manual_acks {
+            if self.unacked_incoming[pubrec.pkid as usize].take().is_none() {
+                debug!(
+                    pkid = pubrec.pkid,
+                    "Dropping pubrec of a stale or acked publish"
+                );
+                return Ok(None);
+            }
+
+            self.unacked_incoming_count -= 1;
+            self.unacked_incoming_qos2 = self.unacked_incoming_qos2.saturating_sub(1);
+        }
+
+        debug!(pkid = pubrec.pkid, "Pubrec");
         let event = Event::Outgoing(Outgoing::PubRec(pubrec.pkid));
         self.events.push_back(event);
 
@@ -426,6 +970,7 @@ fn outgoing_subscribe(
 
         let pkid = self.next_pkid();
         subscription.pkid = pkid;
+        self.subscribed_filters.subscribe(&subscription);
 
         debug!(
             "Subscribe. Topics = {:?}, Pkid = {:?}",
@@ -444,6 +989,7 @@ fn outgoing_unsubscribe(
     ) -> Result<Option<Packet>, StateError> {
         let pkid = self.next_pkid();
         unsub.pkid = pkid;
+        self.subscribed_filters.unsubscribe(&unsub);
 
         debug!(
             "Unsubscribe. Topics = {:?}, Pkid = {:?}",
@@ -468,6 +1014,9 @@ fn outgoing_disconnect(&mut self) -> Result<Option<Packet>, StateError> {
     fn check_collision(&mut self, pkid: u16) -> Option<Publish> {
         if let Some(publish) = &self.collision {
             if publish.pkid == pkid {
+                if let Some(notice) = self.collision_notice.take() {
+                    self.delivery_notices[pkid as usize] = Some(notice);
+                }
                 return self.collision.take();
             }
         }
@@ -512,10 +1061,14 @@ fn next_pkid(&mut self) -> u16 {
 
 #[cfg(test)]
 mod test {
-    use super::{MqttState, StateError};
+    use super::{LastValues, MqttState, StateError, SubscribedFilters};
     use crate::mqttbytes::v4::*;
     use crate::mqttbytes::*;
-    use crate::{Event, Incoming, Outgoing, Request};
+    use crate::{
+        DeliveryNotice, DeliveryStatus, Event, Incoming, Outgoing, Request, TopicValidation,
+    };
+    use std::sync::Arc;
+    use std::time::Duration;
 
     fn build_outgoing_publish(qos: QoS) -> Publish {
         let topic = "hello/world".to_owned();
@@ -657,10 +1210,35 @@ fn incoming_publish_should_not_be_acked_with_manual_acks() {
 
         let pkid = mqtt.incoming_pub[3].unwrap();
         assert_eq!(pkid, 3);
+        assert_eq!(mqtt.unacked_incoming(), 2);
 
         assert!(mqtt.events.is_empty());
     }
 
+    #[test]
+    fn manual_acks_of_unknown_or_forgotten_publishes_are_dropped() {
+        let mut mqtt = build_mqttstate();
+        mqtt.manual_acks = true;
+
+        let publish1 = build_incoming_publish(QoS::AtLeastOnce, 1);
+        let publish2 = build_incoming_publish(QoS::ExactlyOnce, 2);
+        mqtt.handle_incoming_publish(&publish1).unwrap();
+        mqtt.handle_incoming_publish(&publish2).unwrap();
+
+        let puback = Request::PubAck(PubAck::new(1));
+        let packet = mqtt.handle_outgoing_packet(puback.clone()).unwrap();
+        assert!(matches!(packet, Some(Packet::PubAck(PubAck { pkid: 1 }))));
+
+        // Acking twice sends only one puback
+        assert!(mqtt.handle_outgoing_packet(puback).unwrap().is_none());
+
+        // Session wasn't resumed after reconnection
+        mqtt.forget_unacked_incoming();
+        assert_eq!(mqtt.unacked_incoming(), 0);
+        let pubrec = Request::PubRec(PubRec::new(2));
+        assert!(mqtt.handle_outgoing_packet(pubrec).unwrap().is_none());
+    }
+
     #[test]
     fn incoming_qos2_publish_should_send_rec_to_network_and_publish_to_user() {
         let mut mqtt = build_mqttstate();
@@ -673,6 +1251,200 @@ fn incoming_qos2_publish_should_send_rec_to_network_and_publish_to_user() {
         }
     }
 
+    #[test]
+    fn filtered_incoming_publishes_are_acked_but_not_surfaced() {
+        let mut mqtt = MqttState::new(100, true);
+        mqtt.publish_filters.set_subscription(
+            "hello/+".to_owned(),
+            Some(Arc::new(|p: &Publish| p.payload[0] != 1)),
+        );
+
+        let publish = build_incoming_publish(QoS::AtLeastOnce, 1);
+        let packet = mqtt
+            .handle_incoming_packet(Incoming::Publish(publish))
+            .unwrap()
+            .unwrap();
+        match packet {
+            Packet::PubAck(puback) => assert_eq!(puback.pkid, 1),
+            _ => panic!("Invalid network request: {:?}", packet),
+        }
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Outgoing(Outgoing::PubAck(1)))
+        );
+        assert!(mqtt.events.is_empty());
+
+        // publishes on other topics aren't evaluated by the predicate
+        let mut publish = build_incoming_publish(QoS::AtMostOnce, 0);
+        publish.topic = "other/world".to_owned();
+        mqtt.handle_incoming_packet(Incoming::Publish(publish.clone()))
+            .unwrap();
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Incoming(Incoming::Publish(publish)))
+        );
+    }
+
+    #[test]
+    fn publishes_not_matching_subscriptions_are_dropped_when_validated() {
+        let mut mqtt = build_mqttstate();
+        mqtt.topic_validation = TopicValidation::Drop;
+
+        let subscribe = Subscribe::new("hello/+", QoS::AtMostOnce);
+        mqtt.handle_outgoing_packet(Request::Subscribe(subscribe))
+            .unwrap();
+        let unsubscribe = Unsubscribe::new("hello/+");
+        mqtt.handle_outgoing_packet(Request::Unsubscribe(unsubscribe))
+            .unwrap();
+        mqtt.events.clear();
+
+        // filter is active till unsubscribe is acked
+        let publish = build_incoming_publish(QoS::AtMostOnce, 0);
+        mqtt.handle_incoming_packet(Incoming::Publish(publish.clone()))
+            .unwrap();
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Incoming(Incoming::Publish(publish.clone())))
+        );
+
+        mqtt.handle_incoming_packet(Incoming::UnsubAck(UnsubAck::new(2)))
+            .unwrap();
+        mqtt.events.clear();
+
+        // dropped publishes are still acked
+        let mut qos1 = build_incoming_publish(QoS::AtLeastOnce, 1);
+        qos1.topic = "hello/world".to_owned();
+        let packet = mqtt
+            .handle_incoming_packet(Incoming::Publish(qos1))
+            .unwrap();
+        assert_eq!(packet, Some(Packet::PubAck(PubAck::new(1))));
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Outgoing(Outgoing::PubAck(1)))
+        );
+        assert!(mqtt.events.is_empty());
+
+        mqtt.topic_validation = TopicValidation::Warn;
+        mqtt.handle_incoming_packet(Incoming::Publish(publish.clone()))
+            .unwrap();
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Incoming(Incoming::Publish(publish)))
+        );
+    }
+
+    #[test]
+    fn shared_subscriptions_match_topics_of_their_filter() {
+        let mut filters = SubscribedFilters::default();
+        filters.subscribe(&Subscribe::new("$share/group/hello/+", QoS::AtMostOnce));
+
+        assert!(filters.matches("hello/world"));
+        assert!(!filters.matches("group/hello"));
+    }
+
+    #[test]
+    fn incoming_publishes_go_to_sink_instead_of_events() {
+        let mut mqtt = build_mqttstate();
+        let (tx, rx) = flume::unbounded();
+        let sink = move |publish: Publish| tx.send(publish).unwrap();
+        mqtt.publish_sink = Some(Arc::new(sink));
+
+        let publish = build_incoming_publish(QoS::AtLeastOnce, 1);
+        mqtt.handle_incoming_packet(Incoming::Publish(publish.clone()))
+            .unwrap();
+        mqtt.handle_incoming_packet(Incoming::PingResp).unwrap();
+
+        assert_eq!(rx.try_recv().unwrap(), publish);
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Outgoing(Outgoing::PubAck(1)))
+        );
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Incoming(Incoming::PingResp))
+        );
+        assert!(mqtt.events.is_empty());
+    }
+
+    #[test]
+    fn incoming_publish_is_copied_to_every_matching_stream() {
+        let mut mqtt = build_mqttstate();
+        let streams = mqtt.subscription_streams.clone();
+        let wildcard = streams.add("hello/+".to_owned());
+        let exact = streams.add("hello/world".to_owned());
+        let other = streams.add("other/#".to_owned());
+        drop(streams.add("hello/#".to_owned()));
+
+        let publish = build_incoming_publish(QoS::AtMostOnce, 0);
+        mqtt.handle_incoming_packet(Incoming::Publish(publish.clone()))
+            .unwrap();
+
+        assert_eq!(wildcard.try_recv().unwrap(), publish);
+        assert_eq!(exact.try_recv().unwrap(), publish);
+        assert!(other.try_recv().is_err());
+        assert_eq!(streams.streams.lock().unwrap().len(), 3);
+        // still yielded as an event
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Incoming(Incoming::Publish(publish)))
+        );
+    }
+
+    #[test]
+    fn last_values_evict_least_recently_used_topic() {
+        let mut mqtt = build_mqttstate();
+        mqtt.last_values = LastValues::new(Some(2));
+        let last_values = mqtt.last_values.clone();
+
+        let mut incoming = |topic: &str, payload: u8| {
+            let publish = Publish::new(topic, QoS::AtMostOnce, vec![payload]);
+            mqtt.handle_incoming_packet(Incoming::Publish(publish))
+                .unwrap();
+        };
+
+        incoming("a", 1);
+        incoming("b", 1);
+        incoming("a", 2);
+        assert_eq!(last_values.get("a").unwrap().payload[0], 2);
+
+        // b is the least recently used
+        incoming("c", 1);
+        assert!(last_values.get("b").is_none());
+        assert!(last_values.get("a").is_some());
+        assert!(last_values.get("c").is_some());
+
+        assert!(LastValues::default().get("a").is_none());
+    }
+
+    #[test]
+    fn unacked_publishes_are_resent_with_dup_till_maximum_retries() {
+        let mut mqtt = build_mqttstate();
+        let timeout = Duration::from_secs(1);
+
+        mqtt.outgoing_publish(build_outgoing_publish(QoS::AtLeastOnce))
+            .unwrap();
+        mqtt.outgoing_publish(build_outgoing_publish(QoS::AtLeastOnce))
+            .unwrap();
+        mqtt.handle_incoming_puback(&PubAck::new(2)).unwrap();
+        assert!(mqtt.next_ack_deadline(timeout).is_some());
+
+        match mqtt.handle_ack_timeout(1).unwrap() {
+            Packet::Publish(publish) => {
+                assert_eq!(publish.pkid, 1);
+                assert!(publish.dup);
+            }
+            packet => panic!("Invalid packet {packet:?}"),
+        }
+
+        match mqtt.handle_ack_timeout(1) {
+            Err(StateError::AckTimeout(1)) => {}
+            e => panic!("Publish should time out, got {e:?}"),
+        }
+
+        mqtt.handle_incoming_puback(&PubAck::new(1)).unwrap();
+        assert!(mqtt.next_ack_deadline(timeout).is_none());
+    }
+
     #[test]
     fn incoming_puback_should_remove_correct_publish_from_queue() {
         let mut mqtt = build_mqttstate();
@@ -694,16 +1466,94 @@ fn incoming_puback_should_remove_correct_publish_from_queue() {
         assert!(mqtt.outgoing_pub[2].is_none());
     }
 
+    #[test]
+    fn tracked_publishes_are_notified_once_delivered() {
+        let mut mqtt = build_mqttstate();
+        let (tx, rx) = flume::unbounded();
+        let notice = |name: &'static str| {
+            let tx = tx.clone();
+            DeliveryNotice::new(move |status| tx.send((name, status)).unwrap())
+        };
+
+        let qos0 = build_outgoing_publish(QoS::AtMostOnce);
+        let qos1 = build_outgoing_publish(QoS::AtLeastOnce);
+        let qos2 = build_outgoing_publish(QoS::ExactlyOnce);
+        mqtt.handle_outgoing_packet(Request::PublishTracked(qos0, notice("qos0")))
+            .unwrap();
+        mqtt.handle_outgoing_packet(Request::PublishTracked(qos1, notice("qos1")))
+            .unwrap();
+        mqtt.handle_outgoing_packet(Request::PublishTracked(qos2, notice("qos2")))
+            .unwrap();
+        assert_eq!(rx.try_recv(), Ok(("qos0", DeliveryStatus::Delivered)));
+        assert!(rx.try_recv().is_err());
+
+        mqtt.handle_incoming_puback(&PubAck::new(1)).unwrap();
+        assert_eq!(rx.try_recv(), Ok(("qos1", DeliveryStatus::Delivered)));
+
+        // released, but not yet completed
+        mqtt.handle_incoming_pubrec(&PubRec::new(2)).unwrap();
+        assert!(rx.try_recv().is_err());
+        mqtt.handle_incoming_pubcomp(&PubComp::new(2)).unwrap();
+        assert_eq!(rx.try_recv(), Ok(("qos2", DeliveryStatus::Delivered)));
+    }
+
+    #[test]
+    fn tracked_publishes_keep_notices_across_clean_and_report_drops() {
+        let mut mqtt = build_mqttstate();
+        let (tx, rx) = flume::unbounded();
+        let notice = DeliveryNotice::new(move |status| tx.send(status).unwrap());
+
+        let publish = build_outgoing_publish(QoS::AtLeastOnce);
+        mqtt.handle_outgoing_packet(Request::PublishTracked(publish, notice))
+            .unwrap();
+
+        let mut requests = mqtt.clean();
+        assert_eq!(requests.len(), 1);
+        assert!(matches!(requests[0], Request::PublishTracked(..)));
+        assert!(rx.try_recv().is_err());
+
+        // never resent
+        requests.clear();
+        assert_eq!(rx.try_recv(), Ok(DeliveryStatus::Dropped));
+    }
+
     #[test]
     fn incoming_puback_with_pkid_greater_than_max_inflight_should_be_handled_gracefully() {
         let mut mqtt = build_mqttstate();
 
-        let got = mqtt.handle_incoming_puback(&PubAck::new(101)).unwrap_err();
+        let got = mqtt.handle_incoming_puback(&PubAck::new(101)).unwrap();
+        assert!(got.is_none());
+    }
 
-        match got {
-            StateError::Unsolicited(pkid) => assert_eq!(pkid, 101),
-            e => panic!("Unexpected error: {}", e),
-        }
+    #[test]
+    fn acks_of_unknown_packet_ids_are_ignored() {
+        let mut mqtt = build_mqttstate();
+        let publish = build_outgoing_publish(QoS::AtLeastOnce);
+        mqtt.outgoing_publish(publish).unwrap();
+
+        // spurious and duplicate acks
+        assert!(mqtt
+            .handle_incoming_puback(&PubAck::new(2))
+            .unwrap()
+            .is_none());
+        assert!(mqtt
+            .handle_incoming_puback(&PubAck::new(1))
+            .unwrap()
+            .is_none());
+        assert!(mqtt
+            .handle_incoming_puback(&PubAck::new(1))
+            .unwrap()
+            .is_none());
+        assert!(mqtt
+            .handle_incoming_pubrec(&PubRec::new(3))
+            .unwrap()
+            .is_none());
+        assert!(mqtt
+            .handle_incoming_pubcomp(&PubComp::new(3))
+            .unwrap()
+            .is_none());
+        assert_eq!(mqtt.inflight, 0);
+        assert_eq!(mqtt.last_puback, 1);
     }
 
     #[test]
@@ -769,6 +1619,23 @@ fn incoming_pubrel_should_send_comp_to_network_and_nothing_to_user() {
         }
     }
 
+    #[test]
+    fn orphan_pubrel_is_completed() {
+        let mut mqtt = build_mqttstate();
+
+        let packet = mqtt
+            .handle_incoming_packet(Incoming::PubRel(PubRel::new(7)))
+            .unwrap();
+        match packet {
+            Some(Packet::PubComp(pubcomp)) => assert_eq!(pubcomp.pkid, 7),
+            packet => panic!("Invalid network request: {:?}", packet),
+        }
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Outgoing(Outgoing::PubComp(7)))
+        );
+    }
+
     #[test]
     fn incoming_pubcomp_should_release_correct_pkid_from_release_queue() {
         let mut mqtt = build_mqttstate();
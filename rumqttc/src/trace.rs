@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::mqttbytes::v4::Packet;
+use crate::{Event, Outgoing};
+
+/// Last packets exchanged by an `EventLoop`, attached to errors of
+/// [`EventLoop::poll`](crate::EventLoop::poll) as [`ConnectionError::Traced`](crate::ConnectionError::Traced)
+/// when enabled with [`MqttOptions::set_packet_trace`](crate::MqttOptions::set_packet_trace).
+/// Also available while connected with [`EventLoop::packet_trace`](crate::EventLoop::packet_trace)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketTrace {
+    packets: VecDeque<TracedPacket>,
+    capacity: usize,
+}
+
+/// Packet of a [`PacketTrace`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracedPacket {
+    /// Time at which the packet was polled
+    pub at: SystemTime,
+    /// Written to the network if set, read otherwise
+    pub outgoing: bool,
+    /// Type of the packet, e.g. `PubAck`
+    pub packet: &'static str,
+}
+
+impl PacketTrace {
+    pub(crate) fn new(capacity: usize) -> PacketTrace {
+        PacketTrace {
+            packets: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Traced packets, oldest first
+    pub fn packets(&self) -> impl Iterator<Item = &TracedPacket> {
+        self.packets.iter()
+    }
+
+    pub(crate) fn record(&mut self, event: &Event) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let (outgoing, packet) = match event {
+            Event::Incoming(packet) => (false, incoming_name(packet)),
+            Event::Outgoing(outgoing) => (true, outgoing_name(outgoing)),
+        };
+
+        if self.packets.len() == self.capacity {
+            self.packets.pop_front();
+        }
+
+        self.packets.push_back(TracedPacket {
+            at: SystemTime::now(),
+            outgoing,
+            packet,
+        });
+    }
+}
+
+/// Packets as `-> Publish @1700000000123`, with direction and milliseconds since unix epoch
+impl fmt::Display for PacketTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, traced) in self.packets.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+
+            let direction = if traced.outgoing { "->" } else { "<-" };
+            let millis = traced
+                .at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis());
+            write!(f, "{direction} {} @{millis}", traced.packet)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn incoming_name(packet: &Packet) -> &'static str {
+    match packet {
+        Packet::Connect(_) => "Connect",
+        Packet::ConnAck(_) => "ConnAck",
+        Packet::Publish(_) => "Publish",
+        Packet::PubAck(_) => "PubAck",
+        Packet::PubRec(_) => "PubRec",
+        Packet::PubRel(_) => "PubRel",
+        Packet::PubComp(_) => "PubComp",
+        Packet::Subscribe(_) => "Subscribe",
+        Packet::SubAck(_) => "SubAck",
+        Packet::Unsubscribe(_) => "Unsubscribe",
+        Packet::UnsubAck(_) => "UnsubAck",
+        Packet::PingReq => "PingReq",
+        Packet::PingResp => "PingResp",
+        Packet::Disconnect => "Disconnect",
+    }
+}
+
+fn outgoing_name(outgoing: &Outgoing) -> &'static str {
+    match outgoing {
+        Outgoing::Publish(_) => "Publish",
+        Outgoing::Subscribe(_) => "Subscribe",
+        Outgoing::Unsubscribe(_) => "Unsubscribe",
+        Outgoing::PubAck(_) => "PubAck",
+        Outgoing::PubRec(_) => "PubRec",
+        Outgoing::PubRel(_) => "PubRel",
+        Outgoing::PubComp(_) => "PubComp",
+        Outgoing::PingReq => "PingReq",
+        Outgoing::PingResp => "PingResp",
+        Outgoing::Disconnect => "Disconnect",
+        Outgoing::AwaitAck(_) => "AwaitAck",
+        Outgoing::Flush => "Flush",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn oldest_packets_are_evicted_past_capacity() {
+        let mut trace = PacketTrace::new(2);
+        trace.record(&Event::Outgoing(Outgoing::PingReq));
+        trace.record(&Event::Incoming(Packet::PingResp));
+        trace.record(&Event::Outgoing(Outgoing::Publish(1)));
+
+        let packets: Vec<(bool, &str)> = trace
+            .packets()
+            .map(|traced| (traced.outgoing, traced.packet))
+            .collect();
+        assert_eq!(packets, vec![(false, "PingResp"), (true, "Publish")]);
+        assert!(trace.to_string().starts_with("<- PingResp @"));
+    }
+}
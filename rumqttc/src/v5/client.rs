@@ -3,11 +3,12 @@
 use std::time::Duration;
 
 use super::mqttbytes::v5::{
-    Filter, PubAck, PubRec, Publish, PublishProperties, Subscribe, SubscribeProperties,
-    Unsubscribe, UnsubscribeProperties,
+    Filter, PubAck, PubAckProperties, PubRec, PubRecProperties, Publish, PublishProperties,
+    Subscribe, SubscribeProperties, Unsubscribe, UnsubscribeProperties,
 };
-use super::mqttbytes::{valid_filter, QoS};
-use super::{ConnectionError, Event, EventLoop, MqttOptions, Request};
+use super::mqttbytes::{has_wildcards, valid_filter, QoS};
+use super::state::SharedServerInfo;
+use super::{ConnectionError, Event, EventLoop, MqttOptions, Request, ServerInfo};
 use crate::valid_topic;
 
 use bytes::Bytes;
@@ -23,6 +24,20 @@ pub enum ClientError {
     Request(Request),
     #[error("Failed to send mqtt requests to eventloop")]
     TryRequest(Request),
+    #[error("Invalid topic of publish")]
+    InvalidTopic,
+    #[error("Publish of size {pkt_size} is larger than the broker's maximum packet size of {max}")]
+    PacketTooLarge { pkt_size: usize, max: u32 },
+    #[error("Publish with {qos:?} is above the broker's maximum QoS of {max:?}")]
+    QoSNotSupported { qos: QoS, max: QoS },
+    #[error("Broker doesn't support shared subscriptions")]
+    SharedSubscriptionUnavailable,
+    #[error("Broker doesn't support subscriptions with wildcards")]
+    WildcardSubscriptionUnavailable,
+    /// Payload of `publish_json` couldn't be serialized, nothing was published
+    #[cfg(feature = "json")]
+    #[error("Failed to serialize JSON payload: {0}")]
+    Json(#[source] serde_json::Error),
 }
 
 impl From<SendError<Request>> for ClientError {
@@ -47,6 +62,7 @@ fn from(e: TrySendError<Request>) -> Self {
 #[derive(Clone, Debug)]
 pub struct AsyncClient {
     request_tx: Sender<Request>,
+    server_info: SharedServerInfo,
 }
 
 impl AsyncClient {
@@ -56,8 +72,12 @@ impl AsyncClient {
     pub fn new(options: MqttOptions, cap: usize) -> (AsyncClient, EventLoop) {
         let eventloop = EventLoop::new(options, cap);
         let request_tx = eventloop.requests_tx.clone();
+        let server_info = eventloop.state.server_info.clone();
 
-        let client = AsyncClient { request_tx };
+        let client = AsyncClient {
+            request_tx,
+            server_info,
+        };
 
         (client, eventloop)
     }
@@ -67,7 +87,10 @@ pub fn new(options: MqttOptions, cap: usize) -> (AsyncClient, EventLoop) {
     /// This is mostly useful for creating a test instance where you can
     /// listen on the corresponding receiver.
     pub fn from_senders(request_tx: Sender<Request>) -> AsyncClient {
-        AsyncClient { request_tx }
+        AsyncClient {
+            request_tx,
+            server_info: SharedServerInfo::default(),
+        }
     }
 
     /// Sends a MQTT Publish to the `EventLoop`.
@@ -177,9 +200,96 @@ pub fn try_publish<S, P>(
         self.handle_try_publish(topic, qos, retain, payload, None)
     }
 
+    /// Checks whether the broker would accept `publish` without sending it: its topic
+    /// is valid, and its size and QoS are within the maximums the broker sent in the
+    /// CONNACK of the current connection. Only the topic is checked before connecting
+    pub fn validate_publish(&self, publish: &Publish) -> Result<(), ClientError> {
+        if !std::str::from_utf8(&publish.topic).map_or(false, valid_topic) {
+            return Err(ClientError::InvalidTopic);
+        }
+
+        let server_info = self.server_info.get();
+        if let Some(max) = server_info.max_qos {
+            if publish.qos > max {
+                let qos = publish.qos;
+                return Err(ClientError::QoSNotSupported { qos, max });
+            }
+        }
+
+        if let Some(max) = server_info.max_packet_size {
+            // packet id is assigned by the `EventLoop`, but counts towards the size
+            let mut publish = publish.clone();
+            if publish.qos != QoS::AtMostOnce && publish.pkid == 0 {
+                publish.pkid = 1;
+            }
+
+            let pkt_size = publish.size();
+            if pkt_size > max as usize {
+                return Err(ClientError::PacketTooLarge { pkt_size, max });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Capabilities the broker sent in the CONNACK of the current connection, defaults
+    /// before connecting
+    pub fn server_info(&self) -> ServerInfo {
+        self.server_info.get()
+    }
+
+    /// Refuses shared and wildcard subscriptions when the broker said it doesn't support
+    /// them, as it would disconnect on receiving one
+    fn check_subscription(&self, subscribe: &Subscribe) -> Result<(), ClientError> {
+        let server_info = self.server_info.get();
+        let paths = || subscribe.filters.iter().map(|filter| filter.path.as_str());
+        if !server_info.shared_subscription_available && paths().any(|p| p.starts_with("$share/")) {
+            return Err(ClientError::SharedSubscriptionUnavailable);
+        }
+
+        if !server_info.wildcard_subscription_available && paths().any(has_wildcards) {
+            return Err(ClientError::WildcardSubscriptionUnavailable);
+        }
+
+        Ok(())
+    }
+
     /// Sends a MQTT PubAck to the `EventLoop`. Only needed in if `manual_acks` flag is set.
     pub async fn ack(&self, publish: &Publish) -> Result<(), ClientError> {
-        let ack = get_ack_req(publish);
+        self.handle_ack(publish, None).await
+    }
+
+    /// Attempts to send a MQTT PubAck to the `EventLoop`. Only needed in if `manual_acks` flag is set.
+    pub fn try_ack(&self, publish: &Publish) -> Result<(), ClientError> {
+        self.handle_try_ack(publish, None)
+    }
+
+    /// Sends a MQTT PubAck/PubRec carrying a human readable `reason_string` to the `EventLoop`.
+    /// Only needed in if `manual_acks` flag is set.
+    pub async fn ack_with_reason_string<S: Into<String>>(
+        &self,
+        publish: &Publish,
+        reason_string: S,
+    ) -> Result<(), ClientError> {
+        self.handle_ack(publish, Some(reason_string.into())).await
+    }
+
+    /// Attempts to send a MQTT PubAck/PubRec carrying a human readable `reason_string` to the
+    /// `EventLoop`. Only needed in if `manual_acks` flag is set.
+    pub fn try_ack_with_reason_string<S: Into<String>>(
+        &self,
+        publish: &Publish,
+        reason_string: S,
+    ) -> Result<(), ClientError> {
+        self.handle_try_ack(publish, Some(reason_string.into()))
+    }
+
+    async fn handle_ack(
+        &self,
+        publish: &Publish,
+        reason_string: Option<String>,
+    ) -> Result<(), ClientError> {
+        let ack = get_ack_req(publish, reason_string);
 
         if let Some(ack) = ack {
             self.request_tx.send_async(ack).await?;
@@ -187,9 +297,12 @@ pub async fn ack(&self, publish: &Publish) -> Result<(), ClientError> {
         Ok(())
     }
 
-    /// Attempts to send a MQTT PubAck to the `EventLoop`. Only needed in if `manual_acks` flag is set.
-    pub fn try_ack(&self, publish: &Publish) -> Result<(), ClientError> {
-        let ack = get_ack_req(publish);
+    fn handle_try_ack(
+        &self,
+        publish: &Publish,
+        reason_string: Option<String>,
+    ) -> Result<(), ClientError> {
+        let ack = get_ack_req(publish, reason_string);
         if let Some(ack) = ack {
             self.request_tx.try_send(ack)?;
         }
@@ -258,6 +371,7 @@ async fn handle_subscribe<S: Into<String>>(
         let filter = Filter::new(topic, qos);
         let is_filter_valid = valid_filter(&filter.path);
         let subscribe = Subscribe::new(filter, properties);
+        self.check_subscription(&subscribe)?;
         let request: Request = Request::Subscribe(subscribe);
         if !is_filter_valid {
             return Err(ClientError::Request(request));
@@ -289,6 +403,7 @@ fn handle_try_subscribe<S: Into<String>>(
         let filter = Filter::new(topic, qos);
         let is_filter_valid = valid_filter(&filter.path);
         let subscribe = Subscribe::new(filter, properties);
+        self.check_subscription(&subscribe)?;
         let request = Request::Subscribe(subscribe);
         if !is_filter_valid {
             return Err(ClientError::TryRequest(request));
@@ -319,9 +434,12 @@ async fn handle_subscribe_many<T>(
     where
         T: IntoIterator<Item = Filter>,
     {
-        let mut topics_iter = topics.into_iter();
-        let is_valid_filters = topics_iter.all(|filter| valid_filter(&filter.path));
-        let subscribe = Subscribe::new_many(topics_iter, properties);
+        let subscribe = Subscribe::new_many(topics, properties);
+        self.check_subscription(&subscribe)?;
+        let is_valid_filters = subscribe
+            .filters
+            .iter()
+            .all(|filter| valid_filter(&filter.path));
         let request = Request::Subscribe(subscribe);
         if !is_valid_filters {
             return Err(ClientError::Request(request));
@@ -358,9 +476,12 @@ fn handle_try_subscribe_many<T>(
     where
         T: IntoIterator<Item = Filter>,
     {
-        let mut topics_iter = topics.into_iter();
-        let is_valid_filters = topics_iter.all(|filter| valid_filter(&filter.path));
-        let subscribe = Subscribe::new_many(topics_iter, properties);
+        let subscribe = Subscribe::new_many(topics, properties);
+        self.check_subscription(&subscribe)?;
+        let is_valid_filters = subscribe
+            .filters
+            .iter()
+            .all(|filter| valid_filter(&filter.path));
         let request = Request::Subscribe(subscribe);
         if !is_valid_filters {
             return Err(ClientError::TryRequest(request));
@@ -450,11 +571,23 @@ pub fn try_disconnect(&self) -> Result<(), ClientError> {
     }
 }
 
-fn get_ack_req(publish: &Publish) -> Option<Request> {
+fn get_ack_req(publish: &Publish, reason_string: Option<String>) -> Option<Request> {
     let ack = match publish.qos {
         QoS::AtMostOnce => return None,
-        QoS::AtLeastOnce => Request::PubAck(PubAck::new(publish.pkid, None)),
-        QoS::ExactlyOnce => Request::PubRec(PubRec::new(publish.pkid, None)),
+        QoS::AtLeastOnce => {
+            let properties = reason_string.map(|reason_string| PubAckProperties {
+                reason_string: Some(reason_string),
+                user_properties: Vec::new(),
+            });
+            Request::PubAck(PubAck::new(publish.pkid, properties))
+        }
+        QoS::ExactlyOnce => {
+            let properties = reason_string.map(|reason_string| PubRecProperties {
+                reason_string: Some(reason_string),
+                user_properties: Vec::new(),
+            });
+            Request::PubRec(PubRec::new(publish.pkid, properties))
+        }
     };
     Some(ack)
 }
@@ -584,14 +717,20 @@ pub fn try_publish<S, P>(
         self.client.try_publish(topic, qos, retain, payload)
     }
 
+    /// Checks whether the broker would accept `publish` without sending it, see
+    /// [`AsyncClient::validate_publish`]
+    pub fn validate_publish(&self, publish: &Publish) -> Result<(), ClientError> {
+        self.client.validate_publish(publish)
+    }
+
+    /// Capabilities the broker sent in CONNACK, see [`AsyncClient::server_info`]
+    pub fn server_info(&self) -> ServerInfo {
+        self.client.server_info()
+    }
+
     /// Sends a MQTT PubAck to the `EventLoop`. Only needed in if `manual_acks` flag is set.
     pub fn ack(&self, publish: &Publish) -> Result<(), ClientError> {
-        let ack = get_ack_req(publish);
-
-        if let Some(ack) = ack {
-            self.client.request_tx.send(ack)?;
-        }
-        Ok(())
+        self.handle_ack(publish, None)
     }
 
     /// Sends a MQTT PubAck to the `EventLoop`. Only needed in if `manual_acks` flag is set.
@@ -600,6 +739,40 @@ pub fn try_ack(&self, publish: &Publish) -> Result<(), ClientError> {
         Ok(())
     }
 
+    /// Sends a MQTT PubAck/PubRec carrying a human readable `reason_string` to the `EventLoop`.
+    /// Only needed in if `manual_acks` flag is set.
+    pub fn ack_with_reason_string<S: Into<String>>(
+        &self,
+        publish: &Publish,
+        reason_string: S,
+    ) -> Result<(), ClientError> {
+        self.handle_ack(publish, Some(reason_string.into()))
+    }
+
+    /// Attempts to send a MQTT PubAck/PubRec carrying a human readable `reason_string` to the
+    /// `EventLoop`. Only needed in if `manual_acks` flag is set.
+    pub fn try_ack_with_reason_string<S: Into<String>>(
+        &self,
+        publish: &Publish,
+        reason_string: S,
+    ) -> Result<(), ClientError> {
+        self.client
+            .try_ack_with_reason_string(publish, reason_string)
+    }
+
+    fn handle_ack(
+        &self,
+        publish: &Publish,
+        reason_string: Option<String>,
+    ) -> Result<(), ClientError> {
+        let ack = get_ack_req(publish, reason_string);
+
+        if let Some(ack) = ack {
+            self.client.request_tx.send(ack)?;
+        }
+        Ok(())
+    }
+
     /// Sends a MQTT Subscribe to the `EventLoop`
     fn handle_subscribe<S: Into<String>>(
         &self,
@@ -610,6 +783,7 @@ fn handle_subscribe<S: Into<String>>(
         let filter = Filter::new(topic, qos);
         let is_filter_valid = valid_filter(&filter.path);
         let subscribe = Subscribe::new(filter, properties);
+        self.client.check_subscription(&subscribe)?;
         let request = Request::Subscribe(subscribe);
         if !is_filter_valid {
             return Err(ClientError::Request(request));
@@ -655,9 +829,12 @@ fn handle_subscribe_many<T>(
     where
         T: IntoIterator<Item = Filter>,
     {
-        let mut topics_iter = topics.into_iter();
-        let is_valid_filters = topics_iter.all(|filter| valid_filter(&filter.path));
-        let subscribe = Subscribe::new_many(topics_iter, properties);
+        let subscribe = Subscribe::new_many(topics, properties);
+        self.client.check_subscription(&subscribe)?;
+        let is_valid_filters = subscribe
+            .filters
+            .iter()
+            .all(|filter| valid_filter(&filter.path));
         let request = Request::Subscribe(subscribe);
         if !is_valid_filters {
             return Err(ClientError::Request(request));
@@ -868,7 +1045,8 @@ fn next(&mut self) -> Option<Self::Item> {
 
 #[cfg(test)]
 mod test {
-    use crate::v5::mqttbytes::v5::LastWill;
+    use crate::v5::mqttbytes::v5::{ConnAck, ConnAckProperties, ConnectReturnCode, LastWill};
+    use crate::v5::Incoming;
 
     use super::*;
 
@@ -887,6 +1065,162 @@ fn calling_iter_twice_on_connection_shouldnt_panic() {
         let _ = connection.iter();
     }
 
+    fn connack_properties() -> ConnAckProperties {
+        ConnAckProperties {
+            session_expiry_interval: None,
+            receive_max: None,
+            max_qos: None,
+            retain_available: None,
+            max_packet_size: None,
+            assigned_client_identifier: None,
+            topic_alias_max: None,
+            reason_string: None,
+            user_properties: vec![],
+            wildcard_subscription_available: None,
+            subscription_identifiers_available: None,
+            shared_subscription_available: None,
+            server_keep_alive: None,
+            response_information: None,
+            server_reference: None,
+            authentication_method: None,
+            authentication_data: None,
+        }
+    }
+
+    fn connack(properties: Option<ConnAckProperties>) -> Incoming {
+        Incoming::ConnAck(ConnAck {
+            session_present: false,
+            code: ConnectReturnCode::Success,
+            properties,
+        })
+    }
+
+    #[test]
+    fn publishes_are_validated_against_limits_of_broker() {
+        let mqttoptions = MqttOptions::new("test-1", "localhost", 1883);
+        let (client, eventloop) = AsyncClient::new(mqttoptions, 10);
+        let publish =
+            |topic: &str, qos, payload: &[u8]| Publish::new(topic, qos, payload.to_vec(), None);
+
+        assert!(matches!(
+            client.validate_publish(&publish("hello/+", QoS::AtMostOnce, b"")),
+            Err(ClientError::InvalidTopic)
+        ));
+        // nothing but the topic is known before connecting
+        client
+            .validate_publish(&publish("hello/world", QoS::ExactlyOnce, &[0; 1024]))
+            .unwrap();
+
+        let properties = ConnAckProperties {
+            max_qos: Some(1),
+            max_packet_size: Some(100),
+            ..connack_properties()
+        };
+        let mut state = eventloop.state;
+        state
+            .handle_incoming_packet(connack(Some(properties)))
+            .unwrap();
+
+        client
+            .validate_publish(&publish("hello/world", QoS::AtLeastOnce, &[0; 80]))
+            .unwrap();
+        assert!(matches!(
+            client.validate_publish(&publish("hello/world", QoS::ExactlyOnce, b"")),
+            Err(ClientError::QoSNotSupported {
+                qos: QoS::ExactlyOnce,
+                max: QoS::AtLeastOnce
+            })
+        ));
+        assert!(matches!(
+            client.validate_publish(&publish("hello/world", QoS::AtLeastOnce, &[0; 100])),
+            Err(ClientError::PacketTooLarge { max: 100, .. })
+        ));
+
+        // limits are those of the latest connection
+        state.handle_incoming_packet(connack(None)).unwrap();
+        client
+            .validate_publish(&publish("hello/world", QoS::ExactlyOnce, &[0; 100]))
+            .unwrap();
+    }
+
+    #[test]
+    fn shared_subscriptions_are_refused_when_broker_does_not_support_them() {
+        let mqttoptions = MqttOptions::new("test-1", "localhost", 1883);
+        let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+        assert!(client.server_info().shared_subscription_available);
+
+        let properties = ConnAckProperties {
+            shared_subscription_available: Some(0),
+            ..connack_properties()
+        };
+        eventloop
+            .state
+            .handle_incoming_packet(connack(Some(properties)))
+            .unwrap();
+        assert!(!client.server_info().shared_subscription_available);
+
+        assert!(matches!(
+            client.try_subscribe("$share/group/hello/world", QoS::AtMostOnce),
+            Err(ClientError::SharedSubscriptionUnavailable)
+        ));
+        let filters = vec![
+            Filter::new("hello/world", QoS::AtMostOnce),
+            Filter::new("$share/group/hello/world", QoS::AtMostOnce),
+        ];
+        assert!(matches!(
+            client.try_subscribe_many(filters),
+            Err(ClientError::SharedSubscriptionUnavailable)
+        ));
+        client
+            .try_subscribe("hello/world", QoS::AtMostOnce)
+            .unwrap();
+    }
+
+    #[test]
+    fn wildcard_subscriptions_are_refused_when_broker_does_not_support_them() {
+        let mqttoptions = MqttOptions::new("test-1", "localhost", 1883);
+        let (client, mut eventloop) = AsyncClient::new(mqttoptions, 10);
+        assert!(client.server_info().wildcard_subscription_available);
+
+        let properties = ConnAckProperties {
+            wildcard_subscription_available: Some(0),
+            ..connack_properties()
+        };
+        eventloop
+            .state
+            .handle_incoming_packet(connack(Some(properties)))
+            .unwrap();
+        assert!(!client.server_info().wildcard_subscription_available);
+
+        for filter in ["hello/+", "hello/#", "#"] {
+            assert!(matches!(
+                client.try_subscribe(filter, QoS::AtMostOnce),
+                Err(ClientError::WildcardSubscriptionUnavailable)
+            ));
+        }
+        client
+            .try_subscribe("hello/world", QoS::AtMostOnce)
+            .unwrap();
+    }
+
+    #[test]
+    fn subscribe_many_sends_all_filters() {
+        let (tx, rx) = flume::bounded(1);
+        let client = AsyncClient::from_senders(tx);
+        let filters = vec![
+            Filter::new("hello/0", QoS::AtMostOnce),
+            Filter::new("hello/1", QoS::AtLeastOnce),
+            Filter::new("hello/2", QoS::ExactlyOnce),
+        ];
+
+        client.try_subscribe_many(filters.clone()).unwrap();
+
+        match rx.try_recv().unwrap() {
+            Request::Subscribe(subscribe) => assert_eq!(subscribe.filters, filters),
+            request => panic!("Unexpected request {request:?}"),
+        }
+    }
+
     #[test]
     fn should_be_able_to_build_test_client_from_channel() {
         let (tx, rx) = flume::bounded(1);
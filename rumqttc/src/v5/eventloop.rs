@@ -1,8 +1,12 @@
 use super::framed::Network;
-use super::mqttbytes::v5::*;
+use super::mqttbytes::{self, v5::*};
 use super::{Incoming, MqttOptions, MqttState, Outgoing, Request, StateError, Transport};
-use crate::eventloop::socket_connect;
+use crate::dns::DnsCache;
+use crate::eventloop::socket_connect_cached;
 use crate::framed::AsyncReadWrite;
+use crate::mqttbytes::Protocol;
+#[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+use crate::TlsInfo;
 
 use flume::{bounded, Receiver, Sender};
 use tokio::select;
@@ -31,6 +35,10 @@
 #[cfg(feature = "proxy")]
 use crate::proxy::ProxyError;
 
+/// v5 connects in a row closed by the broker before CONNACK after which the connection
+/// falls back to MQTT 3.1.1
+const CLOSES_BEFORE_FALLBACK: usize = 2;
+
 /// Critical errors during eventloop polling
 #[derive(Debug, thiserror::Error)]
 pub enum ConnectionError {
@@ -51,6 +59,12 @@ pub enum ConnectionError {
     Io(#[from] io::Error),
     #[error("Connection refused, return code: `{0:?}`")]
     ConnectionRefused(ConnectReturnCode),
+    #[error("Connection refused, return code: `{code:?}`, use server: `{server_reference}`")]
+    ServerRedirect {
+        code: ConnectReturnCode,
+        /// Other server to use, `host[:port]`
+        server_reference: String,
+    },
     #[error("Expected ConnAck packet, received: {0:?}")]
     NotConnAck(Box<Packet>),
     #[error("Requests done")]
@@ -82,6 +96,14 @@ pub struct EventLoop {
     network: Option<Network>,
     /// Keep alive time
     keepalive_timeout: Option<Pin<Box<Sleep>>>,
+    /// Deadline to receive pingresp for the last pingreq
+    pingresp_timeout: Option<Pin<Box<Sleep>>>,
+    /// Resolved addresses of the broker
+    dns_cache: DnsCache,
+    /// Protocol spoken with the broker, 3.1.1 (`V4`) after falling back from v5
+    protocol: Protocol,
+    /// v5 connects in a row which the broker closed before CONNACK
+    closed_before_connack: usize,
 }
 
 /// Events which can be yielded by the event loop
@@ -110,6 +132,10 @@ pub fn new(options: MqttOptions, cap: usize) -> EventLoop {
             pending,
             network: None,
             keepalive_timeout: None,
+            pingresp_timeout: None,
+            dns_cache: DnsCache::default(),
+            protocol: Protocol::V5,
+            closed_before_connack: 0,
         }
     }
 
@@ -123,6 +149,7 @@ pub fn new(options: MqttOptions, cap: usize) -> EventLoop {
     pub fn clean(&mut self) {
         self.network = None;
         self.keepalive_timeout = None;
+        self.pingresp_timeout = None;
         self.pending.extend(self.state.clean());
 
         // drain requests from channel which weren't yet received
@@ -130,20 +157,38 @@ pub fn clean(&mut self) {
         self.pending.extend(requests_in_channel);
     }
 
+    /// Version and cipher suite negotiated by the current connection when `Transport::Tls`
+    /// is used with rustls
+    #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        self.network
+            .as_ref()
+            .and_then(|network| network.tls_info.clone())
+    }
+
     /// Yields Next notification or outgoing request and periodically pings
     /// the broker. Continuing to poll will reconnect to the broker if there is
     /// a disconnection.
     /// **NOTE** Don't block this while iterating
     pub async fn poll(&mut self) -> Result<Event, ConnectionError> {
         if self.network.is_none() {
-            let (network, connack) = time::timeout(
+            let (network, connack) = match time::timeout(
                 Duration::from_secs(self.options.connection_timeout()),
-                connect(&mut self.options),
+                connect(&mut self.options, &self.dns_cache, self.protocol),
             )
-            .await??;
+            .await?
+            {
+                Ok(connected) => connected,
+                Err(e) => {
+                    self.follow_server_reference(&e);
+                    self.fall_back_to_v311(&e);
+                    return Err(e);
+                }
+            };
             self.network = Some(network);
+            self.closed_before_connack = 0;
 
-            if self.keepalive_timeout.is_none() {
+            if self.keepalive_timeout.is_none() && !self.options.keep_alive.is_zero() {
                 self.keepalive_timeout = Some(Box::pin(time::sleep(self.options.keep_alive)));
             }
 
@@ -154,11 +199,84 @@ pub async fn poll(&mut self) -> Result<Event, ConnectionError> {
             Ok(v) => Ok(v),
             Err(e) => {
                 self.clean();
+                self.follow_server_reference(&e);
                 Err(e)
             }
         }
     }
 
+    /// Connects to the referenced server from the next poll when the broker redirects
+    /// and the options allow following it
+    fn follow_server_reference(&mut self, e: &ConnectionError) {
+        if !self.options.follow_server_reference() {
+            return;
+        }
+
+        let server_reference = match e {
+            ConnectionError::ServerRedirect {
+                server_reference, ..
+            } => server_reference,
+            ConnectionError::MqttState(StateError::ServerDisconnect {
+                server_reference: Some(server_reference),
+                ..
+            }) => server_reference,
+            _ => return,
+        };
+
+        self.options.set_server_reference(server_reference);
+    }
+
+    /// Connects with MQTT 3.1.1 from the next poll when the broker doesn't support v5
+    /// and the options allow falling back
+    fn fall_back_to_v311(&mut self, e: &ConnectionError) {
+        if !self.options.protocol_fallback() || self.protocol == Protocol::V4 {
+            return;
+        }
+
+        let closed = match e {
+            ConnectionError::MqttState(StateError::ConnectionAborted) => true,
+            ConnectionError::MqttState(StateError::Deserialization(mqttbytes::Error::Io(e))) => {
+                e.kind() == io::ErrorKind::ConnectionReset
+            }
+            _ => false,
+        };
+
+        let unsupported = match e {
+            ConnectionError::ConnectionRefused(
+                ConnectReturnCode::RefusedProtocolVersion
+                | ConnectReturnCode::UnsupportedProtocolVersion,
+            ) => true,
+            // Brokers which don't understand v5 CONNECT close the connection instead. A
+            // close can be transient as well, so v5 is retried before falling back
+            _ if closed => {
+                self.closed_before_connack += 1;
+                self.closed_before_connack >= CLOSES_BEFORE_FALLBACK
+            }
+            _ => {
+                self.closed_before_connack = 0;
+                false
+            }
+        };
+
+        if unsupported {
+            warn!("Broker doesn't support MQTT v5 ({e}), downgrading to MQTT 3.1.1");
+            self.protocol = Protocol::V4;
+        }
+    }
+
+    /// Protocol spoken with the broker. `V4` (MQTT 3.1.1) after falling back from v5,
+    /// see [`MqttOptions::set_protocol_fallback`]
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Response Information returned by the broker in the last CONNACK, to construct
+    /// response topics. Only sent when requested with
+    /// [`MqttOptions::set_request_response_information`]
+    pub fn response_information(&self) -> Option<&str> {
+        self.state.response_information.as_deref()
+    }
+
     /// Select on network and requests and generate keepalive pings when necessary
     async fn select(&mut self) -> Result<Event, ConnectionError> {
         let network = self.network.as_mut().unwrap();
@@ -172,6 +290,8 @@ async fn select(&mut self) -> Result<Event, ConnectionError> {
             return Ok(event);
         }
 
+        let mut no_sleep = Box::pin(time::sleep(Duration::ZERO));
+        let mut no_pingresp_sleep = Box::pin(time::sleep(Duration::ZERO));
         // this loop is necessary since self.incoming.pop_front() might return None. In that case,
         // instead of returning a None event, we try again.
         select! {
@@ -209,7 +329,12 @@ async fn select(&mut self) -> Result<Event, ConnectionError> {
                 self.options.pending_throttle
             ), if !self.pending.is_empty() || (!inflight_full && !collision) => match o {
                 Ok(request) => {
-                    self.state.handle_outgoing_packet(request)?;
+                    if let Some(outgoing) = self.state.handle_outgoing_packet(request)? {
+                        network.write(outgoing).await?;
+                    }
+                    while let Some(outgoing) = self.state.next_split_packet() {
+                        network.write(outgoing).await?;
+                    }
                     network.flush().await?;
                     Ok(self.state.events.pop_front().unwrap())
                 }
@@ -223,15 +348,28 @@ async fn select(&mut self) -> Result<Event, ConnectionError> {
                 Ok(self.state.events.pop_front().unwrap())
             },
             // We generate pings irrespective of network activity. This keeps the ping logic
-            // simple. We can change this behavior in future if necessary (to prevent extra pings)
-            _ = self.keepalive_timeout.as_mut().unwrap() => {
+            // simple. We can change this behavior in future if necessary (to prevent extra pings).
+            // There's no timer when keep alive is zero, which disables pings
+            _ = self.keepalive_timeout.as_mut().unwrap_or(&mut no_sleep),
+                if self.keepalive_timeout.is_some() => {
                 let timeout = self.keepalive_timeout.as_mut().unwrap();
                 timeout.as_mut().reset(Instant::now() + self.options.keep_alive);
 
-                self.state.handle_outgoing_packet(Request::PingReq)?;
+                if let Some(outgoing) = self.state.handle_outgoing_packet(Request::PingReq)? {
+                    network.write(outgoing).await?;
+                }
                 network.flush().await?;
+                if let Some(timeout) = self.options.pingresp_timeout {
+                    self.pingresp_timeout = Some(Box::pin(time::sleep(timeout)));
+                }
                 Ok(self.state.events.pop_front().unwrap())
             }
+            // Consider connection dead when pingresp isn't received in time instead of
+            // waiting for the next keep alive
+            _ = self.pingresp_timeout.as_mut().unwrap_or(&mut no_pingresp_sleep),
+                if self.pingresp_timeout.is_some() && self.state.await_pingresp => {
+                Err(ConnectionError::MqttState(StateError::AwaitPingResp))
+            }
         }
     }
 
@@ -259,9 +397,14 @@ async fn next_request(
 /// the stream.
 /// This function (for convenience) includes internal delays for users to perform internal sleeps
 /// between re-connections so that cancel semantics can be used during this sleep
-async fn connect(options: &mut MqttOptions) -> Result<(Network, Incoming), ConnectionError> {
+async fn connect(
+    options: &mut MqttOptions,
+    dns_cache: &DnsCache,
+    protocol: Protocol,
+) -> Result<(Network, Incoming), ConnectionError> {
     // connect to the broker
-    let mut network = network_connect(options).await?;
+    let mut network = network_connect(options, dns_cache).await?;
+    network.set_protocol(protocol);
 
     // make MQTT connection request (which internally awaits for ack)
     let packet = mqtt_connect(options, &mut network).await?;
@@ -274,7 +417,10 @@ async fn connect(options: &mut MqttOptions) -> Result<(Network, Incoming), Conne
     Ok((network, packet))
 }
 
-async fn network_connect(options: &MqttOptions) -> Result<Network, ConnectionError> {
+async fn network_connect(
+    options: &MqttOptions,
+    dns_cache: &DnsCache,
+) -> Result<Network, ConnectionError> {
     let mut max_incoming_pkt_size = Some(options.default_max_incoming_size);
 
     // Override default value if max_packet_size is set on `connect_properties`
@@ -312,14 +458,18 @@ async fn network_connect(options: &MqttOptions) -> Result<Network, ConnectionErr
             }
             None => {
                 let addr = format!("{domain}:{port}");
-                let tcp = socket_connect(addr, options.network_options()).await?;
+                let ttl = options.dns_cache_ttl();
+                let tcp =
+                    socket_connect_cached(addr, options.network_options(), dns_cache, ttl).await?;
                 Box::new(tcp)
             }
         }
         #[cfg(not(feature = "proxy"))]
         {
             let addr = format!("{domain}:{port}");
-            let tcp = socket_connect(addr, options.network_options()).await?;
+            let ttl = options.dns_cache_ttl();
+            let tcp =
+                socket_connect_cached(addr, options.network_options(), dns_cache, ttl).await?;
             Box::new(tcp)
         }
     };
@@ -328,10 +478,12 @@ async fn network_connect(options: &MqttOptions) -> Result<Network, ConnectionErr
         Transport::Tcp => Network::new(tcp_stream, max_incoming_pkt_size),
         #[cfg(any(feature = "use-native-tls", feature = "use-rustls"))]
         Transport::Tls(tls_config) => {
-            let socket =
+            let (socket, tls_info) =
                 tls::tls_connect(&options.broker_addr, options.port, &tls_config, tcp_stream)
                     .await?;
-            Network::new(socket, max_incoming_pkt_size)
+            let mut network = Network::new(socket, max_incoming_pkt_size);
+            network.tls_info = tls_info;
+            network
         }
         #[cfg(unix)]
         Transport::Unix => unreachable!(),
@@ -405,13 +557,30 @@ async fn mqtt_connect(
             // Override local keep_alive value if set by server.
             if let Some(props) = &connack.properties {
                 if let Some(keep_alive) = props.server_keep_alive {
-                    options.keep_alive = Duration::from_secs(keep_alive as u64);
+                    let keep_alive = Duration::from_secs(keep_alive as u64);
+                    if keep_alive != options.keep_alive {
+                        warn!(
+                            "Broker replaced keep alive of {}s with {}s",
+                            options.keep_alive.as_secs(),
+                            keep_alive.as_secs()
+                        );
+                    }
+                    options.keep_alive = keep_alive;
                 }
                 network.set_max_outgoing_size(props.max_packet_size);
             }
             Ok(Packet::ConnAck(connack))
         }
-        Incoming::ConnAck(connack) => Err(ConnectionError::ConnectionRefused(connack.code)),
+        Incoming::ConnAck(connack) => {
+            let server_reference = connack.properties.and_then(|props| props.server_reference);
+            match server_reference {
+                Some(server_reference) => Err(ConnectionError::ServerRedirect {
+                    code: connack.code,
+                    server_reference,
+                }),
+                None => Err(ConnectionError::ConnectionRefused(connack.code)),
+            }
+        }
         packet => Err(ConnectionError::NotConnAck(Box::new(packet))),
     }
 }
@@ -3,6 +3,9 @@
 use tokio_util::codec::Framed;
 
 use crate::framed::AsyncReadWrite;
+use crate::mqttbytes::Protocol;
+#[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+use crate::TlsInfo;
 
 use super::mqttbytes::v5::Packet;
 use super::{mqttbytes, Codec, Connect, MqttOptions, MqttState};
@@ -11,11 +14,17 @@
 /// Network transforms packets <-> frames efficiently. It takes
 /// advantage of pre-allocation, buffering and vectorization when
 /// appropriate to achieve performance
+///
+/// This is an advanced API for bridges and proxies which handle packets themselves,
+/// see [`crate::Network`]
 pub struct Network {
     /// Frame MQTT packets from network connection
     framed: Framed<Box<dyn AsyncReadWrite>, Codec>,
     /// Maximum readv count
     max_readb_count: usize,
+    /// Parameters negotiated by TLS handshake
+    #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+    pub(crate) tls_info: Option<TlsInfo>,
 }
 impl Network {
     pub fn new(socket: impl AsyncReadWrite + 'static, max_incoming_size: Option<u32>) -> Network {
@@ -23,12 +32,15 @@ pub fn new(socket: impl AsyncReadWrite + 'static, max_incoming_size: Option<u32>
         let codec = Codec {
             max_incoming_size,
             max_outgoing_size: None,
+            protocol: Protocol::V5,
         };
         let framed = Framed::new(socket, codec);
 
         Network {
             framed,
             max_readb_count: 10,
+            #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+            tls_info: None,
         }
     }
 
@@ -36,6 +48,11 @@ pub fn set_max_outgoing_size(&mut self, max_outgoing_size: Option<u32>) {
         self.framed.codec_mut().max_outgoing_size = max_outgoing_size;
     }
 
+    /// Speaks 3.1.1 (`V4`) instead of v5 with the broker. Packets are translated by the codec
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.framed.codec_mut().protocol = protocol;
+    }
+
     /// Reads and returns a single packet from network
     pub async fn read(&mut self) -> Result<Incoming, StateError> {
         match self.framed.next().await {
@@ -99,6 +116,7 @@ pub async fn connect(
         self.flush().await
     }
 
+    /// Writes buffered packets to network
     pub async fn flush(&mut self) -> Result<(), StateError> {
         self.framed
             .flush()
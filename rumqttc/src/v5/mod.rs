@@ -21,7 +21,8 @@
 
 pub use client::{AsyncClient, Client, ClientError, Connection, Iter};
 pub use eventloop::{ConnectionError, Event, EventLoop};
-pub use state::{MqttState, StateError};
+pub use framed::Network;
+pub use state::{MqttState, ServerInfo, StateError};
 
 #[cfg(feature = "use-rustls")]
 pub use crate::tls::Error as TlsError;
@@ -70,6 +71,14 @@ pub struct MqttOptions {
     transport: Transport,
     /// keep alive time to send pingreq to broker when the connection is idle
     keep_alive: Duration,
+    /// time to wait for pingresp after sending pingreq before considering connection dead
+    pingresp_timeout: Option<Duration>,
+    /// time for which resolved addresses of the broker are reused
+    dns_cache_ttl: Option<Duration>,
+    /// reconnect to the server referenced by the broker in CONNACK or DISCONNECT
+    follow_server_reference: bool,
+    /// fall back to MQTT 3.1.1 when the broker doesn't support v5
+    protocol_fallback: bool,
     /// clean (or) persistent session
     clean_start: bool,
     /// client identifier
@@ -85,6 +94,8 @@ pub struct MqttOptions {
     pending_throttle: Duration,
     /// Last will that will be issued on unexpected disconnect
     last_will: Option<LastWill>,
+    /// Delay before broker publishes last will after an unexpected disconnect
+    will_delay_interval: Option<Duration>,
     /// Connection timeout
     conn_timeout: u64,
     /// Default value of for maximum incoming packet size.
@@ -122,6 +133,10 @@ pub fn new<S: Into<String>, T: Into<String>>(id: S, host: T, port: u16) -> MqttO
             port,
             transport: Transport::tcp(),
             keep_alive: Duration::from_secs(60),
+            pingresp_timeout: None,
+            dns_cache_ttl: None,
+            follow_server_reference: false,
+            protocol_fallback: false,
             clean_start: true,
             client_id: id.into(),
             credentials: None,
@@ -129,6 +144,7 @@ pub fn new<S: Into<String>, T: Into<String>>(id: S, host: T, port: u16) -> MqttO
             max_request_batch: 0,
             pending_throttle: Duration::from_micros(0),
             last_will: None,
+            will_delay_interval: None,
             conn_timeout: 5,
             default_max_incoming_size: 10 * 1024,
             connect_properties: None,
@@ -185,8 +201,36 @@ pub fn set_last_will(&mut self, will: LastWill) -> &mut Self {
         self
     }
 
+    /// Last will, with will delay interval applied to its properties when set
     pub fn last_will(&self) -> Option<LastWill> {
-        self.last_will.clone()
+        let mut will = self.last_will.clone()?;
+        if let Some(delay) = self.will_delay_interval {
+            let properties = will.properties.get_or_insert_with(|| LastWillProperties {
+                delay_interval: None,
+                payload_format_indicator: None,
+                message_expiry_interval: None,
+                content_type: None,
+                response_topic: None,
+                correlation_data: None,
+                user_properties: Vec::new(),
+            });
+            properties.delay_interval = Some(delay.as_secs() as u32);
+        }
+
+        Some(will)
+    }
+
+    /// Set delay before the broker publishes last will after an unexpected disconnect.
+    /// Will isn't published if client reconnects within this delay. Broker uses the
+    /// smaller of this delay and session expiry interval
+    pub fn set_will_delay_interval(&mut self, delay: Duration) -> &mut Self {
+        self.will_delay_interval = Some(delay);
+        self
+    }
+
+    /// Will delay interval
+    pub fn will_delay_interval(&self) -> Option<Duration> {
+        self.will_delay_interval
     }
 
     #[cfg(feature = "websocket")]
@@ -220,8 +264,19 @@ pub fn transport(&self) -> Transport {
 
     /// Set number of seconds after which client should ping the broker
     /// if there is no other data exchange
+    ///
+    /// Brokers may cap the keep alive, 30 to 300 seconds is accepted by most of them.
+    /// A broker which caps it replies with a Server Keep Alive in CONNACK (also part of
+    /// the `ConnAck` event), which replaces this value and is logged as a warning.
+    ///
+    /// `Duration::ZERO` disables keep alive, as does a Server Keep Alive of 0: no PINGREQ
+    /// is sent, and the broker doesn't expect any. A dead connection is then only detected
+    /// by TCP, i.e. when a write fails or the OS gives up on the socket
     pub fn set_keep_alive(&mut self, duration: Duration) -> &mut Self {
-        assert!(duration.as_secs() >= 5, "Keep alives should be >= 5 secs");
+        assert!(
+            duration.is_zero() || duration.as_secs() >= 5,
+            "Keep alives should be >= 5 secs, except for Duration::ZERO"
+        );
 
         self.keep_alive = duration;
         self
@@ -232,6 +287,18 @@ pub fn keep_alive(&self) -> Duration {
         self.keep_alive
     }
 
+    /// Set duration within which PINGRESP must be received after sending a PINGREQ.
+    /// Connection is considered dead if it isn't, instead of waiting for next keep alive
+    pub fn set_pingresp_timeout(&mut self, duration: Duration) -> &mut Self {
+        self.pingresp_timeout = Some(duration);
+        self
+    }
+
+    /// PINGRESP timeout
+    pub fn pingresp_timeout(&self) -> Option<Duration> {
+        self.pingresp_timeout
+    }
+
     /// Client identifier
     pub fn client_id(&self) -> String {
         self.client_id.clone()
@@ -290,6 +357,19 @@ pub fn pending_throttle(&self) -> Duration {
         self.pending_throttle
     }
 
+    /// Cache resolved addresses of the broker for `ttl` instead of resolving them on every
+    /// (re)connection. All the addresses are tried in order and cache is cleared when none
+    /// of them can be connected to
+    pub fn set_dns_cache_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.dns_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// DNS cache ttl
+    pub fn dns_cache_ttl(&self) -> Option<Duration> {
+        self.dns_cache_ttl
+    }
+
     /// set connection timeout in secs
     pub fn set_connection_timeout(&mut self, timeout: u64) -> &mut Self {
         self.conn_timeout = timeout;
@@ -417,7 +497,30 @@ pub fn request_problem_info(&self) -> Option<u8> {
         }
     }
 
-    /// set user properties on connection properties
+    /// Whether the broker may send Reason Strings and User Properties on packets other than
+    /// PUBLISH, CONNACK and DISCONNECT. Brokers send them by default
+    pub fn set_request_problem_information(&mut self, request: bool) -> &mut Self {
+        self.set_request_problem_info(Some(request as u8))
+    }
+
+    /// Whether Reason Strings and User Properties are requested on all the packets
+    pub fn request_problem_information(&self) -> bool {
+        self.request_problem_info() != Some(0)
+    }
+
+    /// Whether the broker should return Response Information in CONNACK, which is then
+    /// available with [`EventLoop::response_information`]. Not requested by default
+    pub fn set_request_response_information(&mut self, request: bool) -> &mut Self {
+        self.set_request_response_info(Some(request as u8))
+    }
+
+    /// Whether Response Information is requested from the broker
+    pub fn request_response_information(&self) -> bool {
+        self.request_response_info() == Some(1)
+    }
+
+    /// set user properties on connection properties. They're sent in CONNECT, e.g. for
+    /// brokers which select a tenant or feature flags from them
     pub fn set_user_properties(&mut self, user_properties: Vec<(String, String)>) -> &mut Self {
         if let Some(conn_props) = &mut self.connect_properties {
             conn_props.user_properties = user_properties;
@@ -494,6 +597,60 @@ pub fn manual_acks(&self) -> bool {
         self.manual_acks
     }
 
+    /// Reconnect to the server referenced by the broker when it refuses the connection
+    /// or disconnects with a Server Reference, instead of the configured broker address.
+    /// The reference is surfaced in the returned error either way
+    pub fn set_follow_server_reference(&mut self, follow: bool) -> &mut Self {
+        self.follow_server_reference = follow;
+        self
+    }
+
+    /// Whether server references sent by the broker are followed
+    pub fn follow_server_reference(&self) -> bool {
+        self.follow_server_reference
+    }
+
+    /// Reconnect with MQTT 3.1.1 when the broker refuses v5 with an unsupported protocol
+    /// version, or closes the connection in response to the v5 CONNECT on two connects
+    /// in a row, as a single close can be transient. Properties and v5 only features like
+    /// topic aliases and subscription options are unavailable after the fallback,
+    /// [`EventLoop::protocol`] tells which protocol is in use
+    pub fn set_protocol_fallback(&mut self, fallback: bool) -> &mut Self {
+        self.protocol_fallback = fallback;
+        self
+    }
+
+    /// Whether the connection falls back to MQTT 3.1.1 when the broker doesn't support v5
+    pub fn protocol_fallback(&self) -> bool {
+        self.protocol_fallback
+    }
+
+    /// Points the options to the first server of a Server Reference, `host[:port]`,
+    /// keeping the current port when none is referenced
+    fn set_server_reference(&mut self, server_reference: &str) {
+        let reference = match server_reference.split_whitespace().next() {
+            Some(reference) => reference,
+            None => return,
+        };
+
+        // Unbracketed ipv6 addresses can't have a port
+        let (host, port) = match reference.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+                match port.parse() {
+                    Ok(port) => (host, port),
+                    Err(_) => (reference, self.port),
+                }
+            }
+            _ => (reference, self.port),
+        };
+
+        self.broker_addr = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_owned();
+        self.port = port;
+    }
+
     pub fn network_options(&self) -> NetworkOptions {
         self.network_options.clone()
     }
@@ -503,6 +660,23 @@ pub fn set_network_options(&mut self, network_options: NetworkOptions) -> &mut S
         self
     }
 
+    /// Sets send (SO_SNDBUF) and receive (SO_RCVBUF) buffer sizes of the TCP socket in
+    /// network options. See [`crate::MqttOptions::set_socket_buffer_sizes`] for the
+    /// limits platforms apply to them
+    pub fn set_socket_buffer_sizes(
+        &mut self,
+        send: Option<usize>,
+        recv: Option<usize>,
+    ) -> &mut Self {
+        self.network_options.set_socket_buffer_sizes(send, recv);
+        self
+    }
+
+    /// Send and receive buffer sizes of the socket
+    pub fn socket_buffer_sizes(&self) -> (Option<usize>, Option<usize>) {
+        self.network_options.socket_buffer_sizes()
+    }
+
     #[cfg(feature = "proxy")]
     pub fn set_proxy(&mut self, proxy: Proxy) -> &mut Self {
         self.proxy = Some(proxy);
@@ -700,6 +874,10 @@ fn fmt(&self, f: &mut Formatter) -> fmt::Result {
             .field("broker_addr", &self.broker_addr)
             .field("port", &self.port)
             .field("keep_alive", &self.keep_alive)
+            .field("pingresp_timeout", &self.pingresp_timeout)
+            .field("dns_cache_ttl", &self.dns_cache_ttl)
+            .field("follow_server_reference", &self.follow_server_reference)
+            .field("protocol_fallback", &self.protocol_fallback)
             .field("clean_start", &self.clean_start)
             .field("client_id", &self.client_id)
             .field("credentials", &self.credentials)
@@ -707,6 +885,7 @@ fn fmt(&self, f: &mut Formatter) -> fmt::Result {
             .field("max_request_batch", &self.max_request_batch)
             .field("pending_throttle", &self.pending_throttle)
             .field("last_will", &self.last_will)
+            .field("will_delay_interval", &self.will_delay_interval)
             .field("conn_timeout", &self.conn_timeout)
             .field("manual_acks", &self.manual_acks)
             .field("connect properties", &self.connect_properties)
@@ -717,6 +896,7 @@ fn fmt(&self, f: &mut Formatter) -> fmt::Result {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::v5::mqttbytes::QoS;
 
     #[test]
     #[cfg(all(feature = "use-rustls", feature = "websocket"))]
@@ -802,6 +982,56 @@ fn err(s: &str) -> OptionError {
         );
     }
 
+    #[test]
+    fn will_delay_interval_is_set_on_last_will_properties() {
+        let mut options = MqttOptions::new("client", "127.0.0.1", 1883);
+        options.set_will_delay_interval(Duration::from_secs(10));
+        assert!(options.last_will().is_none());
+
+        options.set_last_will(LastWill::new(
+            "hello/world",
+            "bye",
+            QoS::AtLeastOnce,
+            false,
+            None,
+        ));
+        let properties = options.last_will().unwrap().properties.unwrap();
+        assert_eq!(properties.delay_interval, Some(10));
+    }
+
+    #[test]
+    fn server_reference_replaces_broker_address() {
+        let mut options = MqttOptions::new("client", "127.0.0.1", 1883);
+        let mut address = |reference| {
+            options.set_server_reference(reference);
+            options.broker_address()
+        };
+
+        assert_eq!(
+            address("other:1884 backup:1885"),
+            ("other".to_owned(), 1884)
+        );
+        assert_eq!(address("another"), ("another".to_owned(), 1884));
+        assert_eq!(address("[::1]:1886"), ("::1".to_owned(), 1886));
+        assert_eq!(address("2001:db8::1"), ("2001:db8::1".to_owned(), 1886));
+    }
+
+    #[test]
+    fn problem_and_response_information_are_requested_in_connect_properties() {
+        let mut options = MqttOptions::new("client", "127.0.0.1", 1883);
+        assert!(options.request_problem_information());
+        assert!(!options.request_response_information());
+
+        options
+            .set_request_problem_information(false)
+            .set_request_response_information(true);
+        let properties = options.connect_properties().unwrap();
+        assert_eq!(properties.request_problem_info, Some(0));
+        assert_eq!(properties.request_response_info, Some(1));
+        assert!(!options.request_problem_information());
+        assert!(options.request_response_information());
+    }
+
     #[test]
     fn allow_empty_client_id() {
         let _mqtt_opts = MqttOptions::new("", "127.0.0.1", 1883).set_clean_start(true);
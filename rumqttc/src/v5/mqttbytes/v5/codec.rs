@@ -1,7 +1,8 @@
 use bytes::BytesMut;
 use tokio_util::codec::{Decoder, Encoder};
 
-use super::{Error, Packet};
+use super::{v311, Error, Packet};
+use crate::mqttbytes::Protocol;
 
 /// MQTT v4 codec
 #[derive(Debug, Clone)]
@@ -10,6 +11,8 @@ pub struct Codec {
     pub max_incoming_size: Option<u32>,
     /// Maximum packet size allowed by broker
     pub max_outgoing_size: Option<u32>,
+    /// Protocol spoken with the broker. Packets are translated when it's 3.1.1 (`V4`)
+    pub protocol: Protocol,
 }
 
 impl Decoder for Codec {
@@ -17,7 +20,12 @@ impl Decoder for Codec {
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        match Packet::read(src, self.max_incoming_size) {
+        let packet = match self.protocol {
+            Protocol::V5 => Packet::read(src, self.max_incoming_size),
+            Protocol::V3 | Protocol::V4 => v311::read(src, self.max_incoming_size),
+        };
+
+        match packet {
             Ok(packet) => Ok(Some(packet)),
             Err(Error::InsufficientBytes(b)) => {
                 // Get more packets to construct the incomplete packet
@@ -33,7 +41,10 @@ impl Encoder<Packet> for Codec {
     type Error = Error;
 
     fn encode(&mut self, item: Packet, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        item.write(dst, self.max_outgoing_size)?;
+        match self.protocol {
+            Protocol::V5 => item.write(dst, self.max_outgoing_size)?,
+            Protocol::V3 | Protocol::V4 => v311::write(item, dst, self.max_outgoing_size)?,
+        };
 
         Ok(())
     }
@@ -45,6 +56,7 @@ mod tests {
     use tokio_util::codec::Encoder;
 
     use super::Codec;
+    use crate::mqttbytes::Protocol;
     use crate::v5::{
         mqttbytes::{Error, QoS},
         Packet, Publish,
@@ -56,6 +68,7 @@ fn outgoing_max_packet_size_check() {
         let mut codec = Codec {
             max_incoming_size: Some(100),
             max_outgoing_size: Some(200),
+            protocol: Protocol::V5,
         };
 
         let mut small_publish = Publish::new("hello/world", QoS::AtLeastOnce, vec![1; 100], None);
@@ -69,9 +69,21 @@ pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<ConnAck, Erro
 
         let flags = read_u8(&mut bytes)?;
         let return_code = read_u8(&mut bytes)?;
-        let properties = ConnAckProperties::read(&mut bytes)?;
-
         let session_present = (flags & 0x01) == 1;
+
+        // Brokers which don't support v5 refuse the connection with a 3.1.1 CONNACK,
+        // which has no properties
+        if bytes.is_empty() {
+            let connack = ConnAck {
+                session_present,
+                code: v311_connect_return(return_code)?,
+                properties: None,
+            };
+
+            return Ok(connack);
+        }
+
+        let properties = ConnAckProperties::read(&mut bytes)?;
         let code = connect_return(return_code)?;
         let connack = ConnAck {
             session_present,
@@ -454,6 +466,21 @@ fn connect_return(num: u8) -> Result<ConnectReturnCode, Error> {
     Ok(code)
 }
 
+/// Return code of a 3.1.1 CONNACK
+fn v311_connect_return(num: u8) -> Result<ConnectReturnCode, Error> {
+    let code = match num {
+        0 => ConnectReturnCode::Success,
+        1 => ConnectReturnCode::RefusedProtocolVersion,
+        2 => ConnectReturnCode::BadClientId,
+        3 => ConnectReturnCode::ServiceUnavailable,
+        4 => ConnectReturnCode::BadUserNamePassword,
+        5 => ConnectReturnCode::NotAuthorized,
+        num => return Err(Error::InvalidConnectReturnCode(num)),
+    };
+
+    Ok(code)
+}
+
 fn connect_code(return_code: ConnectReturnCode) -> u8 {
     match return_code {
         ConnectReturnCode::Success => 0,
@@ -527,4 +554,16 @@ fn length_calculation() {
         assert_eq!(size_from_write, size_from_bytes);
         assert_eq!(size_from_size, size_from_bytes);
     }
+
+    #[test]
+    fn v311_refusal_is_read() {
+        let mut stream = BytesMut::from(&[0x20, 0x02, 0x00, 0x01][..]);
+        let connack = match Packet::read(&mut stream, None).unwrap() {
+            Packet::ConnAck(connack) => connack,
+            packet => panic!("Expected connack, received: {packet:?}"),
+        };
+
+        assert_eq!(connack.code, ConnectReturnCode::RefusedProtocolVersion);
+        assert_eq!(connack.properties, None);
+    }
 }
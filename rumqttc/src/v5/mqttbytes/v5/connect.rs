@@ -691,4 +691,23 @@ fn length_calculation() {
 
         assert_eq!(reported_size, size_from_bytes);
     }
+
+    #[test]
+    fn will_retain_is_encoded_in_connect_flags() {
+        let connect = Connect {
+            keep_alive: 5,
+            client_id: "client".into(),
+            clean_start: true,
+            properties: None,
+        };
+
+        // +will retain, will qos=1, +last_will, +clean_start
+        for (retain, flags) in [(true, 0b0010_1110), (false, 0b0000_1110)] {
+            let will = LastWill::new("presence/client", "offline", QoS::AtLeastOnce, retain, None);
+
+            let mut buf = BytesMut::new();
+            connect.write(&Some(will), &None, &mut buf).unwrap();
+            assert_eq!(buf[9], flags);
+        }
+    }
 }
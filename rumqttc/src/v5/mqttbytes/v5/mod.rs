@@ -34,6 +34,7 @@
 mod subscribe;
 mod unsuback;
 mod unsubscribe;
+mod v311;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Packet {
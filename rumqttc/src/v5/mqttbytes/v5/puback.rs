@@ -42,6 +42,13 @@ pub fn size(&self) -> usize {
         1 + remaining_len_size + len
     }
 
+    /// Human readable reason sent along with the reason code
+    pub fn reason_string(&self) -> Option<&str> {
+        self.properties
+            .as_ref()
+            .and_then(|properties| properties.reason_string.as_deref())
+    }
+
     fn len(&self) -> usize {
         let mut len = 2 + 1; // pkid + reason
 
@@ -256,4 +263,29 @@ fn length_calculation() {
         assert_eq!(size_from_write, size_from_bytes);
         assert_eq!(size_from_size, size_from_bytes);
     }
+    #[test]
+    fn puback_with_reason_string_parsing_works() {
+        let mut buffer = BytesMut::new();
+        let packet_bytes = [
+            0x40, // Packet type
+            0x15, // Remaining length
+            0x00, 0x0A, // Packet id
+            0x97, // Reason code: quota exceeded
+            0x11, // Properties length
+            0x1F, // Reason string
+            0x00, 0x0E, b'q', b'u', b'o', b't', b'a', b' ', b'e', b'x', b'c', b'e', b'e', b'd',
+            b'e', b'd',
+        ];
+
+        buffer.extend_from_slice(&packet_bytes[..]);
+
+        let fixed_header = parse_fixed_header(buffer.iter()).unwrap();
+        let puback_bytes = buffer.split_to(fixed_header.frame_length()).freeze();
+        let puback = PubAck::read(fixed_header, puback_bytes).unwrap();
+
+        assert_eq!(puback.pkid, 10);
+        assert_eq!(puback.reason, PubAckReason::QuotaExceeded);
+        assert_eq!(puback.reason_string(), Some("quota exceeded"));
+        assert!(puback.properties.unwrap().user_properties.is_empty());
+    }
 }
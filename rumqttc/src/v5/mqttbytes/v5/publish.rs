@@ -6,6 +6,8 @@
 pub struct Publish {
     pub dup: bool,
     pub qos: QoS,
+    /// On incoming publishes, set when the broker delivers a retained message because of a
+    /// new subscription and unset for messages published while subscribed
     pub retain: bool,
     pub topic: Bytes,
     pub pkid: u16,
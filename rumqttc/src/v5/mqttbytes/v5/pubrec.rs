@@ -40,6 +40,13 @@ pub fn size(&self) -> usize {
         1 + remaining_len_size + len
     }
 
+    /// Human readable reason sent along with the reason code
+    pub fn reason_string(&self) -> Option<&str> {
+        self.properties
+            .as_ref()
+            .and_then(|properties| properties.reason_string.as_deref())
+    }
+
     fn len(&self) -> usize {
         let mut len = 2 + 1; // pkid + reason
 
@@ -32,6 +32,13 @@ pub fn size(&self) -> usize {
         1 + remaining_len_size + len
     }
 
+    /// Human readable reason sent along with the reason code
+    pub fn reason_string(&self) -> Option<&str> {
+        self.properties
+            .as_ref()
+            .and_then(|properties| properties.reason_string.as_deref())
+    }
+
     pub fn read(fixed_header: FixedHeader, mut bytes: Bytes) -> Result<UnsubAck, Error> {
         let variable_header_index = fixed_header.fixed_header_len;
         bytes.advance(variable_header_index);
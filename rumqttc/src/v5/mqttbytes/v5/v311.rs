@@ -0,0 +1,224 @@
+//! Translation of v5 packets to and from MQTT 3.1.1, used when the broker doesn't
+//! support v5. Properties and v5 only options have no 3.1.1 equivalent and are dropped
+
+use bytes::{Bytes, BytesMut};
+
+use super::*;
+use crate::mqttbytes::{self as v311, v4};
+
+/// Reads a 3.1.1 packet from `stream` as its v5 equivalent
+pub(crate) fn read(stream: &mut BytesMut, max_size: Option<u32>) -> Result<Packet, Error> {
+    let packet = v4::Packet::read(stream, max_size.map_or(usize::MAX, |max| max as usize))
+        .map_err(|e| match (e, max_size) {
+            (v311::Error::PayloadSizeLimitExceeded(pkt_size), Some(max)) => {
+                Error::PayloadSizeLimitExceeded { pkt_size, max }
+            }
+            (e, _) => error(e),
+        })?;
+
+    let packet = match packet {
+        v4::Packet::Connect(_) => return Err(Error::InvalidPacketType(1)),
+        v4::Packet::ConnAck(connack) => Packet::ConnAck(ConnAck {
+            session_present: connack.session_present,
+            code: connect_return_code(connack.code),
+            properties: None,
+        }),
+        v4::Packet::Publish(publish) => Packet::Publish(Publish {
+            dup: publish.dup,
+            qos: from_qos(publish.qos),
+            retain: publish.retain,
+            topic: Bytes::from(publish.topic),
+            pkid: publish.pkid,
+            payload: publish.payload,
+            properties: None,
+        }),
+        v4::Packet::PubAck(puback) => Packet::PubAck(PubAck::new(puback.pkid, None)),
+        v4::Packet::PubRec(pubrec) => Packet::PubRec(PubRec::new(pubrec.pkid, None)),
+        v4::Packet::PubRel(pubrel) => Packet::PubRel(PubRel::new(pubrel.pkid, None)),
+        v4::Packet::PubComp(pubcomp) => Packet::PubComp(PubComp::new(pubcomp.pkid, None)),
+        v4::Packet::SubAck(suback) => {
+            let return_codes = suback
+                .return_codes
+                .into_iter()
+                .map(|code| match code {
+                    v4::SubscribeReasonCode::Success(qos) => {
+                        SubscribeReasonCode::Success(from_qos(qos))
+                    }
+                    v4::SubscribeReasonCode::Failure => SubscribeReasonCode::Failure,
+                })
+                .collect();
+
+            Packet::SubAck(SubAck {
+                pkid: suback.pkid,
+                return_codes,
+                properties: None,
+            })
+        }
+        // 3.1.1 unsuback has no reasons, unsubscribing always succeeds
+        v4::Packet::UnsubAck(unsuback) => Packet::UnsubAck(UnsubAck {
+            pkid: unsuback.pkid,
+            reasons: vec![],
+            properties: None,
+        }),
+        v4::Packet::Subscribe(_) => return Err(Error::InvalidPacketType(8)),
+        v4::Packet::Unsubscribe(_) => return Err(Error::InvalidPacketType(10)),
+        v4::Packet::PingReq => Packet::PingReq(PingReq),
+        v4::Packet::PingResp => Packet::PingResp(PingResp),
+        v4::Packet::Disconnect => {
+            Packet::Disconnect(Disconnect::new(DisconnectReasonCode::NormalDisconnection))
+        }
+    };
+
+    Ok(packet)
+}
+
+/// Writes v5 `packet` to `buffer` as a 3.1.1 packet
+pub(crate) fn write(
+    packet: Packet,
+    buffer: &mut BytesMut,
+    max_size: Option<u32>,
+) -> Result<usize, Error> {
+    let packet = match packet {
+        Packet::Connect(connect, will, login) => {
+            let last_will = match will {
+                Some(will) => Some(v4::LastWill {
+                    topic: topic(will.topic)?,
+                    message: will.message,
+                    qos: to_qos(will.qos),
+                    retain: will.retain,
+                }),
+                None => None,
+            };
+
+            v4::Packet::Connect(v4::Connect {
+                protocol: v311::Protocol::V4,
+                keep_alive: connect.keep_alive,
+                client_id: connect.client_id,
+                clean_session: connect.clean_start,
+                last_will,
+                login: login.map(|login| v4::Login::new(login.username, login.password)),
+            })
+        }
+        Packet::Publish(publish) => {
+            // Topic aliases don't exist in 3.1.1, the topic is always required
+            if publish.topic.is_empty() {
+                return Err(Error::IncorrectPacketFormat);
+            }
+
+            v4::Packet::Publish(v4::Publish {
+                dup: publish.dup,
+                qos: to_qos(publish.qos),
+                retain: publish.retain,
+                topic: topic(publish.topic)?,
+                pkid: publish.pkid,
+                payload: publish.payload,
+            })
+        }
+        Packet::PubAck(puback) => v4::Packet::PubAck(v4::PubAck::new(puback.pkid)),
+        Packet::PubRec(pubrec) => v4::Packet::PubRec(v4::PubRec::new(pubrec.pkid)),
+        Packet::PubRel(pubrel) => v4::Packet::PubRel(v4::PubRel::new(pubrel.pkid)),
+        Packet::PubComp(pubcomp) => v4::Packet::PubComp(v4::PubComp::new(pubcomp.pkid)),
+        Packet::Subscribe(subscribe) => {
+            let filters = subscribe
+                .filters
+                .into_iter()
+                .map(|filter| v4::SubscribeFilter::new(filter.path, to_qos(filter.qos)))
+                .collect();
+
+            v4::Packet::Subscribe(v4::Subscribe {
+                pkid: subscribe.pkid,
+                filters,
+            })
+        }
+        Packet::Unsubscribe(unsubscribe) => v4::Packet::Unsubscribe(v4::Unsubscribe {
+            pkid: unsubscribe.pkid,
+            topics: unsubscribe.filters,
+        }),
+        Packet::PingReq(_) => v4::Packet::PingReq,
+        Packet::PingResp(_) => v4::Packet::PingResp,
+        Packet::Disconnect(_) => v4::Packet::Disconnect,
+        Packet::ConnAck(_) => return Err(Error::InvalidPacketType(2)),
+        Packet::SubAck(_) => return Err(Error::InvalidPacketType(9)),
+        Packet::UnsubAck(_) => return Err(Error::InvalidPacketType(11)),
+    };
+
+    let max_size = max_size.map_or(usize::MAX, |max| max as usize);
+    packet.write(buffer, max_size).map_err(error)
+}
+
+fn topic(topic: Bytes) -> Result<String, Error> {
+    String::from_utf8(topic.to_vec()).map_err(|_| Error::TopicNotUtf8)
+}
+
+fn to_qos(qos: QoS) -> v311::QoS {
+    match qos {
+        QoS::AtMostOnce => v311::QoS::AtMostOnce,
+        QoS::AtLeastOnce => v311::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => v311::QoS::ExactlyOnce,
+    }
+}
+
+fn from_qos(qos: v311::QoS) -> QoS {
+    match qos {
+        v311::QoS::AtMostOnce => QoS::AtMostOnce,
+        v311::QoS::AtLeastOnce => QoS::AtLeastOnce,
+        v311::QoS::ExactlyOnce => QoS::ExactlyOnce,
+    }
+}
+
+fn connect_return_code(code: v4::ConnectReturnCode) -> ConnectReturnCode {
+    match code {
+        v4::ConnectReturnCode::Success => ConnectReturnCode::Success,
+        v4::ConnectReturnCode::RefusedProtocolVersion => ConnectReturnCode::RefusedProtocolVersion,
+        v4::ConnectReturnCode::BadClientId => ConnectReturnCode::BadClientId,
+        v4::ConnectReturnCode::ServiceUnavailable => ConnectReturnCode::ServiceUnavailable,
+        v4::ConnectReturnCode::BadUserNamePassword => ConnectReturnCode::BadUserNamePassword,
+        v4::ConnectReturnCode::NotAuthorized => ConnectReturnCode::NotAuthorized,
+    }
+}
+
+fn error(e: v311::Error) -> Error {
+    match e {
+        v311::Error::InsufficientBytes(required) => Error::InsufficientBytes(required),
+        v311::Error::OutgoingPacketTooLarge { pkt_size, max } => Error::OutgoingPacketTooLarge {
+            pkt_size: pkt_size as u32,
+            max: max as u32,
+        },
+        v311::Error::InvalidConnectReturnCode(code) => Error::InvalidConnectReturnCode(code),
+        v311::Error::InvalidPacketType(packet_type) => Error::InvalidPacketType(packet_type),
+        v311::Error::InvalidQoS(qos) => Error::InvalidQoS(qos),
+        v311::Error::InvalidSubscribeReasonCode(code) => Error::InvalidSubscribeReasonCode(code),
+        v311::Error::TopicNotUtf8 => Error::TopicNotUtf8,
+        v311::Error::BoundaryCrossed(len) => Error::BoundaryCrossed(len),
+        v311::Error::MalformedRemainingLength => Error::MalformedRemainingLength,
+        v311::Error::Io(e) => Error::Io(e),
+        v311::Error::Decode { source, .. } => error(*source),
+        _ => Error::MalformedPacket,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packets_are_translated_to_and_from_v311() {
+        let mut buffer = BytesMut::new();
+        let mut publish = Publish::new("hello/world", QoS::AtLeastOnce, vec![1, 2, 3], None);
+        publish.pkid = 10;
+        write(Packet::Publish(publish.clone()), &mut buffer, None).unwrap();
+
+        // 3.1.1 publish has no properties length after the packet identifier
+        assert_eq!(&buffer[..2], &[0x32, 18]);
+        assert_eq!(read(&mut buffer, None).unwrap(), Packet::Publish(publish));
+
+        let mut buffer = BytesMut::from(&[0x20, 0x02, 0x01, 0x00][..]);
+        match read(&mut buffer, None).unwrap() {
+            Packet::ConnAck(connack) => {
+                assert!(connack.session_present);
+                assert_eq!(connack.code, ConnectReturnCode::Success);
+            }
+            packet => panic!("Expected connack, received: {packet:?}"),
+        }
+    }
+}
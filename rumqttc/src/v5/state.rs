@@ -1,7 +1,8 @@
 use super::mqttbytes::v5::{
-    ConnAck, ConnectReturnCode, Disconnect, DisconnectReasonCode, Packet, PingReq, PubAck,
-    PubAckReason, PubComp, PubCompReason, PubRec, PubRecReason, PubRel, PubRelReason, Publish,
-    SubAck, Subscribe, SubscribeReasonCode, UnsubAck, UnsubAckReason, Unsubscribe,
+    ConnAck, ConnAckProperties, ConnectReturnCode, Disconnect, DisconnectReasonCode, Packet,
+    PingReq, PubAck, PubAckReason, PubComp, PubCompReason, PubRec, PubRecReason, PubRel,
+    PubRelReason, Publish, SubAck, Subscribe, SubscribeReasonCode, UnsubAck, UnsubAckReason,
+    Unsubscribe,
 };
 use super::mqttbytes::{self, Error as MqttError, QoS};
 
@@ -9,7 +10,9 @@
 
 use bytes::Bytes;
 use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
 use std::{io, time::Instant};
+use tracing::debug;
 
 /// Errors during state handling
 #[derive(Debug, thiserror::Error)]
@@ -41,6 +44,8 @@ pub enum StateError {
         "Cannot use topic alias '{alias:?}'. It's greater than the broker's maximum of '{max:?}'."
     )]
     InvalidAlias { alias: u16, max: u16 },
+    #[error("Cannot use topic alias '{0}' without a topic. It isn't set on this connection")]
+    UnknownAlias(u16),
     #[error("Cannot send packet of size '{pkt_size:?}'. It's greater than the broker's maximum packet size of: '{max:?}'")]
     OutgoingPacketTooLarge { pkt_size: u32, max: u32 },
     #[error("Cannot receive packet of size '{pkt_size:?}'. It's greater than the client's maximum packet size of: '{max:?}'")]
@@ -49,15 +54,33 @@ pub enum StateError {
     ServerDisconnect {
         reason_code: DisconnectReasonCode,
         reason_string: Option<String>,
+        /// Other server to use, `host[:port]`
+        server_reference: Option<String>,
+    },
+    #[error("Unsubscribe failed with reason '{reason:?}' and reason string `{reason_string:?}`")]
+    UnsubFail {
+        reason: UnsubAckReason,
+        reason_string: Option<String>,
+    },
+    #[error("Subscribe failed with reason '{reason:?}' and reason string `{reason_string:?}`")]
+    SubFail {
+        reason: SubscribeReasonCode,
+        reason_string: Option<String>,
+    },
+    #[error(
+        "Publish acknowledgement failed with reason '{reason:?}' and reason string `{reason_string:?}`"
+    )]
+    PubAckFail {
+        reason: PubAckReason,
+        reason_string: Option<String>,
+    },
+    #[error(
+        "Publish receive failed with reason '{reason:?}' and reason string `{reason_string:?}`"
+    )]
+    PubRecFail {
+        reason: PubRecReason,
+        reason_string: Option<String>,
     },
-    #[error("Unsubscribe failed with reason '{reason:?}' ")]
-    UnsubFail { reason: UnsubAckReason },
-    #[error("Subscribe failed with reason '{reason:?}' ")]
-    SubFail { reason: SubscribeReasonCode },
-    #[error("Publish acknowledgement failed with reason '{reason:?}' ")]
-    PubAckFail { reason: PubAckReason },
-    #[error("Publish receive failed with reason '{reason:?}' ")]
-    PubRecFail { reason: PubRecReason },
     #[error("Publish release failed with reason '{reason:?}' ")]
     PubRelFail { reason: PubRelReason },
     #[error("Publish completion failed with reason '{reason:?}' ")]
@@ -107,6 +130,11 @@ pub struct MqttState {
     pub(crate) outgoing_rel: Vec<Option<u16>>,
     /// Packet ids on incoming QoS 2 publishes
     pub(crate) incoming_pub: Vec<Option<u16>>,
+    /// Packet ids of incoming QoS 1, 2 publishes which the user is yet to ack
+    /// when acks are manual
+    pub(crate) unacked_incoming: Vec<Option<u16>>,
+    /// Number of publishes in `unacked_incoming`
+    unacked_incoming_count: usize,
     /// Last collision due to broker not acking in order
     pub collision: Option<Publish>,
     /// Buffered incoming packets
@@ -115,12 +143,92 @@ pub struct MqttState {
     pub manual_acks: bool,
     /// Map of alias_id->topic
     topic_alises: HashMap<u16, Bytes>,
+    /// Map of alias_id->topic of aliases set by outgoing publishes
+    outgoing_topic_aliases: HashMap<u16, Bytes>,
     /// `topic_alias_maximum` RECEIVED via connack packet
     pub broker_topic_alias_max: u16,
     /// Maximum number of allowed inflight QoS1 & QoS2 requests
     pub(crate) max_outgoing_inflight: u16,
     /// Upper limit on the maximum number of allowed inflight QoS1 & QoS2 requests
     max_outgoing_inflight_upper_limit: u16,
+    /// `maximum_packet_size` RECEIVED via connack packet
+    pub(crate) max_outgoing_packet_size: Option<u32>,
+    /// `response_information` RECEIVED via connack packet
+    pub(crate) response_information: Option<String>,
+    /// Subscribes and unsubscribes split into several packets, awaiting acks
+    split_requests: Vec<SplitRequest>,
+    /// Packets of split requests after the first one, yet to be written
+    split_packets: VecDeque<Packet>,
+    /// Capabilities of the broker, shared with clients
+    pub(crate) server_info: SharedServerInfo,
+}
+
+/// Subscribe or unsubscribe which didn't fit in maximum packet size of the broker
+/// and was sent as several packets. Their acks are joined into an ack of the request
+#[derive(Debug, Clone)]
+struct SplitRequest {
+    /// Packet ids of the packets, in the order of their filters. First one is the
+    /// packet id of the request
+    pkids: Vec<u16>,
+    /// Acks received so far
+    acks: HashMap<u16, Incoming>,
+}
+
+/// Capabilities of the broker RECEIVED via connack packet of the current connection.
+/// Absent properties mean the broker has no limit or supports the feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerInfo {
+    /// Maximum QoS of publishes, `None` if the broker supports all
+    pub max_qos: Option<QoS>,
+    /// Maximum size of packets, `None` if the broker didn't limit it
+    pub max_packet_size: Option<u32>,
+    /// Whether `$share/{group}/{filter}` subscriptions are supported
+    pub shared_subscription_available: bool,
+    /// Whether filters with `+` and `#` wildcards are supported
+    pub wildcard_subscription_available: bool,
+}
+
+impl Default for ServerInfo {
+    fn default() -> Self {
+        ServerInfo {
+            max_qos: None,
+            max_packet_size: None,
+            shared_subscription_available: true,
+            wildcard_subscription_available: true,
+        }
+    }
+}
+
+impl ServerInfo {
+    fn new(properties: Option<&ConnAckProperties>) -> ServerInfo {
+        let props = match properties {
+            Some(props) => props,
+            None => return ServerInfo::default(),
+        };
+
+        ServerInfo {
+            max_qos: props.max_qos.and_then(mqttbytes::qos),
+            max_packet_size: props.max_packet_size,
+            shared_subscription_available: props.shared_subscription_available != Some(0),
+            wildcard_subscription_available: props.wildcard_subscription_available != Some(0),
+        }
+    }
+}
+
+/// [`ServerInfo`] shared with clients, to validate requests before sending them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SharedServerInfo {
+    info: Arc<Mutex<ServerInfo>>,
+}
+
+impl SharedServerInfo {
+    fn update(&self, properties: Option<&ConnAckProperties>) {
+        *self.info.lock().unwrap() = ServerInfo::new(properties);
+    }
+
+    pub(crate) fn get(&self) -> ServerInfo {
+        *self.info.lock().unwrap()
+    }
 }
 
 impl MqttState {
@@ -139,15 +247,25 @@ pub fn new(max_inflight: u16, manual_acks: bool) -> Self {
             outgoing_pub: vec![None; max_inflight as usize + 1],
             outgoing_rel: vec![None; max_inflight as usize + 1],
             incoming_pub: vec![None; std::u16::MAX as usize + 1],
+            unacked_incoming: vec![None; u16::MAX as usize + 1],
+            unacked_incoming_count: 0,
             collision: None,
             // TODO: Optimize these sizes later
             events: VecDeque::with_capacity(100),
             manual_acks,
             topic_alises: HashMap::new(),
+            outgoing_topic_aliases: HashMap::new(),
             // Set via CONNACK
             broker_topic_alias_max: 0,
             max_outgoing_inflight: max_inflight,
             max_outgoing_inflight_upper_limit: max_inflight,
+            // Set via CONNACK
+            max_outgoing_packet_size: None,
+            // Set via CONNACK
+            response_information: None,
+            split_requests: Vec::new(),
+            split_packets: VecDeque::new(),
+            server_info: SharedServerInfo::default(),
         }
     }
 
@@ -156,7 +274,15 @@ pub fn clean(&mut self) -> Vec<Request> {
         let mut pending = Vec::with_capacity(100);
         // remove and collect pending publishes
         for publish in self.outgoing_pub.iter_mut() {
-            if let Some(publish) = publish.take() {
+            if let Some(mut publish) = publish.take() {
+                // aliases are scoped to the connection, resent publishes set them again
+                let alias = publish.properties.as_ref().and_then(|p| p.topic_alias);
+                if let Some(topic) = alias.and_then(|a| self.outgoing_topic_aliases.get(&a)) {
+                    if publish.topic.is_empty() {
+                        publish.topic = topic.clone();
+                    }
+                }
+
                 let request = Request::Publish(publish);
                 pending.push(request);
             }
@@ -175,12 +301,36 @@ pub fn clean(&mut self) -> Vec<Request> {
             id.take();
         }
 
+        // acks of split requests aren't awaited in the next connection
+        self.split_requests.clear();
+        self.split_packets.clear();
+
+        // aliases of both directions are negotiated again by the next connection
+        self.topic_alises.clear();
+        self.outgoing_topic_aliases.clear();
+        self.broker_topic_alias_max = 0;
+
         self.await_pingresp = false;
         self.collision_ping_count = 0;
         self.inflight = 0;
         pending
     }
 
+    /// Number of incoming QoS 1, 2 publishes which the user is yet to ack
+    pub fn unacked_incoming(&self) -> usize {
+        self.unacked_incoming_count
+    }
+
+    /// Forgets incoming publishes which the user is yet to ack. Called when the broker
+    /// didn't resume the session, as acks of publishes received in the previous session
+    /// would ack whatever the broker sends next with the same packet ids
+    pub(crate) fn forget_unacked_incoming(&mut self) {
+        for id in self.unacked_incoming.iter_mut() {
+            id.take();
+        }
+        self.unacked_incoming_count = 0;
+    }
+
     pub fn inflight(&self) -> u16 {
         self.inflight
     }
@@ -209,14 +359,29 @@ pub fn handle_outgoing_packet(
         Ok(packet)
     }
 
+    /// Next packet of a split subscribe or unsubscribe, to be written after the packet
+    /// returned by [`handle_outgoing_packet`](Self::handle_outgoing_packet)
+    pub(crate) fn next_split_packet(&mut self) -> Option<Packet> {
+        self.split_packets.pop_front()
+    }
+
     /// Consolidates handling of all incoming mqtt packets. Returns a `Notification` which for the
     /// user to consume and `Packet` which for the eventloop to put on the network
     /// E.g For incoming QoS1 publish packet, this method returns (Publish, Puback). Publish packet will
     /// be forwarded to user and Pubck packet will be written to network
     pub fn handle_incoming_packet(
         &mut self,
-        mut packet: Incoming,
+        packet: Incoming,
     ) -> Result<Option<Packet>, StateError> {
+        let mut packet = match self.join_split_ack(packet) {
+            Some(packet) => packet,
+            // Acks of the other packets of a split request are still awaited
+            None => {
+                self.last_incoming = Instant::now();
+                return Ok(None);
+            }
+        };
+
         let outgoing = match &mut packet {
             Incoming::PingResp(_) => self.handle_incoming_pingresp()?,
             Incoming::Publish(publish) => self.handle_incoming_publish(publish)?,
@@ -239,6 +404,52 @@ pub fn handle_incoming_packet(
         Ok(outgoing)
     }
 
+    /// Holds back acks of packets of split requests till all of them are received and
+    /// returns them joined, in the order of the filters, with packet id of the request
+    fn join_split_ack(&mut self, packet: Incoming) -> Option<Incoming> {
+        let pkid = match &packet {
+            Incoming::SubAck(suback) => suback.pkid,
+            Incoming::UnsubAck(unsuback) => unsuback.pkid,
+            _ => return Some(packet),
+        };
+
+        let index = match self
+            .split_requests
+            .iter()
+            .position(|request| request.pkids.contains(&pkid))
+        {
+            Some(index) => index,
+            None => return Some(packet),
+        };
+
+        let request = &mut self.split_requests[index];
+        request.acks.insert(pkid, packet);
+        if request.acks.len() < request.pkids.len() {
+            return None;
+        }
+
+        let mut request = self.split_requests.remove(index);
+        let mut acks = request
+            .pkids
+            .iter()
+            .filter_map(|pkid| request.acks.remove(pkid));
+
+        let mut joined = acks.next()?;
+        for ack in acks {
+            match (&mut joined, ack) {
+                (Incoming::SubAck(joined), Incoming::SubAck(suback)) => {
+                    joined.return_codes.extend(suback.return_codes)
+                }
+                (Incoming::UnsubAck(joined), Incoming::UnsubAck(unsuback)) => {
+                    joined.reasons.extend(unsuback.reasons)
+                }
+                (_, ack) => warn!("Unexpected ack of a split request: {:?}", ack),
+            }
+        }
+
+        Some(joined)
+    }
+
     pub fn handle_protocol_error(&mut self) -> Result<Option<Packet>, StateError> {
         // send DISCONNECT packet with REASON_CODE 0x82
         self.outgoing_disconnect(DisconnectReasonCode::ProtocolError)
@@ -253,7 +464,12 @@ fn handle_incoming_suback(
                 SubscribeReasonCode::Success(qos) => {
                     debug!("SubAck Pkid = {:?}, QoS = {:?}", suback.pkid, qos);
                 }
-                _ => return Err(StateError::SubFail { reason: *reason }),
+                _ => {
+                    return Err(StateError::SubFail {
+                        reason: *reason,
+                        reason_string: suback.reason_string().map(str::to_owned),
+                    })
+                }
             }
         }
         Ok(None)
@@ -265,7 +481,10 @@ fn handle_incoming_unsuback(
     ) -> Result<Option<Packet>, StateError> {
         for reason in unsuback.reasons.iter() {
             if reason != &UnsubAckReason::Success {
-                return Err(StateError::UnsubFail { reason: *reason });
+                return Err(StateError::UnsubFail {
+                    reason: *reason,
+                    reason_string: unsuback.reason_string().map(str::to_owned),
+                });
             }
         }
         Ok(None)
@@ -281,6 +500,21 @@ fn handle_incoming_connack(
             });
         }
 
+        // Acks of publishes from a session which wasn't resumed aren't valid anymore
+        if !connack.session_present {
+            self.forget_unacked_incoming();
+        }
+
+        self.max_outgoing_packet_size = connack
+            .properties
+            .as_ref()
+            .and_then(|props| props.max_packet_size);
+        self.server_info.update(connack.properties.as_ref());
+        self.response_information = connack
+            .properties
+            .as_ref()
+            .and_then(|props| props.response_information.clone());
+
         if let Some(props) = &connack.properties {
             if let Some(topic_alias_max) = props.topic_alias_max {
                 self.broker_topic_alias_max = topic_alias_max
@@ -301,14 +535,14 @@ fn handle_incoming_disconn(
         disconn: &mut Disconnect,
     ) -> Result<Option<Packet>, StateError> {
         let reason_code = disconn.reason_code;
-        let reason_string = if let Some(props) = &disconn.properties {
-            props.reason_string.clone()
-        } else {
-            None
+        let (reason_string, server_reference) = match &disconn.properties {
+            Some(props) => (props.reason_string.clone(), props.server_reference.clone()),
+            None => (None, None),
         };
         Err(StateError::ServerDisconnect {
             reason_code,
             reason_string,
+            server_reference,
         })
     }
 
@@ -344,6 +578,8 @@ fn handle_incoming_publish(
                     let puback = PubAck::new(publish.pkid, None);
                     return self.outgoing_puback(puback);
                 }
+
+                self.track_unacked_incoming(publish.pkid);
                 Ok(None)
             }
             QoS::ExactlyOnce => {
@@ -354,20 +590,20 @@ fn handle_incoming_publish(
                     let pubrec = PubRec::new(pkid, None);
                     return self.outgoing_pubrec(pubrec);
                 }
+
+                self.track_unacked_incoming(pkid);
                 Ok(None)
             }
         }
     }
 
     fn handle_incoming_puback(&mut self, puback: &PubAck) -> Result<Option<Packet>, StateError> {
-        let publish = self
-            .outgoing_pub
-            .get_mut(puback.pkid as usize)
-            .ok_or(StateError::Unsolicited(puback.pkid))?;
-
-        if publish.take().is_none() {
-            error!("Unsolicited puback packet: {:?}", puback.pkid);
-            return Err(StateError::Unsolicited(puback.pkid));
+        // Duplicate acks and acks of packet ids which were never sent are ignored, quirky
+        // brokers aren't worth a disconnection
+        let publish = self.outgoing_pub.get_mut(puback.pkid as usize);
+        if publish.and_then(Option::take).is_none() {
+            warn!("Ignoring puback of unknown packet id: {:?}", puback.pkid);
+            return Ok(None);
         }
 
         self.inflight -= 1;
@@ -377,6 +613,7 @@ fn handle_incoming_puback(&mut self, puback: &PubAck) -> Result<Option<Packet>,
         {
             return Err(StateError::PubAckFail {
                 reason: puback.reason,
+                reason_string: puback.reason_string().map(str::to_owned),
             });
         }
 
@@ -396,14 +633,10 @@ fn handle_incoming_puback(&mut self, puback: &PubAck) -> Result<Option<Packet>,
     }
 
     fn handle_incoming_pubrec(&mut self, pubrec: &PubRec) -> Result<Option<Packet>, StateError> {
-        let publish = self
-            .outgoing_pub
-            .get_mut(pubrec.pkid as usize)
-            .ok_or(StateError::Unsolicited(pubrec.pkid))?;
-
-        if publish.take().is_none() {
-            error!("Unsolicited pubrec packet: {:?}", pubrec.pkid);
-            return Err(StateError::Unsolicited(pubrec.pkid));
+        let publish = self.outgoing_pub.get_mut(pubrec.pkid as usize);
+        if publish.and_then(Option::take).is_none() {
+            warn!("Ignoring pubrec of unknown packet id: {:?}", pubrec.pkid);
+            return Ok(None);
         }
 
         if pubrec.reason != PubRecReason::Success
@@ -411,6 +644,7 @@ fn handle_incoming_pubrec(&mut self, pubrec: &PubRec) -> Result<Option<Packet>,
         {
             return Err(StateError::PubRecFail {
                 reason: pubrec.reason,
+                reason_string: pubrec.reason_string().map(str::to_owned),
             });
         }
 
@@ -428,9 +662,17 @@ fn handle_incoming_pubrel(&mut self, pubrel: &PubRel) -> Result<Option<Packet>,
             .get_mut(pubrel.pkid as usize)
             .ok_or(StateError::Unsolicited(pubrel.pkid))?;
 
+        // Broker resends PUBREL of a publish which is already released when PUBCOMP was lost,
+        // or after the client restarted without its state. Completing it lets the broker
+        // release the packet id instead of disconnecting
         if publish.take().is_none() {
-            error!("Unsolicited pubrel packet: {:?}", pubrel.pkid);
-            return Err(StateError::Unsolicited(pubrel.pkid));
+            warn!("Pubrel of unknown packet id: {:?}", pubrel.pkid);
+            let event = Event::Outgoing(Outgoing::PubComp(pubrel.pkid));
+            self.events.push_back(event);
+
+            let mut pubcomp = PubComp::new(pubrel.pkid, None);
+            pubcomp.reason = PubCompReason::PacketIdentifierNotFound;
+            return Ok(Some(Packet::PubComp(pubcomp)));
         }
 
         if pubrel.reason != PubRelReason::Success {
@@ -446,6 +688,12 @@ fn handle_incoming_pubrel(&mut self, pubrel: &PubRel) -> Result<Option<Packet>,
     }
 
     fn handle_incoming_pubcomp(&mut self, pubcomp: &PubComp) -> Result<Option<Packet>, StateError> {
+        let pubrel = self.outgoing_rel.get_mut(pubcomp.pkid as usize);
+        if pubrel.and_then(Option::take).is_none() {
+            warn!("Ignoring pubcomp of unknown packet id: {:?}", pubcomp.pkid);
+            return Ok(None);
+        }
+
         let outgoing = self.check_collision(pubcomp.pkid).map(|publish| {
             let pkid = publish.pkid;
             let event = Event::Outgoing(Outgoing::Publish(pkid));
@@ -455,15 +703,6 @@ fn handle_incoming_pubcomp(&mut self, pubcomp: &PubComp) -> Result<Option<Packet
             Packet::Publish(publish)
         });
 
-        let pubrel = self
-            .outgoing_rel
-            .get_mut(pubcomp.pkid as usize)
-            .ok_or(StateError::Unsolicited(pubcomp.pkid))?;
-        if pubrel.take().is_none() {
-            error!("Unsolicited pubcomp packet: {:?}", pubcomp.pkid);
-            return Err(StateError::Unsolicited(pubcomp.pkid));
-        }
-
         if pubcomp.reason != PubCompReason::Success {
             return Err(StateError::PubCompFail {
                 reason: pubcomp.reason,
@@ -482,6 +721,8 @@ fn handle_incoming_pingresp(&mut self) -> Result<Option<Packet>, StateError> {
     /// Adds next packet identifier to QoS 1 and 2 publish packets and returns
     /// it buy wrapping publish in packet
     fn outgoing_publish(&mut self, mut publish: Publish) -> Result<Option<Packet>, StateError> {
+        self.outgoing_alias(&publish)?;
+
         if publish.qos != QoS::AtMostOnce {
             if publish.pkid == 0 {
                 publish.pkid = self.next_pkid();
@@ -515,26 +756,38 @@ fn outgoing_publish(&mut self, mut publish: Publish) -> Result<Option<Packet>, S
         );
 
         let pkid = publish.pkid;
-
-        if let Some(props) = &publish.properties {
-            if let Some(alias) = props.topic_alias {
-                if alias > self.broker_topic_alias_max {
-                    // We MUST NOT send a Topic Alias that is greater than the
-                    // broker's Topic Alias Maximum.
-                    return Err(StateError::InvalidAlias {
-                        alias,
-                        max: self.broker_topic_alias_max,
-                    });
-                }
-            }
-        };
-
         let event = Event::Outgoing(Outgoing::Publish(pkid));
         self.events.push_back(event);
 
         Ok(Some(Packet::Publish(publish)))
     }
 
+    /// Validates the topic alias of an outgoing publish and remembers the topic it sets
+    fn outgoing_alias(&mut self, publish: &Publish) -> Result<(), StateError> {
+        let alias = match publish.properties.as_ref().and_then(|p| p.topic_alias) {
+            Some(alias) => alias,
+            None => return Ok(()),
+        };
+
+        if alias > self.broker_topic_alias_max {
+            // We MUST NOT send a Topic Alias that is greater than the
+            // broker's Topic Alias Maximum.
+            return Err(StateError::InvalidAlias {
+                alias,
+                max: self.broker_topic_alias_max,
+            });
+        }
+
+        if !publish.topic.is_empty() {
+            self.outgoing_topic_aliases
+                .insert(alias, publish.topic.clone());
+        } else if !self.outgoing_topic_aliases.contains_key(&alias) {
+            return Err(StateError::UnknownAlias(alias));
+        }
+
+        Ok(())
+    }
+
     fn outgoing_pubrel(&mut self, pubrel: PubRel) -> Result<Option<Packet>, StateError> {
         let pubrel = self.save_pubrel(pubrel)?;
 
@@ -546,7 +799,35 @@ fn outgoing_pubrel(&mut self, pubrel: PubRel) -> Result<Option<Packet>, StateErr
         Ok(Some(Packet::PubRel(PubRel::new(pubrel.pkid, None))))
     }
 
+    /// Marks an incoming publish as awaiting the ack of the user. The broker resends
+    /// publishes which aren't acked yet after reconnecting
+    fn track_unacked_incoming(&mut self, pkid: u16) {
+        if self.unacked_incoming[pkid as usize].replace(pkid).is_none() {
+            self.unacked_incoming_count += 1;
+        }
+    }
+
+    /// Checks that a manual ack is of a publish awaiting it, so that publishes aren't
+    /// acked twice and acks of a previous session don't ack new publishes
+    fn take_unacked_incoming(&mut self, pkid: u16) -> bool {
+        if !self.manual_acks {
+            return true;
+        }
+
+        if self.unacked_incoming[pkid as usize].take().is_none() {
+            debug!(pkid, "Dropping ack of a stale or acked publish");
+            return false;
+        }
+
+        self.unacked_incoming_count -= 1;
+        true
+    }
+
     fn outgoing_puback(&mut self, puback: PubAck) -> Result<Option<Packet>, StateError> {
+        if !self.take_unacked_incoming(puback.pkid) {
+            return Ok(None);
+        }
+
         let pkid = puback.pkid;
         let event = Event::Outgoing(Outgoing::PubAck(pkid));
         self.events.push_back(event);
@@ -555,6 +836,10 @@ fn outgoing_puback(&mut self, puback: PubAck) -> Result<Option<Packet>, StateErr
     }
 
     fn outgoing_pubrec(&mut self, pubrec: PubRec) -> Result<Option<Packet>, StateError> {
+        if !self.take_unacked_incoming(pubrec.pkid) {
+            return Ok(None);
+        }
+
         let pkid = pubrec.pkid;
         let event = Event::Outgoing(Outgoing::PubRec(pkid));
         self.events.push_back(event);
@@ -614,7 +899,29 @@ fn outgoing_subscribe(
         let event = Event::Outgoing(Outgoing::Subscribe(pkid));
         self.events.push_back(event);
 
-        Ok(Some(Packet::Subscribe(subscription)))
+        let max_size = match self.max_outgoing_packet_size {
+            Some(max) if subscription.size() > max as usize => max as usize,
+            _ => return Ok(Some(Packet::Subscribe(subscription))),
+        };
+
+        let properties = subscription.properties;
+        let chunks = split_to_fit(subscription.filters, max_size, |filters| {
+            Subscribe::new_many(filters.to_vec(), properties.clone()).size()
+        });
+
+        let pkids = self.split_pkids(pkid, chunks.len());
+        debug!(?pkids, "Subscribe split into packets");
+        let mut packets = chunks.into_iter().zip(pkids).map(|(filters, pkid)| {
+            Packet::Subscribe(Subscribe {
+                pkid,
+                filters,
+                properties: properties.clone(),
+            })
+        });
+
+        let first = packets.next();
+        self.split_packets.extend(packets);
+        Ok(first)
     }
 
     fn outgoing_unsubscribe(
@@ -633,7 +940,48 @@ fn outgoing_unsubscribe(
         let event = Event::Outgoing(Outgoing::Unsubscribe(pkid));
         self.events.push_back(event);
 
-        Ok(Some(Packet::Unsubscribe(unsub)))
+        let max_size = match self.max_outgoing_packet_size {
+            Some(max) if unsub.size() > max as usize => max as usize,
+            _ => return Ok(Some(Packet::Unsubscribe(unsub))),
+        };
+
+        let properties = unsub.properties;
+        let chunks = split_to_fit(unsub.filters, max_size, |filters| {
+            let packet = Unsubscribe {
+                pkid: 0,
+                filters: filters.to_vec(),
+                properties: properties.clone(),
+            };
+            packet.size()
+        });
+
+        let pkids = self.split_pkids(pkid, chunks.len());
+        debug!(?pkids, "Unsubscribe split into packets");
+        let mut packets = chunks.into_iter().zip(pkids).map(|(filters, pkid)| {
+            Packet::Unsubscribe(Unsubscribe {
+                pkid,
+                filters,
+                properties: properties.clone(),
+            })
+        });
+
+        let first = packets.next();
+        self.split_packets.extend(packets);
+        Ok(first)
+    }
+
+    /// Packet ids of a request with packet id `pkid` split into `count` packets. Acks
+    /// of the packets are awaited to be joined
+    fn split_pkids(&mut self, pkid: u16, count: usize) -> Vec<u16> {
+        let mut pkids = vec![pkid];
+        pkids.extend((1..count).map(|_| self.next_pkid()));
+
+        self.split_requests.push(SplitRequest {
+            pkids: pkids.clone(),
+            acks: HashMap::new(),
+        });
+
+        pkids
     }
 
     fn outgoing_disconnect(
@@ -692,6 +1040,35 @@ fn next_pkid(&mut self) -> u16 {
     }
 }
 
+/// Splits `items` into chunks which fit in packets of `max_size`, as measured by `size`.
+/// An item which doesn't fit in a packet by itself is put in a chunk of its own
+fn split_to_fit<T: Clone>(
+    items: Vec<T>,
+    max_size: usize,
+    size: impl Fn(&[T]) -> usize,
+) -> Vec<Vec<T>> {
+    let empty = size(&[]);
+    // Remaining length of the packet takes up to 3 more bytes as the packet grows
+    let max_size = max_size.saturating_sub(3);
+
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_size = empty;
+    for item in items {
+        let item_size = size(std::slice::from_ref(&item)) - empty;
+        if !chunk.is_empty() && chunk_size + item_size > max_size {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_size = empty;
+        }
+
+        chunk_size += item_size;
+        chunk.push(item);
+    }
+
+    chunks.push(chunk);
+    chunks
+}
+
 #[cfg(test)]
 mod test {
     use super::mqttbytes::v5::*;
@@ -871,10 +1248,39 @@ fn incoming_publish_should_not_be_acked_with_manual_acks() {
 
         let pkid = mqtt.incoming_pub[3].unwrap();
         assert_eq!(pkid, 3);
+        assert_eq!(mqtt.unacked_incoming(), 2);
 
         assert!(mqtt.events.is_empty());
     }
 
+    #[test]
+    fn manual_acks_of_unknown_or_forgotten_publishes_are_dropped() {
+        let mut mqtt = build_mqttstate();
+        mqtt.manual_acks = true;
+
+        let mut publish1 = build_incoming_publish(QoS::AtLeastOnce, 1);
+        let mut publish2 = build_incoming_publish(QoS::ExactlyOnce, 2);
+        mqtt.handle_incoming_publish(&mut publish1).unwrap();
+        mqtt.handle_incoming_publish(&mut publish2).unwrap();
+
+        let puback = Request::PubAck(PubAck::new(1, None));
+        let packet = mqtt.handle_outgoing_packet(puback.clone()).unwrap();
+        assert!(matches!(
+            packet,
+            Some(Packet::PubAck(PubAck { pkid: 1, .. }))
+        ));
+        assert_eq!(mqtt.unacked_incoming(), 1);
+
+        // Acking twice sends only one puback
+        assert!(mqtt.handle_outgoing_packet(puback).unwrap().is_none());
+
+        // Session wasn't resumed after reconnection
+        mqtt.forget_unacked_incoming();
+        assert_eq!(mqtt.unacked_incoming(), 0);
+        let pubrec = Request::PubRec(PubRec::new(2, None));
+        assert!(mqtt.handle_outgoing_packet(pubrec).unwrap().is_none());
+    }
+
     #[test]
     fn incoming_qos2_publish_should_send_rec_to_network_and_publish_to_user() {
         let mut mqtt = build_mqttstate();
@@ -913,12 +1319,26 @@ fn incoming_puback_with_pkid_greater_than_max_inflight_should_be_handled_gracefu
 
         let got = mqtt
             .handle_incoming_puback(&PubAck::new(101, None))
-            .unwrap_err();
+            .unwrap();
+        assert!(got.is_none());
+    }
 
-        match got {
-            StateError::Unsolicited(pkid) => assert_eq!(pkid, 101),
-            e => panic!("Unexpected error: {}", e),
-        }
+    #[test]
+    fn acks_of_unknown_packet_ids_are_ignored() {
+        let mut mqtt = build_mqttstate();
+        let publish = build_outgoing_publish(QoS::AtLeastOnce);
+        mqtt.outgoing_publish(publish).unwrap();
+
+        // spurious and duplicate acks
+        let puback = |pkid| PubAck::new(pkid, None);
+        assert!(mqtt.handle_incoming_puback(&puback(2)).unwrap().is_none());
+        assert!(mqtt.handle_incoming_puback(&puback(1)).unwrap().is_none());
+        assert!(mqtt.handle_incoming_puback(&puback(1)).unwrap().is_none());
+        let pubrec = PubRec::new(3, None);
+        assert!(mqtt.handle_incoming_pubrec(&pubrec).unwrap().is_none());
+        let pubcomp = PubComp::new(3, None);
+        assert!(mqtt.handle_incoming_pubcomp(&pubcomp).unwrap().is_none());
+        assert_eq!(mqtt.inflight, 0);
     }
 
     #[test]
@@ -982,6 +1402,26 @@ fn incoming_pubrel_should_send_comp_to_network_and_nothing_to_user() {
         }
     }
 
+    #[test]
+    fn orphan_pubrel_is_completed_with_packet_identifier_not_found() {
+        let mut mqtt = build_mqttstate();
+
+        match mqtt
+            .handle_incoming_packet(Incoming::PubRel(PubRel::new(7, None)))
+            .unwrap()
+        {
+            Some(Packet::PubComp(pubcomp)) => {
+                assert_eq!(pubcomp.pkid, 7);
+                assert_eq!(pubcomp.reason, PubCompReason::PacketIdentifierNotFound);
+            }
+            packet => panic!("Invalid network request: {:?}", packet),
+        }
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Outgoing(Outgoing::PubComp(7)))
+        );
+    }
+
     #[test]
     fn incoming_pubcomp_should_release_correct_pkid_from_release_queue() {
         let mut mqtt = build_mqttstate();
@@ -1027,4 +1467,154 @@ fn outgoing_ping_handle_should_succeed_if_pingresp_is_received() {
         // should ping
         mqtt.outgoing_ping().unwrap();
     }
+
+    #[test]
+    fn oversized_subscribe_is_split_and_acks_are_joined() {
+        let mut mqtt = build_mqttstate();
+        mqtt.max_outgoing_packet_size = Some(1024);
+
+        let filters: Vec<Filter> = (0..2000)
+            .map(|i| Filter::new(format!("devices/{i}/status"), QoS::AtLeastOnce))
+            .collect();
+        let subscribe = Subscribe::new_many(filters.clone(), None);
+
+        let first = mqtt
+            .handle_outgoing_packet(Request::Subscribe(subscribe))
+            .unwrap();
+        let packets: Vec<Subscribe> = first
+            .into_iter()
+            .chain(std::iter::from_fn(|| mqtt.next_split_packet()))
+            .map(|packet| match packet {
+                Packet::Subscribe(subscribe) => subscribe,
+                packet => panic!("Expected subscribe, found {packet:?}"),
+            })
+            .collect();
+
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|subscribe| subscribe.size() <= 1024));
+        let split: Vec<Filter> = packets.iter().flat_map(|s| s.filters.clone()).collect();
+        assert_eq!(split, filters);
+
+        let pkid = packets[0].pkid;
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Outgoing(Outgoing::Subscribe(pkid)))
+        );
+        assert!(mqtt.events.is_empty());
+
+        // acks are joined in the order of filters, even when received out of order
+        for subscribe in packets.iter().rev() {
+            let return_codes =
+                vec![SubscribeReasonCode::Success(QoS::AtLeastOnce); subscribe.filters.len()];
+            let suback = SubAck {
+                pkid: subscribe.pkid,
+                return_codes,
+                properties: None,
+            };
+            mqtt.handle_incoming_packet(Incoming::SubAck(suback))
+                .unwrap();
+        }
+
+        match mqtt.events.pop_front() {
+            Some(Event::Incoming(Incoming::SubAck(suback))) => {
+                assert_eq!(suback.pkid, pkid);
+                assert_eq!(suback.return_codes.len(), filters.len());
+            }
+            event => panic!("Expected joined suback, found {event:?}"),
+        }
+        assert!(mqtt.events.is_empty());
+    }
+
+    fn aliased_publish(topic: &str, qos: QoS, pkid: u16) -> Publish {
+        let properties = PublishProperties {
+            topic_alias: Some(1),
+            ..Default::default()
+        };
+        let mut publish = Publish::new(topic, qos, vec![1, 2, 3], Some(properties));
+        publish.pkid = pkid;
+        publish
+    }
+
+    #[test]
+    fn topic_aliases_are_negotiated_again_after_reconnect() {
+        let mut mqtt = build_mqttstate();
+        mqtt.broker_topic_alias_max = 10;
+
+        // incoming alias set by the broker on the first connection
+        mqtt.handle_incoming_publish(&mut aliased_publish("hello/world", QoS::AtMostOnce, 0))
+            .unwrap();
+        let mut publish = aliased_publish("", QoS::AtMostOnce, 0);
+        mqtt.handle_incoming_publish(&mut publish).unwrap();
+        assert_eq!(publish.topic, "hello/world");
+
+        // outgoing alias set by the client on the first connection
+        mqtt.outgoing_publish(aliased_publish("hello/world", QoS::AtMostOnce, 0))
+            .unwrap();
+        mqtt.outgoing_publish(aliased_publish("", QoS::AtLeastOnce, 0))
+            .unwrap();
+
+        let pending = mqtt.clean();
+        match &pending[..] {
+            [Request::Publish(publish)] => assert_eq!(publish.topic, "hello/world"),
+            pending => panic!("Unexpected pending requests {pending:?}"),
+        }
+        assert_eq!(mqtt.broker_topic_alias_max, 0);
+
+        // stale aliases aren't used on the next connection
+        mqtt.broker_topic_alias_max = 10;
+        mqtt.events.clear();
+        let mut publish = aliased_publish("", QoS::AtMostOnce, 0);
+        mqtt.handle_incoming_publish(&mut publish).unwrap();
+        assert!(publish.topic.is_empty());
+        assert_eq!(
+            mqtt.events.pop_front(),
+            Some(Event::Outgoing(Outgoing::Disconnect))
+        );
+        assert!(matches!(
+            mqtt.outgoing_publish(aliased_publish("", QoS::AtMostOnce, 0)),
+            Err(StateError::UnknownAlias(1))
+        ));
+
+        for request in pending {
+            mqtt.handle_outgoing_packet(request).unwrap();
+        }
+        mqtt.outgoing_publish(aliased_publish("", QoS::AtMostOnce, 0))
+            .unwrap();
+    }
+
+    #[test]
+    fn response_information_is_taken_from_connack() {
+        let mut mqtt = build_mqttstate();
+        let properties = ConnAckProperties {
+            session_expiry_interval: None,
+            receive_max: None,
+            max_qos: None,
+            retain_available: None,
+            max_packet_size: None,
+            assigned_client_identifier: None,
+            topic_alias_max: None,
+            reason_string: None,
+            user_properties: vec![],
+            wildcard_subscription_available: None,
+            subscription_identifiers_available: None,
+            shared_subscription_available: None,
+            server_keep_alive: None,
+            response_information: Some("responses/dummy".to_owned()),
+            server_reference: None,
+            authentication_method: None,
+            authentication_data: None,
+        };
+        let connack = ConnAck {
+            session_present: false,
+            code: ConnectReturnCode::Success,
+            properties: Some(properties),
+        };
+
+        mqtt.handle_incoming_packet(Incoming::ConnAck(connack))
+            .unwrap();
+        assert_eq!(
+            mqtt.response_information.as_deref(),
+            Some("responses/dummy")
+        );
+    }
 }
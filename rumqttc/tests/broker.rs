@@ -31,11 +31,12 @@ pub async fn new(port: u16, connack: u8) -> Broker {
         let (outgoing_tx, outgoing_rx) = bounded(10);
         framed.readb(&mut incoming).await.unwrap();
 
-        match incoming.pop_front().unwrap() {
+        match incoming.front().unwrap() {
             Packet::Connect(_) => {
                 let connack = match connack {
                     0 => ConnAck::new(ConnectReturnCode::Success, false),
                     1 => ConnAck::new(ConnectReturnCode::BadUserNamePassword, false),
+                    // Connect is left in `incoming` for inspection
                     _ => {
                         return Broker {
                             framed,
@@ -119,6 +120,11 @@ pub async fn ack(&mut self, pkid: u16) {
         self.framed.write(packet).await.unwrap();
     }
 
+    /// Sends a packet
+    pub async fn send(&mut self, packet: Packet) {
+        self.framed.write_packet(packet).await.unwrap();
+    }
+
     /// Sends an acknowledgement
     pub async fn pingresp(&mut self) {
         let packet = Packet::PingResp;
@@ -254,6 +260,12 @@ pub async fn readb(&mut self, incoming: &mut VecDeque<Incoming>) -> io::Result<(
     #[inline]
     async fn write(&mut self, packet: Packet) -> Result<Outgoing, Error> {
         let outgoing = outgoing(&packet);
+        self.write_packet(packet).await?;
+        Ok(outgoing)
+    }
+
+    /// Writes packets, including the ones clients never send like acks of subscriptions
+    async fn write_packet(&mut self, packet: Packet) -> Result<(), Error> {
         match packet {
             Packet::Publish(packet) => packet.write(&mut self.write)?,
             Packet::PubRel(packet) => packet.write(&mut self.write)?,
@@ -282,7 +294,7 @@ async fn write(&mut self, packet: Packet) -> Result<Outgoing, Error> {
         self.socket.write_all(&self.write[..]).await.unwrap();
         self.write.clear();
 
-        Ok(outgoing)
+        Ok(())
     }
 }
 
@@ -1,4 +1,6 @@
 use matches::assert_matches;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::{task, time};
 
@@ -106,6 +108,84 @@ fn test_zero_keep_alive_values() {
     options.set_keep_alive(Duration::ZERO);
 }
 
+#[tokio::test]
+async fn zero_keep_alive_disables_pings() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2027);
+    options.set_keep_alive(Duration::ZERO);
+
+    task::spawn(async move {
+        let mut eventloop = EventLoop::new(options, 5);
+        run(&mut eventloop, false).await.unwrap();
+    });
+
+    let mut broker = Broker::new(2027, 0).await;
+    let packet = time::timeout(Duration::from_secs(3), broker.read_packet()).await;
+    assert!(packet.is_err(), "Unexpected packet {packet:?}");
+}
+
+#[tokio::test]
+async fn qos2_flows_yield_every_acknowledgement() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2026);
+    options.set_packet_trace(Some(20));
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    client
+        .publish("hello/world", QoS::ExactlyOnce, false, vec![1])
+        .await
+        .unwrap();
+
+    let mut incoming = Publish::new("hello/world", QoS::ExactlyOnce, vec![2]);
+    incoming.pkid = 5;
+    let publish = incoming.clone();
+    task::spawn(async move {
+        let mut broker = Broker::new(2026, 0).await;
+        let outgoing = broker.read_publish().await.unwrap();
+        broker
+            .send(Packet::PubRec(PubRec::new(outgoing.pkid)))
+            .await;
+        assert_matches!(broker.read_packet().await, Some(Packet::PubRel(_)));
+        broker
+            .send(Packet::PubComp(PubComp::new(outgoing.pkid)))
+            .await;
+
+        broker.send(Packet::Publish(publish)).await;
+        assert_matches!(broker.read_packet().await, Some(Packet::PubRec(_)));
+        broker.send(Packet::PubRel(PubRel::new(5))).await;
+        assert_matches!(broker.read_packet().await, Some(Packet::PubComp(_)));
+        time::sleep(Duration::from_secs(10)).await;
+    });
+
+    // let the broker listen before connecting
+    time::sleep(Duration::from_secs(1)).await;
+
+    let mut events = Vec::new();
+    while events.len() < 9 {
+        let event = time::timeout(Duration::from_secs(5), eventloop.poll())
+            .await
+            .unwrap()
+            .unwrap();
+        events.push(event);
+    }
+
+    assert_matches!(events.remove(0), Event::Incoming(Packet::ConnAck(_)));
+    assert_eq!(
+        events,
+        vec![
+            Event::Outgoing(Outgoing::Publish(1)),
+            Event::Outgoing(Outgoing::PubRel(1)),
+            Event::Incoming(Packet::PubRec(PubRec::new(1))),
+            Event::Incoming(Packet::PubComp(PubComp::new(1))),
+            Event::Outgoing(Outgoing::PubRec(5)),
+            Event::Incoming(Packet::Publish(incoming)),
+            Event::Outgoing(Outgoing::PubComp(5)),
+            Event::Incoming(Packet::PubRel(PubRel::new(5))),
+        ]
+    );
+
+    let traced: Vec<_> = eventloop.packet_trace().unwrap().packets().collect();
+    assert_eq!(traced.len(), 9);
+    assert!(traced.windows(2).all(|pair| pair[0].at <= pair[1].at));
+}
+
 #[test]
 fn test_valid_keep_alive_values() {
     let mut options = MqttOptions::new("dummy", "127.0.0.1", 1885);
@@ -231,6 +311,52 @@ async fn some_incoming_and_no_outgoing_should_trigger_pings_on_time() {
     assert_eq!(count, 3);
 }
 
+#[tokio::test]
+async fn outgoing_packets_reset_keepalive_when_configured() {
+    let keep_alive = 5;
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2003);
+    options
+        .set_keep_alive(Duration::from_secs(keep_alive))
+        .set_reset_keep_alive_on_outgoing(true);
+
+    // publish every second for longer than keep alive
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    task::spawn(async move {
+        start_requests(8, QoS::AtMostOnce, 1, client).await;
+        time::sleep(Duration::from_secs(10)).await;
+    });
+
+    task::spawn(async move {
+        run(&mut eventloop, false).await.unwrap();
+    });
+
+    let mut broker = Broker::new(2003, 0).await;
+    let mut publishes = 0;
+    let mut last_publish = Instant::now();
+
+    loop {
+        match broker.tick().await {
+            Event::Incoming(Incoming::Publish(_)) => {
+                publishes += 1;
+                last_publish = Instant::now();
+            }
+            Event::Incoming(Incoming::PingReq) => break,
+            _ => continue,
+        }
+    }
+
+    // ping is only sent after connection is idle for keep alive time, give or take
+    // scheduling delays
+    assert_eq!(publishes, 8);
+    let idle = last_publish.elapsed();
+    let keep_alive = Duration::from_secs(keep_alive);
+    assert!(
+        idle > keep_alive - Duration::from_millis(500)
+            && idle < keep_alive + Duration::from_secs(1),
+        "Ping after {idle:?} of idling"
+    );
+}
+
 #[tokio::test]
 async fn detects_halfopen_connections_in_the_second_ping_request() {
     let mut options = MqttOptions::new("dummy", "127.0.0.1", 2001);
@@ -257,6 +383,34 @@ async fn detects_halfopen_connections_in_the_second_ping_request() {
     assert_eq!(start.elapsed().as_secs(), 10);
 }
 
+#[tokio::test]
+async fn detects_dead_connections_after_pingresp_timeout() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2002);
+    options
+        .set_keep_alive(Duration::from_secs(5))
+        .set_pingresp_timeout(Duration::from_secs(2));
+
+    // A broker which stops responding after connack
+    task::spawn(async move {
+        let mut broker = Broker::new(2002, 0).await;
+        broker.blackhole().await;
+    });
+
+    time::sleep(Duration::from_secs(1)).await;
+    let start = Instant::now();
+    let mut eventloop = EventLoop::new(options, 5);
+    loop {
+        if let Err(e) = eventloop.poll().await {
+            match e {
+                ConnectionError::MqttState(StateError::AwaitPingResp) => break,
+                v => panic!("Expecting pingresp error. Found = {:?}", v),
+            }
+        }
+    }
+
+    assert_eq!(start.elapsed().as_secs(), 7);
+}
+
 //
 // All flow control tests here
 //
@@ -581,3 +735,802 @@ async fn state_is_being_cleaned_properly_and_pending_request_calculated_properly
     });
     handle.await.unwrap();
 }
+
+#[tokio::test]
+async fn writes_to_a_peer_which_stopped_reading_time_out() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 3006);
+    options
+        .set_write_timeout(Duration::from_secs(1))
+        .set_socket_buffer_sizes(Some(1024), None)
+        .set_max_packet_size(10 * 1024, 200 * 1024);
+
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    task::spawn(async move {
+        start_requests_with_payload(200, QoS::AtMostOnce, 0, client, 100 * 1024).await;
+    });
+
+    // Accepts the connection but never reads from it
+    task::spawn(async move {
+        let _broker = Broker::new(3006, 0).await;
+        time::sleep(Duration::from_secs(30)).await;
+    });
+
+    time::sleep(Duration::from_secs(1)).await;
+    let e = time::timeout(Duration::from_secs(20), run(&mut eventloop, false))
+        .await
+        .unwrap()
+        .unwrap_err();
+
+    assert_matches!(e, ConnectionError::WriteTimeout);
+    assert!(eventloop.network.is_none());
+}
+
+#[tokio::test]
+async fn streamed_publishes_to_a_peer_which_stopped_reading_time_out() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 3011);
+    options
+        .set_write_timeout(Duration::from_secs(1))
+        .set_socket_buffer_sizes(Some(1024), None)
+        .set_max_packet_size(10 * 1024, 200 * 1024 * 1024);
+
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    let len = 100 * 1024 * 1024;
+    client
+        .publish_stream("hello/world", false, len, tokio::io::repeat(1))
+        .await
+        .unwrap();
+
+    // Accepts the connection but never reads from it
+    task::spawn(async move {
+        let _broker = Broker::new(3011, 0).await;
+        time::sleep(Duration::from_secs(30)).await;
+    });
+
+    time::sleep(Duration::from_secs(1)).await;
+    let e = time::timeout(Duration::from_secs(20), run(&mut eventloop, false))
+        .await
+        .unwrap()
+        .unwrap_err();
+
+    assert_matches!(e, ConnectionError::WriteTimeout);
+}
+
+/// Queues 3 QoS 0 publishes around a QoS 1 one, with at most 2 QoS 0 publishes kept.
+/// Returns first payload bytes of the queued publishes after cleaning the eventloop
+fn queued_after_limit(policy: Qos0DropPolicy) -> (Vec<u8>, ClientMetrics) {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 1883);
+    options.set_max_queued_qos0(Some(2), policy);
+
+    let mut eventloop = EventLoop::new(options, 5);
+    let qos = [
+        QoS::AtMostOnce,
+        QoS::AtLeastOnce,
+        QoS::AtMostOnce,
+        QoS::AtMostOnce,
+    ];
+    for (i, qos) in qos.into_iter().enumerate() {
+        let publish = Publish::new("hello/world", qos, vec![i as u8]);
+        eventloop.pending.push_back(Request::Publish(publish));
+    }
+
+    eventloop.clean();
+    let queued = eventloop
+        .pending
+        .iter()
+        .map(|request| match request {
+            Request::Publish(publish) => publish.payload[0],
+            request => panic!("Unexpected request {request:?}"),
+        })
+        .collect();
+
+    (queued, eventloop.metrics())
+}
+
+#[test]
+fn oldest_queued_qos0_publishes_are_dropped_past_max() {
+    let (queued, metrics) = queued_after_limit(Qos0DropPolicy::DropOldest);
+    assert_eq!(queued, vec![1, 2, 3]);
+    assert_eq!(metrics.qos0_dropped, 1);
+}
+
+#[test]
+fn newest_queued_qos0_publishes_are_dropped_past_max() {
+    let (queued, metrics) = queued_after_limit(Qos0DropPolicy::DropNewest);
+    assert_eq!(queued, vec![0, 1, 2]);
+    assert_eq!(metrics.qos0_dropped, 1);
+}
+
+/// Sends 6 publishes with at most 4 buffered requests and cleans the eventloop, as if
+/// the connection went down before any of them were written. None of them are
+/// buffered till then, so none are refused
+fn buffered_after_clean(overflow: BufferOverflow) -> (AsyncClient, EventLoop) {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 1883);
+    options.set_max_buffered_messages(Some(4), overflow);
+
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    for i in 0..6 {
+        client
+            .try_publish("hello/world", QoS::AtLeastOnce, false, vec![i])
+            .unwrap();
+    }
+
+    eventloop.clean();
+    (client, eventloop)
+}
+
+#[test]
+fn requests_past_max_buffered_stay_in_channel() {
+    let (client, eventloop) = buffered_after_clean(BufferOverflow::Block);
+    assert_eq!(eventloop.pending.len(), 4);
+    assert_eq!(eventloop.metrics().buffered, 6);
+
+    client
+        .try_publish("hello/world", QoS::AtLeastOnce, false, vec![6])
+        .unwrap();
+}
+
+#[test]
+fn publishes_are_refused_while_buffer_is_full() {
+    let (client, _eventloop) = buffered_after_clean(BufferOverflow::Error);
+    let e = client
+        .try_publish("hello/world", QoS::AtLeastOnce, false, vec![6])
+        .unwrap_err();
+    assert_matches!(e, ClientError::BufferFull(_));
+}
+
+#[test]
+fn oldest_buffered_publishes_are_dropped_past_max() {
+    let (_client, eventloop) = buffered_after_clean(BufferOverflow::DropOldest);
+    let buffered: Vec<u8> = eventloop
+        .pending
+        .iter()
+        .map(|request| match request {
+            Request::Publish(publish) => publish.payload[0],
+            request => panic!("Unexpected request {request:?}"),
+        })
+        .collect();
+    assert_eq!(buffered, vec![2, 3, 4, 5]);
+
+    let metrics = eventloop.metrics();
+    assert_eq!(metrics.buffer_dropped, 2);
+    assert_eq!(metrics.buffered, 4);
+}
+
+#[tokio::test]
+async fn flush_resolves_once_requests_before_it_are_written() {
+    let options = MqttOptions::new("dummy", "127.0.0.1", 3007);
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    task::spawn(async move {
+        run(&mut eventloop, true).await.unwrap();
+    });
+
+    let mut broker = Broker::new(3007, 0).await;
+    for i in 1..=3 {
+        client
+            .publish("hello/world", QoS::AtMostOnce, false, vec![i])
+            .await
+            .unwrap();
+    }
+    client.flush().await.unwrap();
+
+    for i in 1..=3 {
+        let publish = broker.read_publish().await.unwrap();
+        assert_eq!(publish.payload[0], i);
+    }
+}
+
+#[tokio::test]
+async fn flush_fails_when_eventloop_is_dropped() {
+    let options = MqttOptions::new("dummy", "127.0.0.1", 3008);
+    let (client, eventloop) = AsyncClient::new(options, 10);
+    let flush = task::spawn(async move { client.flush().await });
+
+    time::sleep(Duration::from_millis(100)).await;
+    drop(eventloop);
+    assert_matches!(flush.await.unwrap(), Err(ClientError::Flush));
+}
+
+#[tokio::test]
+async fn publishes_of_different_qos_are_written_in_order() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 3009);
+    options.set_inflight(2);
+
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    task::spawn(async move {
+        for i in 1..=10 {
+            let qos = if i % 3 == 0 {
+                QoS::AtMostOnce
+            } else {
+                QoS::AtLeastOnce
+            };
+            client
+                .publish("hello/world", qos, false, vec![i])
+                .await
+                .unwrap();
+        }
+    });
+
+    task::spawn(async move {
+        run(&mut eventloop, true).await.unwrap();
+    });
+
+    // Acks are delayed, so QoS 0 publishes are blocked with a full inflight window
+    let mut broker = Broker::new(3009, 0).await;
+    for i in 1..=10 {
+        let publish = broker.read_publish().await.unwrap();
+        assert_eq!(publish.payload[0], i);
+        if publish.qos == QoS::AtLeastOnce {
+            time::sleep(Duration::from_millis(100)).await;
+            broker.ack(publish.pkid).await;
+        }
+    }
+}
+
+#[tokio::test]
+async fn encoded_publishes_are_sent_with_new_packet_ids() {
+    let options = MqttOptions::new("dummy", "127.0.0.1", 3010);
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    task::spawn(async move {
+        let publish = Publish::new("hello/world", QoS::AtLeastOnce, vec![1, 2, 3]);
+        let encoded = EncodedPublish::new(publish).unwrap();
+        for _ in 0..3 {
+            client.publish_encoded(encoded.clone()).await.unwrap();
+        }
+    });
+
+    task::spawn(async move {
+        run(&mut eventloop, false).await.unwrap();
+    });
+
+    let mut broker = Broker::new(3010, 0).await;
+    for pkid in 1..=3 {
+        let publish = broker.read_publish().await.unwrap();
+        assert_eq!(publish.pkid, pkid);
+        assert_eq!(publish.topic, "hello/world");
+        assert_eq!(&publish.payload[..], &[1, 2, 3]);
+    }
+}
+
+#[tokio::test]
+async fn metrics_are_accumulated_across_reconnections() {
+    let options = MqttOptions::new("dummy", "127.0.0.1", 3005);
+    let connect_size = Packet::Connect(Connect::new("dummy")).size() as u64;
+    let connack_size =
+        Packet::ConnAck(ConnAck::new(ConnectReturnCode::Success, false)).size() as u64;
+
+    task::spawn(async move {
+        let broker = Broker::new(3005, 0).await;
+        time::sleep(Duration::from_secs(1)).await;
+        drop(broker);
+        let _broker = Broker::new(3005, 0).await;
+        time::sleep(Duration::from_secs(10)).await;
+    });
+
+    time::sleep(Duration::from_secs(1)).await;
+    let mut eventloop = EventLoop::new(options, 5);
+    assert_matches!(
+        eventloop.poll().await,
+        Ok(Event::Incoming(Packet::ConnAck(_)))
+    );
+    assert!(eventloop.poll().await.is_err());
+
+    let metrics = eventloop.metrics();
+    assert_eq!(metrics.packets_sent.connect, 1);
+    assert_eq!(metrics.packets_received.connack, 1);
+    assert_eq!(metrics.reconnects, 0);
+    assert!(metrics.last_error.is_some());
+
+    time::sleep(Duration::from_secs(1)).await;
+    assert_matches!(
+        eventloop.poll().await,
+        Ok(Event::Incoming(Packet::ConnAck(_)))
+    );
+
+    let metrics = eventloop.metrics();
+    assert_eq!(metrics.packets_sent.connect, 2);
+    assert_eq!(metrics.packets_received.connack, 2);
+    assert_eq!(metrics.packets_received.total(), 2);
+    assert_eq!(metrics.bytes_sent, 2 * connect_size);
+    assert_eq!(metrics.bytes_received, 2 * connack_size);
+    assert_eq!(metrics.reconnects, 1);
+}
+
+#[cfg(feature = "test-util")]
+#[tokio::test]
+async fn outgoing_requests_are_mirrored_before_being_written() {
+    let options = MqttOptions::new("dummy", "127.0.0.1", 2004);
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    let mirror = eventloop.mirror_requests();
+
+    task::spawn(async move {
+        client.subscribe("hello/#", QoS::AtLeastOnce).await.unwrap();
+        client
+            .publish("hello/world", QoS::AtLeastOnce, false, vec![1, 2, 3])
+            .await
+            .unwrap();
+        time::sleep(Duration::from_secs(5)).await;
+    });
+
+    task::spawn(async move {
+        run(&mut eventloop, false).await.unwrap();
+    });
+
+    let mut broker = Broker::new(2004, 0).await;
+    loop {
+        if let Event::Incoming(Incoming::Publish(_)) = broker.tick().await {
+            break;
+        }
+    }
+
+    let subscribe = Subscribe::new("hello/#", QoS::AtLeastOnce);
+    assert_eq!(mirror.recv().unwrap(), Request::Subscribe(subscribe));
+
+    // Packet ids aren't assigned yet
+    let publish = Publish::new("hello/world", QoS::AtLeastOnce, vec![1, 2, 3]);
+    assert_eq!(mirror.recv().unwrap(), Request::Publish(publish));
+    assert!(mirror.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn connection_is_kept_alive_after_clients_are_dropped_by_default() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2005);
+    options.set_keep_alive(Duration::from_secs(5));
+
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    drop(client);
+
+    let (events_tx, events_rx) = flume::unbounded();
+    task::spawn(async move {
+        loop {
+            let event = eventloop.poll().await;
+            let failed = event.is_err();
+            events_tx.send(event).unwrap();
+            if failed {
+                break;
+            }
+        }
+    });
+
+    let mut broker = Broker::new(2005, 0).await;
+    broker.spawn_publishes(1, QoS::AtMostOnce, 0).await;
+
+    // Incoming packets are still read and pings are still sent
+    loop {
+        match broker.tick().await {
+            Event::Incoming(Incoming::PingReq) => break,
+            Event::Incoming(packet) => panic!("Unexpected packet {packet:?}"),
+            Event::Outgoing(_) => continue,
+        }
+    }
+
+    let events: Vec<_> = events_rx.drain().map(|event| event.unwrap()).collect();
+    assert_matches!(events[0], Event::Incoming(Incoming::ConnAck(_)));
+    assert_matches!(events[1], Event::Incoming(Incoming::Publish(_)));
+    assert_matches!(events[2], Event::Outgoing(Outgoing::PingReq));
+}
+
+#[tokio::test]
+async fn eventloop_disconnects_after_clients_are_dropped_when_configured() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2006);
+    options.set_client_drop_behavior(ClientDropBehavior::Disconnect);
+
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    task::spawn(async move {
+        client
+            .publish("hello/world", QoS::AtMostOnce, false, vec![1, 2, 3])
+            .await
+            .unwrap();
+    });
+
+    let handle = task::spawn(async move {
+        let e = run(&mut eventloop, false).await.unwrap_err();
+        assert_matches!(e, ConnectionError::RequestsDone);
+
+        // Doesn't reconnect
+        let e = eventloop.poll().await.unwrap_err();
+        assert_matches!(e, ConnectionError::RequestsDone);
+    });
+
+    // Packets are read in bulk, the disconnect can come along with the publish
+    let mut broker = Broker::new(2006, 0).await;
+    assert_matches!(broker.read_packet().await, Some(Packet::Publish(_)));
+    assert_matches!(broker.read_packet().await, Some(Packet::Disconnect));
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn eventloop_gives_up_after_max_reconnect_attempts() {
+    // Nothing listens on this port
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2007);
+    options.set_max_reconnect_attempts(Some(2));
+
+    let (_client, mut eventloop) = AsyncClient::new(options, 5);
+    for _ in 0..2 {
+        let e = eventloop.poll().await.unwrap_err();
+        assert_matches!(e, ConnectionError::Io(_));
+    }
+
+    // Doesn't try to connect anymore
+    for _ in 0..2 {
+        let e = eventloop.poll().await.unwrap_err();
+        assert_matches!(e, ConnectionError::GaveUp);
+    }
+}
+
+#[tokio::test]
+async fn credentials_are_provided_before_every_connection() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2008);
+    let tokens = Arc::new(AtomicUsize::new(0));
+    options.set_credentials_provider(move || {
+        let token = tokens.fetch_add(1, Ordering::SeqCst) + 1;
+        async move { ("user".to_owned(), format!("token-{token}")) }
+    });
+
+    let (_client, mut eventloop) = AsyncClient::new(options, 5);
+    task::spawn(async move {
+        loop {
+            if eventloop.poll().await.is_err() {
+                time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    });
+
+    for token in ["token-1", "token-2"] {
+        // Doesn't acknowledge the connection, reconnects once broker is dropped
+        let mut broker = Broker::new(2008, 2).await;
+        match broker.incoming.pop_front() {
+            Some(Packet::Connect(connect)) => {
+                let login = connect.login.unwrap();
+                assert_eq!(login.username, "user");
+                assert_eq!(login.password, token);
+            }
+            packet => panic!("Expected connect, received {packet:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn updated_last_will_is_sent_after_reconnection() {
+    let will = |payload: &str| LastWill::new("status", payload, QoS::AtLeastOnce, false);
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2009);
+    options.set_last_will(will("offline-1"));
+
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    task::spawn(async move {
+        loop {
+            if eventloop.poll().await.is_err() {
+                time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    });
+
+    for payload in ["offline-1", "offline-2"] {
+        // Doesn't acknowledge the connection, reconnects once broker is dropped
+        let mut broker = Broker::new(2009, 2).await;
+        match broker.incoming.pop_front() {
+            Some(Packet::Connect(connect)) => {
+                let will = connect.last_will.unwrap();
+                assert_eq!(will.topic, "status");
+                assert_eq!(will.message, payload.as_bytes());
+            }
+            packet => panic!("Expected connect, received {packet:?}"),
+        }
+
+        client.set_last_will(will("offline-2"));
+    }
+}
+
+#[tokio::test]
+async fn poll_batch_returns_buffered_events() {
+    let options = MqttOptions::new("dummy", "127.0.0.1", 2010);
+    let (_client, mut eventloop) = AsyncClient::new(options, 5);
+
+    let handle = task::spawn(async move {
+        // Connection is a single event
+        let events = eventloop.poll_batch(3).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_matches!(events[0], Event::Incoming(Incoming::ConnAck(_)));
+
+        let mut payloads = Vec::new();
+        while payloads.len() < 10 {
+            let events = eventloop.poll_batch(3).await.unwrap();
+            assert!(!events.is_empty() && events.len() <= 3);
+            for event in events {
+                if let Event::Incoming(Incoming::Publish(publish)) = event {
+                    payloads.push(publish.payload[3]);
+                }
+            }
+        }
+
+        assert_eq!(payloads, (1..=10).collect::<Vec<u8>>());
+    });
+
+    let mut broker = Broker::new(2010, 0).await;
+    broker.spawn_publishes(10, QoS::AtMostOnce, 0).await;
+    task::spawn(async move {
+        loop {
+            broker.tick().await;
+        }
+    });
+
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn reads_pause_while_too_many_incoming_qos2_publishes_are_unacked() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2015);
+    options
+        .set_manual_acks(true)
+        .set_max_unacked_incoming_qos2(Some(2));
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+
+    task::spawn(async move {
+        let mut broker = Broker::new(2015, 0).await;
+        for pkid in 1..=4 {
+            let mut publish = Publish::new("hello/world", QoS::ExactlyOnce, vec![1, 2, 3]);
+            publish.pkid = pkid;
+            broker.send(Packet::Publish(publish)).await;
+            time::sleep(Duration::from_millis(100)).await;
+        }
+
+        broker.blackhole().await;
+    });
+
+    // let the broker listen before connecting
+    time::sleep(Duration::from_secs(1)).await;
+
+    let mut publishes = Vec::new();
+    while let Ok(event) = time::timeout(Duration::from_secs(1), eventloop.poll()).await {
+        if let Event::Incoming(Incoming::Publish(publish)) = event.unwrap() {
+            publishes.push(publish);
+        }
+    }
+
+    assert_eq!(publishes.len(), 2);
+    assert_eq!(eventloop.state.unacked_incoming_qos2(), 2);
+
+    // acking makes room for one more publish
+    client.ack(&publishes[0]).await.unwrap();
+    while let Ok(event) = time::timeout(Duration::from_secs(1), eventloop.poll()).await {
+        if let Event::Incoming(Incoming::Publish(publish)) = event.unwrap() {
+            publishes.push(publish);
+        }
+    }
+
+    let pkids: Vec<u16> = publishes.iter().map(|publish| publish.pkid).collect();
+    assert_eq!(pkids, vec![1, 2, 3]);
+}
+
+#[tokio::test]
+async fn lazy_eventloop_connects_on_first_request() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2016);
+    options.set_lazy_connect(true);
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:2016")
+        .await
+        .unwrap();
+    task::spawn(async move {
+        loop {
+            if eventloop.poll().await.is_err() {
+                time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    });
+
+    let accept = time::timeout(Duration::from_secs(1), listener.accept()).await;
+    assert!(accept.is_err(), "connected before any request");
+
+    client
+        .publish("hello/world", QoS::AtMostOnce, false, vec![1, 2, 3])
+        .await
+        .unwrap();
+    let accept = time::timeout(Duration::from_secs(5), listener.accept()).await;
+    assert!(accept.is_ok(), "didn't connect after a request");
+}
+
+#[tokio::test]
+async fn connect_attempts_are_reported_before_connecting() {
+    let attempts = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let reported = attempts.clone();
+
+    // nothing listens on this port
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2017);
+    options.set_on_connect_attempt(move |address, failed| {
+        reported.lock().unwrap().push((address.to_owned(), failed));
+    });
+    let (_client, mut eventloop) = AsyncClient::new(options, 5);
+
+    for _ in 0..3 {
+        assert!(eventloop.poll().await.is_err());
+    }
+
+    let address = "127.0.0.1:2017".to_owned();
+    assert_eq!(
+        *attempts.lock().unwrap(),
+        vec![(address.clone(), 0), (address.clone(), 1), (address, 2)]
+    );
+}
+
+#[tokio::test]
+async fn subscribe_many_sends_all_filters_in_one_subscribe() {
+    let options = MqttOptions::new("dummy", "127.0.0.1", 2018);
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+
+    let filters = vec![
+        SubscribeFilter::new("hello/0".to_owned(), QoS::AtMostOnce),
+        SubscribeFilter::new("hello/1".to_owned(), QoS::AtLeastOnce),
+        SubscribeFilter::new("hello/2".to_owned(), QoS::ExactlyOnce),
+    ];
+    client.subscribe_many(filters.clone()).await.unwrap();
+
+    task::spawn(async move {
+        let mut broker = Broker::new(2018, 0).await;
+        let subscribe = loop {
+            if let Some(Packet::Subscribe(subscribe)) = broker.read_packet().await {
+                break subscribe;
+            }
+        };
+
+        assert_eq!(subscribe.filters, filters);
+        // broker grants less than requested for the last filter
+        let return_codes = vec![
+            SubscribeReasonCode::Success(QoS::AtMostOnce),
+            SubscribeReasonCode::Success(QoS::AtLeastOnce),
+            SubscribeReasonCode::Failure,
+        ];
+        let suback = SubAck::new(subscribe.pkid, return_codes);
+        broker.send(Packet::SubAck(suback)).await;
+        broker.blackhole().await;
+    });
+
+    // let the broker listen before connecting
+    time::sleep(Duration::from_secs(1)).await;
+
+    // reason codes of the suback are in the order of the filters
+    let suback = loop {
+        let event = time::timeout(Duration::from_secs(5), eventloop.poll()).await;
+        if let Event::Incoming(Incoming::SubAck(suback)) = event.unwrap().unwrap() {
+            break suback;
+        }
+    };
+    assert_eq!(
+        suback.return_codes,
+        vec![
+            SubscribeReasonCode::Success(QoS::AtMostOnce),
+            SubscribeReasonCode::Success(QoS::AtLeastOnce),
+            SubscribeReasonCode::Failure,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn paced_resends_wait_for_inflight_window() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2019);
+    options.set_inflight(3).set_paced_resend(true);
+
+    let (_client, mut eventloop) = AsyncClient::new(options, 5);
+    // backlog of publishes, e.g. restored after a restart, larger than the window
+    for i in 1..=10 {
+        let publish = Publish::new("hello/world", QoS::AtLeastOnce, vec![i]);
+        eventloop.pending.push_back(Request::Publish(publish));
+    }
+
+    task::spawn(async move {
+        run(&mut eventloop, true).await.unwrap();
+    });
+
+    let mut broker = Broker::new(2019, 0).await;
+    for i in 1..=3 {
+        let publish = broker.read_publish().await.unwrap();
+        assert_eq!(publish.payload, vec![i]);
+    }
+
+    // window is full till the broker acks
+    assert!(broker.read_publish().await.is_none());
+
+    broker.ack(1).await;
+    let publish = broker.read_publish().await.unwrap();
+    assert_eq!(publish.payload, vec![4]);
+    assert!(broker.read_publish().await.is_none());
+}
+
+#[tokio::test]
+async fn connection_fails_over_to_next_broker_in_list() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 1883);
+    // nothing listens on the primary
+    options.set_broker_list(vec![
+        ("127.0.0.1".to_owned(), 2020),
+        ("127.0.0.1".to_owned(), 2021),
+    ]);
+    assert_eq!(options.broker_address(), ("127.0.0.1".to_owned(), 2020));
+
+    let (_client, mut eventloop) = AsyncClient::new(options, 5);
+    task::spawn(async move {
+        let _broker = Broker::new(2021, 0).await;
+        time::sleep(Duration::from_secs(10)).await;
+    });
+    time::sleep(Duration::from_millis(100)).await;
+
+    assert!(eventloop.poll().await.is_err());
+    let event = time::timeout(Duration::from_secs(5), eventloop.poll()).await;
+    assert_matches!(event, Ok(Ok(Event::Incoming(Packet::ConnAck(_)))));
+    assert_eq!(
+        eventloop.mqtt_options.broker_address(),
+        ("127.0.0.1".to_owned(), 2021)
+    );
+}
+
+#[tokio::test]
+async fn connection_errors_carry_trace_of_last_packets() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2022);
+    options.set_packet_trace(Some(2));
+
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    client
+        .publish("hello/world", QoS::AtMostOnce, false, vec![1])
+        .await
+        .unwrap();
+
+    task::spawn(async move {
+        let mut broker = Broker::new(2022, 0).await;
+        broker.read_publish().await.unwrap();
+        // broker goes away
+    });
+
+    // let the broker listen before connecting
+    time::sleep(Duration::from_secs(1)).await;
+
+    let e = loop {
+        match time::timeout(Duration::from_secs(5), eventloop.poll()).await {
+            Ok(Ok(_)) => continue,
+            Ok(Err(e)) => break e,
+            Err(_) => panic!("Connection wasn't closed"),
+        }
+    };
+
+    let trace = match e {
+        ConnectionError::Traced { trace, .. } => trace,
+        e => panic!("Untraced error {e:?}"),
+    };
+    let packets: Vec<(bool, &str)> = trace
+        .packets()
+        .map(|traced| (traced.outgoing, traced.packet))
+        .collect();
+    assert_eq!(packets, vec![(false, "ConnAck"), (true, "Publish")]);
+}
+
+#[tokio::test]
+async fn unacked_publishes_are_resent_after_ack_timeout() {
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2023);
+    options.set_ack_timeout(Some(Duration::from_millis(200)), 2);
+
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+    client
+        .publish("hello/world", QoS::AtLeastOnce, false, vec![1])
+        .await
+        .unwrap();
+
+    let handle = task::spawn(async move {
+        loop {
+            if let Err(e) = eventloop.poll().await {
+                break e;
+            }
+        }
+    });
+
+    // acks are withheld
+    let mut broker = Broker::new(2023, 0).await;
+    let publish = broker.read_publish().await.unwrap();
+    assert_eq!((publish.pkid, publish.dup), (1, false));
+    for _ in 0..2 {
+        let publish = broker.read_publish().await.unwrap();
+        assert_eq!((publish.pkid, publish.dup), (1, true));
+    }
+
+    let e = time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_matches!(e, ConnectionError::MqttState(StateError::AckTimeout(1)));
+}
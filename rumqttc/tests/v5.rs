@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use matches::assert_matches;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task;
+
+use rumqttc::v5::mqttbytes::v5::{
+    ConnAck, ConnAckProperties, ConnectReturnCode, Packet, SubscribeReasonCode,
+};
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::{AsyncClient, ConnectionError, Event, MqttOptions};
+use rumqttc::Protocol;
+
+/// Encodes a CONNACK with an optional Server Reference property
+fn connack(code: u8, server_reference: Option<&str>) -> Vec<u8> {
+    let mut properties = Vec::new();
+    if let Some(reference) = server_reference {
+        properties.push(0x1C);
+        properties.extend_from_slice(&(reference.len() as u16).to_be_bytes());
+        properties.extend_from_slice(reference.as_bytes());
+    }
+
+    let mut packet = vec![0x20, 3 + properties.len() as u8, 0x00, code];
+    packet.push(properties.len() as u8);
+    packet.extend(properties);
+    packet
+}
+
+/// Accepts a single connection and answers its CONNECT with `connack`
+async fn stub_broker(listener: TcpListener, connack: Vec<u8>) {
+    let (mut stream, _) = listener.accept().await.unwrap();
+    let mut connect = [0; 1024];
+    let len = stream.read(&mut connect).await.unwrap();
+    // Packet type of CONNECT
+    assert!(len > 0);
+    assert_eq!(connect[0], 0x10);
+    stream.write_all(&connack).await.unwrap();
+}
+
+#[tokio::test]
+async fn client_reconnects_to_referenced_server() {
+    let redirecting = TcpListener::bind("127.0.0.1:2011").await.unwrap();
+    let referenced = TcpListener::bind("127.0.0.1:2012").await.unwrap();
+    task::spawn(stub_broker(
+        redirecting,
+        connack(0x9C, Some("127.0.0.1:2012")),
+    ));
+    task::spawn(stub_broker(referenced, connack(0x00, None)));
+
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2011);
+    options.set_follow_server_reference(true);
+    let (_client, mut eventloop) = AsyncClient::new(options, 5);
+
+    let e = eventloop.poll().await.unwrap_err();
+    assert_matches!(
+        e,
+        ConnectionError::ServerRedirect {
+            code: ConnectReturnCode::UseAnotherServer,
+            ref server_reference,
+        } if server_reference == "127.0.0.1:2012"
+    );
+
+    let event = eventloop.poll().await.unwrap();
+    assert_matches!(event, Event::Incoming(Packet::ConnAck(_)));
+    assert_eq!(
+        eventloop.options.broker_address(),
+        ("127.0.0.1".to_owned(), 2012)
+    );
+}
+
+/// Broker which only speaks 3.1.1. Refuses v5 connections like 3.1.1 brokers do, with
+/// a 3.1.1 CONNACK and a disconnect or, when `close_v5`, with just a disconnect, and
+/// acknowledges the subscription of a 3.1.1 client
+async fn v311_broker(listener: TcpListener, close_v5: bool) {
+    loop {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut connect = [0; 1024];
+        let len = stream.read(&mut connect).await.unwrap();
+        assert!(len > 8);
+        assert_eq!(connect[0], 0x10);
+
+        // Protocol level follows the fixed header and protocol name
+        if connect[8] != 4 {
+            if !close_v5 {
+                stream.write_all(&[0x20, 0x02, 0x00, 0x01]).await.unwrap();
+            }
+            continue;
+        }
+
+        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+        let mut subscribe = [0; 1024];
+        let len = stream.read(&mut subscribe).await.unwrap();
+        assert!(len > 4);
+        assert_eq!(subscribe[0], 0x82);
+        let pkid = [subscribe[2], subscribe[3]];
+        stream
+            .write_all(&[0x90, 0x03, pkid[0], pkid[1], 0x01])
+            .await
+            .unwrap();
+
+        // keep the connection till the client goes away
+        let _ = stream.read(&mut subscribe).await;
+        return;
+    }
+}
+
+#[tokio::test]
+async fn client_falls_back_to_v311() {
+    let listener = TcpListener::bind("127.0.0.1:2013").await.unwrap();
+    task::spawn(v311_broker(listener, false));
+
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2013);
+    options.set_protocol_fallback(true);
+    let (client, mut eventloop) = AsyncClient::new(options, 5);
+
+    let e = eventloop.poll().await.unwrap_err();
+    assert_matches!(
+        e,
+        ConnectionError::ConnectionRefused(ConnectReturnCode::RefusedProtocolVersion)
+    );
+    assert_eq!(eventloop.protocol(), Protocol::V4);
+
+    let event = eventloop.poll().await.unwrap();
+    assert_matches!(event, Event::Incoming(Packet::ConnAck(_)));
+
+    client
+        .subscribe("hello/world", QoS::AtLeastOnce)
+        .await
+        .unwrap();
+    loop {
+        if let Event::Incoming(Packet::SubAck(suback)) = eventloop.poll().await.unwrap() {
+            assert_eq!(
+                suback.return_codes,
+                vec![SubscribeReasonCode::Success(QoS::AtLeastOnce)]
+            );
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn client_retries_v5_before_falling_back_on_close() {
+    let listener = TcpListener::bind("127.0.0.1:2028").await.unwrap();
+    task::spawn(v311_broker(listener, true));
+
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2028);
+    options.set_protocol_fallback(true);
+    let (_client, mut eventloop) = AsyncClient::new(options, 5);
+
+    // a single close can be transient
+    let e = eventloop.poll().await.unwrap_err();
+    assert_matches!(e, ConnectionError::MqttState(_));
+    assert_eq!(eventloop.protocol(), Protocol::V5);
+
+    let e = eventloop.poll().await.unwrap_err();
+    assert_matches!(e, ConnectionError::MqttState(_));
+    assert_eq!(eventloop.protocol(), Protocol::V4);
+
+    let event = eventloop.poll().await.unwrap();
+    assert_matches!(event, Event::Incoming(Packet::ConnAck(_)));
+}
+
+#[tokio::test]
+async fn server_keep_alive_replaces_requested_keep_alive() {
+    let listener = TcpListener::bind("127.0.0.1:2014").await.unwrap();
+    // CONNACK with a Server Keep Alive of 60 seconds
+    let connack = vec![0x20, 0x06, 0x00, 0x00, 0x03, 0x13, 0x00, 0x3C];
+    task::spawn(stub_broker(listener, connack));
+
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2014);
+    options.set_keep_alive(Duration::from_secs(600));
+    let (_client, mut eventloop) = AsyncClient::new(options, 5);
+
+    let event = eventloop.poll().await.unwrap();
+    assert_matches!(
+        event,
+        Event::Incoming(Packet::ConnAck(ConnAck {
+            properties: Some(ConnAckProperties {
+                server_keep_alive: Some(60),
+                ..
+            }),
+            ..
+        }))
+    );
+    assert_eq!(eventloop.options.keep_alive(), Duration::from_secs(60));
+}
+
+#[tokio::test]
+async fn zero_keep_alive_disables_pings() {
+    let listener = TcpListener::bind("127.0.0.1:2025").await.unwrap();
+    let broker = task::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut packet = [0; 1024];
+        let len = stream.read(&mut packet).await.unwrap();
+        assert!(len > 0);
+        stream.write_all(&connack(0x00, None)).await.unwrap();
+
+        // nothing is sent after CONNECT
+        let read = tokio::time::timeout(Duration::from_secs(3), stream.read(&mut packet)).await;
+        assert!(read.is_err(), "Unexpected read {read:?}");
+    });
+
+    let mut options = MqttOptions::new("dummy", "127.0.0.1", 2025);
+    options.set_keep_alive(Duration::ZERO);
+    let (_client, mut eventloop) = AsyncClient::new(options, 5);
+    task::spawn(async move { while eventloop.poll().await.is_ok() {} });
+
+    broker.await.unwrap();
+}
@@ -21,9 +21,12 @@
 pub use link::alerts;
 pub use link::local;
 pub use link::meters;
-pub use router::{Alert, IncomingMeter, Meter, Notification, OutgoingMeter};
+pub use router::{
+    Alert, IncomingMeter, MemoryUsage, Meter, Notification, OutgoingMeter, PrefixRewrite, Session,
+    Stats, TenantUsage, TopicRewrite, TopicStats, WalKey, WalReader, WalRecord,
+};
 use segments::Storage;
-pub use server::Broker;
+pub use server::{Broker, ConfigReloader, Listeners, ReloadReport};
 
 pub use self::router::shared_subs::Strategy;
 
@@ -50,6 +53,21 @@
         + Send
         + Sync,
 >;
+pub type CertSubject = String;
+pub type CertAuthHandler = Arc<
+    dyn Fn(ClientId, CertSubject) -> Pin<Box<dyn std::future::Future<Output = bool> + Send>>
+        + Send
+        + Sync,
+>;
+/// User properties of a CONNECT packet. Always empty for MQTT 3.1.1 clients
+pub type UserProperties = Vec<(String, String)>;
+pub type UserPropertiesAuthHandler = Arc<
+    dyn Fn(ClientId, UserProperties) -> Pin<Box<dyn std::future::Future<Output = bool> + Send>>
+        + Send
+        + Sync,
+>;
+pub type TopicRewriteHandler =
+    Arc<dyn Fn(ClientId, Option<AuthUser>) -> Option<Arc<dyn TopicRewrite>> + Send + Sync>;
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Config {
@@ -83,6 +101,14 @@ pub enum TlsConfig {
         capath: Option<String>,
         certpath: String,
         keypath: String,
+        /// Protocols offered with ALPN. Connections which negotiate `mqtt`, or no
+        /// protocol at all, are MQTT connections
+        #[serde(default)]
+        alpn: Vec<String>,
+        /// Addresses to which connections negotiating other protocols are forwarded,
+        /// decrypted. Connections of protocols without an address are closed
+        #[serde(default)]
+        alpnforward: HashMap<String, SocketAddr>,
     },
     NativeTls {
         pkcs12path: String,
@@ -99,6 +125,7 @@ pub fn validate_paths(&self) -> bool {
                 capath,
                 certpath,
                 keypath,
+                ..
             } => {
                 let ca = capath.is_none() || capath.as_ref().is_some_and(|v| Path::new(v).exists());
 
@@ -107,12 +134,24 @@ pub fn validate_paths(&self) -> bool {
             TlsConfig::NativeTls { pkcs12path, .. } => Path::new(pkcs12path).exists(),
         }
     }
+
+    /// Address to which connections negotiating `protocol` with ALPN are forwarded
+    pub fn alpn_forward(&self, protocol: &str) -> Option<SocketAddr> {
+        match self {
+            TlsConfig::Rustls { alpnforward, .. } => alpnforward.get(protocol).copied(),
+            TlsConfig::NativeTls { .. } => None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ServerSettings {
     pub name: String,
     pub listen: SocketAddr,
+    /// Path of a Unix domain socket on which connections are accepted besides
+    /// `listen`, for clients on the same host. Connections on it don't use `tls`.
+    /// Only supported on Unix
+    pub unix_socket: Option<PathBuf>,
     pub tls: Option<TlsConfig>,
     pub next_connection_delay_ms: u64,
     pub connections: ConnectionSettings,
@@ -127,6 +166,31 @@ pub fn set_auth_handler<F, O>(&mut self, auth_fn: F)
     {
         self.connections.set_auth_handler(auth_fn)
     }
+
+    pub fn set_cert_auth_handler<F, O>(&mut self, auth_fn: F)
+    where
+        F: Fn(ClientId, CertSubject) -> O + Send + Sync + 'static,
+        O: IntoFuture<Output = bool> + 'static,
+        O::IntoFuture: Send,
+    {
+        self.connections.set_cert_auth_handler(auth_fn)
+    }
+
+    pub fn set_user_properties_auth_handler<F, O>(&mut self, auth_fn: F)
+    where
+        F: Fn(ClientId, UserProperties) -> O + Send + Sync + 'static,
+        O: IntoFuture<Output = bool> + 'static,
+        O::IntoFuture: Send,
+    {
+        self.connections.set_user_properties_auth_handler(auth_fn)
+    }
+
+    pub fn set_topic_rewrite_handler<F>(&mut self, rewrite_fn: F)
+    where
+        F: Fn(ClientId, Option<AuthUser>) -> Option<Arc<dyn TopicRewrite>> + Send + Sync + 'static,
+    {
+        self.connections.set_topic_rewrite_handler(rewrite_fn)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -150,8 +214,57 @@ pub struct ConnectionSettings {
     pub auth: Option<HashMap<String, String>>,
     #[serde(skip)]
     external_auth: Option<AuthHandler>,
+    /// Authorizes clients using subject of their TLS certificate
+    #[serde(skip)]
+    cert_auth: Option<CertAuthHandler>,
+    /// Authorizes clients using user properties of their CONNECT packet
+    #[serde(skip)]
+    user_properties_auth: Option<UserPropertiesAuthHandler>,
+    /// Picks the topic rewrite of authenticated clients
+    #[serde(skip)]
+    topic_rewrite: Option<TopicRewriteHandler>,
+    /// Use subject of client's TLS certificate as its client id
+    #[serde(default)]
+    pub client_id_from_cert: bool,
     #[serde(default)]
     pub dynamic_filters: bool,
+    /// Maximum number of levels in topics and filters. MQTT 5 clients get topic name or
+    /// filter invalid for topics and filters beyond the limits, MQTT 3.1.1 clients are
+    /// disconnected. Unlimited if not set
+    pub max_topic_levels: Option<usize>,
+    /// Maximum length of topics and filters in bytes. Unlimited if not set
+    pub max_topic_length: Option<usize>,
+    /// Maximum number of active subscriptions of a client. Filters subscribed past it
+    /// are rejected with quota exceeded. Unlimited if not set
+    pub max_subscriptions: Option<usize>,
+    /// Maximum number of QoS 2 publishes of a client awaiting its PUBREL. Publishes past
+    /// it are rejected with quota exceeded for MQTT 5 clients, while PUBRECs of MQTT 3.1.1
+    /// clients are held back till earlier flows complete. Unlimited if not set
+    pub max_incoming_qos2_flows: Option<usize>,
+    /// Disconnect clients which aren't keeping up with outgoing data
+    pub slow_consumer: Option<SlowConsumerSettings>,
+    /// Reject clients connecting with id of an active connection instead of
+    /// disconnecting the older connection
+    #[serde(default)]
+    pub reject_duplicate_client_id: bool,
+    /// Disconnect clients from which nothing is received for this long, even when
+    /// they negotiated a longer keep alive. Only keep alive applies if not set
+    pub idle_timeout_ms: Option<u64>,
+    /// Delay before accepting a connection and sending its CONNACK, to test how clients
+    /// handle slow brokers and connect timeouts. Not meant for production
+    #[serde(default)]
+    pub connack_delay_ms: u64,
+}
+
+/// Thresholds to detect connections which don't read or ack outgoing publishes fast enough
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SlowConsumerSettings {
+    /// Maximum publishes queued for or awaiting acks from the client
+    pub max_pending_count: Option<usize>,
+    /// Maximum bytes of publishes queued for the client
+    pub max_pending_bytes: Option<usize>,
+    /// Duration for which a threshold can stay exceeded before the client is disconnected
+    pub max_duration_ms: u64,
 }
 
 impl ConnectionSettings {
@@ -166,6 +279,45 @@ pub fn set_auth_handler<F, O>(&mut self, auth_fn: F)
             Box::pin(auth)
         }));
     }
+
+    /// Sets handler to authorize clients using subject (CN or SAN) of their TLS certificate.
+    /// Requires client certificate verification to be enabled
+    pub fn set_cert_auth_handler<F, O>(&mut self, auth_fn: F)
+    where
+        F: Fn(ClientId, CertSubject) -> O + Send + Sync + 'static,
+        O: IntoFuture<Output = bool> + 'static,
+        O::IntoFuture: Send,
+    {
+        self.cert_auth = Some(Arc::new(move |client_id, subject| {
+            let auth = auth_fn(client_id, subject).into_future();
+            Box::pin(auth)
+        }));
+    }
+
+    /// Sets handler to authorize clients using user properties of their CONNECT packet,
+    /// e.g. to select a tenant or feature flags. Runs after username and password
+    /// authentication. MQTT 3.1.1 clients have no user properties
+    pub fn set_user_properties_auth_handler<F, O>(&mut self, auth_fn: F)
+    where
+        F: Fn(ClientId, UserProperties) -> O + Send + Sync + 'static,
+        O: IntoFuture<Output = bool> + 'static,
+        O::IntoFuture: Send,
+    {
+        self.user_properties_auth = Some(Arc::new(move |client_id, user_properties| {
+            let auth = auth_fn(client_id, user_properties).into_future();
+            Box::pin(auth)
+        }));
+    }
+
+    /// Sets handler which picks the topic rewrite of a client from its client id and
+    /// username once it's authenticated, e.g. a [`PrefixRewrite`] with the tenant of
+    /// the user. Topics of clients without a rewrite are used as is
+    pub fn set_topic_rewrite_handler<F>(&mut self, rewrite_fn: F)
+    where
+        F: Fn(ClientId, Option<AuthUser>) -> Option<Arc<dyn TopicRewrite>> + Send + Sync + 'static,
+    {
+        self.topic_rewrite = Some(Arc::new(rewrite_fn));
+    }
 }
 
 impl fmt::Debug for ConnectionSettings {
@@ -176,7 +328,22 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             .field("max_inflight_count", &self.max_inflight_count)
             .field("auth", &self.auth)
             .field("external_auth", &self.external_auth.is_some())
+            .field("cert_auth", &self.cert_auth.is_some())
+            .field("user_properties_auth", &self.user_properties_auth.is_some())
+            .field("topic_rewrite", &self.topic_rewrite.is_some())
+            .field("client_id_from_cert", &self.client_id_from_cert)
             .field("dynamic_filters", &self.dynamic_filters)
+            .field("max_topic_levels", &self.max_topic_levels)
+            .field("max_topic_length", &self.max_topic_length)
+            .field("max_subscriptions", &self.max_subscriptions)
+            .field("max_incoming_qos2_flows", &self.max_incoming_qos2_flows)
+            .field("slow_consumer", &self.slow_consumer)
+            .field(
+                "reject_duplicate_client_id",
+                &self.reject_duplicate_client_id,
+            )
+            .field("idle_timeout_ms", &self.idle_timeout_ms)
+            .field("connack_delay_ms", &self.connack_delay_ms)
             .finish()
     }
 }
@@ -202,6 +369,113 @@ pub struct RouterConfig {
     // defaults to Round Robin
     #[serde(default)]
     pub shared_subscriptions_strategy: Strategy,
+    /// Maximum number of topics with individual publish stats. Publishes on
+    /// topics beyond this are accounted together. Defaults to 1000
+    pub max_tracked_topics: Option<usize>,
+    /// QoS 1 publishes resent with DUP within this window of the original are acked
+    /// but not delivered again. The router remembers client id and packet id of every
+    /// QoS 1 publish received within the window, i.e. about 64 bytes plus length of
+    /// the client id per publish, so the window times the peak QoS 1 publish rate
+    /// bounds its memory. Off if not set
+    pub dedup_window_ms: Option<u64>,
+    /// Publishes to `{prefix}/{seconds}/{topic}` are held by the router and delivered on
+    /// `topic` once `seconds` passed, within a second of it. Held publishes aren't
+    /// persisted, they are lost on restart. Off if not set, e.g. "$delayed"
+    pub delayed_publish_prefix: Option<String>,
+    /// Maximum payload size of publishes to topics matching a filter, the smallest
+    /// applies when several match. QoS 0 publishes exceeding it are dropped, QoS 1
+    /// publishes are acked with implementation specific error (v4 clients can't tell)
+    /// and dropped, and clients sending QoS 2 publishes exceeding it are disconnected
+    pub payload_limits: Option<HashMap<String, usize>>,
+    /// Limits on connections, subscriptions and publish rate of tenants, i.e. clients
+    /// identified by the organization of their TLS client certificate. Clients without
+    /// a tenant aren't limited. Off if not set
+    pub tenant_quotas: Option<TenantQuotas>,
+    /// Records publishes received by the router to files, to replay them into a
+    /// broker with `Broker::replay`. Off if not set
+    pub wal: Option<WalConfig>,
+    /// Maximum retained messages forwarded to a subscription at once. Subscriptions
+    /// matching more, e.g. `#`, get the rest in later router iterations, interleaved
+    /// with live publishes and other connections. All within the inflight window are
+    /// forwarded at once if not set
+    pub retained_chunk_size: Option<usize>,
+    /// Approximate bytes of subscription filters and retained messages beyond which
+    /// subscriptions to new filters are rejected with quota exceeded and new retained
+    /// messages aren't stored, while still being forwarded. Usage is in `Stats::memory`.
+    /// Unlimited if not set
+    pub memory_budget: Option<usize>,
+    /// Limits on retained messages held by the router. Unlimited if not set
+    pub retained_limits: Option<RetainedLimits>,
+}
+
+/// Limits on retained messages, a retained publish exceeding them is handled as per
+/// `policy`. Replacing the message of a topic only counts the difference
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RetainedLimits {
+    pub max_messages: Option<usize>,
+    /// Approximate bytes, as in `MemoryUsage::retained_bytes`
+    pub max_bytes: Option<usize>,
+    #[serde(default)]
+    pub policy: RetainedLimitPolicy,
+}
+
+/// What is done with retained publishes exceeding `RetainedLimits`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetainedLimitPolicy {
+    /// Publishes are rejected like publishes exceeding a quota. QoS 1 publishes are
+    /// acked with quota exceeded (v4 clients can't tell), clients sending QoS 2 publishes
+    /// are disconnected and QoS 0 publishes are dropped
+    #[default]
+    Reject,
+    /// Least recently stored retained messages are dropped to make room
+    EvictOldest,
+}
+
+/// Write-ahead log of publishes received by the router. Records are written by a
+/// separate thread and flushed to the OS without fsync, a crash of the host can
+/// lose the latest records. Records are encrypted if `key_file` is set, see
+/// "Encryption at rest" in `architecture.md`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalConfig {
+    /// Directory of log files, created if missing. `Broker::start` fails if it can't be
+    /// opened
+    pub dir: PathBuf,
+    /// Size in bytes after which a new file is started
+    pub max_file_size: u64,
+    /// Files beyond this are deleted, oldest first
+    pub max_files: usize,
+    /// File holding a 256 bit key as 64 hex characters, with which topics and payloads
+    /// of records are encrypted (AES-256-GCM). `Broker::start` fails if it can't be
+    /// loaded. Plain text if not set
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+}
+
+/// Quotas of tenants, falling back to `default` for tenants without their own
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TenantQuotas {
+    pub default: Option<TenantQuota>,
+    #[serde(default)]
+    pub tenants: HashMap<String, TenantQuota>,
+}
+
+impl TenantQuotas {
+    pub fn get(&self, tenant: &str) -> Option<&TenantQuota> {
+        self.tenants.get(tenant).or(self.default.as_ref())
+    }
+}
+
+/// Limits shared by all connections of a tenant. Unlimited if not set
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TenantQuota {
+    /// Connections beyond this are refused with quota exceeded
+    pub max_connections: Option<usize>,
+    /// Subscriptions of connected clients beyond this are rejected with quota exceeded
+    pub max_subscriptions: Option<usize>,
+    /// Publishes per second beyond this are handled like publishes exceeding
+    /// `payload_limits`, with quota exceeded as reason
+    pub max_publish_rate: Option<u32>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
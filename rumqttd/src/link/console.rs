@@ -53,6 +53,7 @@ pub async fn start(console: Arc<ConsoleLink>) {
         .route("/subscriptions/:filter", get(subscriptions_with_filter))
         .route("/waiters/:filter", get(waiters_with_filter))
         .route("/readyqueue", get(readyqueue))
+        .route("/tenants", get(tenants))
         .route("/logs", post(logs))
         .with_state(console);
 
@@ -144,6 +145,16 @@ async fn readyqueue(State(console): State<Arc<ConsoleLink>>) -> impl IntoRespons
     Response::new("OK".to_owned())
 }
 
+async fn tenants(State(console): State<Arc<ConsoleLink>>) -> impl IntoResponse {
+    let event = Event::PrintStatus(Print::Tenants);
+    let message = (console.connection_id, event);
+    if console.router_tx.send(message).is_err() {
+        return Response::builder().status(404).body("".to_owned()).unwrap();
+    }
+
+    Response::new("OK".to_owned())
+}
+
 async fn logs(State(console): State<Arc<ConsoleLink>>, data: String) -> impl IntoResponse {
     info!("Reloading tracing filter");
     if let Some(handle) = &console.config.filter_handle {
@@ -1,12 +1,13 @@
 use crate::protocol::{
-    Filter, LastWill, LastWillProperties, Packet, Publish, QoS, RetainForwardRule, Subscribe,
+    ConnAck, ConnectReturnCode, Filter, LastWill, LastWillProperties, Packet, Publish, QoS,
+    RetainForwardRule, Subscribe,
 };
 use crate::router::Ack;
 use crate::router::{
     iobufs::{Incoming, Outgoing},
-    Connection, Event, Notification, ShadowRequest,
+    Connection, Event, Notification, ShadowRequest, TopicRewrite,
 };
-use crate::ConnectionId;
+use crate::{ConnectionId, ConnectionSettings, SlowConsumerSettings};
 use bytes::Bytes;
 use flume::{Receiver, RecvError, RecvTimeoutError, SendError, Sender, TrySendError};
 use parking_lot::lock_api::MutexGuard;
@@ -23,6 +24,8 @@ pub enum LinkError {
     NotConnectionAck,
     #[error("ConnAck error {0}")]
     ConnectionAck(String),
+    #[error("Connection refused, return code = {0:?}")]
+    ConnectionRefused(ConnectReturnCode),
     #[error("Channel try send error")]
     TrySend(#[from] TrySendError<(ConnectionId, Event)>),
     #[error("Channel send error")]
@@ -48,6 +51,21 @@ pub struct LinkBuilder<'a> {
     dynamic_filters: bool,
     // default to 0, indicating to not use topic alias
     topic_alias_max: u16,
+    // unlimited by default
+    max_topic_levels: Option<usize>,
+    max_topic_length: Option<usize>,
+    max_subscriptions: Option<usize>,
+    max_incoming_qos2_flows: Option<usize>,
+    // false by default, acks carry no reason codes as in MQTT 3.1.1
+    ack_reasons: bool,
+    // slow consumers aren't disconnected by default
+    slow_consumer: Option<SlowConsumerSettings>,
+    // false by default, older connection with same client id is disconnected
+    reject_duplicate_client_id: bool,
+    // topics are used as is by default
+    topic_rewrite: Option<Arc<dyn TopicRewrite>>,
+    // true by default, publishes replayed from the write-ahead log aren't recorded again
+    record_publishes: bool,
 }
 
 impl<'a> LinkBuilder<'a> {
@@ -61,6 +79,15 @@ pub fn new(client_id: &'a str, router_tx: Sender<(ConnectionId, Event)>) -> Self
             last_will_properties: None,
             dynamic_filters: false,
             topic_alias_max: 0,
+            max_topic_levels: None,
+            max_topic_length: None,
+            max_subscriptions: None,
+            max_incoming_qos2_flows: None,
+            ack_reasons: false,
+            slow_consumer: None,
+            reject_duplicate_client_id: false,
+            topic_rewrite: None,
+            record_publishes: true,
         }
     }
 
@@ -87,6 +114,51 @@ pub fn topic_alias_max(mut self, max: u16) -> Self {
         self
     }
 
+    pub fn max_topic_levels(mut self, max: Option<usize>) -> Self {
+        self.max_topic_levels = max;
+        self
+    }
+
+    pub fn max_topic_length(mut self, max: Option<usize>) -> Self {
+        self.max_topic_length = max;
+        self
+    }
+
+    pub fn max_subscriptions(mut self, max: Option<usize>) -> Self {
+        self.max_subscriptions = max;
+        self
+    }
+
+    pub fn max_incoming_qos2_flows(mut self, max: Option<usize>) -> Self {
+        self.max_incoming_qos2_flows = max;
+        self
+    }
+
+    pub fn ack_reasons(mut self, ack_reasons: bool) -> Self {
+        self.ack_reasons = ack_reasons;
+        self
+    }
+
+    pub fn slow_consumer(mut self, settings: Option<SlowConsumerSettings>) -> Self {
+        self.slow_consumer = settings;
+        self
+    }
+
+    pub fn reject_duplicate_client_id(mut self, reject: bool) -> Self {
+        self.reject_duplicate_client_id = reject;
+        self
+    }
+
+    pub fn topic_rewrite(mut self, rewrite: Option<Arc<dyn TopicRewrite>>) -> Self {
+        self.topic_rewrite = rewrite;
+        self
+    }
+
+    pub fn record_publishes(mut self, record: bool) -> Self {
+        self.record_publishes = record;
+        self
+    }
+
     pub fn clean_session(mut self, clean: bool) -> Self {
         self.clean_session = clean;
         self
@@ -109,7 +181,15 @@ pub fn build(self) -> Result<(LinkTx, LinkRx, Notification), LinkError> {
 
         connection
             .last_will(self.last_will, self.last_will_properties)
-            .topic_alias_max(self.topic_alias_max);
+            .topic_alias_max(self.topic_alias_max)
+            .topic_limits(self.max_topic_levels, self.max_topic_length)
+            .max_subscriptions(self.max_subscriptions)
+            .max_incoming_qos2_flows(self.max_incoming_qos2_flows)
+            .ack_reasons(self.ack_reasons)
+            .slow_consumer(self.slow_consumer)
+            .reject_duplicate_client_id(self.reject_duplicate_client_id)
+            .topic_rewrite(self.topic_rewrite)
+            .record_publishes(self.record_publishes);
         let incoming = Incoming::new(connection.client_id.to_owned());
         let (outgoing, link_rx) = Outgoing::new(connection.client_id.to_owned());
         let outgoing_data_buffer = outgoing.buffer();
@@ -129,6 +209,11 @@ pub fn build(self) -> Result<(LinkTx, LinkRx, Notification), LinkError> {
         // Right now link identifies failure with dropped rx in router,
         // which is probably ok. We need this here to get id assigned by router
         let id = match notification {
+            Notification::DeviceAck(Ack::ConnAck(_, ConnAck { code, .. }, _))
+                if code != ConnectReturnCode::Success =>
+            {
+                return Err(LinkError::ConnectionRefused(code))
+            }
             Notification::DeviceAck(Ack::ConnAck(id, ..)) => id,
             _message => return Err(LinkError::NotConnectionAck),
         };
@@ -163,7 +248,7 @@ pub fn buffer(&self) -> MutexGuard<RawMutex, VecDeque<Packet>> {
     }
 
     /// Send raw device data
-    fn push(&mut self, data: Packet) -> Result<usize, LinkError> {
+    pub(crate) fn push(&mut self, data: Packet) -> Result<usize, LinkError> {
         let len = {
             let mut buffer = self.recv_buffer.lock();
             buffer.push_back(data);
@@ -281,6 +366,58 @@ pub fn try_subscribe<S: Into<String>>(&mut self, filter: S) -> Result<usize, Lin
         Ok(len)
     }
 
+    /// Request broker statistics. Router replies with `Notification::Stats`
+    pub fn stats(&mut self) -> Result<(), LinkError> {
+        self.router_tx
+            .try_send((self.connection_id, Event::Stats))?;
+        Ok(())
+    }
+
+    /// Request connected clients and persistent sessions. Router replies with
+    /// `Notification::Sessions`
+    pub fn list_sessions(&mut self) -> Result<(), LinkError> {
+        self.router_tx
+            .try_send((self.connection_id, Event::ListSessions))?;
+        Ok(())
+    }
+
+    /// Disconnect client with `AdministrativeAction` reason. Persistent session of
+    /// the client is discarded if `clear_session` is set
+    pub fn kick<S: Into<String>>(
+        &mut self,
+        client_id: S,
+        clear_session: bool,
+    ) -> Result<(), LinkError> {
+        let message = Event::Kick {
+            client_id: client_id.into(),
+            clear_session,
+        };
+
+        self.router_tx.try_send((self.connection_id, message))?;
+        Ok(())
+    }
+
+    /// Discard persistent session of a disconnected client, i.e. its subscriptions
+    /// and publishes it missed while offline. Sessions of connected clients aren't
+    /// cleared, [`kick`](Self::kick) them with `clear_session` instead
+    pub fn clear_session<S: Into<String>>(&mut self, client_id: S) -> Result<(), LinkError> {
+        let message = Event::ClearSession(client_id.into());
+        self.router_tx.try_send((self.connection_id, message))?;
+        Ok(())
+    }
+
+    /// Applies reloaded settings to this connection in the router
+    pub(crate) async fn update_settings(
+        &mut self,
+        settings: Arc<ConnectionSettings>,
+    ) -> Result<(), LinkError> {
+        self.router_tx
+            .send_async((self.connection_id, Event::UpdateSettings(settings)))
+            .await?;
+
+        Ok(())
+    }
+
     /// Request to get device shadow
     pub fn shadow<S: Into<String>>(&mut self, filter: S) -> Result<(), LinkError> {
         let message = Event::Shadow(ShadowRequest {
@@ -20,6 +20,8 @@ pub enum Error {
     Protocol(#[from] protocol::Error),
     #[error["Keep alive timeout"]]
     KeepAlive(#[from] Elapsed),
+    #[error("Idle timeout")]
+    IdleTimeout,
 }
 
 /// Network transforms packets <-> frames efficiently. It takes
@@ -38,6 +40,8 @@ pub struct Network<P> {
     max_connection_buffer_len: usize,
     /// Keep alive timeout
     keepalive: Duration,
+    /// Timeout for receiving data, independent of keep alive
+    idle_timeout: Option<Duration>,
     /// Protocol
     protocol: P,
 }
@@ -56,6 +60,7 @@ pub fn new(
             max_incoming_size,
             max_connection_buffer_len,
             keepalive: Duration::ZERO,
+            idle_timeout: None,
             protocol,
         }
     }
@@ -65,6 +70,17 @@ pub fn set_keepalive(&mut self, keepalive: u16) {
         self.keepalive = keepalive + keepalive.mul_f32(0.5);
     }
 
+    /// Disconnects when nothing is received for `idle_timeout`, if it's shorter than keep alive
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    /// Updates maximum packet size and connection buffer count of an established connection
+    pub fn set_limits(&mut self, max_incoming_size: usize, max_connection_buffer_len: usize) {
+        self.max_incoming_size = max_incoming_size;
+        self.max_connection_buffer_len = max_connection_buffer_len;
+    }
+
     /// Reads more than 'required' bytes to frame a packet into self.read buffer
     async fn read_bytes(&mut self, required: usize) -> io::Result<usize> {
         // TODO: Fix this cancellation bug and write unit test
@@ -101,9 +117,23 @@ pub async fn read(&mut self) -> Result<Packet, Error> {
                 Err(e) => return Err(e.into()),
             };
 
+            let (duration, idle) = match self.idle_timeout {
+                Some(idle_timeout) if idle_timeout < self.keepalive => (idle_timeout, true),
+                // Keep alive isn't known before connect is read, callers bound that read
+                _ if self.keepalive.is_zero() => {
+                    self.read_bytes(required).await?;
+                    continue;
+                }
+                _ => (self.keepalive, false),
+            };
+
             // read more packets until a frame can be created. This function
             // blocks until a frame can be created. Use this in a select! branch
-            timeout(self.keepalive, self.read_bytes(required)).await??;
+            match timeout(duration, self.read_bytes(required)).await {
+                Ok(read) => read?,
+                Err(_) if idle => return Err(Error::IdleTimeout),
+                Err(e) => return Err(e.into()),
+            };
         }
     }
 
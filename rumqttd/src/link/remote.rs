@@ -2,13 +2,17 @@
 use crate::link::network;
 use crate::link::network::Network;
 use crate::local::LinkBuilder;
-use crate::protocol::{ConnAck, Connect, ConnectReturnCode, Login, Packet, Protocol};
+use crate::protocol::{
+    ConnAck, Connect, ConnectProperties, ConnectReturnCode, Login, Packet, Protocol,
+};
 use crate::router::{Event, Notification};
+use crate::server::SettingsRx;
 use crate::{ConnectionId, ConnectionSettings};
 
 use flume::{RecvError, SendError, Sender, TrySendError};
 use std::cmp::min;
 use std::collections::VecDeque;
+use std::future::pending;
 use std::io;
 use std::sync::Arc;
 use std::time::Duration;
@@ -58,6 +62,8 @@ pub struct RemoteLink<P> {
     link_rx: LinkRx,
     notifications: VecDeque<Notification>,
     pub(crate) will_delay_interval: u32,
+    /// Settings reloaded from configuration while connection is active
+    settings: Option<SettingsRx>,
 }
 
 impl<P: Protocol> RemoteLink<P> {
@@ -66,10 +72,11 @@ pub async fn new(
         tenant_id: Option<String>,
         mut network: Network<P>,
         connect_packet: Packet,
-        dynamic_filters: bool,
+        config: Arc<ConnectionSettings>,
         assigned_client_id: Option<String>,
     ) -> Result<RemoteLink<P>, Error> {
-        let Packet::Connect(connect, props, lastwill, lastwill_props, _) = connect_packet else {
+        let Packet::Connect(connect, props, lastwill, lastwill_props, login) = connect_packet
+        else {
             return Err(Error::NotConnectPacket(connect_packet));
         };
 
@@ -93,17 +100,49 @@ pub async fn new(
         // the Will Delay Interval has passed or the Session ends, whichever happens first
         let will_delay_interval = min(session_expiry, delay_interval);
 
-        let (link_tx, link_rx, notification) = LinkBuilder::new(client_id, router_tx)
+        let username = login.map(|login| login.username);
+        let topic_rewrite = config
+            .topic_rewrite
+            .as_ref()
+            .and_then(|rewrite_fn| rewrite_fn(client_id.clone(), username));
+
+        let builder = LinkBuilder::new(client_id, router_tx)
             .tenant_id(tenant_id)
             .clean_session(clean_session)
             .last_will(lastwill)
             .last_will_properties(lastwill_props)
-            .dynamic_filters(dynamic_filters)
-            .topic_alias_max(topic_alias_max.unwrap_or(0))
-            .build()?;
+            .dynamic_filters(config.dynamic_filters)
+            .max_topic_levels(config.max_topic_levels)
+            .max_topic_length(config.max_topic_length)
+            .max_subscriptions(config.max_subscriptions)
+            .max_incoming_qos2_flows(config.max_incoming_qos2_flows)
+            .ack_reasons(P::ACK_REASONS)
+            .slow_consumer(config.slow_consumer.clone())
+            .reject_duplicate_client_id(config.reject_duplicate_client_id)
+            .topic_rewrite(topic_rewrite)
+            .topic_alias_max(topic_alias_max.unwrap_or(0));
+
+        if config.connack_delay_ms > 0 {
+            time::sleep(Duration::from_millis(config.connack_delay_ms)).await;
+        }
+
+        let (link_tx, link_rx, notification) = match builder.build() {
+            Ok(link) => link,
+            Err(LinkError::ConnectionRefused(code)) => {
+                let ack = ConnAck {
+                    session_present: false,
+                    code,
+                };
+
+                network.write(Packet::ConnAck(ack, None)).await?;
+                return Err(Error::Link(LinkError::ConnectionRefused(code)));
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         let id = link_rx.id();
         Span::current().record("connection_id", id);
+        network.set_idle_timeout(config.idle_timeout_ms.map(Duration::from_millis));
 
         if let Some(mut packet) = notification.into() {
             if let Packet::ConnAck(_ack, props) = &mut packet {
@@ -122,9 +161,15 @@ pub async fn new(
             link_rx,
             notifications: VecDeque::with_capacity(100),
             will_delay_interval,
+            settings: None,
         })
     }
 
+    /// Applies settings sent on `settings` to this connection until it's closed
+    pub(crate) fn watch_settings(&mut self, settings: SettingsRx) {
+        self.settings = Some(settings);
+    }
+
     pub async fn start(&mut self) -> Result<(), Error> {
         self.network.set_keepalive(self.connect.keep_alive);
 
@@ -164,16 +209,36 @@ pub async fn start(&mut self) -> Result<(), Error> {
                         self.link_rx.wake().await?;
                     }
                 }
+                settings = reloaded_settings(&mut self.settings) => {
+                    self.network.set_limits(settings.max_payload_size, settings.max_inflight_count);
+                    let idle_timeout = settings.idle_timeout_ms.map(Duration::from_millis);
+                    self.network.set_idle_timeout(idle_timeout);
+                    self.link_tx.update_settings(settings).await?;
+                }
             }
         }
     }
 }
 
+/// Waits for settings to be reloaded. Never resolves if settings aren't watched
+async fn reloaded_settings(settings: &mut Option<SettingsRx>) -> Arc<ConnectionSettings> {
+    let Some(settings) = settings else {
+        return pending().await;
+    };
+
+    if settings.changed().await.is_err() {
+        return pending().await;
+    }
+
+    settings.borrow_and_update().clone()
+}
+
 /// Read MQTT connect packet from network and verify it.
 /// authentication and checks are done here.
 pub async fn mqtt_connect<P>(
     config: Arc<ConnectionSettings>,
     network: &mut Network<P>,
+    cert_subject: Option<String>,
 ) -> Result<Packet, Error>
 where
     P: Protocol,
@@ -182,20 +247,26 @@ pub async fn mqtt_connect<P>(
     // DOS attacks by filling total connections that the server can handle with idle open
     // connections which results in server rejecting new connections
     let connection_timeout_ms = config.connection_timeout_ms.into();
-    let packet = time::timeout(Duration::from_millis(connection_timeout_ms), async {
+    let mut packet = time::timeout(Duration::from_millis(connection_timeout_ms), async {
         let packet = network.read().await?;
         Ok::<_, network::Error>(packet)
     })
     .await??;
 
-    let (connect, _props, login) = match packet {
-        Packet::Connect(ref connect, ref props, _, _, ref login) => (connect, props, login),
+    let (connect, props, login) = match packet {
+        Packet::Connect(ref mut connect, ref props, _, _, ref login) => (connect, props, login),
         packet => return Err(Error::NotConnectPacket(packet)),
     };
 
+    if let Some(subject) = cert_subject.as_ref().filter(|_| config.client_id_from_cert) {
+        connect.client_id.clone_from(subject);
+    }
+
     Span::current().record("client_id", &connect.client_id);
 
+    handle_cert_auth(config.clone(), cert_subject, &connect.client_id).await?;
     handle_auth(config.clone(), login.as_ref(), &connect.client_id).await?;
+    handle_user_properties_auth(config.clone(), props.as_ref(), &connect.client_id).await?;
 
     // When keep_alive feature is disabled client can live forever, which is not good in
     // distributed broker context so currenlty we don't allow it.
@@ -222,6 +293,45 @@ pub async fn mqtt_connect<P>(
     Ok(packet)
 }
 
+async fn handle_cert_auth(
+    config: Arc<ConnectionSettings>,
+    cert_subject: Option<String>,
+    client_id: &str,
+) -> Result<(), Error> {
+    let Some(auth) = &config.cert_auth else {
+        return Ok(());
+    };
+
+    // certificate based authorization is configured but client didn't present
+    // a certificate with subject
+    let Some(subject) = cert_subject else {
+        return Err(Error::InvalidAuth);
+    };
+
+    if !auth(client_id.to_owned(), subject).await {
+        return Err(Error::InvalidAuth);
+    }
+
+    Ok(())
+}
+
+async fn handle_user_properties_auth(
+    config: Arc<ConnectionSettings>,
+    props: Option<&ConnectProperties>,
+    client_id: &str,
+) -> Result<(), Error> {
+    let Some(auth) = &config.user_properties_auth else {
+        return Ok(());
+    };
+
+    let user_properties = props.map_or_else(Vec::new, |props| props.user_properties.clone());
+    if !auth(client_id.to_owned(), user_properties).await {
+        return Err(Error::InvalidAuth);
+    }
+
+    Ok(())
+}
+
 async fn handle_auth(
     config: Arc<ConnectionSettings>,
     login: Option<&Login>,
@@ -271,9 +381,12 @@ async fn handle_auth(
 mod tests {
     use std::{collections::HashMap, sync::Arc};
 
-    use crate::{protocol::Login, ConnectionSettings};
+    use crate::{
+        protocol::{ConnectProperties, Login},
+        ConnectionSettings,
+    };
 
-    use super::handle_auth;
+    use super::{handle_auth, handle_cert_auth, handle_user_properties_auth};
 
     fn config() -> ConnectionSettings {
         ConnectionSettings {
@@ -282,7 +395,19 @@ fn config() -> ConnectionSettings {
             max_inflight_count: 0,
             auth: None,
             external_auth: None,
+            cert_auth: None,
+            user_properties_auth: None,
+            topic_rewrite: None,
+            client_id_from_cert: false,
             dynamic_filters: false,
+            max_topic_levels: None,
+            max_topic_length: None,
+            max_subscriptions: None,
+            max_incoming_qos2_flows: None,
+            slow_consumer: None,
+            reject_duplicate_client_id: false,
+            idle_timeout_ms: None,
+            connack_delay_ms: 0,
         }
     }
 
@@ -368,6 +493,67 @@ async fn login_fails_static_fails_external() {
         assert!(r.is_err());
     }
 
+    #[tokio::test]
+    async fn no_cert_auth() {
+        let cfg = Arc::new(config());
+        let r = handle_cert_auth(cfg, None, "").await;
+        assert!(r.is_ok());
+    }
+
+    #[tokio::test]
+    async fn cert_auth_without_subject_fails() {
+        let mut cfg = config();
+        cfg.set_cert_auth_handler(|_: String, _: String| async { true });
+
+        let r = handle_cert_auth(Arc::new(cfg), None, "").await;
+        assert!(r.is_err());
+    }
+
+    #[tokio::test]
+    async fn cert_auth_matches_subject() {
+        let mut cfg = config();
+        cfg.set_cert_auth_handler(|client_id: String, subject: String| async move {
+            client_id == "c" && subject == "device-1"
+        });
+        let cfg = Arc::new(cfg);
+
+        let r = handle_cert_auth(cfg.clone(), Some("device-1".to_owned()), "c").await;
+        assert!(r.is_ok());
+
+        let r = handle_cert_auth(cfg, Some("device-2".to_owned()), "c").await;
+        assert!(r.is_err());
+    }
+
+    #[tokio::test]
+    async fn user_properties_auth_sees_connect_properties() {
+        let mut cfg = config();
+        cfg.set_user_properties_auth_handler(
+            |client_id: String, user_properties: Vec<(String, String)>| async move {
+                client_id == "c" && user_properties.contains(&("tenant".into(), "a".into()))
+            },
+        );
+        let cfg = Arc::new(cfg);
+
+        let props = ConnectProperties {
+            session_expiry_interval: None,
+            receive_maximum: None,
+            max_packet_size: None,
+            topic_alias_max: None,
+            request_response_info: None,
+            request_problem_info: None,
+            user_properties: vec![("tenant".to_owned(), "a".to_owned())],
+            authentication_method: None,
+            authentication_data: None,
+        };
+
+        let r = handle_user_properties_auth(cfg.clone(), Some(&props), "c").await;
+        assert!(r.is_ok());
+
+        // v4 clients have no properties
+        let r = handle_user_properties_auth(cfg, None, "c").await;
+        assert!(r.is_err());
+    }
+
     #[tokio::test]
     async fn external_auth_clousre_or_fnptr_type_check_or_fail_compile() {
         let closure = |_: String, _: String, _: String| async { false };
@@ -4,6 +4,11 @@
 use clap::Parser;
 use tracing::trace;
 
+#[cfg(unix)]
+use rumqttd::ConfigReloader;
+#[cfg(unix)]
+use tracing::{error, info, warn};
+
 static RUMQTTD_DEFAULT_CONFIG: &str = include_str!("../rumqttd.toml");
 
 #[derive(Parser)]
@@ -23,6 +28,12 @@ struct CommandLine {
     /// launch without printing banner
     #[arg(short, long)]
     quiet: bool,
+    /// publish records of the write-ahead log in this directory once started
+    #[arg(long)]
+    replay: Option<String>,
+    /// keep gaps between replayed publishes as they were recorded
+    #[arg(long, requires = "replay")]
+    paced: bool,
 }
 
 #[derive(Parser)]
@@ -87,9 +98,58 @@ fn main() {
     // println!("{:#?}", configs);
 
     let mut broker = Broker::new(configs);
+
+    if let Some(dir) = &commandline.replay {
+        broker.replay(dir, commandline.paced).unwrap();
+    }
+
+    #[cfg(unix)]
+    if let Some(path) = commandline.config {
+        reload_on_hangup(path, broker.reloader());
+    }
+
     broker.start().unwrap();
 }
 
+// Reloads config file on SIGHUP. Connection settings are applied without
+// dropping connections, changes of other settings need a restart.
+#[cfg(unix)]
+fn reload_on_hangup(path: String, reloader: ConfigReloader) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let reload_thread = std::thread::Builder::new().name("reload".to_owned());
+    reload_thread
+        .spawn(move || {
+            let mut runtime = tokio::runtime::Builder::new_current_thread();
+            let runtime = runtime.enable_all().build().unwrap();
+
+            runtime.block_on(async move {
+                let mut hangup = signal(SignalKind::hangup()).expect("registered SIGHUP handler");
+                while hangup.recv().await.is_some() {
+                    let configs = config::Config::builder()
+                        .add_source(config::File::with_name(&path))
+                        .build()
+                        .and_then(|configs| configs.try_deserialize());
+
+                    let configs: rumqttd::Config = match configs {
+                        Ok(configs) => configs,
+                        Err(e) => {
+                            error!(error=?e, "Invalid config, keeping the running one");
+                            continue;
+                        }
+                    };
+
+                    let report = reloader.reload(configs);
+                    info!(applied=?report.applied, "Reloaded config");
+                    for setting in report.requires_restart {
+                        warn!(setting, "Changed setting can only be applied on restart");
+                    }
+                }
+            });
+        })
+        .expect("spawned reload thread");
+}
+
 // Do any extra validation that needs to be done before starting the broker here.
 fn validate_config(configs: &rumqttd::Config) {
     if let Some(v4) = &configs.v4 {
@@ -746,6 +746,9 @@ pub enum Error {
 }
 
 pub trait Protocol {
+    /// Whether acks carry a reason code, i.e. clients can be told that a publish failed
+    const ACK_REASONS: bool;
+
     fn read_mut(&mut self, stream: &mut BytesMut, max_size: usize) -> Result<Packet, Error>;
     fn write(&self, packet: Packet, write: &mut BytesMut) -> Result<usize, Error>;
 }
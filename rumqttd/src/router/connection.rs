@@ -1,9 +1,12 @@
 use slab::Slab;
 
 use crate::protocol::LastWillProperties;
-use crate::Filter;
+use crate::router::TopicRewrite;
 use crate::{protocol::LastWill, Topic};
+use crate::{Filter, SlowConsumerSettings};
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
 
 use super::ConnectionEvents;
 
@@ -13,6 +16,8 @@
 #[derive(Debug)]
 pub struct Connection {
     pub client_id: String,
+    /// Tenant of the client, its quota applies to this connection
+    pub tenant_id: Option<String>,
     /// Id of client's organisation/tenant and the prefix associated with tenant's MQTT topic
     pub tenant_prefix: Option<String>,
     /// Dynamically create subscription filters incase they didn't exist during a publish
@@ -33,6 +38,26 @@ pub struct Connection {
     pub(crate) broker_topic_aliases: Option<BrokerAliases>,
     /// subscription IDs for a connection
     pub(crate) subscription_ids: HashMap<Filter, usize>,
+    /// Maximum number of levels allowed in topics and filters
+    pub max_topic_levels: Option<usize>,
+    /// Maximum length allowed for topics and filters
+    pub max_topic_length: Option<usize>,
+    /// Maximum number of active subscriptions
+    pub max_subscriptions: Option<usize>,
+    /// Maximum number of incoming QoS 2 publishes awaiting PUBREL
+    pub max_incoming_qos2_flows: Option<usize>,
+    /// Whether acks sent to the client carry reason codes, i.e. it's a MQTT 5 client
+    pub ack_reasons: bool,
+    /// Thresholds to detect connection as a slow consumer
+    pub slow_consumer: Option<SlowConsumerSettings>,
+    /// Time since which this connection is exceeding slow consumer thresholds
+    pub(crate) slow_since: Option<Instant>,
+    /// Reject this connection if client id is already in use by an active connection
+    pub reject_duplicate_client_id: bool,
+    /// Maps topics of this client to internal topics and back
+    pub topic_rewrite: Option<Arc<dyn TopicRewrite>>,
+    /// Whether publishes of this connection are recorded in the write-ahead log
+    pub record_publishes: bool,
 }
 
 impl Connection {
@@ -45,10 +70,10 @@ pub fn new(
     ) -> Connection {
         // Change client id to -> tenant_id.client_id and derive topic path prefix
         // to validate topics
-        let (client_id, tenant_prefix) = match tenant_id {
+        let (client_id, tenant_prefix) = match &tenant_id {
             Some(tenant_id) => {
-                let tenant_prefix = Some("/tenants/".to_owned() + &tenant_id + "/");
-                let client_id = tenant_id + "." + &client_id;
+                let tenant_prefix = Some("/tenants/".to_owned() + tenant_id + "/");
+                let client_id = tenant_id.to_owned() + "." + &client_id;
                 (client_id, tenant_prefix)
             }
             None => (client_id, None),
@@ -56,6 +81,7 @@ pub fn new(
 
         Connection {
             client_id,
+            tenant_id,
             tenant_prefix,
             dynamic_filters,
             clean,
@@ -66,6 +92,16 @@ pub fn new(
             topic_aliases: HashMap::new(),
             broker_topic_aliases: None,
             subscription_ids: HashMap::new(),
+            max_topic_levels: None,
+            max_topic_length: None,
+            max_subscriptions: None,
+            max_incoming_qos2_flows: None,
+            ack_reasons: false,
+            slow_consumer: None,
+            slow_since: None,
+            reject_duplicate_client_id: false,
+            topic_rewrite: None,
+            record_publishes: true,
         }
     }
 
@@ -77,6 +113,76 @@ pub fn topic_alias_max(&mut self, max: u16) -> &mut Connection {
         self
     }
 
+    pub fn topic_limits(
+        &mut self,
+        max_levels: Option<usize>,
+        max_length: Option<usize>,
+    ) -> &mut Connection {
+        self.max_topic_levels = max_levels;
+        self.max_topic_length = max_length;
+        self
+    }
+
+    pub fn ack_reasons(&mut self, ack_reasons: bool) -> &mut Connection {
+        self.ack_reasons = ack_reasons;
+        self
+    }
+
+    /// Checks if topic or filter is within the depth and length limits of this connection
+    pub fn within_topic_limits(&self, topic: &str) -> bool {
+        if self.max_topic_length.is_some_and(|max| topic.len() > max) {
+            return false;
+        }
+
+        if self
+            .max_topic_levels
+            .is_some_and(|max| topic.split('/').count() > max)
+        {
+            return false;
+        }
+
+        true
+    }
+
+    pub fn max_subscriptions(&mut self, max: Option<usize>) -> &mut Connection {
+        self.max_subscriptions = max;
+        self
+    }
+
+    /// Checks if subscribing to `filter` keeps this connection within its subscription
+    /// limit. Subscribing again to an active filter doesn't add a subscription
+    pub fn within_subscription_limit(&self, filter: &str) -> bool {
+        self.subscriptions.contains(filter)
+            || !self
+                .max_subscriptions
+                .is_some_and(|max| self.subscriptions.len() >= max)
+    }
+
+    pub fn max_incoming_qos2_flows(&mut self, max: Option<usize>) -> &mut Connection {
+        self.max_incoming_qos2_flows = max;
+        self
+    }
+
+    pub fn slow_consumer(&mut self, settings: Option<SlowConsumerSettings>) -> &mut Connection {
+        self.slow_consumer = settings;
+        self
+    }
+
+    pub fn reject_duplicate_client_id(&mut self, reject: bool) -> &mut Connection {
+        self.reject_duplicate_client_id = reject;
+        self
+    }
+
+    pub fn topic_rewrite(&mut self, rewrite: Option<Arc<dyn TopicRewrite>>) -> &mut Connection {
+        self.topic_rewrite = rewrite;
+        self
+    }
+
+    pub fn record_publishes(&mut self, record: bool) -> &mut Connection {
+        self.record_publishes = record;
+        self
+    }
+
     pub fn last_will(
         &mut self,
         will: Option<LastWill>,
@@ -0,0 +1,75 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Remembers QoS 1 publishes received within a time window, keyed by client id and
+/// packet id, to detect publishes resent with DUP (e.g. after a reconnect) which
+/// were already appended to the commitlog
+pub struct DedupWindow {
+    window: Duration,
+    /// Time at which a packet id of a client was last received
+    received: HashMap<(String, u16), Instant>,
+    /// Keys in the order they were received, to expire them
+    order: VecDeque<(Instant, (String, u16))>,
+}
+
+impl DedupWindow {
+    pub fn new(window: Duration) -> DedupWindow {
+        DedupWindow {
+            window,
+            received: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Records the publish and returns true if it's a resend of a publish received
+    /// within the window. Publishes without DUP are never duplicates, clients reuse
+    /// packet ids once they are acked
+    pub fn is_duplicate(&mut self, client_id: &str, pkid: u16, dup: bool, now: Instant) -> bool {
+        self.expire(now);
+
+        let key = (client_id.to_owned(), pkid);
+        if dup && self.received.contains_key(&key) {
+            return true;
+        }
+
+        self.received.insert(key.clone(), now);
+        self.order.push_back((now, key));
+        false
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((received, _)) = self.order.front() {
+            if now.duration_since(*received) < self.window {
+                break;
+            }
+
+            let (received, key) = self.order.pop_front().unwrap();
+            // Packet id might've been received again since, which keeps it
+            if self.received.get(&key) == Some(&received) {
+                self.received.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resends_are_duplicates_only_within_window() {
+        let mut dedup = DedupWindow::new(Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(!dedup.is_duplicate("client", 1, false, now));
+        assert!(dedup.is_duplicate("client", 1, true, now));
+        assert!(!dedup.is_duplicate("other", 1, true, now));
+        // reused packet id is a new publish
+        assert!(!dedup.is_duplicate("client", 1, false, now));
+
+        let later = now + Duration::from_secs(10);
+        assert!(!dedup.is_duplicate("client", 2, false, later));
+        assert_eq!(dedup.received.len(), 1);
+        assert!(!dedup.is_duplicate("client", 1, true, later));
+    }
+}
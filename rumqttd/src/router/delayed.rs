@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+use crate::protocol::{Publish, PublishProperties};
+
+/// Number of one second slots in the wheel. Publishes delayed by more wait for
+/// more turns of the wheel
+const WHEEL_SLOTS: usize = 512;
+
+const TICK: Duration = Duration::from_secs(1);
+
+struct Delayed {
+    /// Turns of the wheel left before the publish is due
+    rounds: usize,
+    publish: Publish,
+    properties: Option<PublishProperties>,
+}
+
+/// Publishes sent to `{prefix}/{seconds}/{topic}`, held back in a hashed timer wheel
+/// till they are due to be delivered on `topic`. Publishes are due within a second
+/// after their delay, never before
+pub struct DelayedPublishes {
+    prefix: String,
+    slots: Vec<Vec<Delayed>>,
+    /// Slot of the last tick
+    current: usize,
+    /// Time of the last tick
+    tick: Instant,
+}
+
+impl DelayedPublishes {
+    pub fn new(prefix: &str, now: Instant) -> DelayedPublishes {
+        DelayedPublishes {
+            prefix: format!("{}/", prefix.trim_end_matches('/')),
+            slots: (0..WHEEL_SLOTS).map(|_| Vec::new()).collect(),
+            current: 0,
+            tick: now,
+        }
+    }
+
+    /// Returns the rest of the topic if it's a delayed publish, i.e. `{seconds}/{topic}`
+    pub fn strip_prefix<'a>(&self, topic: &'a str) -> Option<&'a str> {
+        topic.strip_prefix(self.prefix.as_str())
+    }
+
+    pub fn schedule(
+        &mut self,
+        delay: Duration,
+        publish: Publish,
+        properties: Option<PublishProperties>,
+        now: Instant,
+    ) {
+        // Round up to whole ticks from the last one so that publishes are never early
+        let due = now.saturating_duration_since(self.tick) + delay;
+        let ticks = (due.as_secs() + (due.subsec_nanos() > 0) as u64).max(1) as usize;
+
+        let slot = (self.current + ticks) % WHEEL_SLOTS;
+        self.slots[slot].push(Delayed {
+            rounds: (ticks - 1) / WHEEL_SLOTS,
+            publish,
+            properties,
+        });
+    }
+
+    /// Advances the wheel to `now` and returns publishes which are due, in the order
+    /// they were due
+    pub fn advance(&mut self, now: Instant) -> Vec<(Publish, Option<PublishProperties>)> {
+        let mut due = Vec::new();
+        while now.saturating_duration_since(self.tick) >= TICK {
+            self.tick += TICK;
+            self.current = (self.current + 1) % WHEEL_SLOTS;
+
+            let slot = &mut self.slots[self.current];
+            for mut delayed in std::mem::take(slot) {
+                if delayed.rounds == 0 {
+                    due.push((delayed.publish, delayed.properties));
+                } else {
+                    delayed.rounds -= 1;
+                    slot.push(delayed);
+                }
+            }
+        }
+
+        due
+    }
+
+    /// Number of publishes which aren't due yet
+    pub fn pending(&self) -> usize {
+        self.slots.iter().map(Vec::len).sum()
+    }
+}
+
+/// Splits `{seconds}/{topic}` into the delay and topic
+pub fn parse_delay(topic: &str) -> Option<(Duration, &str)> {
+    let (seconds, topic) = topic.split_once('/')?;
+    let seconds: u32 = seconds.parse().ok()?;
+    if topic.is_empty() {
+        return None;
+    }
+
+    Some((Duration::from_secs(seconds as u64), topic))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn publish(topic: &'static str) -> Publish {
+        Publish::new(topic, "payload", false)
+    }
+
+    fn topics(due: Vec<(Publish, Option<PublishProperties>)>) -> Vec<String> {
+        due.into_iter()
+            .map(|(publish, _)| String::from_utf8(publish.topic.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn publishes_are_due_after_their_delay() {
+        let now = Instant::now();
+        let at = |secs| now + Duration::from_secs(secs);
+        // more than a turn of the wheel
+        let long = WHEEL_SLOTS as u64 + 1;
+
+        let mut delayed = DelayedPublishes::new("$delayed", now);
+        delayed.schedule(Duration::from_secs(2), publish("a"), None, now);
+        delayed.schedule(Duration::from_secs(1), publish("b"), None, now);
+        delayed.schedule(Duration::from_secs(long), publish("c"), None, now);
+
+        assert!(delayed.advance(now + Duration::from_millis(999)).is_empty());
+        assert_eq!(topics(delayed.advance(at(1))), ["b"]);
+        assert_eq!(topics(delayed.advance(at(10))), ["a"]);
+        assert_eq!(delayed.pending(), 1);
+        assert!(delayed.advance(at(long - 1)).is_empty());
+        assert_eq!(topics(delayed.advance(at(long))), ["c"]);
+    }
+
+    #[test]
+    fn delay_is_parsed_from_topic() {
+        let delayed = DelayedPublishes::new("$delayed", Instant::now());
+        let rest = delayed.strip_prefix("$delayed/15/hello/world").unwrap();
+        assert_eq!(
+            parse_delay(rest),
+            Some((Duration::from_secs(15), "hello/world"))
+        );
+        assert!(delayed.strip_prefix("hello/world").is_none());
+        assert!(parse_delay("soon/hello/world").is_none());
+        assert!(parse_delay("15/").is_none());
+    }
+}
@@ -22,6 +22,20 @@ pub fn retrieve(&mut self, id: &str) -> Option<SavedState> {
         self.connections.remove(id)
     }
 
+    /// Persistent sessions of disconnected clients
+    pub fn sessions(&self) -> impl Iterator<Item = (&String, &SessionState)> {
+        self.connections
+            .iter()
+            .filter_map(|(id, saved)| saved.session_state.as_ref().map(|s| (id, s)))
+    }
+
+    /// Discard session state of a client while retaining its metrics
+    pub fn clear_session(&mut self, id: &str) {
+        if let Some(saved) = self.connections.get_mut(id) {
+            saved.session_state = None;
+        }
+    }
+
     /// Save connection tracker
     pub fn save_state(
         &mut self,
@@ -8,7 +8,7 @@
 use tracing::{error, warn};
 
 use crate::{
-    protocol::Packet,
+    protocol::{Packet, QoS},
     router::{FilterIdx, MAX_CHANNEL_CAPACITY},
     Cursor, Notification,
 };
@@ -102,6 +102,31 @@ pub fn free_slots(&self) -> usize {
         MAX_INFLIGHT - self.inflight_buffer.len()
     }
 
+    pub fn inflight(&self) -> usize {
+        self.inflight_buffer.len()
+    }
+
+    /// Returns count of publishes which are either queued or awaiting acks and
+    /// size of queued publishes in bytes
+    pub fn pending(&self) -> (usize, usize) {
+        let buffer = self.data_buffer.lock();
+        let mut count = self.inflight_buffer.len();
+        let mut size = 0;
+
+        for notification in buffer.iter() {
+            if let Notification::Forward(forward) = notification {
+                // qos 1 and 2 publishes are already accounted for in inflight buffer
+                if forward.publish.qos == QoS::AtMostOnce {
+                    count += 1;
+                }
+
+                size += forward.publish.topic.len() + forward.publish.payload.len();
+            }
+        }
+
+        (count, size)
+    }
+
     pub fn push_notification(&mut self, notification: Notification) -> usize {
         let mut buffer = self.data_buffer.lock();
         buffer.push_back(notification);
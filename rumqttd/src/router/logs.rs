@@ -1,19 +1,20 @@
 use super::Ack;
 use slab::Slab;
-use tracing::{info, trace};
+use tracing::{debug, info, trace, warn};
 
 use crate::protocol::{
-    matches, ConnAck, ConnAckProperties, PingResp, PubAck, PubComp, PubRec, PubRel, Publish,
-    PublishProperties, SubAck, UnsubAck,
+    matches, ConnAck, ConnAckProperties, PingResp, PubAck, PubComp, PubRec, PubRecReason, PubRel,
+    Publish, PublishProperties, SubAck, UnsubAck,
 };
-use crate::router::{DataRequest, FilterIdx, SubscriptionMeter, Waiters};
-use crate::{ConnectionId, Filter, Offset, RouterConfig, Topic};
+use crate::router::{DataRequest, FilterIdx, MemoryUsage, SubscriptionMeter, Waiters};
+use crate::{ConnectionId, Filter, Offset, RetainedLimitPolicy, RouterConfig, Topic};
 
 use crate::segments::{CommitLog, Position};
 use crate::Storage;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::io;
-use std::time::Instant;
+use std::mem;
+use std::time::{Duration, Instant};
 
 type PubWithProp = (Publish, Option<PublishProperties>);
 
@@ -22,14 +23,23 @@ pub struct PublishData {
     pub publish: Publish,
     pub properties: Option<PublishProperties>,
     pub timestamp: Instant,
+    /// Time after which the message is dropped, as per its message expiry interval
+    pub expires_at: Option<Instant>,
 }
 
 impl From<PubWithProp> for PublishData {
     fn from((publish, properties): PubWithProp) -> Self {
+        let timestamp = Instant::now();
+        let expires_at = properties
+            .as_ref()
+            .and_then(|p| p.message_expiry_interval)
+            .map(|interval| timestamp + Duration::from_secs(interval as u64));
+
         PublishData {
             publish,
             properties,
-            timestamp: Instant::now(),
+            timestamp,
+            expires_at,
         }
     }
 }
@@ -59,8 +69,14 @@ pub struct DataLog {
     /// Map of subscription filter name to filter index
     filter_indexes: HashMap<Filter, FilterIdx>,
     retained_publishes: HashMap<Topic, PublishData>,
+    /// Topics of `retained_publishes` by the time they were stored, oldest first
+    retained_order: BTreeSet<(Instant, Topic)>,
     /// List of filters associated with a topic
     publish_filters: HashMap<Topic, Vec<FilterIdx>>,
+    /// Approximate bytes of `filter_indexes` and `native`, excluding commitlog contents
+    filter_bytes: usize,
+    /// Approximate bytes of `retained_publishes`
+    retained_bytes: usize,
 }
 
 impl DataLog {
@@ -69,10 +85,12 @@ pub fn new(config: RouterConfig) -> io::Result<DataLog> {
         let mut filter_indexes = HashMap::new();
         let retained_publishes = HashMap::new();
         let publish_filters = HashMap::new();
+        let mut filter_bytes = 0;
 
         if let Some(warmup_filters) = config.initialized_filters.clone() {
             for filter in warmup_filters {
                 let data = Data::new(&filter, &config);
+                filter_bytes += filter_size(&filter);
 
                 // Add commitlog to datalog and add datalog index to filter to
                 // datalog index map
@@ -87,9 +105,35 @@ pub fn new(config: RouterConfig) -> io::Result<DataLog> {
             publish_filters,
             filter_indexes,
             retained_publishes,
+            retained_order: BTreeSet::new(),
+            filter_bytes,
+            retained_bytes: 0,
         })
     }
 
+    /// Approximate memory of filters and retained messages
+    pub fn memory(&self) -> MemoryUsage {
+        MemoryUsage {
+            filters: self.filter_indexes.len(),
+            filter_bytes: self.filter_bytes,
+            retained_messages: self.retained_publishes.len(),
+            retained_bytes: self.retained_bytes,
+        }
+    }
+
+    /// Whether `bytes` more fit in the memory budget, if any
+    fn within_memory_budget(&self, bytes: usize) -> bool {
+        self.config.memory_budget.map_or(true, |budget| {
+            self.filter_bytes + self.retained_bytes + bytes <= budget
+        })
+    }
+
+    /// Whether a subscription to `filter` fits in the memory budget. Filters which
+    /// already have a commitlog don't take more memory
+    pub fn filter_within_memory_budget(&self, filter: &str) -> bool {
+        self.filter_indexes.contains_key(filter) || self.within_memory_budget(filter_size(filter))
+    }
+
     pub fn meter(&mut self, filter: &str) -> Option<&mut SubscriptionMeter> {
         let data = self.native.get_mut(*self.filter_indexes.get(filter)?)?;
         Some(&mut data.meter)
@@ -157,6 +201,7 @@ pub fn next_native_offset(&mut self, filter: &str) -> (FilterIdx, Offset) {
                 // datalog index map
                 let idx = self.native.insert(data);
                 self.filter_indexes.insert(filter.to_owned(), idx);
+                self.filter_bytes += filter_size(filter);
 
                 // Match new filter to existing topics and add to publish_filters if it matches
                 for (topic, filters) in publish_filters.iter_mut() {
@@ -261,51 +306,179 @@ pub fn insert_to_retained_publishes(
         publish_properties: Option<PublishProperties>,
         topic: Topic,
     ) {
-        let pub_with_props = (publish, publish_properties);
-        self.retained_publishes.insert(topic, pub_with_props.into());
+        let size = retained_size(&topic, &publish);
+        if let Some(limits) = &self.config.retained_limits {
+            // A message which doesn't fit alone would evict every other one for nothing
+            if limits.max_bytes.is_some_and(|max| size > max) || limits.max_messages == Some(0) {
+                warn!(
+                    topic,
+                    "Retained message not stored, larger than retained limits"
+                );
+                return;
+            }
+
+            if limits.policy == RetainedLimitPolicy::EvictOldest {
+                self.evict_oldest_retained(&topic, size);
+            }
+        }
+
+        if self.retained_exceeds_limits(&topic, size) {
+            warn!(
+                topic,
+                "Retained message not stored, retained limits exceeded"
+            );
+            return;
+        }
+
+        let replaced = self.replaced_retained_size(&topic);
+        // Replacing a message only takes the difference
+        if !self.within_memory_budget(size.saturating_sub(replaced)) {
+            warn!(topic, "Retained message not stored, memory budget exceeded");
+            return;
+        }
+
+        self.retained_bytes = self.retained_bytes + size - replaced;
+        let data: PublishData = (publish, publish_properties).into();
+        self.retained_order.insert((data.timestamp, topic.clone()));
+        if let Some(old) = self.retained_publishes.insert(topic.clone(), data) {
+            self.retained_order.remove(&(old.timestamp, topic));
+        }
     }
 
-    pub fn remove_from_retained_publishes(&mut self, topic: Topic) {
-        self.retained_publishes.remove(&topic);
+    /// Whether a retained `publish` on `topic` is rejected as per `RetainedLimits`
+    pub fn rejects_retained(&self, topic: &str, publish: &Publish) -> bool {
+        let reject = self
+            .config
+            .retained_limits
+            .as_ref()
+            .is_some_and(|limits| limits.policy == RetainedLimitPolicy::Reject);
+
+        reject && self.retained_exceeds_limits(topic, retained_size(topic, publish))
     }
 
-    pub fn read_retained_messages(&mut self, filter: &str) -> Vec<PubWithProp> {
-        trace!(info = "reading retain msg", filter = &filter);
-        let now = Instant::now();
+    /// Whether storing a retained message of `size` on `topic` exceeds `RetainedLimits`
+    fn retained_exceeds_limits(&self, topic: &str, size: usize) -> bool {
+        let Some(limits) = &self.config.retained_limits else {
+            return false;
+        };
 
-        // discard expired retained messages
-        self.retained_publishes.retain(|_, pubdata| {
-            // Keep data if no properties exists, which implies no message expiry!
-            let Some(properties) = pubdata.properties.as_mut() else {
-                return true;
-            };
+        let replaced = self.replaced_retained_size(topic);
+        let mut count = self.retained_publishes.len();
+        if !self.retained_publishes.contains_key(topic) {
+            count += 1;
+        }
 
-            // Keep data if there is no message_expiry_interval
-            let Some(message_expiry_interval) = properties.message_expiry_interval.as_mut() else {
-                return true;
+        let bytes = self.retained_bytes - replaced + size;
+        limits.max_messages.is_some_and(|max| count > max)
+            || limits.max_bytes.is_some_and(|max| bytes > max)
+    }
+
+    /// Drops least recently stored retained messages of other topics till a message
+    /// of `size` on `topic` fits in `RetainedLimits`
+    fn evict_oldest_retained(&mut self, topic: &str, size: usize) {
+        while self.retained_exceeds_limits(topic, size) {
+            let oldest = self
+                .retained_order
+                .iter()
+                .map(|(_, retained)| retained)
+                .find(|retained| retained.as_str() != topic)
+                .cloned();
+
+            let Some(oldest) = oldest else {
+                return;
             };
 
-            let time_spent = (now - pubdata.timestamp).as_secs() as u32;
+            debug!(topic = oldest.as_str(), "Evicting oldest retained message");
+            self.remove_from_retained_publishes(oldest);
+        }
+    }
 
-            let is_valid = time_spent < *message_expiry_interval;
+    fn replaced_retained_size(&self, topic: &str) -> usize {
+        self.retained_publishes
+            .get(topic)
+            .map_or(0, |old| retained_size(topic, &old.publish))
+    }
 
-            // ignore expired messages
-            if is_valid {
-                // set message_expiry_interval to (original value - time spent waiting in server)
-                // ref: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901112
-                *message_expiry_interval -= time_spent;
+    pub fn retained_count(&self) -> usize {
+        self.retained_publishes.len()
+    }
+
+    pub fn remove_from_retained_publishes(&mut self, topic: Topic) {
+        if let Some(data) = self.retained_publishes.remove(&topic) {
+            self.retained_bytes -= retained_size(&topic, &data.publish);
+            self.retained_order.remove(&(data.timestamp, topic));
+        }
+    }
+
+    /// Drops retained messages whose message expiry interval has passed.
+    /// Returns number of dropped messages
+    pub fn expire_retained_publishes(&mut self, now: Instant) -> usize {
+        let count = self.retained_publishes.len();
+        let retained_bytes = &mut self.retained_bytes;
+        let retained_order = &mut self.retained_order;
+        self.retained_publishes.retain(|topic, pubdata| {
+            let keep = pubdata.expires_at.map_or(true, |expiry| now < expiry);
+            if !keep {
+                *retained_bytes -= retained_size(topic, &pubdata.publish);
+                retained_order.remove(&(pubdata.timestamp, topic.clone()));
             }
 
-            is_valid
+            keep
         });
 
-        // no need to include timestamp when returning
+        count - self.retained_publishes.len()
+    }
+
+    /// Topics of retained messages matching the filter. Messages are then read one
+    /// at a time with `read_retained_message`, to forward them in chunks
+    pub fn retained_topics(&mut self, filter: &str) -> VecDeque<Topic> {
+        trace!(info = "reading retain msg", filter = &filter);
+
+        // discard expired retained messages
+        self.expire_retained_publishes(Instant::now());
+
         self.retained_publishes
-            .iter()
-            .filter(|(topic, _)| matches(topic, filter))
-            .map(|(_, p)| (p.publish.clone(), p.properties.clone()))
+            .keys()
+            .filter(|topic| matches(topic, filter))
+            .cloned()
             .collect()
     }
+
+    /// Retained message on the topic, `None` if it was removed or expired since
+    /// its topic was returned by `retained_topics`
+    pub fn read_retained_message(&self, topic: &str) -> Option<PubWithProp> {
+        let now = Instant::now();
+        let p = self.retained_publishes.get(topic)?;
+        if p.expires_at.is_some_and(|expiry| now >= expiry) {
+            return None;
+        }
+
+        // no need to include timestamp when returning
+        let mut properties = p.properties.clone();
+        if let Some(interval) = properties
+            .as_mut()
+            .and_then(|p| p.message_expiry_interval.as_mut())
+        {
+            // set message_expiry_interval to (original value - time spent waiting in server)
+            // ref: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901112
+            *interval -= (now - p.timestamp).as_secs() as u32;
+        }
+
+        Some((p.publish.clone(), properties))
+    }
+}
+
+/// Approximate bytes of a filter in `DataLog::filter_indexes` and its `Data`
+fn filter_size(filter: &str) -> usize {
+    // filter is held by both
+    2 * filter.len() + mem::size_of::<(Filter, FilterIdx)>() + mem::size_of::<Data<PublishData>>()
+}
+
+/// Approximate bytes of a message in `DataLog::retained_publishes`
+fn retained_size(topic: &str, publish: &Publish) -> usize {
+    // as in `PublishData::size`
+    let data = 4 + publish.topic.len() + publish.payload.len();
+    topic.len() + data + mem::size_of::<(Topic, PublishData)>()
 }
 
 pub struct Data<T> {
@@ -374,6 +547,8 @@ pub struct AckLog {
     committed: VecDeque<Ack>,
     // Recorded qos 2 publishes
     recorded: VecDeque<(Publish, Option<PublishProperties>)>,
+    // Packet ids of recorded qos 2 publishes whose pubrec is held back
+    withheld: VecDeque<u16>,
 }
 
 impl AckLog {
@@ -382,6 +557,7 @@ pub fn new() -> AckLog {
         AckLog {
             committed: VecDeque::with_capacity(100),
             recorded: VecDeque::with_capacity(100),
+            withheld: VecDeque::new(),
         }
     }
 
@@ -406,6 +582,24 @@ pub fn pubrec(&mut self, publish: Publish, props: Option<PublishProperties>, ack
         self.committed.push_back(ack);
     }
 
+    /// Records a qos 2 publish without acking it, its pubrec is sent once a
+    /// previously recorded publish is released
+    pub fn withhold_pubrec(&mut self, publish: Publish, props: Option<PublishProperties>) {
+        self.withheld.push_back(publish.pkid);
+        self.recorded.push_back((publish, props));
+    }
+
+    /// Acks a qos 2 publish which isn't recorded, e.g. with a failure reason
+    pub fn reject_pubrec(&mut self, ack: PubRec) {
+        let ack = Ack::PubRec(ack);
+        self.committed.push_back(ack);
+    }
+
+    /// Number of qos 2 publishes awaiting pubrel, held back ones included
+    pub fn incoming_qos2_flows(&self) -> usize {
+        self.recorded.len()
+    }
+
     pub fn pubrel(&mut self, ack: PubRel) {
         let ack = Ack::PubRel(ack);
         self.committed.push_back(ack);
@@ -414,7 +608,18 @@ pub fn pubrel(&mut self, ack: PubRel) {
     pub fn pubcomp(&mut self, ack: PubComp) -> Option<(Publish, Option<PublishProperties>)> {
         let ack = Ack::PubComp(ack);
         self.committed.push_back(ack);
-        self.recorded.pop_front()
+        let recorded = self.recorded.pop_front()?;
+
+        // completed flow makes room for the oldest held back one
+        if let Some(pkid) = self.withheld.pop_front() {
+            let pubrec = PubRec {
+                pkid,
+                reason: PubRecReason::Success,
+            };
+            self.committed.push_back(Ack::PubRec(pubrec));
+        }
+
+        Some(recorded)
     }
 
     pub fn pingresp(&mut self, ack: PingResp) {
@@ -434,9 +639,13 @@ pub fn readv(&mut self) -> &mut VecDeque<Ack> {
 
 #[cfg(test)]
 mod test {
-    use super::DataLog;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{filter_size, DataLog};
+    use crate::protocol::{Publish, PublishProperties};
     use crate::router::shared_subs::Strategy;
-    use crate::RouterConfig;
+    use crate::{RetainedLimitPolicy, RetainedLimits, RouterConfig, Topic};
 
     #[test]
     fn publish_filters_updating_correctly_on_new_topic_subscription() {
@@ -448,6 +657,15 @@ fn publish_filters_updating_correctly_on_new_topic_subscription() {
             custom_segment: None,
             initialized_filters: None,
             shared_subscriptions_strategy: Strategy::RoundRobin,
+            max_tracked_topics: None,
+            dedup_window_ms: None,
+            delayed_publish_prefix: None,
+            payload_limits: None,
+            tenant_quotas: None,
+            wal: None,
+            retained_chunk_size: None,
+            memory_budget: None,
+            retained_limits: None,
         };
         let mut data = DataLog::new(config).unwrap();
         data.next_native_offset("topic/a");
@@ -468,6 +686,15 @@ fn publish_filters_updating_correctly_on_new_publish() {
             custom_segment: None,
             initialized_filters: None,
             shared_subscriptions_strategy: Strategy::RoundRobin,
+            max_tracked_topics: None,
+            dedup_window_ms: None,
+            delayed_publish_prefix: None,
+            payload_limits: None,
+            tenant_quotas: None,
+            wal: None,
+            retained_chunk_size: None,
+            memory_budget: None,
+            retained_limits: None,
         };
         let mut data = DataLog::new(config).unwrap();
         data.next_native_offset("+/+");
@@ -477,6 +704,130 @@ fn publish_filters_updating_correctly_on_new_publish() {
         assert_eq!(data.publish_filters.get("topic/a").unwrap().len(), 1);
     }
 
+    #[test]
+    fn expired_retained_publishes_are_dropped() {
+        let config = RouterConfig {
+            max_segment_size: 1024,
+            max_connections: 10,
+            max_segment_count: 10,
+            max_outgoing_packet_count: 1024,
+            custom_segment: None,
+            initialized_filters: None,
+            shared_subscriptions_strategy: Strategy::RoundRobin,
+            max_tracked_topics: None,
+            dedup_window_ms: None,
+            delayed_publish_prefix: None,
+            payload_limits: None,
+            tenant_quotas: None,
+            wal: None,
+            retained_chunk_size: None,
+            memory_budget: None,
+            retained_limits: None,
+        };
+        let mut data = DataLog::new(config).unwrap();
+        let properties = PublishProperties {
+            message_expiry_interval: Some(1),
+            ..Default::default()
+        };
+
+        let publish = Publish::new("topic/a", "hello", true);
+        data.insert_to_retained_publishes(publish, Some(properties), "topic/a".to_owned());
+        let publish = Publish::new("topic/b", "hello", true);
+        data.insert_to_retained_publishes(publish, None, "topic/b".to_owned());
+        assert_eq!(data.retained_topics("topic/a").len(), 1);
+
+        thread::sleep(Duration::from_millis(1100));
+
+        // topic read before expiry doesn't return the expired message
+        assert!(data.read_retained_message("topic/a").is_none());
+
+        // sweeper drops the expired message and later subscriber gets nothing
+        assert_eq!(data.expire_retained_publishes(Instant::now()), 1);
+        assert_eq!(data.retained_count(), 1);
+        assert!(data.retained_topics("topic/a").is_empty());
+        assert_eq!(data.retained_topics("topic/+").len(), 1);
+    }
+
+    #[test]
+    fn memory_budget_rejects_new_filters_and_retained_messages() {
+        let budget = filter_size("topic/+");
+        let config = RouterConfig {
+            max_segment_size: 1024,
+            max_connections: 10,
+            max_segment_count: 10,
+            max_outgoing_packet_count: 1024,
+            memory_budget: Some(budget),
+            ..RouterConfig::default()
+        };
+        let mut data = DataLog::new(config).unwrap();
+
+        assert!(data.filter_within_memory_budget("topic/+"));
+        data.next_native_offset("topic/+");
+        // filters which already exist take no more memory
+        assert!(data.filter_within_memory_budget("topic/+"));
+        assert!(!data.filter_within_memory_budget("other/+"));
+
+        let publish = Publish::new("topic/a", "hello", true);
+        data.insert_to_retained_publishes(publish, None, "topic/a".to_owned());
+        assert_eq!(data.retained_count(), 0);
+
+        let memory = data.memory();
+        assert_eq!(memory.filters, 1);
+        assert_eq!(memory.total_bytes(), budget);
+    }
+
+    #[test]
+    fn oldest_retained_messages_are_evicted_past_limit() {
+        let config = RouterConfig {
+            max_segment_size: 1024,
+            max_connections: 10,
+            max_segment_count: 10,
+            max_outgoing_packet_count: 1024,
+            retained_limits: Some(RetainedLimits {
+                max_messages: Some(2),
+                max_bytes: None,
+                policy: RetainedLimitPolicy::EvictOldest,
+            }),
+            ..RouterConfig::default()
+        };
+        let mut data = DataLog::new(config).unwrap();
+
+        for topic in ["topic/a", "topic/b", "topic/a", "topic/c"] {
+            let publish = Publish::new(topic, "hello", true);
+            assert!(!data.rejects_retained(topic, &publish));
+            data.insert_to_retained_publishes(publish, None, topic.to_owned());
+        }
+
+        let mut topics: Vec<Topic> = data.retained_topics("#").into();
+        topics.sort();
+        assert_eq!(topics, vec!["topic/a", "topic/c"]);
+    }
+
+    #[test]
+    fn retained_messages_larger_than_limit_evict_nothing() {
+        let config = RouterConfig {
+            max_segment_size: 1024,
+            max_connections: 10,
+            max_segment_count: 10,
+            max_outgoing_packet_count: 1024,
+            retained_limits: Some(RetainedLimits {
+                max_messages: None,
+                max_bytes: Some(1024),
+                policy: RetainedLimitPolicy::EvictOldest,
+            }),
+            ..RouterConfig::default()
+        };
+        let mut data = DataLog::new(config).unwrap();
+
+        let publish = Publish::new("topic/a", "hello", true);
+        data.insert_to_retained_publishes(publish, None, "topic/a".to_owned());
+        let publish = Publish::new(b"topic/b".to_vec(), vec![0; 2048], true);
+        data.insert_to_retained_publishes(publish, None, "topic/b".to_owned());
+
+        let topics: Vec<Topic> = data.retained_topics("#").into();
+        assert_eq!(topics, vec!["topic/a"]);
+    }
+
     //     #[test]
     //     fn appends_are_written_to_correct_commitlog() {
     //         pretty_env_logger::init();
@@ -1,6 +1,7 @@
 use std::{
     collections::{HashMap, HashSet, VecDeque},
     fmt,
+    sync::Arc,
 };
 
 use bytes::Bytes;
@@ -12,23 +13,30 @@
         PubAckProperties, PubComp, PubCompProperties, PubRec, PubRecProperties, PubRel,
         PubRelProperties, Publish, PublishProperties, SubAck, SubAckProperties, UnsubAck,
     },
-    ConnectionId, Filter, RouterId, Topic,
+    ConnectionId, ConnectionSettings, Filter, RouterId, TenantQuota, Topic,
 };
 
 mod alertlog;
 mod connection;
+mod dedup;
+mod delayed;
 mod graveyard;
 pub mod iobufs;
 mod logs;
+mod quotas;
+mod rewrite;
 mod routing;
 mod scheduler;
 pub(crate) mod shared_subs;
 mod waiters;
+mod wal;
 
 pub use alertlog::Alert;
 pub use connection::Connection;
+pub use rewrite::{PrefixRewrite, TopicRewrite};
 pub use routing::Router;
 pub use waiters::Waiters;
+pub use wal::{WalKey, WalReader, WalRecord};
 
 pub const MAX_SCHEDULE_ITERATIONS: usize = 100;
 pub const MAX_CHANNEL_CAPACITY: usize = 200;
@@ -65,6 +73,21 @@ pub enum Event {
     PrintStatus(Print),
     /// Publish Will message
     PublishWill((String, Option<String>)),
+    /// Get broker statistics
+    Stats,
+    /// List connected clients and persistent sessions
+    ListSessions,
+    /// Disconnect a client and optionally discard its session
+    Kick {
+        client_id: String,
+        clear_session: bool,
+    },
+    /// Discard persistent session of a disconnected client
+    ClearSession(String),
+    /// Connection settings reloaded from configuration
+    UpdateSettings(Arc<ConnectionSettings>),
+    /// Stop the router, notified once it's stopped
+    Shutdown(flume::Sender<()>),
 }
 
 /// Notification from router to connection
@@ -87,6 +110,10 @@ pub enum Notification {
     },
     /// Shadow
     Shadow(ShadowReply),
+    /// Broker statistics
+    Stats(Stats),
+    /// Connected clients and persistent sessions
+    Sessions(Vec<Session>),
     Unschedule,
     Disconnect(Disconnect, Option<DisconnectProperties>),
 }
@@ -195,6 +222,8 @@ pub struct DataRequest {
     /// Maximum count of payload buffer per replica
     max_count: usize,
     pub(crate) forward_retained: bool,
+    /// Topics of retained messages yet to be forwarded
+    pub(crate) pending_retained: VecDeque<Topic>,
     pub(crate) group: Option<String>,
 }
 
@@ -268,6 +297,76 @@ pub struct ShadowReply {
     pub payload: Bytes,
 }
 
+/// Snapshot of broker statistics
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Stats {
+    pub connections: usize,
+    pub subscriptions: usize,
+    pub retained_messages: usize,
+    /// Outgoing QoS 1 and 2 publishes awaiting acks
+    pub inflight: usize,
+    /// Publishes received since start
+    pub total_publishes: u64,
+    /// Bytes of topics and payloads received since start
+    pub total_publish_bytes: u64,
+    /// Time since start in milliseconds
+    pub uptime_ms: u128,
+    /// Publishes received per topic, limited to `max_tracked_topics` topics
+    pub topics: HashMap<Topic, TopicStats>,
+    /// Publishes received on topics beyond `max_tracked_topics`
+    pub other_topics: TopicStats,
+    /// Usage of tenants with connected clients
+    pub tenants: HashMap<String, TenantUsage>,
+    /// Approximate memory of subscription filters and retained messages
+    pub memory: MemoryUsage,
+}
+
+/// Approximate memory used by the router to hold subscription filters and retained
+/// messages, counted against `RouterConfig::memory_budget`
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryUsage {
+    /// Distinct filters subscribed to, each with its own commitlog
+    pub filters: usize,
+    /// Bytes of filters and their bookkeeping, excluding publishes in their commitlogs
+    pub filter_bytes: usize,
+    pub retained_messages: usize,
+    /// Bytes of topics and payloads of retained messages and their bookkeeping
+    pub retained_bytes: usize,
+}
+
+impl MemoryUsage {
+    pub fn total_bytes(&self) -> usize {
+        self.filter_bytes + self.retained_bytes
+    }
+}
+
+/// Usage of a tenant against its quota
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TenantUsage {
+    pub connections: usize,
+    pub subscriptions: usize,
+    /// Publishes in the current one second window
+    pub publish_rate: u32,
+    pub quota: Option<TenantQuota>,
+}
+
+/// Session of a connected client or a persisted session of a disconnected client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub client_id: String,
+    pub connected: bool,
+    pub clean: bool,
+    pub subscriptions: Vec<Filter>,
+}
+
+/// Publishes received on a topic since start
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopicStats {
+    pub count: u64,
+    /// Bytes of topics and payloads
+    pub bytes: u64,
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct RouterMeter {
     pub timestamp: u128,
@@ -277,11 +376,17 @@ pub struct RouterMeter {
     pub total_subscriptions: usize,
     pub total_publishes: usize,
     pub failed_publishes: usize,
+    pub slow_consumer_disconnections: usize,
+    /// Bytes of publish payloads received
+    pub publish_payload_size: usize,
 }
 
 impl RouterMeter {
     pub fn get(&mut self) -> Option<Self> {
-        if self.total_publishes > 0 || self.failed_publishes > 0 {
+        if self.total_publishes > 0
+            || self.failed_publishes > 0
+            || self.slow_consumer_disconnections > 0
+        {
             self.timestamp = std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
@@ -300,6 +405,8 @@ pub fn get(&mut self) -> Option<Self> {
     fn reset(&mut self) {
         self.total_publishes = 0;
         self.failed_publishes = 0;
+        self.slow_consumer_disconnections = 0;
+        self.publish_payload_size = 0;
     }
 }
 
@@ -410,4 +517,5 @@ pub enum Print {
     Subscriptions,
     Subscription(Filter),
     Waiters(Filter),
+    Tenants,
 }
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Publishes of each tenant within its current one second window, to enforce
+/// publish rate quotas
+#[derive(Default)]
+pub struct PublishRates {
+    windows: HashMap<String, (Instant, u32)>,
+}
+
+impl PublishRates {
+    /// Counts a publish of the tenant unless `max` publishes were already counted
+    /// in the current window, in which case false is returned
+    pub fn allow(&mut self, tenant: &str, max: u32, now: Instant) -> bool {
+        let (start, count) = self.windows.entry(tenant.to_owned()).or_insert((now, 0));
+
+        if now.duration_since(*start) >= WINDOW {
+            *start = now;
+            *count = 0;
+        }
+
+        if *count >= max {
+            return false;
+        }
+
+        *count += 1;
+        true
+    }
+
+    /// Publishes of the tenant counted in the current window
+    pub fn current(&self, tenant: &str, now: Instant) -> u32 {
+        match self.windows.get(tenant) {
+            Some((start, count)) if now.duration_since(*start) < WINDOW => *count,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn publishes_beyond_rate_are_refused_till_next_window() {
+        let mut rates = PublishRates::default();
+        let now = Instant::now();
+
+        assert!(rates.allow("a", 2, now));
+        assert!(rates.allow("a", 2, now));
+        assert!(!rates.allow("a", 2, now));
+        assert!(rates.allow("b", 2, now));
+        assert_eq!(rates.current("a", now), 2);
+
+        let later = now + WINDOW;
+        assert_eq!(rates.current("a", later), 0);
+        assert!(rates.allow("a", 2, later));
+    }
+}
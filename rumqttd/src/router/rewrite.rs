@@ -0,0 +1,71 @@
+use std::fmt::Debug;
+
+/// Maps topics used by a client to the topics used inside the broker and back. The
+/// router rewrites topics of PUBLISH, will messages and filters of SUBSCRIBE and
+/// UNSUBSCRIBE with `ingress`, and topics of publishes forwarded to the client with
+/// `egress`, so `egress` has to undo `ingress` for subscriptions and publishes to align
+pub trait TopicRewrite: Debug + Send + Sync {
+    /// Internal topic or filter for a topic or filter used by the client
+    fn ingress(&self, topic: &str) -> String;
+
+    /// Topic seen by the client for an internal topic
+    fn egress(&self, topic: &str) -> String;
+}
+
+/// Namespaces topics of a client under a prefix, e.g. a tenant id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixRewrite {
+    prefix: String,
+}
+
+impl PrefixRewrite {
+    /// Prefix is joined to topics with a `/`
+    pub fn new(prefix: impl Into<String>) -> PrefixRewrite {
+        let mut prefix = prefix.into();
+        if !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+
+        PrefixRewrite { prefix }
+    }
+}
+
+impl TopicRewrite for PrefixRewrite {
+    fn ingress(&self, topic: &str) -> String {
+        self.prefix.clone() + topic
+    }
+
+    fn egress(&self, topic: &str) -> String {
+        topic.strip_prefix(&self.prefix).unwrap_or(topic).to_owned()
+    }
+}
+
+/// Rewrites filter of a subscription, leaving the group of shared subscriptions as is
+pub(crate) fn ingress_filter(rewrite: &dyn TopicRewrite, filter: &str) -> String {
+    let shared = filter
+        .strip_prefix("$share/")
+        .and_then(|s| s.split_once('/'));
+
+    match shared {
+        Some((group, path)) => format!("$share/{group}/{}", rewrite.ingress(path)),
+        None => rewrite.ingress(filter),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prefix_is_added_on_ingress_and_removed_on_egress() {
+        let rewrite = PrefixRewrite::new("tenant-a");
+
+        assert_eq!(rewrite.ingress("hello/world"), "tenant-a/hello/world");
+        assert_eq!(rewrite.egress("tenant-a/hello/world"), "hello/world");
+        assert_eq!(
+            ingress_filter(&rewrite, "$share/group/hello/+"),
+            "$share/group/tenant-a/hello/+"
+        );
+        assert_eq!(ingress_filter(&rewrite, "#"), "tenant-a/#");
+    }
+}
@@ -1,32 +1,39 @@
 use crate::protocol::{
-    ConnAck, ConnAckProperties, ConnectReturnCode, Disconnect, DisconnectReasonCode, LastWill,
-    LastWillProperties, Packet, PingResp, PubAck, PubAckReason, PubComp, PubCompReason, PubRec,
-    PubRecReason, PubRel, PubRelReason, Publish, PublishProperties, QoS, SubAck,
-    SubscribeReasonCode, UnsubAck, UnsubAckReason,
+    matches, ConnAck, ConnAckProperties, ConnectReturnCode, Disconnect, DisconnectReasonCode,
+    LastWill, LastWillProperties, Packet, PingResp, PubAck, PubAckReason, PubComp, PubCompReason,
+    PubRec, PubRecReason, PubRel, PubRelReason, Publish, PublishProperties, QoS, RetainForwardRule,
+    SubAck, SubscribeReasonCode, UnsubAck, UnsubAckReason,
 };
 use crate::router::alertlog::alert;
 use crate::router::scheduler::{PauseReason, Tracker};
 use crate::router::{ConnectionEvents, Forward};
 use crate::segments::Position;
 use crate::*;
-use flume::{bounded, Receiver, RecvError, Sender, TryRecvError};
+use flume::{bounded, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError};
 use slab::Slab;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
 use std::str::Utf8Error;
 use std::thread;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 use thiserror::Error;
 use tracing::{debug, error, info, trace, warn};
 
 use super::alertlog::{Alert, AlertLog};
+use super::dedup::DedupWindow;
+use super::delayed::{parse_delay, DelayedPublishes};
 use super::graveyard::Graveyard;
 use super::iobufs::{Incoming, Outgoing};
 use super::logs::{AckLog, DataLog};
+use super::quotas::PublishRates;
+use super::rewrite::ingress_filter;
 use super::scheduler::{ScheduleReason, Scheduler};
 use super::shared_subs::SharedGroup;
+use super::wal::WalWriter;
 use super::{
-    packetid, Connection, DataRequest, Event, FilterIdx, Meter, Notification, Print, RouterMeter,
-    ShadowRequest, MAX_CHANNEL_CAPACITY, MAX_SCHEDULE_ITERATIONS,
+    packetid, Ack, Connection, DataRequest, Event, FilterIdx, Meter, Notification, Print,
+    RouterMeter, Session, ShadowRequest, Stats, TenantUsage, TopicStats, MAX_CHANNEL_CAPACITY,
+    MAX_SCHEDULE_ITERATIONS,
 };
 
 #[derive(Error, Debug)]
@@ -55,6 +62,15 @@ pub enum RouterError {
 // TODO: set this to some appropriate value
 const TOPIC_ALIAS_MAX: u16 = 4096;
 
+/// Interval at which connections are checked for being slow consumers
+const SLOW_CONSUMER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval at which expired retained messages are dropped
+const RETAINED_EXPIRY_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default number of topics with individual publish stats
+const DEFAULT_MAX_TRACKED_TOPICS: usize = 1000;
+
 pub struct Router {
     id: RouterId,
     /// Id of this router. Used to index native commitlog to store data from
@@ -102,6 +118,30 @@ pub struct Router {
     shared_subscriptions: HashMap<String, SharedGroup>,
     /// Will messages per client_id
     last_wills: HashMap<String, (LastWill, Option<LastWillProperties>)>,
+    /// Last time connections were checked for being slow consumers
+    last_slow_consumer_check: Instant,
+    /// Last time expired retained messages were dropped
+    last_retained_sweep: Instant,
+    /// Recently received QoS 1 publishes, to drop their resends
+    dedup: Option<DedupWindow>,
+    /// Publishes held back till their delay passes
+    delayed: Option<DelayedPublishes>,
+    /// Publishes of tenants in the current second, for publish rate quotas
+    publish_rates: PublishRates,
+    /// Log of received publishes
+    wal: Option<WalWriter>,
+    /// Notified once the router is stopped on request
+    shutdown: Option<Sender<()>>,
+    /// Time at which router was created
+    start: Instant,
+    /// Publishes received since start
+    total_publishes: u64,
+    /// Bytes of publishes received since start
+    total_publish_bytes: u64,
+    /// Publishes received per topic since start
+    topic_stats: HashMap<Topic, TopicStats>,
+    /// Publishes received on topics which couldn't be tracked individually
+    other_topic_stats: TopicStats,
 }
 
 impl Router {
@@ -121,6 +161,13 @@ pub fn new(router_id: RouterId, config: RouterConfig) -> Router {
         };
 
         let max_connections = config.max_connections;
+        let dedup = config
+            .dedup_window_ms
+            .map(|window| DedupWindow::new(Duration::from_millis(window)));
+        let delayed = config
+            .delayed_publish_prefix
+            .as_deref()
+            .map(|prefix| DelayedPublishes::new(prefix, Instant::now()));
         Router {
             id: router_id,
             config: config.clone(),
@@ -143,7 +190,29 @@ pub fn new(router_id: RouterId, config: RouterConfig) -> Router {
             cache: Some(VecDeque::with_capacity(MAX_CHANNEL_CAPACITY)),
             shared_subscriptions: HashMap::new(),
             last_wills: HashMap::new(),
+            last_slow_consumer_check: Instant::now(),
+            last_retained_sweep: Instant::now(),
+            dedup,
+            delayed,
+            publish_rates: PublishRates::default(),
+            wal: None,
+            shutdown: None,
+            start: Instant::now(),
+            total_publishes: 0,
+            total_publish_bytes: 0,
+            topic_stats: HashMap::new(),
+            other_topic_stats: TopicStats::default(),
+        }
+    }
+
+    /// Opens the write-ahead log of `RouterConfig::wal`, if any. Publishes aren't
+    /// recorded before
+    pub fn open_wal(&mut self) -> io::Result<()> {
+        if let Some(wal) = self.config.wal.clone() {
+            self.wal = Some(WalWriter::spawn(wal)?);
         }
+
+        Ok(())
     }
 
     /// Gets handle to the router. This is not a public method to ensure that link
@@ -166,8 +235,18 @@ pub fn spawn(mut self) -> Sender<(ConnectionId, Event)> {
         let link = self.link();
         router
             .spawn(move || {
-                let e = self.run(0);
-                error!(reason=?e, "Router done!");
+                if let Err(e) = self.run(0) {
+                    error!(reason=?e, "Router done!");
+                    return;
+                }
+
+                let shutdown = self.shutdown.take();
+                // Closes the write-ahead log, writing out pending records
+                drop(self);
+                info!("Router stopped");
+                if let Some(tx) = shutdown {
+                    tx.send(()).ok();
+                }
             })
             .unwrap();
         link
@@ -179,9 +258,11 @@ pub fn spawn(mut self) -> Sender<(ConnectionId, Event)> {
     #[tracing::instrument(skip_all)]
     fn run(&mut self, count: usize) -> Result<(), RouterError> {
         match count {
-            0 => loop {
-                self.run_inner()?;
-            },
+            0 => {
+                while self.shutdown.is_none() {
+                    self.run_inner()?;
+                }
+            }
             n => {
                 for _ in 0..n {
                     self.run_inner()?;
@@ -196,8 +277,15 @@ fn run_inner(&mut self) -> Result<(), RouterError> {
         // Block on incoming events if there are no ready connections for consumption
         if self.consume().is_none() {
             // trace!("{}:: {:20} {:20} {:?}", self.id, "", "done-await", self.readyqueue);
-            let (id, data) = self.router_rx.recv()?;
-            self.events(id, data);
+            // Wake up periodically even without events to check for slow consumers
+            // and expired retained messages
+            match self.router_rx.recv_timeout(SLOW_CONSUMER_CHECK_INTERVAL) {
+                Ok((id, data)) => self.events(id, data),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(RouterError::Recv(RecvError::Disconnected))
+                }
+            }
         }
 
         // Try reading more from connections in a non-blocking
@@ -205,6 +293,11 @@ fn run_inner(&mut self) -> Result<(), RouterError> {
         // Accumulating more data lets requests retrieve bigger
         // bulks which in turn increases efficiency
         for _ in 0..500 {
+            // Events after a shutdown are left unhandled
+            if self.shutdown.is_some() {
+                break;
+            }
+
             // All these methods will handle state and errors
             match self.router_rx.try_recv() {
                 Ok((id, data)) => self.events(id, data),
@@ -227,6 +320,22 @@ fn run_inner(&mut self) -> Result<(), RouterError> {
             self.consume();
         }
 
+        if self.last_slow_consumer_check.elapsed() >= SLOW_CONSUMER_CHECK_INTERVAL {
+            self.disconnect_slow_consumers();
+            self.last_slow_consumer_check = Instant::now();
+        }
+
+        if self.last_retained_sweep.elapsed() >= RETAINED_EXPIRY_SWEEP_INTERVAL {
+            let now = Instant::now();
+            let expired = self.datalog.expire_retained_publishes(now);
+            if expired > 0 {
+                debug!(count = expired, "Dropped expired retained messages");
+            }
+            self.last_retained_sweep = now;
+        }
+
+        self.deliver_delayed_publishes();
+
         // self.send_all_alerts();
         Ok(())
     }
@@ -261,6 +370,15 @@ fn events(&mut self, id: ConnectionId, data: Event) {
                 #[cfg(feature = "validate-tenant-prefix")]
                 _tenant_id,
             ),
+            Event::Stats => self.send_stats(id),
+            Event::ListSessions => self.send_sessions(id),
+            Event::Kick {
+                client_id,
+                clear_session,
+            } => self.kick(client_id, clear_session),
+            Event::ClearSession(client_id) => self.clear_session(client_id),
+            Event::UpdateSettings(settings) => self.update_connection_settings(id, &settings),
+            Event::Shutdown(tx) => self.shutdown = Some(tx),
         }
     }
 
@@ -283,16 +401,59 @@ fn handle_new_connection(
             // Check if same client_id already exists and if so, replace it with this new connection
             // ref: https://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718032
 
-            let connection_id = self.connection_map.get(&client_id);
-            if let Some(connection_id) = connection_id {
+            if let Some(&connection_id) = self.connection_map.get(&client_id) {
+                if connection.reject_duplicate_client_id {
+                    warn!(
+                        "Duplicate client_id, rejecting new connection as connection_id: {} is active",
+                        connection_id
+                    );
+
+                    let ack = ConnAck {
+                        session_present: false,
+                        code: ConnectReturnCode::ClientIdentifierNotValid,
+                    };
+
+                    // Connection isn't registered, so there is no id to assign
+                    let notification = Notification::DeviceAck(Ack::ConnAck(0, ack, None));
+                    outgoing.push_notification(notification);
+                    outgoing.handle.try_send(()).ok();
+                    return;
+                }
+
                 error!(
                     "Duplicate client_id, dropping previous connection with connection_id: {}",
                     connection_id
                 );
-                self.handle_disconnection(*connection_id, None);
+                self.handle_disconnection(
+                    connection_id,
+                    Some(DisconnectReasonCode::SessionTakenOver),
+                );
             }
         }
 
+        if !self.within_connection_quota(&connection) {
+            warn!(
+                tenant = ?connection.tenant_id,
+                "Refusing connection exceeding connection quota of tenant"
+            );
+
+            // MQTT 3.1.1 has no return code for quotas
+            let code = if connection.ack_reasons {
+                ConnectReturnCode::QuotaExceeded
+            } else {
+                ConnectReturnCode::ServiceUnavailable
+            };
+            let ack = ConnAck {
+                session_present: false,
+                code,
+            };
+
+            let notification = Notification::DeviceAck(Ack::ConnAck(0, ack, None));
+            outgoing.push_notification(notification);
+            outgoing.handle.try_send(()).ok();
+            return;
+        }
+
         if self.connections.len() >= self.config.max_connections {
             error!("no space for new connection");
             // let ack = ConnectionAck::Failure("No space for new connection".to_owned());
@@ -347,7 +508,13 @@ fn handle_new_connection(
             connection.events.events.pop_front();
         }
 
-        if let Some(will) = connection.last_will.take() {
+        if let Some(mut will) = connection.last_will.take() {
+            if let Some(rewrite) = &connection.topic_rewrite {
+                if let Ok(topic) = std::str::from_utf8(&will.topic) {
+                    will.topic = rewrite.ingress(topic).into();
+                }
+            }
+
             self.last_wills.insert(
                 client_id.clone(),
                 (will, connection.last_will_properties.take()),
@@ -530,6 +697,354 @@ fn handle_disconnection(&mut self, id: ConnectionId, reason: Option<DisconnectRe
         self.router_meters.total_connections -= 1;
     }
 
+    /// Replies connection with a snapshot of broker statistics
+    fn send_stats(&mut self, id: ConnectionId) {
+        let subscriptions = self
+            .subscription_map
+            .values()
+            .map(|connections| connections.len())
+            .sum();
+        let inflight = self.obufs.iter().map(|(_, o)| o.inflight()).sum();
+
+        let stats = Stats {
+            connections: self.connections.len(),
+            subscriptions,
+            retained_messages: self.datalog.retained_count(),
+            inflight,
+            total_publishes: self.total_publishes,
+            total_publish_bytes: self.total_publish_bytes,
+            uptime_ms: self.start.elapsed().as_millis(),
+            topics: self.topic_stats.clone(),
+            other_topics: self.other_topic_stats.clone(),
+            tenants: self.tenant_usage(),
+            memory: self.datalog.memory(),
+        };
+
+        let Some(outgoing) = self.obufs.get_mut(id) else {
+            error!("no-connection id {} is already gone", id);
+            return;
+        };
+
+        let len = outgoing.push_notification(Notification::Stats(stats));
+        if len >= MAX_CHANNEL_CAPACITY - 1 {
+            outgoing.push_notification(Notification::Unschedule);
+        }
+        outgoing.handle.try_send(()).ok();
+    }
+
+    /// Replies connection with sessions of connected clients and persistent
+    /// sessions of disconnected clients
+    fn send_sessions(&mut self, id: ConnectionId) {
+        let connected = self.connections.iter().map(|(_, connection)| Session {
+            client_id: connection.client_id.clone(),
+            connected: true,
+            clean: connection.clean,
+            subscriptions: connection.subscriptions.iter().cloned().collect(),
+        });
+
+        let persisted = self.graveyard.sessions().map(|(client_id, state)| Session {
+            client_id: client_id.clone(),
+            connected: false,
+            clean: false,
+            subscriptions: state.subscriptions.iter().cloned().collect(),
+        });
+
+        let mut sessions: Vec<Session> = connected.chain(persisted).collect();
+        sessions.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+
+        let Some(outgoing) = self.obufs.get_mut(id) else {
+            error!("no-connection id {} is already gone", id);
+            return;
+        };
+
+        let len = outgoing.push_notification(Notification::Sessions(sessions));
+        if len >= MAX_CHANNEL_CAPACITY - 1 {
+            outgoing.push_notification(Notification::Unschedule);
+        }
+        outgoing.handle.try_send(()).ok();
+    }
+
+    /// Disconnects client with `AdministrativeAction` reason. Saved session state of
+    /// the client is discarded when `clear_session` is set
+    fn kick(&mut self, client_id: String, clear_session: bool) {
+        match self.connection_map.get(&client_id) {
+            Some(&id) => {
+                warn!(client_id, "Kicking client");
+                self.handle_disconnection(id, Some(DisconnectReasonCode::AdministrativeAction));
+            }
+            None => info!(client_id, "Client to kick is not connected"),
+        }
+
+        if clear_session {
+            self.clear_session(client_id);
+        }
+    }
+
+    /// Discards subscriptions and positions in the commitlog saved in the persistent
+    /// session of a disconnected client, so that it doesn't receive publishes sent
+    /// while it was offline. Sessions of connected clients are left as is
+    fn clear_session(&mut self, client_id: String) {
+        if self.connection_map.contains_key(&client_id) {
+            warn!(
+                client_id,
+                "Not clearing session of connected client, kick it instead"
+            );
+            return;
+        }
+
+        info!(client_id, "Clearing session");
+        self.graveyard.clear_session(&client_id);
+    }
+
+    /// Applies reloaded settings to an existing connection
+    fn update_connection_settings(&mut self, id: ConnectionId, settings: &ConnectionSettings) {
+        let Some(connection) = self.connections.get_mut(id) else {
+            return;
+        };
+
+        connection.dynamic_filters = settings.dynamic_filters;
+        connection
+            .topic_limits(settings.max_topic_levels, settings.max_topic_length)
+            .slow_consumer(settings.slow_consumer.clone())
+            .reject_duplicate_client_id(settings.reject_duplicate_client_id);
+        debug!(
+            client_id = connection.client_id,
+            "Applied reloaded connection settings"
+        );
+    }
+
+    fn tenant_quota(&self, tenant: &str) -> Option<&TenantQuota> {
+        self.config.tenant_quotas.as_ref()?.get(tenant)
+    }
+
+    /// Checks if the tenant of a new connection has room for another connection
+    fn within_connection_quota(&self, connection: &Connection) -> bool {
+        let Some(tenant) = connection.tenant_id.as_deref() else {
+            return true;
+        };
+
+        let Some(max) = self.tenant_quota(tenant).and_then(|q| q.max_connections) else {
+            return true;
+        };
+
+        let connections = self
+            .connections
+            .iter()
+            .filter(|(_, c)| c.tenant_id.as_deref() == Some(tenant))
+            .count();
+
+        connections < max
+    }
+
+    /// Checks if subscribing to `filter` keeps the tenant of the connection within its
+    /// subscription quota. Subscriptions of all connected clients of the tenant count
+    fn within_subscription_quota(&self, id: ConnectionId, filter: &str) -> bool {
+        let connection = &self.connections[id];
+        let Some(tenant) = connection.tenant_id.as_deref() else {
+            return true;
+        };
+
+        let Some(max) = self.tenant_quota(tenant).and_then(|q| q.max_subscriptions) else {
+            return true;
+        };
+
+        if connection.subscriptions.contains(filter) {
+            return true;
+        }
+
+        let subscriptions: usize = self
+            .connections
+            .iter()
+            .filter(|(_, c)| c.tenant_id.as_deref() == Some(tenant))
+            .map(|(_, c)| c.subscriptions.len())
+            .sum();
+
+        subscriptions < max
+    }
+
+    /// Counts a publish of the connection against publish rate quota of its tenant
+    fn within_publish_rate_quota(&mut self, id: ConnectionId) -> bool {
+        let Some(tenant) = self.connections[id].tenant_id.as_deref() else {
+            return true;
+        };
+
+        let Some(max) = self.tenant_quota(tenant).and_then(|q| q.max_publish_rate) else {
+            return true;
+        };
+
+        self.publish_rates.allow(tenant, max, Instant::now())
+    }
+
+    /// Usage of tenants with connected clients
+    fn tenant_usage(&self) -> HashMap<String, TenantUsage> {
+        let mut tenants: HashMap<String, TenantUsage> = HashMap::new();
+        for (_, connection) in self.connections.iter() {
+            let Some(tenant) = &connection.tenant_id else {
+                continue;
+            };
+
+            let usage = tenants.entry(tenant.clone()).or_default();
+            usage.connections += 1;
+            usage.subscriptions += connection.subscriptions.len();
+        }
+
+        let now = Instant::now();
+        for (tenant, usage) in tenants.iter_mut() {
+            usage.publish_rate = self.publish_rates.current(tenant, now);
+            usage.quota = self.tenant_quota(tenant).cloned();
+        }
+
+        tenants
+    }
+
+    /// Topic of the publish or of its topic alias. Unknown aliases are left to be
+    /// rejected on append
+    fn publish_topic<'a>(
+        &'a self,
+        id: ConnectionId,
+        publish: &'a Publish,
+        properties: Option<&PublishProperties>,
+    ) -> Option<&'a str> {
+        match properties.and_then(|p| p.topic_alias) {
+            Some(alias) if publish.topic.is_empty() => self.connections[id]
+                .topic_aliases
+                .get(&alias)
+                .map(String::as_str),
+            _ => std::str::from_utf8(&publish.topic).ok(),
+        }
+    }
+
+    /// Smallest of `payload_limits` which the payload exceeds, matched on the topic of the
+    /// publish or of its topic alias
+    fn exceeded_payload_limit(
+        &self,
+        id: ConnectionId,
+        publish: &Publish,
+        properties: Option<&PublishProperties>,
+    ) -> Option<usize> {
+        let limits = self.config.payload_limits.as_ref()?;
+        let topic = self.publish_topic(id, publish, properties)?;
+
+        limits
+            .iter()
+            .filter(|(filter, _)| matches(topic, filter))
+            .map(|(_, max)| *max)
+            .min()
+            .filter(|max| publish.payload.len() > *max)
+    }
+
+    /// Whether the topic of the publish or of its topic alias is within topic limits of
+    /// the connection, without the delay of delayed publishes
+    fn within_topic_limits(
+        &self,
+        id: ConnectionId,
+        publish: &Publish,
+        properties: Option<&PublishProperties>,
+    ) -> bool {
+        let Some(topic) = self.publish_topic(id, publish, properties) else {
+            return true;
+        };
+
+        let topic = match self.delayed.as_ref().and_then(|d| d.strip_prefix(topic)) {
+            Some(rest) => parse_delay(rest).map_or(topic, |(_, topic)| topic),
+            None => topic,
+        };
+
+        self.connections[id].within_topic_limits(topic)
+    }
+
+    /// Whether the publish is a retained message rejected as per `retained_limits`, on
+    /// the topic it's stored on
+    fn exceeds_retained_limits(
+        &self,
+        id: ConnectionId,
+        publish: &Publish,
+        properties: Option<&PublishProperties>,
+    ) -> bool {
+        if !publish.retain || publish.payload.is_empty() {
+            return false;
+        }
+
+        let Some(topic) = self.publish_topic(id, publish, properties) else {
+            return false;
+        };
+
+        match &self.connections[id].topic_rewrite {
+            Some(rewrite) => self
+                .datalog
+                .rejects_retained(&rewrite.ingress(topic), publish),
+            None => self.datalog.rejects_retained(topic, publish),
+        }
+    }
+
+    /// Accounts publish to its topic. Once `max_tracked_topics` are being tracked,
+    /// publishes on new topics are accounted under `other_topic_stats`
+    fn register_publish_stats(&mut self, publish: &Publish) {
+        let bytes = (publish.topic.len() + publish.payload.len()) as u64;
+        self.total_publishes += 1;
+        self.total_publish_bytes += bytes;
+        self.router_meters.publish_payload_size += publish.payload.len();
+
+        let max_tracked_topics = self
+            .config
+            .max_tracked_topics
+            .unwrap_or(DEFAULT_MAX_TRACKED_TOPICS);
+
+        let topic = String::from_utf8_lossy(&publish.topic);
+        let tracked = self.topic_stats.contains_key(topic.as_ref())
+            || self.topic_stats.len() < max_tracked_topics;
+        let stats = if tracked {
+            self.topic_stats.entry(topic.into_owned()).or_default()
+        } else {
+            &mut self.other_topic_stats
+        };
+
+        stats.count += 1;
+        stats.bytes += bytes;
+    }
+
+    /// Disconnects connections which have exceeded their pending publish thresholds
+    /// for longer than allowed duration
+    fn disconnect_slow_consumers(&mut self) {
+        let now = Instant::now();
+        let mut slow_consumers = Vec::new();
+
+        for (id, connection) in self.connections.iter_mut() {
+            let Some(settings) = &connection.slow_consumer else {
+                continue;
+            };
+
+            let Some(outgoing) = self.obufs.get(id) else {
+                continue;
+            };
+
+            let (count, size) = outgoing.pending();
+            let exceeded = settings.max_pending_count.is_some_and(|max| count > max)
+                || settings.max_pending_bytes.is_some_and(|max| size > max);
+
+            if !exceeded {
+                connection.slow_since = None;
+                continue;
+            }
+
+            let slow_since = *connection.slow_since.get_or_insert(now);
+            if now.duration_since(slow_since) >= Duration::from_millis(settings.max_duration_ms) {
+                warn!(
+                    client_id = connection.client_id,
+                    pending_count = count,
+                    pending_size = size,
+                    "Disconnecting slow consumer"
+                );
+                slow_consumers.push(id);
+            }
+        }
+
+        for id in slow_consumers {
+            self.router_meters.slow_consumer_disconnections += 1;
+            self.handle_disconnection(id, Some(DisconnectReasonCode::QuotaExceeded));
+        }
+    }
+
     /// Handles new incoming data on a topic
     fn handle_device_payload(&mut self, id: ConnectionId) {
         // TODO: Retun errors and move error handling to the caller
@@ -564,6 +1079,80 @@ fn handle_device_payload(&mut self, id: ConnectionId) {
                     let qos = publish.qos;
                     let pkid = publish.pkid;
 
+                    self.register_publish_stats(&publish);
+
+                    // MQTT 3.1.1 clients are disconnected when appending to the commitlog
+                    if self.connections[id].ack_reasons
+                        && !self.within_topic_limits(id, &publish, properties.as_ref())
+                    {
+                        warn!("Rejecting publish exceeding topic limits");
+                        self.router_meters.failed_publishes += 1;
+                        let ackslog = self.ackslog.get_mut(id).unwrap();
+                        match qos {
+                            QoS::AtMostOnce => {}
+                            QoS::AtLeastOnce => ackslog.puback(PubAck {
+                                pkid,
+                                reason: PubAckReason::TopicNameInvalid,
+                            }),
+                            QoS::ExactlyOnce => ackslog.reject_pubrec(PubRec {
+                                pkid,
+                                reason: PubRecReason::TopicNameInvalid,
+                            }),
+                        }
+
+                        force_ack = true;
+                        continue;
+                    }
+
+                    let rejection = if let Some(max) =
+                        self.exceeded_payload_limit(id, &publish, properties.as_ref())
+                    {
+                        warn!(
+                            size = publish.payload.len(),
+                            max, "Rejecting publish exceeding payload limit"
+                        );
+                        Some((
+                            PubAckReason::ImplementationSpecificError,
+                            DisconnectReasonCode::PacketTooLarge,
+                        ))
+                    } else if !self.within_publish_rate_quota(id) {
+                        warn!("Rejecting publish exceeding publish rate quota of tenant");
+                        Some((
+                            PubAckReason::QuotaExceeded,
+                            DisconnectReasonCode::QuotaExceeded,
+                        ))
+                    } else if self.exceeds_retained_limits(id, &publish, properties.as_ref()) {
+                        warn!("Rejecting retained publish exceeding retained limits");
+                        Some((
+                            PubAckReason::QuotaExceeded,
+                            DisconnectReasonCode::QuotaExceeded,
+                        ))
+                    } else {
+                        None
+                    };
+
+                    if let Some((reason, disconnect_code)) = rejection {
+                        self.router_meters.failed_publishes += 1;
+                        match qos {
+                            QoS::AtMostOnce => continue,
+                            QoS::AtLeastOnce => {
+                                let puback = PubAck { pkid, reason };
+
+                                let ackslog = self.ackslog.get_mut(id).unwrap();
+                                ackslog.puback(puback);
+                                force_ack = true;
+                                continue;
+                            }
+                            // A failed PUBREC ends the flow for v5 clients, but v4 clients
+                            // send PUBREL anyway, which can't be told apart here
+                            QoS::ExactlyOnce => {
+                                disconnect = true;
+                                disconnect_reason = Some(disconnect_code);
+                                break;
+                            }
+                        }
+                    }
+
                     // Prepare acks for the above publish
                     // If any of the publish in the batch results in force flush,
                     // set global force flush flag. Force flush is triggered when the
@@ -588,15 +1177,44 @@ fn handle_device_payload(&mut self, id: ConnectionId) {
                             let ackslog = self.ackslog.get_mut(id).unwrap();
                             ackslog.puback(puback);
                             force_ack = true;
+
+                            if let Some(dedup) = &mut self.dedup {
+                                let client_id = &self.connections[id].client_id;
+                                if dedup.is_duplicate(client_id, pkid, publish.dup, Instant::now())
+                                {
+                                    debug!("Dropping resent publish already received");
+                                    continue;
+                                }
+                            }
                         }
                         QoS::ExactlyOnce => {
-                            let pubrec = PubRec {
-                                pkid,
-                                reason: PubRecReason::Success,
-                            };
-
+                            let connection = &self.connections[id];
                             let ackslog = self.ackslog.get_mut(id).unwrap();
-                            ackslog.pubrec(publish, properties, pubrec);
+                            let exceeded = connection
+                                .max_incoming_qos2_flows
+                                .is_some_and(|max| ackslog.incoming_qos2_flows() >= max);
+
+                            if exceeded && connection.ack_reasons {
+                                warn!("Rejecting publish exceeding concurrent QoS 2 flows");
+                                self.router_meters.failed_publishes += 1;
+                                let pubrec = PubRec {
+                                    pkid,
+                                    reason: PubRecReason::QuotaExceeded,
+                                };
+                                ackslog.reject_pubrec(pubrec);
+                            } else if exceeded {
+                                // MQTT 3.1.1 clients can't be told, they are slowed down
+                                // by holding back the PUBREC till an earlier flow completes
+                                debug!("Holding back PUBREC past concurrent QoS 2 flows");
+                                ackslog.withhold_pubrec(publish, properties);
+                            } else {
+                                let pubrec = PubRec {
+                                    pkid,
+                                    reason: PubRecReason::Success,
+                                };
+                                ackslog.pubrec(publish, properties, pubrec);
+                            }
+
                             force_ack = true;
                             continue;
                         }
@@ -615,6 +1233,8 @@ fn handle_device_payload(&mut self, id: ConnectionId) {
                         &mut self.datalog,
                         &mut self.notifications,
                         &mut self.connections,
+                        self.delayed.as_mut(),
+                        self.wal.as_mut(),
                     ) {
                         Ok(_offset) => {
                             // Even if one of the data in the batch is appended to commitlog,
@@ -673,6 +1293,24 @@ fn handle_device_payload(&mut self, id: ConnectionId) {
                             filter = filter_path;
                         };
 
+                        if !connection.within_topic_limits(&filter) {
+                            warn!("Subscription filter {} exceeds topic limits", f.path);
+                            // MQTT 3.1.1 has no return code for invalid filters
+                            if connection.ack_reasons {
+                                return_codes.push(SubscribeReasonCode::TopicFilterInvalid);
+                                continue;
+                            }
+
+                            disconnect = true;
+                            disconnect_reason = Some(DisconnectReasonCode::TopicFilterInvalid);
+                            break;
+                        }
+
+                        if let Some(rewrite) = &connection.topic_rewrite {
+                            f.path = ingress_filter(rewrite.as_ref(), &f.path);
+                            filter = rewrite.ingress(&filter);
+                        }
+
                         let subscription_id = props.as_ref().and_then(|p| p.id);
 
                         if subscription_id == Some(0) {
@@ -682,6 +1320,27 @@ fn handle_device_payload(&mut self, id: ConnectionId) {
                             break;
                         }
 
+                        if !connection.within_subscription_limit(&f.path) {
+                            warn!("Subscription on {} exceeds subscription limit", f.path);
+                            return_codes.push(SubscribeReasonCode::QuotaExceeded);
+                            continue;
+                        }
+
+                        if !self.within_subscription_quota(id, &f.path) {
+                            warn!(
+                                "Subscription on {} exceeds subscription quota of tenant",
+                                f.path
+                            );
+                            return_codes.push(SubscribeReasonCode::QuotaExceeded);
+                            continue;
+                        }
+
+                        if !self.datalog.filter_within_memory_budget(&filter) {
+                            warn!("Subscription on {} exceeds memory budget", f.path);
+                            return_codes.push(SubscribeReasonCode::QuotaExceeded);
+                            continue;
+                        }
+
                         let (idx, cursor) = self.datalog.next_native_offset(&filter);
 
                         // in case of shared sub original_filter will be $share/group/topic
@@ -710,7 +1369,13 @@ fn handle_device_payload(&mut self, id: ConnectionId) {
                 Packet::Unsubscribe(unsubscribe, _) => {
                     let connection = self.connections.get_mut(id).unwrap();
                     let pkid = unsubscribe.pkid;
+                    let rewrite = connection.topic_rewrite.clone();
                     for filter in &unsubscribe.filters {
+                        let filter = &match &rewrite {
+                            Some(rewrite) => ingress_filter(rewrite.as_ref(), filter),
+                            None => filter.to_owned(),
+                        };
+
                         let span = tracing::info_span!("unsubscribe", topic = filter, pkid);
                         let _guard = span.enter();
 
@@ -826,6 +1491,8 @@ fn handle_device_payload(&mut self, id: ConnectionId) {
                         &mut self.datalog,
                         &mut self.notifications,
                         &mut self.connections,
+                        self.delayed.as_mut(),
+                        self.wal.as_mut(),
                     ) {
                         Ok(_offset) => {
                             // Even if one of the data in the batch is appended to commitlog,
@@ -840,6 +1507,11 @@ fn handle_device_payload(&mut self, id: ConnectionId) {
                             );
                             self.router_meters.failed_publishes += 1;
                             disconnect = true;
+
+                            if let RouterError::Disconnect(code) = e {
+                                disconnect_reason = Some(code)
+                            }
+
                             break;
                         }
                     };
@@ -957,10 +1629,10 @@ fn prepare_filter(
                 .insert(filter_path.clone(), subscription_id);
         }
 
-        // check is group is None because retained messages aren't sent
-        // for shared subscriptions
-        // TODO: use retain forward rules
-        let forward_retained = group.is_none();
+        // retained messages aren't sent for shared subscriptions. For the rest,
+        // retain handling option of the subscription decides (MQTT 5, 3.8.3.1)
+        let retain_rule = &filter.retain_forward_rule;
+        let forward_retained = group.is_none() && *retain_rule != RetainForwardRule::Never;
 
         // call to `insert(_)` returns `true` if it didn't contain the filter_path already
         // i.e. its a new subscription
@@ -972,20 +1644,28 @@ fn prepare_filter(
                 cursor,
                 read_count: 0,
                 max_count: 100,
-                // set true for new subscriptions
+                // set true for new subscriptions unless retain handling says otherwise
                 forward_retained,
+                pending_retained: VecDeque::new(),
                 group,
             };
 
             self.scheduler.track(id, request);
             self.scheduler.reschedule(id, ScheduleReason::NewFilter);
             debug_assert!(self.scheduler.check_tracker_duplicates(id).is_none())
+        } else if forward_retained && *retain_rule == RetainForwardRule::OnEverySubscribe {
+            // re-subscription: existing data request continues from its cursor,
+            // but retained messages have to be forwarded again. The request is
+            // either parked in datalog waiting for new data or with the tracker
+            if let Some(mut request) = self.datalog.remove_waiters_for_id(id, filter_path) {
+                request.forward_retained = true;
+                self.scheduler.track(id, request);
+                self.scheduler.reschedule(id, ScheduleReason::NewFilter);
+            } else if self.scheduler.forward_retained(id, filter_path) {
+                self.scheduler.reschedule(id, ScheduleReason::NewFilter);
+            }
         }
 
-        // TODO: figure out how we can update existing DataRequest
-        // helpful in re-subscriptions and forwarding retained messages on
-        // every subscribe
-
         let meter = &mut self.ibufs.get_mut(id).unwrap().meter;
         meter.register_subscription(filter_path.clone());
     }
@@ -1083,6 +1763,10 @@ fn consume(&mut self) -> Option<()> {
                 ConsumeStatus::SkipRequest => {
                     skipped_requests.push_back(request);
                 }
+                ConsumeStatus::RetainedPending => {
+                    // not read again in this iteration, to pace retained messages
+                    skipped_requests.push_back(request);
+                }
             }
         }
 
@@ -1149,6 +1833,35 @@ pub fn handle_last_will(
         };
     }
 
+    /// Appends delayed publishes which are due like wills, as they aren't tied to the
+    /// connection which sent them anymore
+    fn deliver_delayed_publishes(&mut self) {
+        let Some(delayed) = &mut self.delayed else {
+            return;
+        };
+
+        for (publish, properties) in delayed.advance(Instant::now()) {
+            match append_will_message(
+                publish,
+                properties,
+                &mut self.datalog,
+                &mut self.notifications,
+                // Tenant prefix was validated when the publish was received
+                #[cfg(feature = "validate-tenant-prefix")]
+                None,
+            ) {
+                Ok(_offset) => {
+                    while let Some((id, request)) = self.notifications.pop_front() {
+                        self.scheduler.track(id, request);
+                        self.scheduler.reschedule(id, ScheduleReason::FreshData);
+                    }
+                }
+                // Nobody subscribed to the topic
+                Err(e) => debug!(reason = ?e, "Dropped delayed publish"),
+            }
+        }
+    }
+
     fn send_meters(&mut self) {
         let mut meters = Vec::with_capacity(10);
         if let Some(router_meter) = self.router_meters.get() {
@@ -1184,6 +1897,7 @@ fn send_alerts(&mut self) {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn append_to_commitlog(
     id: ConnectionId,
     mut publish: Publish,
@@ -1191,6 +1905,8 @@ fn append_to_commitlog(
     datalog: &mut DataLog,
     notifications: &mut VecDeque<(ConnectionId, DataRequest)>,
     connections: &mut Slab<Connection>,
+    delayed: Option<&mut DelayedPublishes>,
+    wal: Option<&mut WalWriter>,
 ) -> Result<Offset, RouterError> {
     let connection = connections.get_mut(id).unwrap();
 
@@ -1216,6 +1932,32 @@ fn append_to_commitlog(
 
     let topic = std::str::from_utf8(&publish.topic)?;
 
+    // Delayed publishes are checked and rewritten like any other publish to their topic
+    let delay = match delayed.as_ref().and_then(|d| d.strip_prefix(topic)) {
+        Some(rest) => {
+            let Some((delay, topic)) = parse_delay(rest) else {
+                error!("Invalid delayed publish topic {topic}");
+                return Err(RouterError::Disconnect(
+                    DisconnectReasonCode::TopicNameInvalid,
+                ));
+            };
+
+            let topic = topic.to_owned();
+            publish.topic = topic.into();
+            Some(delay)
+        }
+        None => None,
+    };
+
+    let topic = std::str::from_utf8(&publish.topic)?;
+
+    if !connection.within_topic_limits(topic) {
+        error!("Topic {topic} exceeds topic limits");
+        return Err(RouterError::Disconnect(
+            DisconnectReasonCode::TopicNameInvalid,
+        ));
+    }
+
     // Ensure that only clients associated with a tenant can publish to tenant's topic
     #[cfg(feature = "validate-tenant-prefix")]
     if let Some(tenant_prefix) = &connection.tenant_prefix {
@@ -1227,6 +1969,23 @@ fn append_to_commitlog(
         }
     }
 
+    if let Some(rewrite) = &connection.topic_rewrite {
+        publish.topic = rewrite.ingress(topic).into();
+    }
+
+    let topic = std::str::from_utf8(&publish.topic)?;
+
+    // Recorded on the topic it's delivered on, delayed publishes without their delay
+    if let Some(wal) = wal.filter(|_| connection.record_publishes) {
+        wal.append(&publish);
+    }
+
+    if let (Some(delay), Some(delayed)) = (delay, delayed) {
+        delayed.schedule(delay, publish, properties, Instant::now());
+        debug!(?delay, pending = delayed.pending(), "Delayed publish");
+        return Ok((0, 0));
+    }
+
     if publish.payload.is_empty() {
         datalog.remove_from_retained_publishes(topic.to_owned());
     } else if publish.retain {
@@ -1406,6 +2165,9 @@ enum ConsumeStatus {
     /// Use to indicate we want to skip the datareqest
     /// for shared subscriptions
     SkipRequest,
+    /// A chunk of retained messages has been forwarded, rest are
+    /// forwarded when the connection is scheduled next
+    RetainedPending,
 }
 
 /// Sweep datalog from offset in DataRequest and updates DataRequest
@@ -1462,19 +2224,32 @@ fn forward_device_data(
     let mut publishes = Vec::new();
 
     if request.forward_retained {
-        // NOTE: ideally we want to limit the number of read messages
-        // and skip the messages previously read while reading next time.
-        // but for now, we just try to read all messages and drop the excess ones
-        let mut retained_publishes = datalog.read_retained_messages(&request.filter);
-        retained_publishes.truncate(inflight_slots as usize);
-
-        publishes.extend(retained_publishes.into_iter().map(|p| (p, None)));
-        inflight_slots -= publishes.len() as u64;
+        request.pending_retained = datalog.retained_topics(&request.filter);
 
         // we only want to forward retained messages once
         request.forward_retained = false;
     }
 
+    // Retained messages are forwarded in chunks, within free inflight slots. The rest
+    // wait for the next schedule, so that filters matching many of them don't starve
+    // live publishes and other connections
+    let chunk_size = datalog
+        .config
+        .retained_chunk_size
+        .map_or(usize::MAX, |size| size.max(1))
+        .min(inflight_slots as usize);
+    while publishes.len() < chunk_size {
+        let Some(topic) = request.pending_retained.pop_front() else {
+            break;
+        };
+
+        // message might have been removed or expired meanwhile
+        if let Some(publish) = datalog.read_retained_message(&topic) {
+            publishes.push((publish, None));
+        }
+    }
+    inflight_slots -= publishes.len() as u64;
+
     let (next, publishes_from_datalog) =
         match datalog.native_readv(request.filter_idx, request.cursor, inflight_slots) {
             Ok(v) => v,
@@ -1541,21 +2316,9 @@ fn forward_device_data(
         return ConsumeStatus::FilterCaughtup;
     }
 
-    let broker_topic_aliases = &mut connection.broker_topic_aliases;
-    let mut topic_alias = broker_topic_aliases
-        .as_ref()
-        .and_then(|aliases| aliases.get_alias(&request.filter));
-
-    let topic_alias_already_exists = topic_alias.is_some();
-
-    // if topic alias doesn't exists, try creating new one!
-    if !topic_alias_already_exists {
-        topic_alias = broker_topic_aliases
-            .as_mut()
-            .and_then(|broker_aliases| broker_aliases.set_new_alias(&request.filter))
-    }
-
+    let mut broker_topic_aliases = connection.broker_topic_aliases.as_mut();
     let subscription_id = connection.subscription_ids.get(&request.filter);
+    let topic_rewrite = &connection.topic_rewrite;
 
     // Fill and notify device data
     let forwards = publishes
@@ -1563,16 +2326,29 @@ fn forward_device_data(
         .map(|((mut publish, mut properties), offset)| {
             publish.qos = protocol::qos(qos).unwrap();
 
-            // if there is some topic alias to use, set it in publish properties
-            if topic_alias.is_some() {
-                let mut props = properties.unwrap_or_default();
-                props.topic_alias = topic_alias;
-                properties = Some(props);
+            if let Some(rewrite) = topic_rewrite {
+                if let Ok(topic) = std::str::from_utf8(&publish.topic) {
+                    publish.topic = rewrite.egress(topic).into();
+                }
             }
 
-            // We want to clear topic if we are using an existing alias
-            if topic_alias_already_exists {
-                publish.topic.clear()
+            // Aliases are per topic, as topics matching a wildcard filter differ. Topic is
+            // cleared when using an existing alias, new ones are sent along with the topic
+            if let Some(aliases) = broker_topic_aliases.as_mut() {
+                let topic = std::str::from_utf8(&publish.topic).unwrap_or_default();
+                let topic_alias = match aliases.get_alias(topic) {
+                    Some(alias) => {
+                        publish.topic.clear();
+                        Some(alias)
+                    }
+                    None => aliases.set_new_alias(topic),
+                };
+
+                if topic_alias.is_some() {
+                    let mut props = properties.unwrap_or_default();
+                    props.topic_alias = topic_alias;
+                    properties = Some(props);
+                }
             }
 
             if let Some(&subscription_id) = subscription_id {
@@ -1618,7 +2394,9 @@ fn forward_device_data(
         share.cursor = request.cursor;
     }
 
-    if caughtup {
+    if !request.pending_retained.is_empty() {
+        ConsumeStatus::RetainedPending
+    } else if caughtup {
         ConsumeStatus::FilterCaughtup
     } else {
         ConsumeStatus::PartialRead
@@ -1713,6 +2491,10 @@ fn print_status(router: &mut Router, metrics: Print) {
             let metrics = router.scheduler.readyqueue.clone();
             println!("{metrics:#?}");
         }
+        Print::Tenants => {
+            let metrics = router.tenant_usage();
+            println!("{metrics:#?}");
+        }
     };
 }
 
@@ -1756,6 +2538,1183 @@ fn extract_group(filter: &str) -> Option<(String, String)> {
             .map(|(group, path)| (group.to_string(), path.to_string()))
     })
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::link::local::{LinkBuilder, LinkError, LinkRx, LinkTx};
+    use crate::protocol::{Filter, Subscribe, Unsubscribe};
+    use crate::router::Ack;
+
+    fn router() -> Sender<(ConnectionId, Event)> {
+        let config = RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 10,
+            max_segment_count: 10,
+            ..RouterConfig::default()
+        };
+
+        Router::new(0, config).spawn()
+    }
+
+    fn link(client_id: &str, router_tx: Sender<(ConnectionId, Event)>) -> (LinkTx, LinkRx) {
+        let (tx, rx, _) = LinkBuilder::new(client_id, router_tx).build().unwrap();
+        (tx, rx)
+    }
+
+    fn retained_publish(topic: &str) -> Packet {
+        let publish = Publish {
+            dup: false,
+            qos: QoS::AtMostOnce,
+            retain: true,
+            topic: topic.to_owned().into(),
+            pkid: 0,
+            payload: vec![1, 2, 3].into(),
+        };
+
+        Packet::Publish(publish, None)
+    }
+
+    fn subscribe(filter: &str, retain_forward_rule: RetainForwardRule) -> Packet {
+        let filters = vec![Filter {
+            path: filter.to_owned(),
+            qos: QoS::AtMostOnce,
+            nolocal: false,
+            preserve_retain: false,
+            retain_forward_rule,
+        }];
+
+        Packet::Subscribe(Subscribe { pkid: 1, filters }, None)
+    }
+
+    /// Collects notifications till no new notification is received for a while
+    /// and returns the number of suback and retained publishes among them
+    fn suback_and_retained_count(rx: &mut LinkRx) -> (usize, usize) {
+        let (mut subacks, mut retained) = (0, 0);
+        while let Ok(notification) = rx.recv_deadline(Instant::now() + Duration::from_millis(200)) {
+            match notification {
+                Some(Notification::DeviceAck(Ack::SubAck(_))) => subacks += 1,
+                Some(Notification::Forward(forward)) if forward.publish.retain => retained += 1,
+                _ => {}
+            }
+        }
+
+        (subacks, retained)
+    }
+
+    /// Publishes a retained message and then subscribes twice to the same
+    /// filter with given rule. Returns retained forwards after each subscribe
+    async fn retained_on_subscriptions(rule: RetainForwardRule) -> (usize, usize) {
+        let router_tx = router();
+        let (mut publisher, _publisher_rx) = link("publisher", router_tx.clone());
+        let (mut subscriber, mut subscriber_rx) = link("subscriber", router_tx);
+
+        publisher
+            .send(retained_publish("hello/world"))
+            .await
+            .unwrap();
+
+        subscriber
+            .send(subscribe("hello/world", rule.clone()))
+            .await
+            .unwrap();
+        let (subacks, first) = suback_and_retained_count(&mut subscriber_rx);
+        assert_eq!(subacks, 1);
+
+        subscriber
+            .send(subscribe("hello/world", rule))
+            .await
+            .unwrap();
+        let (subacks, second) = suback_and_retained_count(&mut subscriber_rx);
+        assert_eq!(subacks, 1);
+
+        (first, second)
+    }
+
+    #[tokio::test]
+    async fn retained_messages_are_forwarded_on_every_subscribe() {
+        let retained = retained_on_subscriptions(RetainForwardRule::OnEverySubscribe).await;
+        assert_eq!(retained, (1, 1));
+    }
+
+    #[tokio::test]
+    async fn retained_messages_are_forwarded_only_on_new_subscribe() {
+        let retained = retained_on_subscriptions(RetainForwardRule::OnNewSubscribe).await;
+        assert_eq!(retained, (1, 0));
+    }
+
+    #[tokio::test]
+    async fn retained_messages_are_forwarded_in_chunks_between_live_publishes() {
+        let config = RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 10,
+            max_segment_count: 10,
+            retained_chunk_size: Some(10),
+            ..RouterConfig::default()
+        };
+        let router_tx = Router::new(0, config).spawn();
+        let (mut publisher, _publisher_rx) = link("publisher", router_tx.clone());
+        let (mut subscriber, mut subscriber_rx) = link("subscriber", router_tx);
+
+        for i in 0..500 {
+            publisher
+                .send(retained_publish(&format!("retained/{i}")))
+                .await
+                .unwrap();
+        }
+
+        subscriber
+            .send(subscribe("#", RetainForwardRule::OnEverySubscribe))
+            .await
+            .unwrap();
+        let mut live = retained_publish("live");
+        if let Packet::Publish(publish, _) = &mut live {
+            publish.retain = false;
+        }
+
+        // more than fit the outgoing buffer, none are dropped and the live
+        // publish isn't held back till all of them are forwarded
+        let mut live = Some(live);
+        let mut topics = Vec::new();
+        let deadline = || Instant::now() + Duration::from_millis(200);
+        while let Ok(notification) = subscriber_rx.recv_deadline(deadline()) {
+            match notification {
+                Some(Notification::Forward(forward)) => {
+                    topics.push(String::from_utf8(forward.publish.topic.to_vec()).unwrap());
+                    // published once the subscription is in place
+                    if let Some(live) = live.take() {
+                        publisher.send(live).await.unwrap();
+                    }
+                }
+                // like remote links, ask for more once the outgoing buffer is drained
+                Some(Notification::Unschedule) => subscriber_rx.ready().unwrap(),
+                _ => {}
+            }
+        }
+
+        assert_eq!(topics.len(), 501);
+        let live = topics.iter().position(|topic| topic == "live").unwrap();
+        assert!(live < 500, "live publish forwarded after retained messages");
+    }
+
+    #[tokio::test]
+    async fn retained_messages_are_never_forwarded() {
+        let retained = retained_on_subscriptions(RetainForwardRule::Never).await;
+        assert_eq!(retained, (0, 0));
+    }
+
+    #[tokio::test]
+    async fn retain_flag_is_set_only_on_retained_messages_forwarded_on_subscribe() {
+        let router_tx = router();
+        let (mut publisher, _publisher_rx) = link("publisher", router_tx.clone());
+        let (mut subscriber, mut subscriber_rx) = link("subscriber", router_tx);
+
+        publisher
+            .send(retained_publish("hello/world"))
+            .await
+            .unwrap();
+        subscriber
+            .send(subscribe("hello/world", RetainForwardRule::OnNewSubscribe))
+            .await
+            .unwrap();
+        assert_eq!(suback_and_retained_count(&mut subscriber_rx), (1, 1));
+
+        // Live publishes are forwarded to existing subscriptions without retain,
+        // even when they are retained
+        publisher
+            .send(retained_publish("hello/world"))
+            .await
+            .unwrap();
+        let forward = subscriber_rx
+            .recv_deadline(Instant::now() + Duration::from_secs(1))
+            .unwrap();
+        assert!(matches!(
+            forward,
+            Some(Notification::Forward(forward)) if !forward.publish.retain
+        ));
+    }
+
+    fn disconnect_reason(rx: &mut LinkRx) -> Option<DisconnectReasonCode> {
+        while let Ok(notification) = rx.recv_deadline(Instant::now() + Duration::from_millis(200)) {
+            if let Some(Notification::Disconnect(disconnect, _)) = notification {
+                return Some(disconnect.reason_code);
+            }
+        }
+
+        None
+    }
+
+    #[tokio::test]
+    async fn publish_exceeding_topic_levels_disconnects() {
+        let router_tx = router();
+        let (mut tx, mut rx, _) = LinkBuilder::new("publisher", router_tx)
+            .dynamic_filters(true)
+            .max_topic_levels(Some(2))
+            .build()
+            .unwrap();
+
+        tx.send(retained_publish("hello/world")).await.unwrap();
+        assert_eq!(disconnect_reason(&mut rx), None);
+
+        tx.send(retained_publish("hello/world/1")).await.unwrap();
+        assert_eq!(
+            disconnect_reason(&mut rx),
+            Some(DisconnectReasonCode::TopicNameInvalid)
+        );
+    }
+
+    #[tokio::test]
+    async fn publish_exceeding_topic_levels_is_rejected_with_reason() {
+        let router_tx = router();
+        let (mut tx, mut rx, _) = LinkBuilder::new("publisher", router_tx)
+            .dynamic_filters(true)
+            .max_topic_levels(Some(1))
+            .ack_reasons(true)
+            .build()
+            .unwrap();
+
+        tx.send(qos1_publish(1, false)).await.unwrap();
+        assert_eq!(
+            puback_reasons(&mut rx),
+            vec![PubAckReason::TopicNameInvalid]
+        );
+
+        // MQTT 5 clients stay connected
+        tx.send(retained_publish("hello")).await.unwrap();
+        assert_eq!(disconnect_reason(&mut rx), None);
+    }
+
+    #[tokio::test]
+    async fn slow_consumer_is_disconnected() {
+        let router_tx = router();
+        let (mut publisher, _publisher_rx) = link("publisher", router_tx.clone());
+        let settings = SlowConsumerSettings {
+            max_pending_count: Some(5),
+            max_pending_bytes: None,
+            max_duration_ms: 0,
+        };
+        let (mut tx, mut rx, _) = LinkBuilder::new("subscriber", router_tx)
+            .slow_consumer(Some(settings))
+            .build()
+            .unwrap();
+
+        tx.send(subscribe("hello/world", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        assert_eq!(suback_and_retained_count(&mut rx), (1, 0));
+
+        for _ in 0..10 {
+            publisher.publish("hello/world", vec![1, 2, 3]).unwrap();
+        }
+
+        // don't read notifications so that they pile up till the next check
+        std::thread::sleep(SLOW_CONSUMER_CHECK_INTERVAL * 2);
+        assert_eq!(
+            disconnect_reason(&mut rx),
+            Some(DisconnectReasonCode::QuotaExceeded)
+        );
+    }
+
+    #[tokio::test]
+    async fn blocked_subscriber_does_not_stall_other_subscribers() {
+        const COUNT: usize = 500;
+
+        let router_tx = router();
+        let (mut publisher, _publisher_rx) = link("publisher", router_tx.clone());
+        let settings = SlowConsumerSettings {
+            max_pending_count: Some(100),
+            max_pending_bytes: None,
+            max_duration_ms: 0,
+        };
+        let (mut blocked, mut blocked_rx, _) = LinkBuilder::new("blocked", router_tx.clone())
+            .slow_consumer(Some(settings))
+            .build()
+            .unwrap();
+        let (mut fast, mut fast_rx) = link("fast", router_tx);
+
+        for (tx, rx) in [(&mut blocked, &mut blocked_rx), (&mut fast, &mut fast_rx)] {
+            tx.send(subscribe("hello/world", RetainForwardRule::Never))
+                .await
+                .unwrap();
+            assert_eq!(suback_and_retained_count(rx), (1, 0));
+        }
+
+        // blocked subscriber never reads, so its buffer fills up well before the end
+        for _ in 0..COUNT {
+            publisher.publish("hello/world", vec![1, 2, 3]).unwrap();
+        }
+
+        let mut received = 0;
+        while received < COUNT {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            match fast_rx.recv_deadline(deadline).unwrap() {
+                Some(Notification::Forward(_)) => received += 1,
+                Some(Notification::Unschedule) => fast_rx.ready().unwrap(),
+                _ => {}
+            }
+        }
+
+        std::thread::sleep(SLOW_CONSUMER_CHECK_INTERVAL * 2);
+        assert_eq!(
+            disconnect_reason(&mut blocked_rx),
+            Some(DisconnectReasonCode::QuotaExceeded)
+        );
+    }
+
+    #[tokio::test]
+    async fn stats_are_sent_on_request() {
+        let router_tx = router();
+        let (mut publisher, _publisher_rx) = link("publisher", router_tx.clone());
+        let (mut tx, mut rx) = link("subscriber", router_tx);
+
+        tx.send(subscribe("hello/world", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        assert_eq!(suback_and_retained_count(&mut rx), (1, 0));
+
+        publisher
+            .send(retained_publish("hello/world"))
+            .await
+            .unwrap();
+        tx.stats().unwrap();
+
+        let stats = loop {
+            match rx.recv_deadline(Instant::now() + Duration::from_secs(1)) {
+                Ok(Some(Notification::Stats(stats))) => break stats,
+                Ok(_) => continue,
+                Err(e) => panic!("Stats not received: {e}"),
+            }
+        };
+
+        assert_eq!(stats.connections, 2);
+        assert_eq!(stats.subscriptions, 1);
+        assert_eq!(stats.retained_messages, 1);
+        assert_eq!(stats.memory.retained_messages, 1);
+        assert!(stats.memory.retained_bytes > 0);
+        assert_eq!(stats.inflight, 0);
+        assert_eq!(stats.total_publishes, 1);
+        assert_eq!(stats.total_publish_bytes, 14);
+    }
+
+    #[tokio::test]
+    async fn topic_stats_beyond_max_tracked_topics_are_aggregated() {
+        let config = RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 10,
+            max_segment_count: 10,
+            max_tracked_topics: Some(1),
+            ..RouterConfig::default()
+        };
+        let router_tx = Router::new(0, config).spawn();
+        let (mut tx, mut rx) = link("publisher", router_tx);
+
+        tx.send(retained_publish("hello/world")).await.unwrap();
+        tx.send(retained_publish("hello/world")).await.unwrap();
+        tx.send(retained_publish("hello/other")).await.unwrap();
+        tx.stats().unwrap();
+
+        let stats = loop {
+            match rx.recv_deadline(Instant::now() + Duration::from_secs(1)) {
+                Ok(Some(Notification::Stats(stats))) => break stats,
+                Ok(_) => continue,
+                Err(e) => panic!("Stats not received: {e}"),
+            }
+        };
+
+        assert_eq!(stats.topics.len(), 1);
+        assert_eq!(
+            stats.topics["hello/world"],
+            TopicStats {
+                count: 2,
+                bytes: 28
+            }
+        );
+        assert_eq!(
+            stats.other_topics,
+            TopicStats {
+                count: 1,
+                bytes: 14
+            }
+        );
+    }
+
+    #[cfg(not(feature = "allow-duplicate-clientid"))]
+    #[tokio::test]
+    async fn duplicate_client_id_takes_over_previous_connection() {
+        let router_tx = router();
+        let (_tx, mut rx) = link("client", router_tx.clone());
+        let (_new_tx, mut new_rx) = link("client", router_tx);
+
+        assert_eq!(
+            disconnect_reason(&mut rx),
+            Some(DisconnectReasonCode::SessionTakenOver)
+        );
+        assert_eq!(disconnect_reason(&mut new_rx), None);
+    }
+
+    #[cfg(not(feature = "allow-duplicate-clientid"))]
+    #[tokio::test]
+    async fn duplicate_client_id_is_rejected_when_configured() {
+        let router_tx = router();
+        let (_tx, mut rx) = link("client", router_tx.clone());
+
+        let rejected = LinkBuilder::new("client", router_tx)
+            .reject_duplicate_client_id(true)
+            .build();
+
+        assert!(matches!(
+            rejected,
+            Err(LinkError::ConnectionRefused(
+                ConnectReturnCode::ClientIdentifierNotValid
+            ))
+        ));
+        assert_eq!(disconnect_reason(&mut rx), None);
+    }
+
+    #[cfg(feature = "allow-duplicate-clientid")]
+    #[tokio::test]
+    async fn duplicate_client_ids_are_allowed() {
+        let router_tx = router();
+        let (_tx, mut rx) = link("client", router_tx.clone());
+
+        let (_new_tx, mut new_rx, _) = LinkBuilder::new("client", router_tx)
+            .reject_duplicate_client_id(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(disconnect_reason(&mut rx), None);
+        assert_eq!(disconnect_reason(&mut new_rx), None);
+    }
+
+    /// Topics of publishes forwarded till nothing is received for a while
+    fn forwarded_topics(rx: &mut LinkRx) -> Vec<String> {
+        let mut topics = Vec::new();
+        while let Ok(notification) = rx.recv_deadline(Instant::now() + Duration::from_millis(200)) {
+            if let Some(Notification::Forward(forward)) = notification {
+                topics.push(String::from_utf8(forward.publish.topic.to_vec()).unwrap());
+            }
+        }
+
+        topics
+    }
+
+    #[tokio::test]
+    async fn topics_are_rewritten_on_ingress_and_egress() {
+        let router_tx = router();
+        let tenant_link = |client_id| {
+            let rewrite: Arc<dyn TopicRewrite> = Arc::new(PrefixRewrite::new("tenant-a"));
+            let (tx, rx, _) = LinkBuilder::new(client_id, router_tx.clone())
+                .topic_rewrite(Some(rewrite))
+                .build()
+                .unwrap();
+            (tx, rx)
+        };
+
+        let (mut publisher, _publisher_rx) = tenant_link("publisher");
+        let (mut subscriber, mut subscriber_rx) = tenant_link("subscriber");
+        let (mut observer, mut observer_rx) = link("observer", router_tx.clone());
+
+        subscriber
+            .send(subscribe("hello/#", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        observer
+            .send(subscribe("tenant-a/#", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        forwarded_topics(&mut subscriber_rx);
+        forwarded_topics(&mut observer_rx);
+
+        publisher
+            .send(retained_publish("hello/world"))
+            .await
+            .unwrap();
+
+        assert_eq!(forwarded_topics(&mut subscriber_rx), vec!["hello/world"]);
+        assert_eq!(
+            forwarded_topics(&mut observer_rx),
+            vec!["tenant-a/hello/world"]
+        );
+    }
+
+    #[tokio::test]
+    async fn delayed_publish_is_delivered_after_its_delay() {
+        let config = RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 10,
+            max_segment_count: 10,
+            delayed_publish_prefix: Some("$delayed".to_owned()),
+            ..RouterConfig::default()
+        };
+        let router_tx = Router::new(0, config).spawn();
+
+        let (mut subscriber, mut subscriber_rx) = link("subscriber", router_tx.clone());
+        subscriber
+            .send(subscribe("hello/world", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        forwarded_topics(&mut subscriber_rx);
+
+        let (mut publisher, _publisher_rx) = link("publisher", router_tx);
+        let start = Instant::now();
+        publisher.publish("$delayed/2/hello/world", "data").unwrap();
+
+        let mut topics = Vec::new();
+        while topics.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            topics = forwarded_topics(&mut subscriber_rx);
+        }
+
+        assert_eq!(topics, vec!["hello/world"]);
+        assert!(start.elapsed() >= Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn topic_aliases_are_resolved_and_assigned_per_topic() {
+        let router_tx = router();
+        let (mut subscriber, mut subscriber_rx, _) =
+            LinkBuilder::new("subscriber", router_tx.clone())
+                .topic_alias_max(10)
+                .build()
+                .unwrap();
+        subscriber
+            .send(subscribe("hello/+", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        forwarded_topics(&mut subscriber_rx);
+
+        let (mut publisher, _publisher_rx) = link("publisher", router_tx);
+        let aliased = |topic: &str, alias| {
+            let publish = Publish::new(topic.to_owned(), "data".to_owned(), false);
+            let properties = PublishProperties {
+                topic_alias: Some(alias),
+                ..Default::default()
+            };
+            Packet::Publish(publish, Some(properties))
+        };
+
+        // alias is set up along with the topic and then used without it
+        publisher.send(aliased("hello/world", 1)).await.unwrap();
+        publisher.send(aliased("", 1)).await.unwrap();
+        publisher.send(aliased("hello/other", 2)).await.unwrap();
+        publisher.send(aliased("", 1)).await.unwrap();
+
+        let mut forwards = Vec::new();
+        while let Ok(notification) =
+            subscriber_rx.recv_deadline(Instant::now() + Duration::from_millis(200))
+        {
+            if let Some(Notification::Forward(forward)) = notification {
+                let topic = String::from_utf8(forward.publish.topic.to_vec()).unwrap();
+                let alias = forward.properties.and_then(|p| p.topic_alias);
+                forwards.push((topic, alias));
+            }
+        }
+
+        // topics matching the same filter get aliases of their own
+        assert_eq!(
+            forwards,
+            vec![
+                ("hello/world".to_owned(), Some(1)),
+                ("".to_owned(), Some(1)),
+                ("hello/other".to_owned(), Some(2)),
+                ("".to_owned(), Some(1)),
+            ]
+        );
+    }
+
+    fn recv_sessions(rx: &mut LinkRx) -> Vec<Session> {
+        loop {
+            match rx.recv_deadline(Instant::now() + Duration::from_secs(1)) {
+                Ok(Some(Notification::Sessions(sessions))) => break sessions,
+                Ok(_) => continue,
+                Err(e) => panic!("Sessions not received: {e}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn kicked_client_is_disconnected_and_session_cleared() {
+        let router_tx = router();
+        let (mut admin, mut admin_rx) = link("admin", router_tx.clone());
+        let (mut tx, mut rx, _) = LinkBuilder::new("client", router_tx)
+            .clean_session(false)
+            .build()
+            .unwrap();
+
+        tx.send(subscribe("hello/world", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        assert_eq!(suback_and_retained_count(&mut rx), (1, 0));
+
+        admin.kick("client", false).unwrap();
+        assert_eq!(
+            disconnect_reason(&mut rx),
+            Some(DisconnectReasonCode::AdministrativeAction)
+        );
+
+        admin.list_sessions().unwrap();
+        let sessions = recv_sessions(&mut admin_rx);
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(
+            sessions[1],
+            Session {
+                client_id: "client".to_owned(),
+                connected: false,
+                clean: false,
+                subscriptions: vec!["hello/world".to_owned()],
+            }
+        );
+
+        admin.kick("client", true).unwrap();
+        admin.list_sessions().unwrap();
+        let sessions = recv_sessions(&mut admin_rx);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].client_id, "admin");
+        assert!(sessions[0].connected);
+    }
+
+    #[tokio::test]
+    async fn cleared_offline_session_gets_no_publishes_sent_while_offline() {
+        let router_tx = router();
+        let (mut admin, mut admin_rx) = link("admin", router_tx.clone());
+        let (mut publisher, _publisher_rx) = link("publisher", router_tx.clone());
+        let persistent = || {
+            LinkBuilder::new("client", router_tx.clone())
+                .clean_session(false)
+                .build()
+                .unwrap()
+        };
+
+        let (mut tx, mut rx, _) = persistent();
+        tx.send(subscribe("hello/world", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        assert_eq!(suback_and_retained_count(&mut rx), (1, 0));
+
+        // connected clients are to be kicked to clear their sessions
+        admin.clear_session("client").unwrap();
+        admin.kick("client", false).unwrap();
+        assert_eq!(
+            disconnect_reason(&mut rx),
+            Some(DisconnectReasonCode::AdministrativeAction)
+        );
+
+        publisher.publish("hello/world", vec![1, 2, 3]).unwrap();
+        admin.clear_session("client").unwrap();
+        admin.list_sessions().unwrap();
+        let sessions = recv_sessions(&mut admin_rx);
+        assert!(sessions.iter().all(|session| session.client_id != "client"));
+
+        let (_tx, mut rx, _) = persistent();
+        assert!(forwarded_topics(&mut rx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn publishes_of_a_topic_are_forwarded_in_order() {
+        const PUBLISHERS: usize = 4;
+        const COUNT: u32 = 5000;
+
+        // Large enough commitlog that a lagging subscriber never loses data
+        let config = RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 1024,
+            max_segment_count: 10,
+            ..RouterConfig::default()
+        };
+        let router_tx = Router::new(0, config).spawn();
+        let (mut tx, mut rx) = link("subscriber", router_tx.clone());
+
+        let mut filter = subscribe("ordering/+", RetainForwardRule::Never);
+        if let Packet::Subscribe(subscribe, _) = &mut filter {
+            subscribe.filters[0].qos = QoS::AtLeastOnce;
+        }
+        tx.send(filter).await.unwrap();
+        assert_eq!(suback_and_retained_count(&mut rx), (1, 0));
+
+        for i in 0..PUBLISHERS {
+            let router_tx = router_tx.clone();
+            std::thread::spawn(move || {
+                let client_id = format!("publisher-{i}");
+                let (mut publisher, _publisher_rx) = link(&client_id, router_tx);
+                for sequence in 0..COUNT {
+                    let topic = format!("ordering/{i}");
+                    publisher
+                        .publish(topic, sequence.to_be_bytes().to_vec())
+                        .unwrap();
+                }
+            });
+        }
+
+        let mut next = [0u32; PUBLISHERS];
+        while next.iter().any(|&n| n < COUNT) {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let forward = match rx.recv_deadline(deadline).unwrap() {
+                Some(Notification::Forward(forward)) => forward,
+                Some(Notification::Unschedule) => {
+                    rx.ready().unwrap();
+                    continue;
+                }
+                _ => continue,
+            };
+
+            let publish = forward.publish;
+            let topic = std::str::from_utf8(&publish.topic).unwrap();
+            let publisher: usize = topic.strip_prefix("ordering/").unwrap().parse().unwrap();
+            let sequence = u32::from_be_bytes(publish.payload[..].try_into().unwrap());
+            assert_eq!(sequence, next[publisher], "out of order on {topic}");
+            next[publisher] += 1;
+
+            let puback = PubAck {
+                pkid: publish.pkid,
+                reason: PubAckReason::Success,
+            };
+            tx.send(Packet::PubAck(puback, None)).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_exceeding_topic_length_disconnects() {
+        let router_tx = router();
+        let (mut tx, mut rx, _) = LinkBuilder::new("subscriber", router_tx)
+            .max_topic_length(Some(11))
+            .build()
+            .unwrap();
+
+        let rule = RetainForwardRule::Never;
+        tx.send(subscribe("hello/world", rule.clone()))
+            .await
+            .unwrap();
+        assert_eq!(suback_and_retained_count(&mut rx), (1, 0));
+
+        tx.send(subscribe("hello/world/+", rule)).await.unwrap();
+        assert_eq!(
+            disconnect_reason(&mut rx),
+            Some(DisconnectReasonCode::TopicFilterInvalid)
+        );
+    }
+
+    #[tokio::test]
+    async fn subscribe_exceeding_topic_length_is_rejected_with_reason() {
+        let router_tx = router();
+        let (mut tx, mut rx, _) = LinkBuilder::new("subscriber", router_tx)
+            .max_topic_length(Some(11))
+            .ack_reasons(true)
+            .build()
+            .unwrap();
+
+        tx.send(subscribe("hello/world/+", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        assert_eq!(
+            suback_codes(&mut rx),
+            vec![SubscribeReasonCode::TopicFilterInvalid]
+        );
+        assert_eq!(disconnect_reason(&mut rx), None);
+    }
+
+    fn suback_codes(rx: &mut LinkRx) -> Vec<SubscribeReasonCode> {
+        loop {
+            match rx.recv_deadline(Instant::now() + Duration::from_secs(1)) {
+                Ok(Some(Notification::DeviceAck(Ack::SubAck(suback)))) => {
+                    return suback.return_codes
+                }
+                Ok(_) => continue,
+                Err(e) => panic!("Suback not received: {e}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn subscriptions_past_limit_are_rejected() {
+        let router_tx = router();
+        let (mut tx, mut rx, _) = LinkBuilder::new("subscriber", router_tx)
+            .max_subscriptions(Some(2))
+            .build()
+            .unwrap();
+
+        let subscribe_to = |filter| subscribe(filter, RetainForwardRule::Never);
+        for filter in ["hello/+", "world/#"] {
+            tx.send(subscribe_to(filter)).await.unwrap();
+            assert_eq!(suback_codes(&mut rx), vec![SubscribeReasonCode::QoS0]);
+        }
+
+        tx.send(subscribe_to("limit/#")).await.unwrap();
+        assert_eq!(
+            suback_codes(&mut rx),
+            vec![SubscribeReasonCode::QuotaExceeded]
+        );
+
+        // subscribing again to an active filter doesn't count
+        tx.send(subscribe_to("hello/+")).await.unwrap();
+        assert_eq!(suback_codes(&mut rx), vec![SubscribeReasonCode::QoS0]);
+
+        let unsubscribe = Unsubscribe {
+            pkid: 2,
+            filters: vec!["world/#".to_owned()],
+        };
+        tx.send(Packet::Unsubscribe(unsubscribe, None))
+            .await
+            .unwrap();
+        tx.send(subscribe_to("limit/#")).await.unwrap();
+        assert_eq!(suback_codes(&mut rx), vec![SubscribeReasonCode::QoS0]);
+    }
+
+    fn qos1_publish(pkid: u16, dup: bool) -> Packet {
+        let publish = Publish {
+            dup,
+            qos: QoS::AtLeastOnce,
+            retain: false,
+            topic: "hello/world".into(),
+            pkid,
+            payload: vec![1, 2, 3].into(),
+        };
+
+        Packet::Publish(publish, None)
+    }
+
+    fn puback_count(rx: &mut LinkRx) -> usize {
+        let mut pubacks = 0;
+        while let Ok(notification) = rx.recv_deadline(Instant::now() + Duration::from_millis(200)) {
+            if let Some(Notification::DeviceAck(Ack::PubAck(_))) = notification {
+                pubacks += 1;
+            }
+        }
+
+        pubacks
+    }
+
+    #[tokio::test]
+    async fn resent_publish_within_dedup_window_is_not_delivered_again() {
+        let config = RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 10,
+            max_segment_count: 10,
+            dedup_window_ms: Some(60_000),
+            ..RouterConfig::default()
+        };
+        let router_tx = Router::new(0, config).spawn();
+
+        let (mut subscriber, mut subscriber_rx) = link("subscriber", router_tx.clone());
+        subscriber
+            .send(subscribe("hello/world", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        assert_eq!(suback_and_retained_count(&mut subscriber_rx), (1, 0));
+
+        let (mut publisher, mut publisher_rx) = link("publisher", router_tx.clone());
+        publisher.send(qos1_publish(1, false)).await.unwrap();
+        assert_eq!(puback_count(&mut publisher_rx), 1);
+        assert_eq!(forwarded_topics(&mut subscriber_rx).len(), 1);
+
+        // client reconnects and resends the publish as it missed the puback
+        let (mut publisher, mut publisher_rx) = link("publisher", router_tx);
+        publisher.send(qos1_publish(1, true)).await.unwrap();
+        assert_eq!(puback_count(&mut publisher_rx), 1);
+        assert!(forwarded_topics(&mut subscriber_rx).is_empty());
+
+        // packet id reused for a new publish
+        publisher.send(qos1_publish(1, false)).await.unwrap();
+        assert_eq!(puback_count(&mut publisher_rx), 1);
+        assert_eq!(forwarded_topics(&mut subscriber_rx).len(), 1);
+    }
+
+    fn puback_reasons(rx: &mut LinkRx) -> Vec<PubAckReason> {
+        let mut reasons = Vec::new();
+        while let Ok(notification) = rx.recv_deadline(Instant::now() + Duration::from_millis(200)) {
+            if let Some(Notification::DeviceAck(Ack::PubAck(puback))) = notification {
+                reasons.push(puback.reason);
+            }
+        }
+
+        reasons
+    }
+
+    #[tokio::test]
+    async fn publishes_exceeding_payload_limit_of_topic_are_rejected() {
+        let config = RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 10,
+            max_segment_count: 10,
+            payload_limits: Some(HashMap::from([("control/#".to_owned(), 16)])),
+            ..RouterConfig::default()
+        };
+        let router_tx = Router::new(0, config).spawn();
+
+        let (mut subscriber, mut subscriber_rx) = link("subscriber", router_tx.clone());
+        subscriber
+            .send(subscribe("#", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        forwarded_topics(&mut subscriber_rx);
+
+        let (mut publisher, mut publisher_rx) = link("publisher", router_tx);
+        let publish = |pkid, topic: &str, size| {
+            let publish = Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                retain: false,
+                topic: topic.to_owned().into(),
+                pkid,
+                payload: vec![0; size].into(),
+            };
+            Packet::Publish(publish, None)
+        };
+
+        publisher
+            .send(publish(1, "control/reboot", 1024))
+            .await
+            .unwrap();
+        assert_eq!(
+            puback_reasons(&mut publisher_rx),
+            vec![PubAckReason::ImplementationSpecificError]
+        );
+        assert!(forwarded_topics(&mut subscriber_rx).is_empty());
+
+        publisher
+            .send(publish(2, "control/reboot", 16))
+            .await
+            .unwrap();
+        publisher
+            .send(publish(3, "data/upload", 1024))
+            .await
+            .unwrap();
+        assert_eq!(
+            puback_reasons(&mut publisher_rx),
+            vec![PubAckReason::Success, PubAckReason::Success]
+        );
+        assert_eq!(
+            forwarded_topics(&mut subscriber_rx),
+            vec!["control/reboot", "data/upload"]
+        );
+    }
+
+    #[tokio::test]
+    async fn retained_publishes_past_limit_are_rejected() {
+        let config = RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 10,
+            max_segment_count: 10,
+            retained_limits: Some(crate::RetainedLimits {
+                max_messages: Some(1),
+                max_bytes: None,
+                policy: crate::RetainedLimitPolicy::Reject,
+            }),
+            ..RouterConfig::default()
+        };
+        let router_tx = Router::new(0, config).spawn();
+        let (mut publisher, mut publisher_rx) = link("publisher", router_tx);
+        let publish = |pkid, topic: &str| {
+            let publish = Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                retain: true,
+                topic: topic.to_owned().into(),
+                pkid,
+                payload: vec![1, 2, 3].into(),
+            };
+            Packet::Publish(publish, None)
+        };
+
+        publisher.send(publish(1, "retained/a")).await.unwrap();
+        publisher.send(publish(2, "retained/b")).await.unwrap();
+        // replacing the retained message of a topic doesn't count
+        publisher.send(publish(3, "retained/a")).await.unwrap();
+        assert_eq!(
+            puback_reasons(&mut publisher_rx),
+            vec![
+                PubAckReason::Success,
+                PubAckReason::QuotaExceeded,
+                PubAckReason::Success
+            ]
+        );
+    }
+
+    fn pubrecs(rx: &mut LinkRx) -> Vec<(u16, PubRecReason)> {
+        let mut pubrecs = Vec::new();
+        while let Ok(notification) = rx.recv_deadline(Instant::now() + Duration::from_millis(200)) {
+            if let Some(Notification::DeviceAck(Ack::PubRec(pubrec))) = notification {
+                pubrecs.push((pubrec.pkid, pubrec.reason));
+            }
+        }
+
+        pubrecs
+    }
+
+    #[tokio::test]
+    async fn concurrent_qos2_flows_of_client_are_limited() {
+        let config = RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 10,
+            max_segment_count: 10,
+            ..RouterConfig::default()
+        };
+        let router_tx = Router::new(0, config).spawn();
+        let limited_link = |client_id, ack_reasons| {
+            LinkBuilder::new(client_id, router_tx.clone())
+                .dynamic_filters(true)
+                .max_incoming_qos2_flows(Some(2))
+                .ack_reasons(ack_reasons)
+                .build()
+                .unwrap()
+        };
+        let publish = |pkid| {
+            let publish = Publish {
+                dup: false,
+                qos: QoS::ExactlyOnce,
+                retain: false,
+                topic: "hello/world".into(),
+                pkid,
+                payload: vec![1, 2, 3].into(),
+            };
+            Packet::Publish(publish, None)
+        };
+        let pubrel = |pkid| {
+            let pubrel = PubRel {
+                pkid,
+                reason: PubRelReason::Success,
+            };
+            Packet::PubRel(pubrel, None)
+        };
+
+        // MQTT 5 clients are told about publishes past the limit
+        let (mut v5, mut v5_rx, _) = limited_link("v5", true);
+        for pkid in 1..=4 {
+            v5.send(publish(pkid)).await.unwrap();
+        }
+        assert_eq!(
+            pubrecs(&mut v5_rx),
+            vec![
+                (1, PubRecReason::Success),
+                (2, PubRecReason::Success),
+                (3, PubRecReason::QuotaExceeded),
+                (4, PubRecReason::QuotaExceeded)
+            ]
+        );
+
+        v5.send(pubrel(1)).await.unwrap();
+        v5.send(publish(5)).await.unwrap();
+        assert_eq!(pubrecs(&mut v5_rx), vec![(5, PubRecReason::Success)]);
+
+        // MQTT 3.1.1 clients get PUBRECs past the limit as earlier flows complete
+        let (mut v4, mut v4_rx, _) = limited_link("v4", false);
+        for pkid in 1..=4 {
+            v4.send(publish(pkid)).await.unwrap();
+        }
+        assert_eq!(
+            pubrecs(&mut v4_rx),
+            vec![(1, PubRecReason::Success), (2, PubRecReason::Success)]
+        );
+
+        v4.send(pubrel(1)).await.unwrap();
+        assert_eq!(pubrecs(&mut v4_rx), vec![(3, PubRecReason::Success)]);
+        v4.send(pubrel(2)).await.unwrap();
+        v4.send(pubrel(3)).await.unwrap();
+        assert_eq!(pubrecs(&mut v4_rx), vec![(4, PubRecReason::Success)]);
+    }
+
+    #[tokio::test]
+    async fn tenant_quotas_are_shared_by_connections_of_tenant() {
+        let quota = TenantQuota {
+            max_connections: Some(2),
+            max_subscriptions: Some(1),
+            max_publish_rate: Some(2),
+        };
+        let config = RouterConfig {
+            max_connections: 10,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 1024 * 10,
+            max_segment_count: 10,
+            tenant_quotas: Some(TenantQuotas {
+                default: None,
+                tenants: HashMap::from([("acme".to_owned(), quota)]),
+            }),
+            ..RouterConfig::default()
+        };
+        let router_tx = Router::new(0, config).spawn();
+        let tenant_link = |client_id, ack_reasons| {
+            LinkBuilder::new(client_id, router_tx.clone())
+                .tenant_id(Some("acme".to_owned()))
+                .ack_reasons(ack_reasons)
+        };
+
+        let (mut first, mut first_rx, _) = tenant_link("first", true).build().unwrap();
+        let (mut second, mut second_rx, _) = tenant_link("second", true).build().unwrap();
+        assert!(matches!(
+            tenant_link("third", true).build(),
+            Err(LinkError::ConnectionRefused(
+                ConnectReturnCode::QuotaExceeded
+            ))
+        ));
+        // MQTT 3.1.1 clients are refused with a return code they know
+        assert!(matches!(
+            tenant_link("third", false).build(),
+            Err(LinkError::ConnectionRefused(
+                ConnectReturnCode::ServiceUnavailable
+            ))
+        ));
+        // clients without the tenant aren't limited
+        let (_other, _other_rx) = link("third", router_tx.clone());
+
+        // filters and topics are within the tenant prefix, validated with `validate-tenant-prefix`
+        first
+            .send(subscribe("/tenants/acme/a", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        assert_eq!(suback_codes(&mut first_rx), vec![SubscribeReasonCode::QoS0]);
+        second
+            .send(subscribe("/tenants/acme/b", RetainForwardRule::Never))
+            .await
+            .unwrap();
+        assert_eq!(
+            suback_codes(&mut second_rx),
+            vec![SubscribeReasonCode::QuotaExceeded]
+        );
+
+        for pkid in 1..=3 {
+            let publish = Publish {
+                dup: false,
+                qos: QoS::AtLeastOnce,
+                retain: false,
+                topic: "/tenants/acme/hello".into(),
+                pkid,
+                payload: vec![1, 2, 3].into(),
+            };
+            second.send(Packet::Publish(publish, None)).await.unwrap();
+        }
+        assert_eq!(
+            puback_reasons(&mut second_rx),
+            vec![
+                PubAckReason::Success,
+                PubAckReason::Success,
+                PubAckReason::QuotaExceeded
+            ]
+        );
+
+        first.stats().unwrap();
+        let tenants = loop {
+            match first_rx.recv_deadline(Instant::now() + Duration::from_secs(1)) {
+                Ok(Some(Notification::Stats(stats))) => break stats.tenants,
+                Ok(_) => continue,
+                Err(e) => panic!("Stats not received: {e}"),
+            }
+        };
+        let usage = &tenants["acme"];
+        assert_eq!((usage.connections, usage.subscriptions), (2, 1));
+        assert_eq!(tenants.len(), 1);
+    }
+}
+
 // #[cfg(test)]
 // #[allow(non_snake_case)]
 // mod test {
@@ -54,6 +54,13 @@ pub fn untrack(&mut self, id: ConnectionId, filter: &Filter) {
         tracker.unregister_data_request(filter.clone());
     }
 
+    /// Marks an existing subscription of this connection to forward retained
+    /// messages again on its next read. Returns `false` if the filter isn't tracked
+    pub fn forward_retained(&mut self, id: ConnectionId, filter: &Filter) -> bool {
+        let tracker = self.trackers.get_mut(id).unwrap();
+        tracker.forward_retained(filter)
+    }
+
     pub fn trackv(&mut self, id: ConnectionId, requests: VecDeque<DataRequest>) {
         let tracker = self.trackers.get_mut(id).unwrap();
         tracker.data_requests.extend(requests);
@@ -192,6 +199,20 @@ pub fn register_data_request(&mut self, request: DataRequest) {
         self.data_requests.push_back(request);
     }
 
+    pub fn forward_retained(&mut self, filter: &Filter) -> bool {
+        match self
+            .data_requests
+            .iter_mut()
+            .find(|data_req| &data_req.filter == filter)
+        {
+            Some(request) => {
+                request.forward_retained = true;
+                true
+            }
+            None => false,
+        }
+    }
+
     pub fn unregister_data_request(&mut self, filter: Filter) {
         self.data_requests
             .retain(|data_req| data_req.filter != filter);
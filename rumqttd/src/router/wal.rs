@@ -0,0 +1,672 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use flume::{Receiver, Sender, TrySendError};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use tracing::{error, warn};
+
+use crate::protocol::{qos, Publish, QoS};
+use crate::WalConfig;
+
+/// Records waiting to be written beyond which new records are dropped
+const MAX_PENDING_RECORDS: usize = 10_000;
+const EXTENSION: &str = "wal";
+/// Sequence, timestamp, flags and topic length
+const HEADER_LEN: usize = 8 + 8 + 1 + 2;
+/// Flags and topic length, encrypted along with topic and payload
+const SEALED_HEADER_LEN: usize = 1 + 2;
+/// Sequence, timestamp and nonce of encrypted records, authenticated but not encrypted
+const ENCRYPTED_HEADER_LEN: usize = 8 + 8 + NONCE_LEN;
+/// Start of encrypted log files, followed by the id of their key
+const ENCRYPTED_MAGIC: &[u8; 8] = b"RWALAES1";
+
+/// AES-256-GCM key of encrypted log files, loaded from a file holding it as 64 hex
+/// characters. Its id, the start of the key's SHA-256, is written in file headers
+/// to tell files of other keys apart
+#[derive(Clone)]
+pub struct WalKey {
+    key: LessSafeKey,
+    id: [u8; 8],
+}
+
+impl WalKey {
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<WalKey> {
+        let hex = fs::read_to_string(path)?;
+        WalKey::from_hex(hex.trim())
+    }
+
+    fn from_hex(hex: &str) -> io::Result<WalKey> {
+        let malformed = || invalid("key isn't 64 hex characters");
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(malformed());
+        }
+
+        let mut bytes = [0; 32];
+        for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).map_err(|_| malformed())?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| malformed())?;
+        }
+
+        let key = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| malformed())?;
+        let mut id = [0; 8];
+        id.copy_from_slice(&digest(&SHA256, &bytes).as_ref()[..8]);
+        Ok(WalKey {
+            key: LessSafeKey::new(key),
+            id,
+        })
+    }
+}
+
+impl std::fmt::Debug for WalKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WalKey").field("id", &self.id).finish()
+    }
+}
+
+/// Publish received by the router. Records are numbered in the order publishes
+/// were received, gaps are records dropped because the log fell behind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalRecord {
+    pub sequence: u64,
+    /// Milliseconds since unix epoch at which the router received the publish
+    pub timestamp_ms: u64,
+    /// QoS with which the publish was received
+    pub qos: QoS,
+    pub publish: Publish,
+}
+
+impl WalRecord {
+    /// Length prefixed record. Qos is in the lower 2 bits of flags, retain in the 3rd
+    fn write(&self, buffer: &mut BytesMut) {
+        let topic = &self.publish.topic;
+        let payload = &self.publish.payload;
+        let flags = self.qos as u8 | (self.publish.retain as u8) << 2;
+
+        buffer.put_u32((HEADER_LEN + topic.len() + payload.len()) as u32);
+        buffer.put_u64(self.sequence);
+        buffer.put_u64(self.timestamp_ms);
+        buffer.put_u8(flags);
+        buffer.put_u16(topic.len() as u16);
+        buffer.put_slice(topic);
+        buffer.put_slice(payload);
+    }
+
+    /// Length prefixed record with flags, topic and payload encrypted with a random
+    /// nonce. Sequence and timestamp stay readable, they're authenticated with the rest
+    fn write_encrypted(&self, key: &WalKey, buffer: &mut BytesMut) -> io::Result<()> {
+        let topic = &self.publish.topic;
+        let payload = &self.publish.payload;
+        let flags = self.qos as u8 | (self.publish.retain as u8) << 2;
+        let sealed_len = SEALED_HEADER_LEN + topic.len() + payload.len() + AES_256_GCM.tag_len();
+
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| io::Error::new(ErrorKind::Other, "failed to generate nonce"))?;
+
+        buffer.put_u32((ENCRYPTED_HEADER_LEN + sealed_len) as u32);
+        buffer.put_u64(self.sequence);
+        buffer.put_u64(self.timestamp_ms);
+        buffer.put_slice(&nonce);
+
+        let mut sealed = buffer.split_off(buffer.len());
+        sealed.put_u8(flags);
+        sealed.put_u16(topic.len() as u16);
+        sealed.put_slice(topic);
+        sealed.put_slice(payload);
+        key.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(&buffer[4..]),
+                &mut sealed,
+            )
+            .map_err(|_| io::Error::new(ErrorKind::Other, "failed to encrypt record"))?;
+
+        buffer.unsplit(sealed);
+        Ok(())
+    }
+
+    fn read(mut record: Bytes) -> io::Result<WalRecord> {
+        if record.len() < HEADER_LEN {
+            return Err(invalid("record shorter than header"));
+        }
+
+        let sequence = record.get_u64();
+        let timestamp_ms = record.get_u64();
+        WalRecord::read_sealed(sequence, timestamp_ms, record)
+    }
+
+    fn read_encrypted(mut record: Bytes, key: &WalKey) -> io::Result<WalRecord> {
+        if record.len() < ENCRYPTED_HEADER_LEN + SEALED_HEADER_LEN + AES_256_GCM.tag_len() {
+            return Err(invalid("record shorter than header"));
+        }
+
+        let header = record.split_to(ENCRYPTED_HEADER_LEN);
+        let mut sealed = record.to_vec();
+        let nonce = Nonce::try_assume_unique_for_key(&header[16..])
+            .map_err(|_| invalid("invalid nonce"))?;
+        let opened = key
+            .key
+            .open_in_place(nonce, Aad::from(&header[..]), &mut sealed)
+            .map_err(|_| invalid("record can't be decrypted"))?
+            .len();
+        sealed.truncate(opened);
+
+        let mut header = header;
+        let sequence = header.get_u64();
+        let timestamp_ms = header.get_u64();
+        WalRecord::read_sealed(sequence, timestamp_ms, sealed.into())
+    }
+
+    /// Reads flags, topic and payload of a record
+    fn read_sealed(sequence: u64, timestamp_ms: u64, mut record: Bytes) -> io::Result<WalRecord> {
+        if record.len() < SEALED_HEADER_LEN {
+            return Err(invalid("record shorter than header"));
+        }
+
+        let flags = record.get_u8();
+        let topic_len = record.get_u16() as usize;
+        if record.len() < topic_len {
+            return Err(invalid("topic longer than record"));
+        }
+
+        let qos = qos(flags & 0b11).ok_or_else(|| invalid("invalid qos"))?;
+        let topic = record.split_to(topic_len);
+        let publish = Publish::new(topic, record, flags & 0b100 != 0);
+
+        Ok(WalRecord {
+            sequence,
+            timestamp_ms,
+            qos,
+            publish,
+        })
+    }
+}
+
+/// Appends publishes to log files from a dedicated thread, so that the router never
+/// waits on disk. Records are dropped when the thread can't keep up
+pub struct WalWriter {
+    tx: Option<Sender<WalRecord>>,
+    handle: Option<JoinHandle<()>>,
+    sequence: u64,
+    dropped: u64,
+}
+
+impl WalWriter {
+    /// Starts writing to a new file in `config.dir`, continuing the sequence of
+    /// records already in it
+    pub fn spawn(config: WalConfig) -> io::Result<WalWriter> {
+        let key = config
+            .key_file
+            .as_ref()
+            .map(WalKey::from_file)
+            .transpose()?;
+        let mut dir = fs::DirBuilder::new();
+        // Records can be plain text, only the broker's user can read them
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut dir, 0o700);
+        dir.recursive(true).create(&config.dir)?;
+
+        let sequence = log_files(&config.dir)?
+            .into_iter()
+            .rev()
+            .find_map(|path| {
+                WalReader::new(vec![path], key.clone())
+                    .filter_map(Result::ok)
+                    .last()
+            })
+            .map_or(0, |record| record.sequence);
+
+        let (tx, rx) = flume::bounded(MAX_PENDING_RECORDS);
+        let mut files = LogFiles {
+            config,
+            key,
+            file: None,
+            size: 0,
+            buffer: BytesMut::new(),
+        };
+
+        let handle = thread::Builder::new()
+            .name("wal".to_owned())
+            .spawn(move || files.run(rx))?;
+
+        Ok(WalWriter {
+            tx: Some(tx),
+            handle: Some(handle),
+            sequence,
+            dropped: 0,
+        })
+    }
+
+    pub fn append(&mut self, publish: &Publish) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_millis() as u64);
+
+        self.sequence += 1;
+        let record = WalRecord {
+            sequence: self.sequence,
+            timestamp_ms,
+            qos: publish.qos,
+            publish: publish.clone(),
+        };
+
+        match tx.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                if self.dropped % 1000 == 1 {
+                    warn!(dropped = self.dropped, "Write-ahead log is falling behind");
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                error!("Write-ahead log stopped, not recording publishes anymore");
+                self.tx = None;
+            }
+        }
+    }
+}
+
+impl Drop for WalWriter {
+    /// Waits for pending records to be written
+    fn drop(&mut self) {
+        self.tx.take();
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+struct LogFiles {
+    config: WalConfig,
+    /// Records are encrypted with this when set
+    key: Option<WalKey>,
+    file: Option<BufWriter<File>>,
+    /// Bytes written to the current file
+    size: u64,
+    buffer: BytesMut,
+}
+
+impl LogFiles {
+    fn run(&mut self, rx: Receiver<WalRecord>) {
+        // Exits once the writer is dropped and pending records are written
+        while let Ok(record) = rx.recv() {
+            if let Err(e) = self.append(&record) {
+                error!(error = ?e, sequence = record.sequence, "Failed to write to write-ahead log");
+                // Retry with a new file
+                self.file = None;
+                continue;
+            }
+
+            // Flush once caught up, bursts are written together
+            if rx.is_empty() {
+                self.flush();
+            }
+        }
+
+        self.flush();
+    }
+
+    fn append(&mut self, record: &WalRecord) -> io::Result<()> {
+        if self.file.is_none() || self.size >= self.config.max_file_size {
+            self.rotate(record.sequence)?;
+        }
+
+        self.buffer.clear();
+        match &self.key {
+            Some(key) => record.write_encrypted(key, &mut self.buffer)?,
+            None => record.write(&mut self.buffer),
+        }
+
+        let file = self.file.as_mut().unwrap();
+        file.write_all(&self.buffer)?;
+        self.size += self.buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Starts a new file named after its first record and deletes the oldest files
+    /// beyond `max_files`
+    fn rotate(&mut self, sequence: u64) -> io::Result<()> {
+        self.flush();
+
+        let path = self.config.dir.join(format!("{sequence:020}.{EXTENSION}"));
+        let mut options = OpenOptions::new();
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = BufWriter::new(options.create(true).append(true).open(path)?);
+        self.size = 0;
+        if let Some(key) = &self.key {
+            file.write_all(ENCRYPTED_MAGIC)?;
+            file.write_all(&key.id)?;
+            self.size = (ENCRYPTED_MAGIC.len() + key.id.len()) as u64;
+        }
+
+        self.file = Some(file);
+
+        let files = log_files(&self.config.dir)?;
+        let excess = files.len().saturating_sub(self.config.max_files.max(1));
+        for path in &files[..excess] {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        if let Some(file) = &mut self.file {
+            if let Err(e) = file.flush() {
+                error!(error = ?e, "Failed to flush write-ahead log");
+            }
+        }
+    }
+}
+
+/// Reads records of log files in a directory, oldest first. A record cut short, e.g.
+/// by a crash while it was being written, ends its file. Encrypted files which can't
+/// be decrypted with the key of the reader are an error and skipped
+pub struct WalReader {
+    files: std::vec::IntoIter<PathBuf>,
+    current: Option<BufReader<File>>,
+    key: Option<WalKey>,
+    /// Whether the current file is encrypted
+    encrypted: bool,
+}
+
+impl WalReader {
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<WalReader> {
+        Ok(WalReader::new(log_files(dir.as_ref())?, None))
+    }
+
+    /// Decrypts encrypted files with `key`, plain text files are still read
+    pub fn decrypt_with(mut self, key: WalKey) -> WalReader {
+        self.key = Some(key);
+        self
+    }
+
+    fn new(files: Vec<PathBuf>, key: Option<WalKey>) -> WalReader {
+        WalReader {
+            files: files.into_iter(),
+            current: None,
+            key,
+            encrypted: false,
+        }
+    }
+
+    /// Opens a file, checking the key of encrypted files
+    fn open_file(&mut self, path: PathBuf) -> io::Result<()> {
+        let mut reader = BufReader::new(File::open(path)?);
+        self.encrypted = reader.fill_buf()?.starts_with(ENCRYPTED_MAGIC);
+        if self.encrypted {
+            let mut header = [0; ENCRYPTED_MAGIC.len() + 8];
+            reader.read_exact(&mut header)?;
+            match &self.key {
+                Some(key) if header[ENCRYPTED_MAGIC.len()..] == key.id => {}
+                Some(_) => return Err(invalid("file is encrypted with another key")),
+                None => return Err(invalid("file is encrypted, no key given")),
+            }
+        }
+
+        self.current = Some(reader);
+        Ok(())
+    }
+}
+
+impl Iterator for WalReader {
+    type Item = io::Result<WalRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.current.is_none() {
+                let path = self.files.next()?;
+                if let Err(e) = self.open_file(path) {
+                    return Some(Err(e));
+                }
+            }
+
+            let reader = self.current.as_mut().unwrap();
+            let key = self.key.as_ref().filter(|_| self.encrypted);
+            match read_record(reader, key) {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => self.current = None,
+                Err(e) => {
+                    self.current = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the next record, `None` at the end of the file or of a partially written record.
+/// Records are decrypted with `key` if set
+fn read_record(reader: &mut impl Read, key: Option<&WalKey>) -> io::Result<Option<WalRecord>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+
+    let mut record = vec![0; u32::from_be_bytes(len) as usize];
+    match reader.read_exact(&mut record) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+
+    match key {
+        Some(key) => WalRecord::read_encrypted(record.into(), key).map(Some),
+        None => WalRecord::read(record.into()).map(Some),
+    }
+}
+
+fn invalid(reason: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, reason)
+}
+
+/// Log files in the directory, oldest first
+fn log_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == EXTENSION) {
+            files.push(path);
+        }
+    }
+
+    // names are zero padded sequences
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config(max_file_size: u64, max_files: usize) -> WalConfig {
+        let dir = std::env::temp_dir().join(format!("rumqttd-wal-{}", uuid::Uuid::new_v4()));
+        WalConfig {
+            dir,
+            max_file_size,
+            max_files,
+            key_file: None,
+        }
+    }
+
+    fn topics(dir: &Path) -> Vec<(u64, String)> {
+        WalReader::open(dir)
+            .unwrap()
+            .map(|record| {
+                let record = record.unwrap();
+                let topic = String::from_utf8(record.publish.topic.to_vec()).unwrap();
+                (record.sequence, topic)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn records_are_read_back_after_restart() {
+        let config = config(1024 * 1024, 10);
+
+        let mut writer = WalWriter::spawn(config.clone()).unwrap();
+        writer.append(&Publish::new("a", "1", true));
+        drop(writer);
+
+        let mut writer = WalWriter::spawn(config.clone()).unwrap();
+        writer.append(&Publish::new("b", "2", false));
+        drop(writer);
+
+        let records: Vec<WalRecord> = WalReader::open(&config.dir)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].publish, Publish::new("a", "1", true));
+        assert_eq!(records[1].publish, Publish::new("b", "2", false));
+        assert_eq!(records[1].sequence, 2);
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn oldest_files_are_deleted_on_rotation() {
+        // every record starts a new file
+        let config = config(1, 2);
+
+        let mut writer = WalWriter::spawn(config.clone()).unwrap();
+        for topic in ["a", "b", "c"] {
+            writer.append(&Publish::new(topic, "payload", false));
+        }
+        drop(writer);
+
+        let expected = vec![(2, "b".to_owned()), (3, "c".to_owned())];
+        assert_eq!(topics(&config.dir), expected);
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn log_files_are_only_accessible_by_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let config = config(1024 * 1024, 10);
+
+        let mut writer = WalWriter::spawn(config.clone()).unwrap();
+        writer.append(&Publish::new("a", "payload", false));
+        drop(writer);
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&config.dir), 0o700);
+        assert_eq!(mode(&log_files(&config.dir).unwrap()[0]), 0o600);
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn encrypted_records_are_read_back_with_key() {
+        let mut config = config(1024 * 1024, 10);
+        let key_file = config.dir.with_extension("key");
+        fs::write(&key_file, format!("{KEY}\n")).unwrap();
+        config.key_file = Some(key_file.clone());
+
+        let mut writer = WalWriter::spawn(config.clone()).unwrap();
+        writer.append(&Publish::new("secret/topic", "secret payload", true));
+        drop(writer);
+
+        // sequence continues after a restart
+        let mut writer = WalWriter::spawn(config.clone()).unwrap();
+        writer.append(&Publish::new("secret/topic", "more", false));
+        drop(writer);
+
+        for path in log_files(&config.dir).unwrap() {
+            let file = fs::read(path).unwrap();
+            assert!(file.starts_with(ENCRYPTED_MAGIC));
+            assert!(!file.windows(6).any(|w| w == b"secret"));
+        }
+
+        let key = WalKey::from_file(&key_file).unwrap();
+        let records: Vec<WalRecord> = WalReader::open(&config.dir)
+            .unwrap()
+            .decrypt_with(key)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].publish,
+            Publish::new("secret/topic", "secret payload", true)
+        );
+        assert_eq!(
+            records[1].publish,
+            Publish::new("secret/topic", "more", false)
+        );
+        assert_eq!(records[1].sequence, 2);
+
+        fs::remove_dir_all(&config.dir).unwrap();
+        fs::remove_file(&key_file).unwrap();
+    }
+
+    #[test]
+    fn encrypted_files_need_their_key() {
+        let mut config = config(1024 * 1024, 10);
+        let key_file = config.dir.with_extension("key");
+        fs::write(&key_file, KEY).unwrap();
+        config.key_file = Some(key_file.clone());
+
+        let mut writer = WalWriter::spawn(config.clone()).unwrap();
+        writer.append(&Publish::new("a", "payload", false));
+        drop(writer);
+
+        let mut records = WalReader::open(&config.dir).unwrap();
+        assert!(records.next().unwrap().is_err());
+        assert!(records.next().is_none());
+
+        let other = WalKey::from_hex(&KEY.replace('0', "f")).unwrap();
+        let mut records = WalReader::open(&config.dir).unwrap().decrypt_with(other);
+        assert!(records.next().unwrap().is_err());
+        assert!(records.next().is_none());
+
+        fs::remove_dir_all(&config.dir).unwrap();
+        fs::remove_file(&key_file).unwrap();
+    }
+
+    #[test]
+    fn writer_fails_to_start_with_invalid_key() {
+        let mut config = config(1024 * 1024, 10);
+        let key_file = config.dir.with_extension("key");
+        fs::write(&key_file, &KEY[..32]).unwrap();
+        config.key_file = Some(key_file.clone());
+
+        assert!(WalWriter::spawn(config.clone()).is_err());
+        assert!(!config.dir.exists());
+
+        fs::remove_file(&key_file).unwrap();
+    }
+
+    #[test]
+    fn partially_written_record_ends_file() {
+        let config = config(1024 * 1024, 10);
+
+        let mut writer = WalWriter::spawn(config.clone()).unwrap();
+        writer.append(&Publish::new("a", "payload", false));
+        drop(writer);
+
+        let path = log_files(&config.dir).unwrap().remove(0);
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(&[0, 0, 0, 100, 1, 2]).unwrap();
+
+        assert_eq!(topics(&config.dir), vec![(1, "a".to_owned())]);
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+}
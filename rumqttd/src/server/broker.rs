@@ -6,13 +6,17 @@
 use crate::local::LinkBuilder;
 use crate::protocol::v4::V4;
 use crate::protocol::v5::V5;
-use crate::protocol::{Packet, Protocol};
+use crate::protocol::{Packet, PingReq, Protocol};
+use crate::router::{WalKey, WalReader};
+use crate::server::listener::{Listeners, RunningRx};
+use crate::server::reload::{ConfigReloader, SettingsRx};
 #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
-use crate::server::tls::{self, TLSAcceptor};
-use crate::{meters, ConnectionSettings, Meter};
-use flume::{RecvError, SendError, Sender};
+use crate::server::tls::{self, PeerIdentity, TLSAcceptor};
+use crate::{meters, Meter};
+use flume::{Receiver, RecvError, SendError, Sender};
 use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use tracing::{error, field, info, warn, Instrument};
 use uuid::Uuid;
@@ -30,17 +34,26 @@
 
 use metrics::gauge;
 use metrics_exporter_prometheus::PrometheusBuilder;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{io, thread};
 
 use crate::link::console;
 use crate::link::local::{self, LinkRx, LinkTx};
-use crate::router::{Event, Router};
+use crate::router::{Ack, Event, Notification, Router};
 use crate::{Config, ConnectionId, ServerSettings};
 
 use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
 use tokio::time::error::Elapsed;
-use tokio::{task, time};
+use tokio::{select, task, time};
+
+/// ALPN protocol of MQTT connections
+#[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+const MQTT_ALPN: &str = "mqtt";
+
+/// Publishes replayed before waiting for the router to handle them
+const REPLAY_BATCH_SIZE: usize = 100;
 
 #[derive(Debug, thiserror::Error)]
 #[error("Acceptor error")]
@@ -62,18 +75,27 @@ pub enum Error {
     Remote(#[from] remote::Error),
     #[error("Invalid configuration")]
     Config(String),
+    #[error("Link error = {0}")]
+    Link(#[from] local::LinkError),
 }
 
 pub struct Broker {
     config: Arc<Config>,
     router_tx: Sender<(ConnectionId, Event)>,
+    reloader: ConfigReloader,
+    listeners: Listeners,
+    /// Failure to open the write-ahead log, returned by `start`
+    wal_error: Option<io::Error>,
 }
 
 impl Broker {
     pub fn new(config: Config) -> Broker {
         let config = Arc::new(config);
         let router_config = config.router.clone();
-        let router: Router = Router::new(config.id, router_config);
+        let mut router: Router = Router::new(config.id, router_config);
+        let wal_error = router.open_wal().err();
+        let reloader = ConfigReloader::new(&config);
+        let listeners = Listeners::new(&config);
 
         // Setup cluster if cluster settings are configured.
         match config.cluster.clone() {
@@ -87,11 +109,23 @@ pub fn new(config: Config) -> Broker {
                 // Start router first and then cluster in the background
                 let router_tx = router.spawn();
                 // cluster.spawn();
-                Broker { config, router_tx }
+                Broker {
+                    config,
+                    router_tx,
+                    reloader,
+                    listeners,
+                    wal_error,
+                }
             }
             None => {
                 let router_tx = router.spawn();
-                Broker { config, router_tx }
+                Broker {
+                    config,
+                    router_tx,
+                    reloader,
+                    listeners,
+                    wal_error,
+                }
             }
         }
     }
@@ -147,6 +181,16 @@ pub fn alerts(&self) -> Result<alerts::AlertsLink, alerts::LinkError> {
         Ok(link)
     }
 
+    /// Handle to apply configuration changes while the broker is running
+    pub fn reloader(&self) -> ConfigReloader {
+        self.reloader.clone()
+    }
+
+    /// Handle to start and stop listeners while the broker is running
+    pub fn listeners(&self) -> Listeners {
+        self.listeners.clone()
+    }
+
     pub fn link(&self, client_id: &str) -> Result<(LinkTx, LinkRx), local::LinkError> {
         // Register this connection with the router. Router replies with ack which if ok will
         // start the link. Router can sometimes reject the connection (ex. max connection limit).
@@ -155,8 +199,93 @@ pub fn link(&self, client_id: &str) -> Result<(LinkTx, LinkRx), local::LinkError
         Ok((link_tx, link_rx))
     }
 
+    /// Publishes records of the write-ahead log in `dir` to this broker in the order
+    /// they were received, from a separate thread. Records are published with QoS 0
+    /// as nobody acks them and aren't recorded again. When `paced`, gaps between
+    /// publishes are kept as they were recorded, otherwise records are published as
+    /// fast as the router handles them. Encrypted records are decrypted with the key of
+    /// `wal.key_file` of the router config
+    pub fn replay(&self, dir: impl AsRef<Path>, paced: bool) -> io::Result<thread::JoinHandle<()>> {
+        let mut records = WalReader::open(dir)?;
+        let key_file = self
+            .config
+            .router
+            .wal
+            .as_ref()
+            .and_then(|wal| wal.key_file.as_ref());
+        if let Some(key_file) = key_file {
+            records = records.decrypt_with(WalKey::from_file(key_file)?);
+        }
+
+        let builder = LinkBuilder::new("replay", self.router_tx.clone())
+            .dynamic_filters(true)
+            .record_publishes(false);
+
+        let replay_thread = thread::Builder::new().name("replay".to_owned());
+        replay_thread.spawn(move || {
+            let (mut link_tx, mut link_rx, _ack) = match builder.build() {
+                Ok(link) => link,
+                Err(e) => {
+                    error!(error = ?e, "Failed to connect replay link");
+                    return;
+                }
+            };
+
+            let start = Instant::now();
+            let mut first = None;
+            let mut count = 0;
+
+            for record in records {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) => {
+                        error!(error = ?e, "Failed to read write-ahead log");
+                        break;
+                    }
+                };
+
+                if paced {
+                    let first = *first.get_or_insert(record.timestamp_ms);
+                    let due =
+                        start + Duration::from_millis(record.timestamp_ms.saturating_sub(first));
+                    thread::sleep(due.saturating_duration_since(Instant::now()));
+                }
+
+                if let Err(e) = link_tx.push(Packet::Publish(record.publish, None)) {
+                    error!(error = ?e, "Failed to replay publish");
+                    break;
+                }
+                count += 1;
+
+                if count % REPLAY_BATCH_SIZE == 0 {
+                    if let Err(e) = wait_for_router(&mut link_tx, &mut link_rx) {
+                        error!(error = ?e, "Failed to replay publish");
+                        break;
+                    }
+                }
+            }
+
+            info!(count, "Replayed write-ahead log");
+        })
+    }
+
+    /// Stops the router once events received before are handled, disconnecting all
+    /// clients. Returns once pending records of the write-ahead log are written
+    pub fn shutdown(&self) {
+        let (tx, rx) = flume::bounded(1);
+        if self.router_tx.send((0, Event::Shutdown(tx))).is_ok() {
+            rx.recv().ok();
+        }
+    }
+
     #[tracing::instrument(skip(self))]
     pub fn start(&mut self) -> Result<(), Error> {
+        if let Some(e) = self.wal_error.take() {
+            return Err(Error::Config(format!(
+                "Failed to open write-ahead log in wal.dir: {e}"
+            )));
+        }
+
         if self.config.v4.is_none()
             && self.config.v5.is_none()
             && (cfg!(not(feature = "websocket")) || self.config.ws.is_none())
@@ -203,9 +332,11 @@ pub fn start(&mut self) -> Result<(), Error> {
 
         // Spawn servers in a separate thread.
         if let Some(v4_config) = &self.config.v4 {
-            for (_, config) in v4_config.clone() {
+            for (name, config) in v4_config.clone() {
                 let server_thread = thread::Builder::new().name(config.name.clone());
-                let mut server = Server::new(config, self.router_tx.clone(), V4);
+                let settings = self.reloader.subscribe("v4", &name);
+                let running = self.listeners.subscribe("v4", &name);
+                let mut server = Server::new(config, settings, running, self.router_tx.clone(), V4);
                 let handle = server_thread.spawn(move || {
                     let mut runtime = tokio::runtime::Builder::new_current_thread();
                     let runtime = runtime.enable_all().build().unwrap();
@@ -221,9 +352,11 @@ pub fn start(&mut self) -> Result<(), Error> {
         }
 
         if let Some(v5_config) = &self.config.v5 {
-            for (_, config) in v5_config.clone() {
+            for (name, config) in v5_config.clone() {
                 let server_thread = thread::Builder::new().name(config.name.clone());
-                let mut server = Server::new(config, self.router_tx.clone(), V5);
+                let settings = self.reloader.subscribe("v5", &name);
+                let running = self.listeners.subscribe("v5", &name);
+                let mut server = Server::new(config, settings, running, self.router_tx.clone(), V5);
                 let handle = server_thread.spawn(move || {
                     let mut runtime = tokio::runtime::Builder::new_current_thread();
                     let runtime = runtime.enable_all().build().unwrap();
@@ -245,10 +378,12 @@ pub fn start(&mut self) -> Result<(), Error> {
 
         #[cfg(feature = "websocket")]
         if let Some(ws_config) = &self.config.ws {
-            for (_, config) in ws_config.clone() {
+            for (name, config) in ws_config.clone() {
                 let server_thread = thread::Builder::new().name(config.name.clone());
+                let settings = self.reloader.subscribe("ws", &name);
+                let running = self.listeners.subscribe("ws", &name);
                 //TODO: Add support for V5 procotol with websockets. Registered in config or on ServerSettings
-                let mut server = Server::new(config, self.router_tx.clone(), V4);
+                let mut server = Server::new(config, settings, running, self.router_tx.clone(), V4);
                 let handle = server_thread.spawn(move || {
                     let mut runtime = tokio::runtime::Builder::new_current_thread();
                     let runtime = runtime.enable_all().build().unwrap();
@@ -287,6 +422,9 @@ pub fn start(&mut self) -> Result<(), Error> {
                 let total_publishes = gauge!("metrics.router.total_publishes");
                 let total_connections = gauge!("metrics.router.total_connections");
                 let failed_publishes = gauge!("metrics.router.failed_publishes");
+                let slow_consumer_disconnections =
+                    gauge!("metrics.router.slow_consumer_disconnections");
+                let publish_payload_size = gauge!("metrics.router.publish_payload_size");
                 loop {
                     if let Ok(metrics) = meter_link.recv() {
                         for m in metrics {
@@ -295,6 +433,9 @@ pub fn start(&mut self) -> Result<(), Error> {
                                     total_connections.set(r.total_connections as f64);
                                     total_publishes.set(r.total_publishes as f64);
                                     failed_publishes.set(r.failed_publishes as f64);
+                                    slow_consumer_disconnections
+                                        .set(r.slow_consumer_disconnections as f64);
+                                    publish_payload_size.set(r.publish_payload_size as f64);
                                 }
                                 _ => continue,
                             }
@@ -346,6 +487,10 @@ enum AwaitingWill {
 
 struct Server<P> {
     config: ServerSettings,
+    /// Connection settings, updated when configuration is reloaded
+    settings: SettingsRx,
+    /// Whether listener should be accepting connections
+    running: RunningRx,
     router_tx: Sender<(ConnectionId, Event)>,
     protocol: P,
     awaiting_will_handler: Arc<Mutex<HashMap<String, Sender<AwaitingWill>>>>,
@@ -354,67 +499,125 @@ struct Server<P> {
 impl<P: Protocol + Clone + Send + 'static> Server<P> {
     pub fn new(
         config: ServerSettings,
+        settings: SettingsRx,
+        running: RunningRx,
         router_tx: Sender<(ConnectionId, Event)>,
         protocol: P,
     ) -> Server<P> {
         Server {
             config,
+            settings,
+            running,
             router_tx,
             protocol,
             awaiting_will_handler: Arc::new(Mutex::new(HashMap::default())),
         }
     }
 
-    // Depending on TLS or not create a new Network
-    async fn tls_accept(&self, stream: TcpStream) -> Result<(Box<dyn N>, Option<String>), Error> {
+    // Depending on TLS or not create a new Network. Connections forwarded to the
+    // backend of their ALPN protocol are handled here and `None` is returned
+    async fn tls_accept(
+        &self,
+        stream: TcpStream,
+    ) -> Result<Option<(Box<dyn N>, Option<String>, Option<String>)>, Error> {
         #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
         match &self.config.tls {
             Some(c) => {
-                let (tenant_id, network) = TLSAcceptor::new(c)?.accept(stream).await?;
-                Ok((network, tenant_id))
+                let (identity, network) = TLSAcceptor::new(c)?.accept(stream).await?;
+                let PeerIdentity {
+                    tenant_id,
+                    subject,
+                    protocol,
+                } = identity;
+
+                match protocol.as_deref() {
+                    // Clients which don't use ALPN are MQTT clients too
+                    None | Some(MQTT_ALPN) => Ok(Some((network, tenant_id, subject))),
+                    Some(protocol) => {
+                        let addr = c.alpn_forward(protocol).ok_or_else(|| {
+                            Error::Accept(format!("No forward address for ALPN {protocol}"))
+                        })?;
+
+                        task::spawn(forward(network, addr, protocol.to_owned()));
+                        Ok(None)
+                    }
+                }
             }
-            None => Ok((Box::new(stream), None)),
+            None => Ok(Some((Box::new(stream), None, None))),
         }
         #[cfg(not(any(feature = "use-rustls", feature = "use-native-tls")))]
-        Ok((Box::new(stream), None))
+        Ok(Some((Box::new(stream), None, None)))
     }
 
+    /// Listens for connections whenever listener is started with [`Listeners`]
     async fn start(&mut self, link_type: LinkType) -> Result<(), Error> {
-        let listener = TcpListener::bind(&self.config.listen).await?;
-        let delay = Duration::from_millis(self.config.next_connection_delay_ms);
         let mut count: usize = 0;
+        loop {
+            while !*self.running.borrow_and_update() {
+                // Listener can't be started anymore once broker is dropped
+                if self.running.changed().await.is_err() {
+                    return Ok(());
+                }
+            }
+
+            self.accept(link_type, &mut count).await?;
+            info!(config = self.config.name, "Stopped listening");
+        }
+    }
+
+    /// Accepts connections until listener is stopped
+    async fn accept(&mut self, link_type: LinkType, count: &mut usize) -> Result<(), Error> {
+        let listener = Listener::bind(&self.config).await?;
+        let delay = Duration::from_millis(self.config.next_connection_delay_ms);
 
-        let config = Arc::new(self.config.connections.clone());
         info!(
             config = self.config.name,
             listen_addr = self.config.listen.to_string(),
             "Listening for remote connections",
         );
         loop {
-            // Await new network connection.
-            let (stream, addr) = match listener.accept().await {
-                Ok((s, r)) => (s, r),
-                Err(e) => {
-                    error!(error=?e, "Unable to accept socket.");
-                    continue;
+            // Await new network connection or stop request
+            let accepted = select! {
+                accepted = listener.accept() => accepted,
+                Ok(()) = self.running.changed() => {
+                    if *self.running.borrow_and_update() {
+                        continue;
+                    }
+
+                    return Ok(());
                 }
             };
 
-            let (network, tenant_id) = match self.tls_accept(stream).await {
-                Ok(o) => o,
+            let (network, tenant_id, cert_subject, addr) = match accepted {
+                Ok(Accepted::Tcp(stream, addr)) => match self.tls_accept(stream).await {
+                    Ok(Some((network, tenant_id, cert_subject))) => {
+                        (network, tenant_id, cert_subject, addr.to_string())
+                    }
+                    Ok(None) => continue,
+                    Err(e) => {
+                        error!(error=?e, "Tls accept error");
+                        continue;
+                    }
+                },
+                // Clients on the same host don't need TLS
+                #[cfg(unix)]
+                Ok(Accepted::Unix(stream, path)) => {
+                    (Box::new(stream) as Box<dyn N>, None, None, path)
+                }
                 Err(e) => {
-                    error!(error=?e, "Tls accept error");
+                    error!(error=?e, "Unable to accept socket.");
                     continue;
                 }
             };
 
             info!(
-                name=?self.config.name, ?addr, count, tenant=?tenant_id, "accept"
+                name=?self.config.name, ?addr, count=*count,
+                tenant=?tenant_id, ?cert_subject, "accept"
             );
 
-            let config = config.clone();
+            let settings = self.settings.clone();
             let router_tx = self.router_tx.clone();
-            count += 1;
+            *count += 1;
 
             let protocol = self.protocol.clone();
             match link_type {
@@ -429,8 +632,9 @@ async fn start(&mut self, link_type: LinkType) -> Result<(), Error> {
                     };
                     task::spawn(
                         remote(
-                            config,
+                            settings,
                             tenant_id.clone(),
+                            cert_subject,
                             router_tx,
                             stream,
                             protocol,
@@ -445,8 +649,9 @@ async fn start(&mut self, link_type: LinkType) -> Result<(), Error> {
                 }
                 LinkType::Remote => task::spawn(
                     remote(
-                        config,
+                        settings,
                         tenant_id.clone(),
+                        cert_subject.clone(),
                         router_tx,
                         network,
                         protocol,
@@ -455,6 +660,7 @@ async fn start(&mut self, link_type: LinkType) -> Result<(), Error> {
                     .instrument(tracing::error_span!(
                         "remote_link",
                         ?tenant_id,
+                        ?cert_subject,
                         client_id = field::Empty,
                         connection_id = field::Empty,
                     )),
@@ -466,6 +672,116 @@ async fn start(&mut self, link_type: LinkType) -> Result<(), Error> {
     }
 }
 
+/// Connection accepted on one of the sockets of a server
+enum Accepted {
+    Tcp(TcpStream, SocketAddr),
+    /// Stream and path of the socket
+    #[cfg(unix)]
+    Unix(UnixStream, String),
+}
+
+/// Sockets on which a server accepts connections
+struct Listener {
+    tcp: TcpListener,
+    #[cfg(unix)]
+    unix: Option<(UnixListener, std::path::PathBuf)>,
+}
+
+impl Listener {
+    async fn bind(config: &ServerSettings) -> io::Result<Listener> {
+        let tcp = TcpListener::bind(&config.listen).await?;
+
+        #[cfg(unix)]
+        let unix = match &config.unix_socket {
+            Some(path) => {
+                remove_stale_socket(path)?;
+                let listener = UnixListener::bind(path)?;
+                info!(config = config.name, ?path, "Listening on unix socket");
+                Some((listener, path.clone()))
+            }
+            None => None,
+        };
+
+        #[cfg(not(unix))]
+        if config.unix_socket.is_some() {
+            warn!(
+                config = config.name,
+                "Unix sockets aren't supported on this platform"
+            );
+        }
+
+        Ok(Listener {
+            tcp,
+            #[cfg(unix)]
+            unix,
+        })
+    }
+
+    async fn accept(&self) -> io::Result<Accepted> {
+        #[cfg(unix)]
+        if let Some((unix, path)) = &self.unix {
+            let path = path.display().to_string();
+            return select! {
+                accepted = self.tcp.accept() => {
+                    accepted.map(|(stream, addr)| Accepted::Tcp(stream, addr))
+                }
+                accepted = unix.accept() => accepted.map(|(stream, _)| Accepted::Unix(stream, path)),
+            };
+        }
+
+        let (stream, addr) = self.tcp.accept().await?;
+        Ok(Accepted::Tcp(stream, addr))
+    }
+}
+
+/// Removes the socket file once the listener stops, clients can't connect to it anymore
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Some((_, path)) = &self.unix {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// Waits for the router to handle packets pushed on the link before, as it answers
+/// a ping only after them
+fn wait_for_router(link_tx: &mut LinkTx, link_rx: &mut LinkRx) -> Result<(), local::LinkError> {
+    link_tx.push(Packet::PingReq(PingReq))?;
+    loop {
+        if let Some(Notification::DeviceAck(Ack::PingResp(_))) = link_rx.recv()? {
+            return Ok(());
+        }
+    }
+}
+
+/// Socket file left behind by a broker which didn't stop cleanly fails the bind
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+/// Proxies a TLS connection which negotiated another protocol than MQTT to `addr`
+#[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
+async fn forward(mut network: Box<dyn N>, addr: SocketAddr, protocol: String) {
+    let mut backend = match TcpStream::connect(addr).await {
+        Ok(backend) => backend,
+        Err(e) => {
+            error!(error=?e, %addr, protocol, "Unable to connect to ALPN forward address");
+            return;
+        }
+    };
+
+    if let Err(e) = tokio::io::copy_bidirectional(&mut network, &mut backend).await {
+        warn!(error=?e, %addr, protocol, "ALPN forwarding stopped");
+    }
+}
+
 /// Configures the Websocket connection to indicate the correct protocol
 /// by adding the "sec-websocket-protocol" with value of "mqtt" to the response header
 #[cfg(feature = "websocket")]
@@ -484,19 +800,47 @@ fn on_request(
     }
 }
 
+/// Settles will of previous connection with same client id and registers a handler
+/// for will of the new connection
+fn take_over_will(
+    will_handlers: &Mutex<HashMap<String, Sender<AwaitingWill>>>,
+    client_id: &str,
+    clean_session: bool,
+) -> Receiver<AwaitingWill> {
+    if let Some(sender) = will_handlers.lock().unwrap().remove(client_id) {
+        let awaiting_will = if clean_session {
+            AwaitingWill::Fire
+        } else {
+            AwaitingWill::Cancel
+        };
+        sender.try_send(awaiting_will).unwrap();
+    }
+
+    let (will_tx, will_rx) = flume::bounded::<AwaitingWill>(1);
+    will_handlers
+        .lock()
+        .unwrap()
+        .insert(client_id.to_owned(), will_tx);
+
+    will_rx
+}
+
 /// A new network connection should wait for a mqtt connect packet. This should be handled
 /// asynchronously to avoid blocking other new connections while this connection is
 /// waiting for mqtt connect packet. Also this honours connection wait time as per config to prevent
 /// denial of service attacks (rogue clients which only establish network connections without
 /// sending a mqtt connection packet to make the server reach its concurrent connection limit).
 async fn remote<P: Protocol>(
-    config: Arc<ConnectionSettings>,
+    mut settings: SettingsRx,
     tenant_id: Option<String>,
+    cert_subject: Option<String>,
     router_tx: Sender<(ConnectionId, Event)>,
     stream: Box<dyn N>,
     protocol: P,
     will_handlers: Arc<Mutex<HashMap<String, Sender<AwaitingWill>>>>,
 ) {
+    // Marked as seen so that only later reloads are applied to the link
+    let config = settings.borrow_and_update().clone();
     let mut network = Network::new(
         stream,
         config.max_payload_size,
@@ -504,9 +848,7 @@ async fn remote<P: Protocol>(
         protocol,
     );
 
-    let dynamic_filters = config.dynamic_filters;
-
-    let connect_packet = match mqtt_connect(config, &mut network).await {
+    let connect_packet = match mqtt_connect(config.clone(), &mut network, cert_subject).await {
         Ok(p) => p,
         Err(e) => {
             error!(error=?e, "Error while handling MQTT connect packet");
@@ -535,20 +877,11 @@ async fn remote<P: Protocol>(
         client_id = format!("{tenant_id}.{client_id}");
     }
 
-    if let Some(sender) = will_handlers.lock().unwrap().remove(&client_id) {
-        let awaiting_will = if clean_session {
-            AwaitingWill::Fire
-        } else {
-            AwaitingWill::Cancel
-        };
-        sender.try_send(awaiting_will).unwrap();
-    }
-
-    let (will_tx, will_rx) = flume::bounded::<AwaitingWill>(1);
-    will_handlers
-        .lock()
-        .unwrap()
-        .insert(client_id.clone(), will_tx);
+    let will_rx = if config.reject_duplicate_client_id {
+        None
+    } else {
+        Some(take_over_will(&will_handlers, &client_id, clean_session))
+    };
 
     // Start the link
     let mut link = match RemoteLink::new(
@@ -556,7 +889,7 @@ async fn remote<P: Protocol>(
         tenant_id.clone(),
         network,
         connect_packet,
-        dynamic_filters,
+        config,
         assigned_client_id,
     )
     .await
@@ -568,6 +901,14 @@ async fn remote<P: Protocol>(
         }
     };
 
+    // Router might reject this connection if client id is in use. In that case will of
+    // previous connection with same client id shouldn't be touched
+    let will_rx = match will_rx {
+        Some(will_rx) => will_rx,
+        None => take_over_will(&will_handlers, &client_id, clean_session),
+    };
+
+    link.watch_settings(settings);
     let connection_id = link.connection_id;
     let will_delay_interval = link.will_delay_interval;
     let mut send_disconnect = true;
@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use crate::server::servers;
+use crate::Config;
+
+pub(crate) type RunningRx = watch::Receiver<bool>;
+
+/// Starts and stops listeners of servers configured in `v4`, `v5` and `ws` while
+/// the broker is running. Listeners are named `v4.<name>`, `v5.<name>` and `ws.<name>`
+/// after their config. Obtained with [`Broker::listeners`](crate::Broker::listeners)
+#[derive(Clone)]
+pub struct Listeners {
+    running: Arc<HashMap<String, watch::Sender<bool>>>,
+}
+
+impl Listeners {
+    pub(crate) fn new(config: &Config) -> Listeners {
+        let mut running = HashMap::new();
+        for (kind, servers) in servers(config) {
+            for name in servers.into_iter().flat_map(|servers| servers.keys()) {
+                let (tx, _) = watch::channel(true);
+                running.insert(format!("{kind}.{name}"), tx);
+            }
+        }
+
+        Listeners {
+            running: Arc::new(running),
+        }
+    }
+
+    pub(crate) fn subscribe(&self, kind: &str, name: &str) -> RunningRx {
+        self.running[&format!("{kind}.{name}")].subscribe()
+    }
+
+    /// Names of all the listeners
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.running.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Whether `listener` is accepting connections. `None` if there is no such listener
+    pub fn is_running(&self, listener: &str) -> Option<bool> {
+        self.running.get(listener).map(|running| *running.borrow())
+    }
+
+    /// Starts accepting connections on a stopped listener. Returns false if there is
+    /// no such listener
+    pub fn start(&self, listener: &str) -> bool {
+        self.set_running(listener, true)
+    }
+
+    /// Closes the socket of `listener`. Connections which were already accepted
+    /// by it stay connected. Returns false if there is no such listener
+    pub fn stop(&self, listener: &str) -> bool {
+        self.set_running(listener, false)
+    }
+
+    fn set_running(&self, listener: &str, running: bool) -> bool {
+        match self.running.get(listener) {
+            Some(tx) => {
+                tx.send_if_modified(|current| std::mem::replace(current, running) != running);
+                true
+            }
+            None => false,
+        }
+    }
+}
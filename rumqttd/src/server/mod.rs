@@ -1,10 +1,28 @@
+use std::collections::HashMap;
+
 use tokio::io::{AsyncRead, AsyncWrite};
 
+use crate::{Config, ServerSettings};
+
 mod broker;
+mod listener;
+mod reload;
 #[cfg(any(feature = "use-rustls", feature = "use-native-tls"))]
 mod tls;
 
 pub use broker::Broker;
+pub use listener::Listeners;
+pub(crate) use reload::SettingsRx;
+pub use reload::{ConfigReloader, ReloadReport};
 
 pub trait IO: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
 impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> IO for T {}
+
+/// Servers configured in `v4`, `v5` and `ws`
+pub(crate) fn servers(config: &Config) -> [(&str, Option<&HashMap<String, ServerSettings>>); 3] {
+    [
+        ("v4", config.v4.as_ref()),
+        ("v5", config.v5.as_ref()),
+        ("ws", config.ws.as_ref()),
+    ]
+}
@@ -0,0 +1,270 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::info;
+
+use crate::server::servers;
+use crate::{Config, ConnectionSettings, ServerSettings};
+
+type SettingsTx = watch::Sender<Arc<ConnectionSettings>>;
+pub(crate) type SettingsRx = watch::Receiver<Arc<ConnectionSettings>>;
+
+/// Applies configuration changes to a running broker without dropping connections.
+/// Connection settings of servers are applied to new and existing connections. Other
+/// changes are reported as requiring a restart. Obtained with [`Broker::reloader`](crate::Broker::reloader)
+#[derive(Clone)]
+pub struct ConfigReloader {
+    current: Arc<Mutex<Config>>,
+    /// Connection settings of servers, keyed by `v4.<name>`, `v5.<name>` and `ws.<name>`
+    settings: Arc<HashMap<String, SettingsTx>>,
+}
+
+/// Outcome of [`ConfigReloader::reload`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// Changed settings which are applied to new and existing connections
+    pub applied: Vec<String>,
+    /// Changed settings which can't be hot applied and take effect after a restart
+    pub requires_restart: Vec<String>,
+}
+
+impl ConfigReloader {
+    pub(crate) fn new(config: &Config) -> ConfigReloader {
+        let mut settings = HashMap::new();
+        for (kind, servers) in servers(config) {
+            for (name, server) in servers.into_iter().flatten() {
+                let (tx, _) = watch::channel(Arc::new(server.connections.clone()));
+                settings.insert(format!("{kind}.{name}"), tx);
+            }
+        }
+
+        ConfigReloader {
+            current: Arc::new(Mutex::new(config.clone())),
+            settings: Arc::new(settings),
+        }
+    }
+
+    /// Receiver of connection settings of a server configured in `v4`, `v5` or `ws`
+    pub(crate) fn subscribe(&self, kind: &str, name: &str) -> SettingsRx {
+        self.settings[&format!("{kind}.{name}")].subscribe()
+    }
+
+    /// Applies connection settings of servers in `config` which differ from the
+    /// running ones. Authentication handlers aren't part of config files, so servers
+    /// keep their handlers unless `config` sets new ones
+    pub fn reload(&self, mut config: Config) -> ReloadReport {
+        let mut current = self.current.lock().unwrap();
+        let mut report = ReloadReport::default();
+
+        for kind in ["v4", "v5", "ws"] {
+            let running = servers_of(&mut current, kind);
+            let new = servers_of(&mut config, kind).take().unwrap_or_default();
+            self.reload_servers(kind, running, new, &mut report);
+        }
+
+        let sections = [
+            ("id", changed(&current.id, &config.id)),
+            ("router", changed(&current.router, &config.router)),
+            ("cluster", changed(&current.cluster, &config.cluster)),
+            ("console", changed(&current.console, &config.console)),
+            ("bridge", changed(&current.bridge, &config.bridge)),
+            (
+                "prometheus",
+                changed(&current.prometheus, &config.prometheus),
+            ),
+            ("metrics", changed(&current.metrics, &config.metrics)),
+        ];
+
+        for (section, changed) in sections {
+            if changed {
+                report.requires_restart.push(section.to_owned());
+            }
+        }
+
+        report
+    }
+
+    fn reload_servers(
+        &self,
+        kind: &str,
+        running: &mut Option<HashMap<String, ServerSettings>>,
+        mut new: HashMap<String, ServerSettings>,
+        report: &mut ReloadReport,
+    ) {
+        let running_names = running.iter().flat_map(|servers| servers.keys());
+        let names: BTreeSet<String> = running_names.chain(new.keys()).cloned().collect();
+
+        for name in names {
+            let key = format!("{kind}.{name}");
+            let running = running.as_mut().and_then(|servers| servers.get_mut(&name));
+            let (running, mut new) = match (running, new.remove(&name)) {
+                (Some(running), Some(new)) => (running, new),
+                (Some(_), None) => {
+                    report.requires_restart.push(format!("{key} removed"));
+                    continue;
+                }
+                (None, _) => {
+                    report.requires_restart.push(format!("{key} added"));
+                    continue;
+                }
+            };
+
+            let server_changes = [
+                ("name", changed(&running.name, &new.name)),
+                ("listen", changed(&running.listen, &new.listen)),
+                (
+                    "unix_socket",
+                    changed(&running.unix_socket, &new.unix_socket),
+                ),
+                ("tls", changed(&running.tls, &new.tls)),
+                (
+                    "next_connection_delay_ms",
+                    changed(
+                        &running.next_connection_delay_ms,
+                        &new.next_connection_delay_ms,
+                    ),
+                ),
+            ];
+
+            for (setting, changed) in server_changes {
+                if changed {
+                    report.requires_restart.push(format!("{key}.{setting}"));
+                }
+            }
+
+            let connections = &mut new.connections;
+            if connections.external_auth.is_none() {
+                connections.external_auth = running.connections.external_auth.clone();
+            }
+
+            if connections.cert_auth.is_none() {
+                connections.cert_auth = running.connections.cert_auth.clone();
+            }
+
+            if connections.user_properties_auth.is_none() {
+                connections.user_properties_auth = running.connections.user_properties_auth.clone();
+            }
+
+            if connections.topic_rewrite.is_none() {
+                connections.topic_rewrite = running.connections.topic_rewrite.clone();
+            }
+
+            let handlers_changed = !same_handler(
+                &connections.external_auth,
+                &running.connections.external_auth,
+            ) || !same_handler(
+                &connections.cert_auth,
+                &running.connections.cert_auth,
+            ) || !same_handler(
+                &connections.user_properties_auth,
+                &running.connections.user_properties_auth,
+            ) || !same_handler(
+                &connections.topic_rewrite,
+                &running.connections.topic_rewrite,
+            );
+
+            if !handlers_changed && !changed(&running.connections, connections) {
+                continue;
+            }
+
+            info!(server = key, "Applying reloaded connection settings");
+            running.connections = new.connections;
+            self.settings[&key].send_replace(Arc::new(running.connections.clone()));
+            report.applied.push(format!("{key}.connections"));
+        }
+    }
+}
+
+fn servers_of<'a>(
+    config: &'a mut Config,
+    kind: &str,
+) -> &'a mut Option<HashMap<String, ServerSettings>> {
+    match kind {
+        "v4" => &mut config.v4,
+        "v5" => &mut config.v5,
+        _ => &mut config.ws,
+    }
+}
+
+/// Settings are compared by their serialized form as not all of them implement `PartialEq`
+fn changed<T: Serialize>(running: &T, new: &T) -> bool {
+    serde_json::to_value(running).ok() != serde_json::to_value(new).ok()
+}
+
+fn same_handler<T: ?Sized>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        id = 0
+
+        [router]
+        id = 0
+        max_connections = 10
+        max_outgoing_packet_count = 200
+        max_segment_size = 104857600
+        max_segment_count = 10
+
+        [v4.1]
+        name = "v4-1"
+        listen = "127.0.0.1:1883"
+        next_connection_delay_ms = 1
+            [v4.1.connections]
+            connection_timeout_ms = 60000
+            max_payload_size = 20480
+            max_inflight_count = 100
+    "#;
+
+    fn config() -> Config {
+        config::Config::builder()
+            .add_source(config::File::from_str(CONFIG, config::FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn connection_settings_are_applied_and_rest_is_reported() {
+        let mut config = config();
+        config
+            .v4
+            .as_mut()
+            .unwrap()
+            .get_mut("1")
+            .unwrap()
+            .set_auth_handler(|_, _, _| async { true });
+
+        let reloader = ConfigReloader::new(&config);
+        let mut settings = reloader.subscribe("v4", "1");
+        assert_eq!(reloader.reload(self::config()), ReloadReport::default());
+        assert!(!settings.has_changed().unwrap());
+
+        let mut config = self::config();
+        let server = config.v4.as_mut().unwrap().get_mut("1").unwrap();
+        server.connections.max_payload_size = 1024;
+        server.connections.max_topic_levels = Some(4);
+        server.listen = "127.0.0.1:1884".parse().unwrap();
+        config.router.max_connections = 20;
+
+        let report = reloader.reload(config);
+        assert_eq!(report.applied, vec!["v4.1.connections"]);
+        assert_eq!(report.requires_restart, vec!["v4.1.listen", "router"]);
+
+        assert!(settings.has_changed().unwrap());
+        let settings = settings.borrow_and_update();
+        assert_eq!(settings.max_payload_size, 1024);
+        assert_eq!(settings.max_topic_levels, Some(4));
+        assert!(settings.external_auth.is_some());
+    }
+}
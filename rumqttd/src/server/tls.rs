@@ -84,6 +84,46 @@ fn extract_tenant_id(der: &[u8]) -> Result<Option<String>, Error> {
     Ok(Some(tenant_id))
 }
 
+#[cfg(feature = "verify-client-cert")]
+/// Extract subject from certificate's common name, falling back to the first
+/// DNS, email or URI subject alternative name
+fn extract_cert_subject(der: &[u8]) -> Result<Option<String>, Error> {
+    use x509_parser::extensions::GeneralName;
+
+    let (_, cert) =
+        x509_parser::parse_x509_certificate(der).map_err(|_| Error::CertificateParse)?;
+    if let Some(cn) = cert.subject().iter_common_name().next() {
+        let cn = cn.as_str().map_err(|_| Error::CertificateParse)?;
+        return Ok(Some(cn.to_owned()));
+    }
+
+    let Some(san) = cert
+        .subject_alternative_name()
+        .map_err(|_| Error::CertificateParse)?
+    else {
+        return Ok(None);
+    };
+
+    let subject = san.value.general_names.iter().find_map(|name| match name {
+        GeneralName::DNSName(v) | GeneralName::RFC822Name(v) | GeneralName::URI(v) => {
+            Some(v.to_string())
+        }
+        _ => None,
+    });
+
+    Ok(subject)
+}
+
+/// Identity of a client derived from its TLS certificate
+#[derive(Debug, Default)]
+pub struct PeerIdentity {
+    pub tenant_id: Option<String>,
+    /// Common name or subject alternative name of the certificate
+    pub subject: Option<String>,
+    /// Application protocol negotiated with ALPN
+    pub protocol: Option<String>,
+}
+
 #[allow(dead_code)]
 pub enum TLSAcceptor {
     #[cfg(feature = "use-rustls")]
@@ -102,7 +142,9 @@ pub fn new(config: &TlsConfig) -> Result<Self, Error> {
                 capath,
                 certpath,
                 keypath,
-            } => Self::rustls(capath, certpath, keypath),
+                alpn,
+                ..
+            } => Self::rustls(capath, certpath, keypath, alpn),
             #[cfg(feature = "use-native-tls")]
             TlsConfig::NativeTls {
                 pkcs12path,
@@ -115,25 +157,35 @@ pub fn new(config: &TlsConfig) -> Result<Self, Error> {
         }
     }
 
-    pub async fn accept(&self, stream: TcpStream) -> Result<(Option<String>, Box<dyn N>), Error> {
+    pub async fn accept(&self, stream: TcpStream) -> Result<(PeerIdentity, Box<dyn N>), Error> {
         match self {
             #[cfg(feature = "use-rustls")]
             TLSAcceptor::Rustls { acceptor } => {
                 let stream = acceptor.accept(stream).await?;
+                let (_, session) = stream.get_ref();
+                let protocol = session
+                    .alpn_protocol()
+                    .map(|protocol| String::from_utf8_lossy(protocol).into_owned());
 
                 #[cfg(feature = "verify-client-cert")]
-                let tenant_id = {
-                    let (_, session) = stream.get_ref();
+                let identity = {
                     let peer_certificates = session
                         .peer_certificates()
                         .ok_or(Error::NoPeerCertificate)?;
-                    extract_tenant_id(&peer_certificates[0])?
+                    PeerIdentity {
+                        tenant_id: extract_tenant_id(&peer_certificates[0])?,
+                        subject: extract_cert_subject(&peer_certificates[0])?,
+                        protocol,
+                    }
                 };
                 #[cfg(not(feature = "verify-client-cert"))]
-                let tenant_id: Option<String> = None;
+                let identity = PeerIdentity {
+                    protocol,
+                    ..PeerIdentity::default()
+                };
 
                 let network = Box::new(stream);
-                Ok((tenant_id, network))
+                Ok((identity, network))
             }
             #[cfg(feature = "use-native-tls")]
             TLSAcceptor::NativeTLS { acceptor } => {
@@ -146,7 +198,7 @@ pub async fn accept(&self, stream: TcpStream) -> Result<(Option<String>, Box<dyn
                 //     .to_der()?;
                 // let tenant_id = extract_tenant_id(&peer_certificate)?;
                 let network = Box::new(stream);
-                Ok((None, network))
+                Ok((PeerIdentity::default(), network))
             }
         }
     }
@@ -181,6 +233,7 @@ fn rustls(
         ca_path: &Option<String>,
         cert_path: &String,
         key_path: &String,
+        alpn: &[String],
     ) -> Result<TLSAcceptor, Error> {
         #[cfg(feature = "verify-client-cert")]
         let Some(ca_path) = ca_path
@@ -239,7 +292,8 @@ fn rustls(
         #[cfg(not(feature = "verify-client-cert"))]
         let builder = builder.with_no_client_auth();
 
-        let server_config = builder.with_single_cert(certs, key)?;
+        let mut server_config = builder.with_single_cert(certs, key)?;
+        server_config.alpn_protocols = alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
 
         let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
         Ok(TLSAcceptor::Rustls { acceptor })
@@ -0,0 +1,102 @@
+// Client certificates aren't presented, which `verify-client-cert` requires
+#![cfg(all(feature = "use-rustls", not(feature = "verify-client-cert")))]
+
+use std::thread;
+use std::time::Duration;
+
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, Transport};
+use rumqttd::{Broker, Config};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+
+const CONFIG: &str = r#"
+id = 0
+
+[router]
+id = 0
+max_connections = 10
+max_outgoing_packet_count = 200
+max_segment_size = 104857600
+max_segment_count = 10
+
+[v4.1]
+name = "v4-1"
+listen = "127.0.0.1:1905"
+next_connection_delay_ms = 1
+    [v4.1.tls]
+    certpath = "{dir}/server.cert.pem"
+    keypath = "{dir}/server.key.pem"
+    alpn = ["mqtt", "x-other"]
+    [v4.1.tls.alpnforward]
+    x-other = "127.0.0.1:1906"
+    [v4.1.connections]
+    connection_timeout_ms = 60000
+    max_payload_size = 20480
+    max_inflight_count = 100
+"#;
+
+/// Writes a server certificate signed by a new CA to `dir` and returns the CA
+fn write_certs(dir: &std::path::Path) -> Vec<u8> {
+    let mut ca_params = CertificateParams::new(vec![]);
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(ca_params).unwrap();
+    let server =
+        Certificate::from_params(CertificateParams::new(vec!["localhost".into()])).unwrap();
+
+    std::fs::create_dir_all(dir).unwrap();
+    let cert = server.serialize_pem_with_signer(&ca).unwrap();
+    std::fs::write(dir.join("server.cert.pem"), cert).unwrap();
+    std::fs::write(
+        dir.join("server.key.pem"),
+        server.serialize_private_key_pem(),
+    )
+    .unwrap();
+    ca.serialize_pem().unwrap().into_bytes()
+}
+
+fn options(client_id: &str, ca: &[u8], alpn: Option<&str>) -> MqttOptions {
+    let mut options = MqttOptions::new(client_id, "localhost", 1905);
+    let alpn = alpn.map(|protocol| vec![protocol.as_bytes().to_vec()]);
+    options.set_transport(Transport::tls(ca.to_vec(), None, alpn));
+    options
+}
+
+#[tokio::test]
+async fn connections_are_routed_by_alpn() {
+    let dir = std::env::temp_dir().join(format!("rumqttd-alpn-{}", std::process::id()));
+    let ca = write_certs(&dir);
+    let config = CONFIG.replace("{dir}", dir.to_str().unwrap());
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(&config, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    let config: Config = config.try_deserialize().unwrap();
+
+    let backend = TcpListener::bind("127.0.0.1:1906").await.unwrap();
+    let mut broker = Broker::new(config);
+    thread::spawn(move || broker.start().unwrap());
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // mqtt protocol reaches the broker
+    let (_client, mut eventloop) = AsyncClient::new(options("mqtt", &ca, Some("mqtt")), 10);
+    let event = eventloop.poll().await.unwrap();
+    assert!(matches!(event, Event::Incoming(Packet::ConnAck(_))));
+
+    // protocols which aren't offered fail the handshake
+    let (_client, mut eventloop) = AsyncClient::new(options("h2", &ca, Some("h2")), 10);
+    assert!(eventloop.poll().await.is_err());
+
+    // other offered protocols are forwarded decrypted, connect packet shows up as is
+    let (_client, mut eventloop) = AsyncClient::new(options("other", &ca, Some("x-other")), 10);
+    tokio::spawn(async move { eventloop.poll().await });
+    let (mut forwarded, _) = tokio::time::timeout(Duration::from_secs(5), backend.accept())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut connect = [0; 1];
+    forwarded.read_exact(&mut connect).await.unwrap();
+    assert_eq!(connect[0], 0x10);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,52 @@
+use std::thread;
+use std::time::Duration;
+
+use rumqttc::{ConnectionError, Event, EventLoop, MqttOptions, Packet};
+use rumqttd::{Broker, Config};
+
+const CONFIG: &str = r#"
+id = 0
+
+[router]
+id = 0
+max_connections = 10
+max_outgoing_packet_count = 200
+max_segment_size = 104857600
+max_segment_count = 10
+
+[v4.1]
+name = "v4-1"
+listen = "127.0.0.1:1907"
+next_connection_delay_ms = 1
+    [v4.1.connections]
+    connection_timeout_ms = 60000
+    max_payload_size = 20480
+    max_inflight_count = 100
+    connack_delay_ms = 2000
+"#;
+
+#[tokio::test]
+async fn slow_connack_times_out_client_connect() {
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(CONFIG, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    let config: Config = config.try_deserialize().unwrap();
+
+    let mut broker = Broker::new(config);
+    thread::spawn(move || broker.start().unwrap());
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let options = MqttOptions::new("slow-connack", "127.0.0.1", 1907);
+    let mut eventloop = EventLoop::new(options, 10);
+    eventloop.network_options.set_connection_timeout(1);
+    assert!(matches!(
+        eventloop.poll().await,
+        Err(ConnectionError::NetworkTimeout)
+    ));
+
+    // connects when waiting long enough
+    eventloop.network_options.set_connection_timeout(5);
+    let event = eventloop.poll().await.unwrap();
+    assert!(matches!(event, Event::Incoming(Packet::ConnAck(_))));
+}
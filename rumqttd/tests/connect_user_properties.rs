@@ -0,0 +1,79 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rumqttc::v5::mqttbytes::v5::Packet;
+use rumqttc::v5::{Event, EventLoop, MqttOptions};
+use rumqttd::{Broker, Config, UserProperties};
+
+const CONFIG: &str = r#"
+id = 0
+
+[router]
+id = 0
+max_connections = 10
+max_outgoing_packet_count = 200
+max_segment_size = 104857600
+max_segment_count = 10
+
+[v5.1]
+name = "v5-1"
+listen = "127.0.0.1:1904"
+next_connection_delay_ms = 1
+    [v5.1.connections]
+    connection_timeout_ms = 60000
+    max_payload_size = 20480
+    max_inflight_count = 100
+"#;
+
+fn options(client_id: &str, tenant: &str) -> MqttOptions {
+    let mut options = MqttOptions::new(client_id, "127.0.0.1", 1904);
+    options.set_user_properties(vec![("tenant".to_owned(), tenant.to_owned())]);
+    options
+}
+
+#[tokio::test]
+async fn connect_user_properties_reach_auth_handler() {
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(CONFIG, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    let mut config: Config = config.try_deserialize().unwrap();
+
+    let seen: Arc<Mutex<Vec<(String, UserProperties)>>> = Arc::default();
+    let handler_seen = seen.clone();
+    let server = config.v5.as_mut().unwrap().get_mut("1").unwrap();
+    server.set_user_properties_auth_handler(move |client_id, user_properties| {
+        let allowed = user_properties.contains(&("tenant".to_owned(), "acme".to_owned()));
+        handler_seen
+            .lock()
+            .unwrap()
+            .push((client_id, user_properties));
+        async move { allowed }
+    });
+
+    let mut broker = Broker::new(config);
+    thread::spawn(move || broker.start().unwrap());
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    let mut eventloop = EventLoop::new(options("acme-client", "acme"), 10);
+    let event = eventloop.poll().await.unwrap();
+    assert!(matches!(event, Event::Incoming(Packet::ConnAck(_))));
+
+    // connection is closed without a connack
+    let mut eventloop = EventLoop::new(options("other-client", "other"), 10);
+    assert!(eventloop.poll().await.is_err());
+
+    let seen = seen.lock().unwrap();
+    let expected = vec![
+        (
+            "acme-client".to_owned(),
+            vec![("tenant".to_owned(), "acme".to_owned())],
+        ),
+        (
+            "other-client".to_owned(),
+            vec![("tenant".to_owned(), "other".to_owned())],
+        ),
+    ];
+    assert_eq!(*seen, expected);
+}
@@ -0,0 +1,60 @@
+use std::thread;
+use std::time::Duration;
+
+use rumqttc::{Event, EventLoop, MqttOptions, Packet};
+use rumqttd::{Broker, Config};
+
+const CONFIG: &str = r#"
+id = 0
+
+[router]
+id = 0
+max_connections = 10
+max_outgoing_packet_count = 200
+max_segment_size = 104857600
+max_segment_count = 10
+
+[v4.1]
+name = "v4-1"
+listen = "127.0.0.1:1902"
+next_connection_delay_ms = 1
+    [v4.1.connections]
+    connection_timeout_ms = 60000
+    max_payload_size = 20480
+    max_inflight_count = 100
+    idle_timeout_ms = 500
+"#;
+
+#[tokio::test]
+async fn silent_client_is_disconnected_before_keep_alive() {
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(CONFIG, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    let config: Config = config.try_deserialize().unwrap();
+
+    let mut broker = Broker::new(config);
+    thread::spawn(move || broker.start().unwrap());
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // Client doesn't ping for an hour
+    let mut options = MqttOptions::new("silent-client", "127.0.0.1", 1902);
+    options.set_keep_alive(Duration::from_secs(3600));
+    let mut eventloop = EventLoop::new(options, 10);
+    loop {
+        if let Event::Incoming(Packet::ConnAck(_)) = eventloop.poll().await.unwrap() {
+            break;
+        }
+    }
+
+    let disconnected = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if eventloop.poll().await.is_err() {
+                return;
+            }
+        }
+    })
+    .await;
+
+    assert!(disconnected.is_ok());
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "websocket")]
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, Packet, QoS, Transport};
+use rumqttd::local::LinkRx;
+use rumqttd::{Broker, Config, Notification};
+
+const CONFIG: &str = r#"
+id = 0
+
+[router]
+id = 0
+max_connections = 10
+max_outgoing_packet_count = 200
+max_segment_size = 104857600
+max_segment_count = 10
+
+[v4.1]
+name = "v4-1"
+listen = "127.0.0.1:1900"
+next_connection_delay_ms = 1
+    [v4.1.connections]
+    connection_timeout_ms = 60000
+    max_payload_size = 20480
+    max_inflight_count = 100
+
+[ws.1]
+name = "ws-1"
+listen = "127.0.0.1:1901"
+next_connection_delay_ms = 1
+    [ws.1.connections]
+    connection_timeout_ms = 60000
+    max_payload_size = 20480
+    max_inflight_count = 100
+"#;
+
+fn tcp_options(id: &str) -> MqttOptions {
+    MqttOptions::new(id, "127.0.0.1", 1900)
+}
+
+fn ws_options(id: &str) -> MqttOptions {
+    let mut options = MqttOptions::new(id, "ws://127.0.0.1:1901/mqtt", 1901);
+    options.set_transport(Transport::Ws);
+    options
+}
+
+async fn connect(
+    options: MqttOptions,
+) -> Result<(AsyncClient, EventLoop), rumqttc::ConnectionError> {
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    loop {
+        if let Event::Incoming(Packet::ConnAck(_)) = eventloop.poll().await? {
+            return Ok((client, eventloop));
+        }
+    }
+}
+
+async fn publish(client: &AsyncClient, eventloop: &mut EventLoop, topic: &str) {
+    client
+        .publish(topic, QoS::AtMostOnce, false, "hello")
+        .await
+        .unwrap();
+
+    loop {
+        if let Event::Outgoing(Outgoing::Publish(_)) = eventloop.poll().await.unwrap() {
+            return;
+        }
+    }
+}
+
+fn received(link_rx: &mut LinkRx, topic: &str) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while let Ok(notification) = link_rx.recv_deadline(deadline) {
+        if let Some(Notification::Forward(forward)) = notification {
+            if forward.publish.topic == topic {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+#[tokio::test]
+async fn clients_on_different_transports_share_the_router() {
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(CONFIG, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    let config: Config = config.try_deserialize().unwrap();
+
+    let mut broker = Broker::new(config);
+    let listeners = broker.listeners();
+    let (mut link_tx, mut link_rx) = broker.link("observer").unwrap();
+    thread::spawn(move || broker.start().unwrap());
+    link_tx.subscribe("listeners/#").unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    assert_eq!(listeners.names(), vec!["v4.1", "ws.1"]);
+
+    let (tcp_client, mut tcp_eventloop) = connect(tcp_options("tcp-client")).await.unwrap();
+    let (ws_client, mut ws_eventloop) = connect(ws_options("ws-client")).await.unwrap();
+
+    publish(&tcp_client, &mut tcp_eventloop, "listeners/tcp").await;
+    assert!(received(&mut link_rx, "listeners/tcp"));
+    publish(&ws_client, &mut ws_eventloop, "listeners/ws").await;
+    assert!(received(&mut link_rx, "listeners/ws"));
+
+    // Stopped listener doesn't accept new connections, but keeps the accepted ones
+    assert!(listeners.stop("ws.1"));
+    assert_eq!(listeners.is_running("ws.1"), Some(false));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(connect(ws_options("ws-client-2")).await.is_err());
+    publish(&ws_client, &mut ws_eventloop, "listeners/ws").await;
+    assert!(received(&mut link_rx, "listeners/ws"));
+    publish(&tcp_client, &mut tcp_eventloop, "listeners/tcp").await;
+    assert!(received(&mut link_rx, "listeners/tcp"));
+
+    assert!(listeners.start("ws.1"));
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(connect(ws_options("ws-client-2")).await.is_ok());
+
+    assert!(!listeners.stop("v5.1"));
+}
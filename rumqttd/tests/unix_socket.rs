@@ -0,0 +1,76 @@
+#![cfg(unix)]
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing, Packet, QoS, Transport};
+use rumqttd::{Broker, Config, Notification};
+
+const CONFIG: &str = r#"
+id = 0
+
+[router]
+id = 0
+max_connections = 10
+max_outgoing_packet_count = 200
+max_segment_size = 104857600
+max_segment_count = 10
+
+[v4.1]
+name = "v4-1"
+listen = "127.0.0.1:1903"
+unix_socket = "{path}"
+next_connection_delay_ms = 1
+    [v4.1.connections]
+    connection_timeout_ms = 60000
+    max_payload_size = 20480
+    max_inflight_count = 100
+"#;
+
+#[tokio::test]
+async fn clients_connect_over_unix_socket() {
+    let path = std::env::temp_dir().join(format!("rumqttd-{}.sock", std::process::id()));
+    let config = CONFIG.replace("{path}", path.to_str().unwrap());
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(&config, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    let config: Config = config.try_deserialize().unwrap();
+
+    let mut broker = Broker::new(config);
+    let (mut link_tx, mut link_rx) = broker.link("observer").unwrap();
+    thread::spawn(move || broker.start().unwrap());
+    link_tx.subscribe("unix/#").unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // port is ignored for unix sockets
+    let mut options = MqttOptions::new("unix-client", path.to_str().unwrap(), 0);
+    options.set_transport(Transport::Unix);
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    loop {
+        if let Event::Incoming(Packet::ConnAck(_)) = eventloop.poll().await.unwrap() {
+            break;
+        }
+    }
+
+    client
+        .publish("unix/hello", QoS::AtMostOnce, false, "hello")
+        .await
+        .unwrap();
+    loop {
+        if let Event::Outgoing(Outgoing::Publish(_)) = eventloop.poll().await.unwrap() {
+            break;
+        }
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut received = false;
+    while let Ok(notification) = link_rx.recv_deadline(deadline) {
+        if let Some(Notification::Forward(forward)) = notification {
+            received = forward.publish.topic == "unix/hello";
+            break;
+        }
+    }
+
+    assert!(received);
+}
@@ -0,0 +1,104 @@
+use std::fs;
+
+use rumqttd::{Broker, Config, WalConfig, WalReader};
+
+const CONFIG: &str = r#"
+id = 0
+
+[router]
+id = 0
+max_connections = 10
+max_outgoing_packet_count = 200
+max_segment_size = 104857600
+max_segment_count = 10
+"#;
+
+fn config() -> Config {
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(CONFIG, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    config.try_deserialize().unwrap()
+}
+
+#[test]
+fn publishes_are_on_disk_after_shutdown() {
+    let mut config = config();
+    let dir = std::env::temp_dir().join(format!("rumqttd-wal-shutdown-{}", std::process::id()));
+    config.router.wal = Some(WalConfig {
+        dir: dir.clone(),
+        max_file_size: 1024 * 1024,
+        max_files: 10,
+        key_file: None,
+    });
+
+    let broker = Broker::new(config);
+    let (mut link_tx, _link_rx) = broker.link("publisher").unwrap();
+    for i in 0..100 {
+        link_tx.publish("hello/world", i.to_string()).unwrap();
+    }
+    broker.shutdown();
+
+    let payloads: Vec<_> = WalReader::open(&dir)
+        .unwrap()
+        .map(|record| record.unwrap().publish.payload)
+        .collect();
+    let expected: Vec<_> = (0..100).map(|i| i.to_string()).collect();
+    assert_eq!(payloads, expected);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn broker_fails_to_start_when_wal_cannot_be_opened() {
+    let mut config = config();
+    // Directories can't be created under a file
+    let file = std::env::temp_dir().join(format!("rumqttd-wal-file-{}", std::process::id()));
+    fs::write(&file, "").unwrap();
+    config.router.wal = Some(WalConfig {
+        dir: file.join("wal"),
+        max_file_size: 1024 * 1024,
+        max_files: 10,
+        key_file: None,
+    });
+
+    let mut broker = Broker::new(config);
+    let error = broker.start().unwrap_err();
+    assert!(format!("{error:?}").contains("write-ahead log"));
+
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn replayed_publishes_are_not_recorded_again() {
+    let id = std::process::id();
+    let recorded = std::env::temp_dir().join(format!("rumqttd-wal-recorded-{id}"));
+    let replayed = std::env::temp_dir().join(format!("rumqttd-wal-replayed-{id}"));
+    let wal = |dir: &std::path::Path| WalConfig {
+        dir: dir.to_owned(),
+        max_file_size: 1024 * 1024,
+        max_files: 10,
+        key_file: None,
+    };
+
+    let mut recording = config();
+    recording.router.wal = Some(wal(&recorded));
+    let broker = Broker::new(recording);
+    let (mut link_tx, _link_rx) = broker.link("publisher").unwrap();
+    for i in 0..250 {
+        link_tx.publish("hello/world", i.to_string()).unwrap();
+    }
+    broker.shutdown();
+
+    // replay returns once the router handled every publish
+    let mut replaying = config();
+    replaying.router.wal = Some(wal(&replayed));
+    let broker = Broker::new(replaying);
+    broker.replay(&recorded, false).unwrap().join().unwrap();
+    broker.shutdown();
+
+    assert_eq!(WalReader::open(&replayed).unwrap().count(), 0);
+
+    fs::remove_dir_all(&recorded).unwrap();
+    fs::remove_dir_all(&replayed).unwrap();
+}
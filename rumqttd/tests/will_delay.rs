@@ -0,0 +1,96 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rumqttc::v5::mqttbytes::v5::{ConnectProperties, LastWill, Packet};
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::{Event, EventLoop, MqttOptions};
+use rumqttd::local::LinkRx;
+use rumqttd::{Broker, Config, Notification};
+
+const CONFIG: &str = r#"
+id = 0
+
+[router]
+id = 0
+max_connections = 10
+max_outgoing_packet_count = 200
+max_segment_size = 104857600
+max_segment_count = 10
+
+[v5.1]
+name = "v5-1"
+listen = "127.0.0.1:1899"
+next_connection_delay_ms = 1
+    [v5.1.connections]
+    connection_timeout_ms = 60000
+    max_payload_size = 20480
+    max_inflight_count = 100
+"#;
+
+fn options() -> MqttOptions {
+    let mut options = MqttOptions::new("will-client", "127.0.0.1", 1899);
+    let mut properties = ConnectProperties::new();
+    properties.session_expiry_interval = Some(60);
+    options
+        .set_clean_start(false)
+        .set_connect_properties(properties)
+        .set_last_will(LastWill::new(
+            "will/client",
+            "gone",
+            QoS::AtMostOnce,
+            false,
+            None,
+        ))
+        .set_will_delay_interval(Duration::from_secs(3));
+
+    options
+}
+
+async fn connect() -> EventLoop {
+    let mut eventloop = EventLoop::new(options(), 10);
+    loop {
+        if let Event::Incoming(Packet::ConnAck(_)) = eventloop.poll().await.unwrap() {
+            return eventloop;
+        }
+    }
+}
+
+fn will_published(link_rx: &mut LinkRx, within: Duration) -> bool {
+    let deadline = Instant::now() + within;
+    while let Ok(notification) = link_rx.recv_deadline(deadline) {
+        if let Some(Notification::Forward(forward)) = notification {
+            if forward.publish.topic == "will/client" {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+#[tokio::test]
+async fn will_is_not_published_when_client_reconnects_within_delay() {
+    let config = config::Config::builder()
+        .add_source(config::File::from_str(CONFIG, config::FileFormat::Toml))
+        .build()
+        .unwrap();
+    let config: Config = config.try_deserialize().unwrap();
+
+    let mut broker = Broker::new(config);
+    let (mut link_tx, mut link_rx) = broker.link("observer").unwrap();
+    thread::spawn(move || broker.start().unwrap());
+    link_tx.subscribe("will/#").unwrap();
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // Dropping eventloop closes the connection without a disconnect packet
+    let eventloop = connect().await;
+    drop(eventloop);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let eventloop = connect().await;
+    assert!(!will_published(&mut link_rx, Duration::from_secs(5)));
+
+    // Will is published after the delay when client doesn't come back
+    drop(eventloop);
+    assert!(will_published(&mut link_rx, Duration::from_secs(5)));
+}